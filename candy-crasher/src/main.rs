@@ -2,7 +2,9 @@ use std::ptr::null_mut;
 use std::time::Duration;
 use std::{ops::Range, sync::atomic::AtomicU64, sync::atomic::Ordering::SeqCst};
 
-use candystore::{CandyStore, Config, Result};
+use candystore::{
+    siphash_key_hasher, CandyStore, Config, EntrySeq, Result, ShardFileLayout, MAX_VALUE_SIZE,
+};
 use rand::Rng;
 
 const TARGET: u32 = 1_000_000;
@@ -10,12 +12,24 @@ const CONFIG: Config = Config {
     max_shard_size: 64 * 1024 * 1024,
     min_compaction_threashold: 8 * 1024 * 1024,
     hash_seed: *b"kOYLu0xvq2WtzcKJ",
+    key_hasher: siphash_key_hasher,
     expected_number_of_keys: 0,
+    initial_num_shards: 0,
     max_concurrent_list_ops: 64,
     truncate_up: true,
     clear_on_unsupported_version: true,
     mlock_headers: false,
     num_compaction_threads: 4,
+    typed_entry_seq: EntrySeq::Disabled,
+    typed_value_tags: false,
+    max_inline_value: MAX_VALUE_SIZE,
+    auto_big_value_threshold: MAX_VALUE_SIZE,
+    shard_file_layout: ShardFileLayout::Flat,
+    change_feed_capacity: 0,
+    change_feed_persistent: false,
+    max_update_retries: 100,
+    value_codec: None,
+    value_compression_threshold: 256,
 };
 
 fn child_inserts() -> Result<()> {
@@ -1,4 +1,4 @@
-use candystore::{CandyStore, Config, Result};
+use candystore::{CandyFixedStore, CandyStore, CandyTypedStore, Config, Result};
 use std::{
     hint::black_box,
     sync::{atomic::AtomicU64, Arc},
@@ -84,6 +84,45 @@ fn test_small_keys(num_keys: u32) -> Result<()> {
     Ok(())
 }
 
+fn test_typed_keys(num_keys: u32) -> Result<()> {
+    let db = Arc::new(CandyStore::open(
+        "./dbdir",
+        Config {
+            expected_number_of_keys: num_keys as usize,
+            ..Default::default()
+        },
+    )?);
+    db.clear()?;
+
+    println!("{num_keys} u64 keys, typed vs fixed");
+
+    let typed = CandyTypedStore::<u64, u32>::new(db.clone());
+    run("  Typed insert", num_keys, |i| {
+        typed.set(&(i as u64), &i)?;
+        Ok(())
+    })?;
+    run("  Typed get", num_keys, |i| {
+        black_box(typed.get(&(i as u64))?.unwrap());
+        Ok(())
+    })?;
+
+    // start from the same (empty) state, so both get the same number of splits
+    db.clear()?;
+
+    let fixed = CandyFixedStore::<8, u32>::new(db.clone())?;
+    run("  Fixed insert", num_keys, |i| {
+        fixed.set(&(i as u64).to_le_bytes(), &i)?;
+        Ok(())
+    })?;
+    run("  Fixed get", num_keys, |i| {
+        black_box(fixed.get(&(i as u64).to_le_bytes())?.unwrap());
+        Ok(())
+    })?;
+
+    println!();
+    Ok(())
+}
+
 fn test_large_keys(num_keys: u32) -> Result<()> {
     for pre_split in [true, false] {
         let db = CandyStore::open(
@@ -422,6 +461,7 @@ fn test_concurrency_with_contention(num_threads: u32, num_keys: u32) -> Result<(
 
 fn main() -> Result<()> {
     test_small_keys(1_000_000)?;
+    test_typed_keys(1_000_000)?;
     test_large_keys(500_000)?;
     test_lists(10, 100_000)?;
     test_concurrency_without_contention(10, 100_000)?;
@@ -0,0 +1,111 @@
+//! Derive macros for [candystore](https://docs.rs/candystore). These are re-exported by candystore, so
+//! there's no need to depend on this crate directly
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Expr, Fields};
+
+/// Implements `CandyTypedKey` for a struct or an enum, with the `TYPE_ID` given by the
+/// `#[candy(type_id = ...)]` attribute. See the documentation of `CandyTypedKey` in candystore
+#[proc_macro_derive(CandyTypedKey, attributes(candy))]
+pub fn derive_candy_typed_key(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn parse_type_id(input: &DeriveInput) -> syn::Result<Expr> {
+    let mut type_id = None;
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("candy"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("type_id") {
+                type_id = Some(meta.value()?.parse::<Expr>()?);
+                Ok(())
+            } else {
+                Err(meta.error("unsupported candy attribute, expected `type_id`"))
+            }
+        })?;
+    }
+    type_id.ok_or_else(|| {
+        Error::new_spanned(
+            &input.ident,
+            "missing `#[candy(type_id = ...)]` attribute (a random u32 that must never change)",
+        )
+    })
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let type_id = parse_type_id(input)?;
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let mut tokens = quote! {
+        impl #impl_generics ::candystore::CandyTypedKey for #name #ty_generics #where_clause {
+            const TYPE_ID: u32 = #type_id;
+        }
+    };
+
+    // fieldless enums are encoded by their discriminant, while the rest must derive (or implement) the
+    // databuf traits themselves
+    if let Data::Enum(data) = &input.data {
+        if data
+            .variants
+            .iter()
+            .all(|v| matches!(v.fields, Fields::Unit))
+        {
+            tokens.extend(expand_fieldless_enum(input, data)?);
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn expand_fieldless_enum(input: &DeriveInput, data: &syn::DataEnum) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &input.generics,
+            "generic fieldless enums are not supported",
+        ));
+    }
+    let variants = data.variants.iter().map(|v| &v.ident).collect::<Vec<_>>();
+    let err = format!("invalid discriminant {{}} for {name}");
+
+    // the discriminants are widened to i64, which covers every discriminant losslessly (u64 ones wrap around
+    // but remain distinct)
+    Ok(quote! {
+        impl ::candystore::databuf::Encode for #name {
+            fn encode<const CONFIG: u16>(
+                &self,
+                writer: &mut (impl ::std::io::Write + ?Sized),
+            ) -> ::std::io::Result<()> {
+                let discriminant = match *self {
+                    #(Self::#variants => Self::#variants as i64,)*
+                };
+                ::candystore::databuf::Encode::encode::<CONFIG>(&discriminant, writer)
+            }
+        }
+
+        impl<'de> ::candystore::databuf::Decode<'de> for #name {
+            fn decode<const CONFIG: u16>(
+                reader: &mut &'de [u8],
+            ) -> ::candystore::databuf::Result<Self> {
+                let discriminant =
+                    <i64 as ::candystore::databuf::Decode>::decode::<CONFIG>(reader)?;
+                #(
+                    if discriminant == Self::#variants as i64 {
+                        return Ok(Self::#variants);
+                    }
+                )*
+                Err(format!(#err, discriminant).into())
+            }
+        }
+    })
+}
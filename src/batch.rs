@@ -0,0 +1,396 @@
+use anyhow::{anyhow, ensure};
+use siphasher::sip128::SipHasher24;
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+};
+
+use crate::{
+    CandyError, CandyStore, Result, MAX_KEY_SIZE, MAX_TOTAL_KEY_SIZE, MAX_TOTAL_VALUE_SIZE,
+};
+
+const JOURNAL_FILENAME: &str = "journal";
+const JOURNAL_TMP_FILENAME: &str = "journal.tmp";
+const JOURNAL_MAGIC: [u8; 8] = *b"CandyWAL";
+const JOURNAL_HEADER_SIZE: usize = JOURNAL_MAGIC.len() + 2 * size_of::<u64>();
+
+// list items are stored with their index (u64) appended to the value, and their key is suffixed by the
+// list's hash (u64) and a namespace byte
+const LIST_ITEM_OVERHEAD: usize = size_of::<u64>();
+const LIST_ITEM_KEY_OVERHEAD: usize = size_of::<u64>() + 1;
+
+// all keys are full keys (including their namespace), except for the list operations, which take the same
+// (user-level) list and item keys as [CandyStore::set_in_list]
+enum BatchOp {
+    Set {
+        key: Vec<u8>,
+        val: Vec<u8>,
+    },
+    Remove {
+        key: Vec<u8>,
+    },
+    SetInList {
+        list_key: Vec<u8>,
+        item_key: Vec<u8>,
+        val: Vec<u8>,
+    },
+    RemoveFromList {
+        list_key: Vec<u8>,
+        item_key: Vec<u8>,
+    },
+}
+
+impl BatchOp {
+    const SET: u8 = 1;
+    const REMOVE: u8 = 2;
+    const SET_IN_LIST: u8 = 3;
+    const REMOVE_FROM_LIST: u8 = 4;
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let (tag, fields): (u8, &[&[u8]]) = match self {
+            Self::Set { key, val } => (Self::SET, &[key, val]),
+            Self::Remove { key } => (Self::REMOVE, &[key]),
+            Self::SetInList {
+                list_key,
+                item_key,
+                val,
+            } => (Self::SET_IN_LIST, &[list_key, item_key, val]),
+            Self::RemoveFromList { list_key, item_key } => {
+                (Self::REMOVE_FROM_LIST, &[list_key, item_key])
+            }
+        };
+        buf.push(tag);
+        for field in fields {
+            buf.extend_from_slice(&(field.len() as u32).to_le_bytes());
+            buf.extend_from_slice(field);
+        }
+    }
+
+    fn decode_field(buf: &mut &[u8]) -> Result<Vec<u8>> {
+        let corrupt = || anyhow!(CandyError::Corruption("truncated journal entry".into()));
+        let (len, rest) = buf.split_at_checked(size_of::<u32>()).ok_or_else(corrupt)?;
+        let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+        let (field, rest) = rest.split_at_checked(len).ok_or_else(corrupt)?;
+        *buf = rest;
+        Ok(field.to_vec())
+    }
+
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        let Some((&tag, rest)) = buf.split_first() else {
+            return Err(anyhow!(CandyError::Corruption(
+                "truncated journal entry".into()
+            )));
+        };
+        *buf = rest;
+        Ok(match tag {
+            Self::SET => Self::Set {
+                key: Self::decode_field(buf)?,
+                val: Self::decode_field(buf)?,
+            },
+            Self::REMOVE => Self::Remove {
+                key: Self::decode_field(buf)?,
+            },
+            Self::SET_IN_LIST => Self::SetInList {
+                list_key: Self::decode_field(buf)?,
+                item_key: Self::decode_field(buf)?,
+                val: Self::decode_field(buf)?,
+            },
+            Self::REMOVE_FROM_LIST => Self::RemoveFromList {
+                list_key: Self::decode_field(buf)?,
+                item_key: Self::decode_field(buf)?,
+            },
+            _ => {
+                return Err(anyhow!(CandyError::Corruption(format!(
+                    "unknown journal entry {tag}"
+                ))))
+            }
+        })
+    }
+}
+
+/// A group of writes that are applied atomically with respect to crashes: either all of them survive a crash
+/// (or power loss), or none of them do. Obtained by [CandyStore::batch], and applied by [Self::commit].
+///
+/// On commit, the batch is first written to a journal file (and synced to disk), and only then applied to the
+/// store. If the process crashes mid-way, the journal is replayed the next time the store is opened. Once the
+/// batch is applied, the shards are flushed to disk and the journal is removed, so commits are costly (they
+/// involve several fsyncs) and should be used for writes that must be durable as a group, e.g., the two legs of
+/// a transfer.
+///
+/// Notes:
+/// * The batch is atomic with respect to crashes, not to concurrent readers, which may observe some of its
+///   writes before others. Commits are serialized with each other, but not with other writes: a concurrent
+///   write to a key of the batch may be overwritten if the batch is replayed
+/// * All keys and values are validated when staged, so that commit won't fail mid-way on a bad entry
+/// * Typed wrappers stage their writes via `stage_*` methods (e.g., [crate::CandyTypedStore::stage_set]).
+///   Staged writes do not call the on-change callbacks of typed wrappers
+/// * Big values and queues cannot be staged
+pub struct WriteBatch<'a> {
+    store: &'a CandyStore,
+    ops: Vec<BatchOp>,
+    // called after a successful commit, e.g., to invalidate the read caches of typed wrappers
+    on_commit: Vec<Box<dyn FnOnce() + 'a>>,
+}
+
+impl<'a> WriteBatch<'a> {
+    fn ensure_raw_sizes(&self, key_len: usize, val_len: usize) -> Result<()> {
+        ensure!(
+            key_len <= MAX_TOTAL_KEY_SIZE,
+            CandyError::KeyTooLong(key_len)
+        );
+        ensure!(
+            val_len <= MAX_TOTAL_VALUE_SIZE,
+            CandyError::ValueTooLong(val_len)
+        );
+        let max_shard_size = self.store.config.max_shard_size as usize;
+        ensure!(
+            key_len + val_len <= max_shard_size,
+            CandyError::EntryCannotFitInShard(key_len + val_len, max_shard_size)
+        );
+        Ok(())
+    }
+
+    /// Stages [CandyStore::set] of the given key and value
+    pub fn set<B1: AsRef<[u8]> + ?Sized, B2: AsRef<[u8]> + ?Sized>(
+        &mut self,
+        key: &B1,
+        val: &B2,
+    ) -> Result<()> {
+        self.store.ensure_sizes(key.as_ref(), val.as_ref())?;
+        let key = self.store.make_user_key(key.as_ref().to_owned());
+        self.set_raw(key, val.as_ref().to_owned())
+    }
+
+    /// Stages [CandyStore::remove] of the given key
+    pub fn remove<B: AsRef<[u8]> + ?Sized>(&mut self, key: &B) -> Result<()> {
+        let key = key.as_ref();
+        ensure!(key.len() <= MAX_KEY_SIZE, CandyError::KeyTooLong(key.len()));
+        self.ops.push(BatchOp::Remove {
+            key: self.store.make_user_key(key.to_owned()),
+        });
+        Ok(())
+    }
+
+    /// Stages [CandyStore::set_in_list] of the given list, item and value
+    pub fn set_in_list<
+        B1: AsRef<[u8]> + ?Sized,
+        B2: AsRef<[u8]> + ?Sized,
+        B3: AsRef<[u8]> + ?Sized,
+    >(
+        &mut self,
+        list_key: &B1,
+        item_key: &B2,
+        val: &B3,
+    ) -> Result<()> {
+        let (list_key, item_key, val) = (list_key.as_ref(), item_key.as_ref(), val.as_ref());
+        self.ensure_raw_sizes(
+            item_key.len() + LIST_ITEM_KEY_OVERHEAD,
+            val.len() + LIST_ITEM_OVERHEAD,
+        )?;
+        self.ops.push(BatchOp::SetInList {
+            list_key: list_key.to_owned(),
+            item_key: item_key.to_owned(),
+            val: val.to_owned(),
+        });
+        Ok(())
+    }
+
+    /// Stages [CandyStore::remove_from_list] of the given list and item
+    pub fn remove_from_list<B1: AsRef<[u8]> + ?Sized, B2: AsRef<[u8]> + ?Sized>(
+        &mut self,
+        list_key: &B1,
+        item_key: &B2,
+    ) -> Result<()> {
+        self.ops.push(BatchOp::RemoveFromList {
+            list_key: list_key.as_ref().to_owned(),
+            item_key: item_key.as_ref().to_owned(),
+        });
+        Ok(())
+    }
+
+    pub(crate) fn set_raw(&mut self, full_key: Vec<u8>, val: Vec<u8>) -> Result<()> {
+        self.ensure_raw_sizes(full_key.len(), val.len())?;
+        self.ops.push(BatchOp::Set { key: full_key, val });
+        Ok(())
+    }
+
+    pub(crate) fn remove_raw(&mut self, full_key: Vec<u8>) -> Result<()> {
+        ensure!(
+            full_key.len() <= MAX_TOTAL_KEY_SIZE,
+            CandyError::KeyTooLong(full_key.len())
+        );
+        self.ops.push(BatchOp::Remove { key: full_key });
+        Ok(())
+    }
+
+    pub(crate) fn on_commit(&mut self, f: impl FnOnce() + 'a) {
+        self.on_commit.push(Box::new(f));
+    }
+
+    /// Returns the number of staged writes
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    /// Returns true if no writes were staged
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Applies all the staged writes (in the order they were staged), atomically with respect to crashes.
+    /// Once this returns successfully, the writes are durable.
+    ///
+    /// If this fails after the journal was written (e.g., due to an IO error), the batch is not lost: it will
+    /// be completed by the next commit, or when the store is next opened
+    pub fn commit(self) -> Result<()> {
+        if self.ops.is_empty() {
+            return Ok(());
+        }
+
+        let _guard = self.store.journal_lock.lock();
+        // complete a previous commit that had failed mid-way, so that its journal won't be overwritten
+        self.store.replay_journal()?;
+
+        self.store.write_journal(&self.ops)?;
+        self.store.apply_ops(&self.ops)?;
+        self.store.complete_journal()?;
+
+        for f in self.on_commit {
+            f();
+        }
+        Ok(())
+    }
+
+    /// Writes the journal but does not apply it, as if the process crashed right after writing it. The batch will
+    /// be applied when the store is next opened
+    #[cfg(feature = "whitebox_testing")]
+    pub fn commit_journal_only(self) -> Result<()> {
+        let _guard = self.store.journal_lock.lock();
+        self.store.write_journal(&self.ops)
+    }
+}
+
+fn sync_dir(dir: &Path) -> Result<()> {
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+impl CandyStore {
+    /// Returns a new, empty [WriteBatch], in which writes can be staged and then committed atomically (with
+    /// respect to crashes)
+    pub fn batch(&self) -> WriteBatch<'_> {
+        WriteBatch {
+            store: self,
+            ops: vec![],
+            on_commit: vec![],
+        }
+    }
+
+    // the journal is written to a temp file which is then renamed, so that it's either complete or absent. the
+    // rename is the commit point
+    fn write_journal(&self, ops: &[BatchOp]) -> Result<()> {
+        let mut body = vec![];
+        for op in ops {
+            op.encode(&mut body);
+        }
+        let checksum = SipHasher24::new().hash(&body);
+
+        let tmp_filename = self.config.dir_path.join(JOURNAL_TMP_FILENAME);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&tmp_filename)?;
+        file.write_all(&JOURNAL_MAGIC)?;
+        file.write_all(&checksum.h1.to_le_bytes())?;
+        file.write_all(&checksum.h2.to_le_bytes())?;
+        file.write_all(&body)?;
+        file.sync_all()?;
+
+        std::fs::rename(&tmp_filename, self.config.dir_path.join(JOURNAL_FILENAME))?;
+        sync_dir(&self.config.dir_path)
+    }
+
+    // all ops are idempotent, so they can be applied again if we crashed while applying them
+    fn apply_ops(&self, ops: &[BatchOp]) -> Result<()> {
+        for op in ops {
+            match op {
+                BatchOp::Set { key, val } => {
+                    self.set_raw(key, val)?;
+                }
+                BatchOp::Remove { key } => {
+                    self.remove_raw(key)?;
+                }
+                BatchOp::SetInList {
+                    list_key,
+                    item_key,
+                    val,
+                } => {
+                    self.owned_set_in_list(list_key.clone(), item_key.clone(), val.clone(), false)?;
+                }
+                BatchOp::RemoveFromList { list_key, item_key } => {
+                    self.owned_remove_from_list(list_key.clone(), item_key.clone())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // makes the applied ops durable, and only then removes the journal (durably, so it won't be replayed over
+    // later writes)
+    fn complete_journal(&self) -> Result<()> {
+        self.flush()?;
+        std::fs::remove_file(self.config.dir_path.join(JOURNAL_FILENAME))?;
+        sync_dir(&self.config.dir_path)
+    }
+
+    // removes the journal left by a commit that did not complete, if any, without applying it
+    pub(crate) fn discard_journal(&self) -> Result<()> {
+        for filename in [JOURNAL_TMP_FILENAME, JOURNAL_FILENAME] {
+            let path = self.config.dir_path.join(filename);
+            if std::fs::exists(&path)? {
+                std::fs::remove_file(&path)?;
+            }
+        }
+        sync_dir(&self.config.dir_path)
+    }
+
+    // applies the journal left by a commit that did not complete, if any. returns true if a journal was replayed
+    pub(crate) fn replay_journal(&self) -> Result<bool> {
+        // a temp journal means we crashed before the commit point, so the batch is discarded
+        let tmp_filename = self.config.dir_path.join(JOURNAL_TMP_FILENAME);
+        if std::fs::exists(&tmp_filename)? {
+            std::fs::remove_file(&tmp_filename)?;
+        }
+
+        let filename = self.config.dir_path.join(JOURNAL_FILENAME);
+        let journal = match std::fs::read(&filename) {
+            Ok(journal) => journal,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+
+        ensure!(
+            journal.len() >= JOURNAL_HEADER_SIZE && journal[..JOURNAL_MAGIC.len()] == JOURNAL_MAGIC,
+            CandyError::Corruption(format!("{filename:?} has a bad header"))
+        );
+        let (header, body) = journal.split_at(JOURNAL_HEADER_SIZE);
+        let checksum = SipHasher24::new().hash(body);
+        ensure!(
+            header[8..16] == checksum.h1.to_le_bytes()
+                && header[16..24] == checksum.h2.to_le_bytes(),
+            CandyError::Corruption(format!("{filename:?} has a bad checksum"))
+        );
+
+        let mut ops = vec![];
+        let mut buf = body;
+        while !buf.is_empty() {
+            ops.push(BatchOp::decode(&mut buf)?);
+        }
+
+        self.apply_ops(&ops)?;
+        self.complete_journal()?;
+        Ok(true)
+    }
+}
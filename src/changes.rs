@@ -0,0 +1,391 @@
+use anyhow::anyhow;
+use parking_lot::Mutex;
+use siphasher::sip128::SipHasher24;
+use std::{
+    collections::VecDeque,
+    fs::OpenOptions,
+    io::Write,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    hashing::PartedHash,
+    store::{
+        InternalConfig, ITEM_NAMESPACE, LIST_NAMESPACE, QUEUE_NAMESPACE, TYPED_FIXED_NAMESPACE,
+        TYPED_NAMESPACE, TYPED_NS_NAMESPACE, USER_NAMESPACE,
+    },
+    CandyError, CandyStore, Result,
+};
+
+const CHANGES_FILENAME: &str = "changes";
+const CHANGES_TMP_FILENAME: &str = "changes.tmp";
+const CHANGES_MAGIC: [u8; 8] = *b"CandyCDC";
+const CHANGES_HEADER_SIZE: usize = CHANGES_MAGIC.len() + 2 * size_of::<u64>();
+
+/// The kind of a change recorded in the change feed (see [CandyStore::changes_since])
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChangeOp {
+    /// the entry was created or updated
+    Set,
+    /// the entry was removed
+    Remove,
+    /// the whole store was cleared (see [CandyStore::clear]). The record's key is empty
+    Clear,
+}
+
+/// The kind of entry a change was made to, which determines what the record's key is
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ChangeKind {
+    /// a key-value entry (see [CandyStore::set]). The key is the entry's key
+    Kv,
+    /// an entry of a [crate::CandyTypedStore] whose key type has the given `TYPE_ID` (and which was
+    /// created in the given namespace, if any). The key is the encoded typed key
+    Typed {
+        type_id: u32,
+        namespace: Option<String>,
+    },
+    /// an entry of a [crate::CandyFixedStore] whose value type has the given tag (see
+    /// [crate::register_value_tag]), in the given namespace. The key is the entry's key bytes
+    Fixed { type_id: u32, namespace: u32 },
+    /// a list was created, removed, or had its length or extent changed (i.e., elements were added or
+    /// removed). The key is the list's key (for typed lists, the encoded key followed by its `TYPE_ID`)
+    List,
+    /// an element of a list was created, updated, removed or moved. The key is the element's key, but the
+    /// list it belongs to is not recorded (adding and removing elements also record a [Self::List] change)
+    ListItem,
+    /// a queue (or a big value, see [CandyStore::set_big]) was created, removed or modified. The key is the
+    /// queue's key (for typed queues, the encoded key followed by its `TYPE_ID`)
+    Queue,
+}
+
+/// A change recorded in the change feed (see [CandyStore::changes_since]). Records tell which entries
+/// changed, not their new values, so consumers should read the current values from the store
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangeRecord {
+    /// the sequence number of the record, which increases by one for every record
+    pub seq: u64,
+    pub op: ChangeOp,
+    pub kind: ChangeKind,
+    pub key: Vec<u8>,
+}
+
+/// A position in the change feed, as returned by [CandyStore::changes_since] and
+/// [CandyStore::changes_cursor]. The default cursor is older than any record, so polling with it returns
+/// all records (unless some were already dropped). Cursors can be persisted via [Self::cookie] and
+/// [Self::from_cookie]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ChangeCursor(u64);
+
+impl ChangeCursor {
+    /// Returns the cookie of this cursor, from which it can be reconstructed using [Self::from_cookie].
+    /// This is the sequence number of the next record
+    pub fn cookie(&self) -> u64 {
+        self.0
+    }
+
+    /// Reconstructs a cursor from the cookie returned by [Self::cookie]
+    pub fn from_cookie(cookie: u64) -> Self {
+        Self(cookie)
+    }
+}
+
+// returns the kind of change and the (user-level) key of the given full key, or None if changes to its
+// namespace are not reported (e.g., list chains and queue elements)
+fn classify(full_key: &[u8]) -> Option<(ChangeKind, &[u8])> {
+    fn type_id_of(rest: &[u8]) -> Option<(u32, &[u8])> {
+        let (rest, type_id) = rest.split_at_checked(rest.len().checked_sub(size_of::<u32>())?)?;
+        Some((u32::from_ne_bytes(type_id.try_into().unwrap()), rest))
+    }
+
+    let (&ns, rest) = full_key.split_last()?;
+    match std::slice::from_ref(&ns) {
+        USER_NAMESPACE => Some((ChangeKind::Kv, rest)),
+        TYPED_NAMESPACE => {
+            let (type_id, key) = type_id_of(rest)?;
+            Some((
+                ChangeKind::Typed {
+                    type_id,
+                    namespace: None,
+                },
+                key,
+            ))
+        }
+        TYPED_NS_NAMESPACE => {
+            // the namespace is followed by its length (u16), see CandyTypedStore::make_key_suffix
+            let (type_id, rest) = type_id_of(rest)?;
+            let (rest, ns_len) =
+                rest.split_at_checked(rest.len().checked_sub(size_of::<u16>())?)?;
+            let ns_len = u16::from_ne_bytes(ns_len.try_into().unwrap()) as usize;
+            let (key, ns) = rest.split_at_checked(rest.len().checked_sub(ns_len)?)?;
+            Some((
+                ChangeKind::Typed {
+                    type_id,
+                    namespace: Some(String::from_utf8_lossy(ns).into_owned()),
+                },
+                key,
+            ))
+        }
+        TYPED_FIXED_NAMESPACE => {
+            // the key is followed by the namespace and the value type tag, see CandyFixedStore::new_in_namespace
+            let (rest, trailer) =
+                rest.split_at_checked(rest.len().checked_sub(2 * size_of::<u32>())?)?;
+            Some((
+                ChangeKind::Fixed {
+                    type_id: u32::from_le_bytes(trailer[4..].try_into().unwrap()),
+                    namespace: u32::from_le_bytes(trailer[..4].try_into().unwrap()),
+                },
+                rest,
+            ))
+        }
+        LIST_NAMESPACE => Some((ChangeKind::List, rest)),
+        ITEM_NAMESPACE => Some((
+            ChangeKind::ListItem,
+            // item keys are suffixed by the list's hash
+            &rest[..rest.len().checked_sub(size_of::<PartedHash>())?],
+        )),
+        QUEUE_NAMESPACE => Some((ChangeKind::Queue, rest)),
+        _ => None,
+    }
+}
+
+// records are kept with their full keys, and are classified when they are read
+struct LoggedChange {
+    op: ChangeOp,
+    full_key: Vec<u8>,
+}
+
+impl LoggedChange {
+    const SET: u8 = 1;
+    const REMOVE: u8 = 2;
+    const CLEAR: u8 = 3;
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(match self.op {
+            ChangeOp::Set => Self::SET,
+            ChangeOp::Remove => Self::REMOVE,
+            ChangeOp::Clear => Self::CLEAR,
+        });
+        buf.extend_from_slice(&(self.full_key.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&self.full_key);
+    }
+
+    fn decode(buf: &mut &[u8]) -> Option<Self> {
+        let (&tag, rest) = buf.split_first()?;
+        let (len, rest) = rest.split_at_checked(size_of::<u32>())?;
+        let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+        let (full_key, rest) = rest.split_at_checked(len)?;
+        *buf = rest;
+        let op = match tag {
+            Self::SET => ChangeOp::Set,
+            Self::REMOVE => ChangeOp::Remove,
+            Self::CLEAR => ChangeOp::Clear,
+            _ => return None,
+        };
+        Some(Self {
+            op,
+            full_key: full_key.to_vec(),
+        })
+    }
+}
+
+struct ChangeLog {
+    // the sequence number of the next record. the records held are the ones preceding it
+    next_seq: u64,
+    records: VecDeque<LoggedChange>,
+}
+
+impl ChangeLog {
+    fn oldest_seq(&self) -> u64 {
+        self.next_seq - self.records.len() as u64
+    }
+}
+
+pub(crate) struct ChangeFeed {
+    capacity: usize,
+    // where the log is saved when the store is dropped, if it's persistent
+    path: Option<PathBuf>,
+    log: Mutex<ChangeLog>,
+}
+
+impl ChangeFeed {
+    /// creates the change feed configured by [crate::Config::change_feed_capacity], or returns None if it's
+    /// disabled. A persistent feed picks up the log saved when the store was last dropped
+    pub(crate) fn open(config: &InternalConfig) -> Result<Option<Self>> {
+        if config.change_feed_capacity == 0 {
+            return Ok(None);
+        }
+
+        // a fresh log starts at the current time, so that cursors of a previous log (which was lost in a crash
+        // or is not persisted) are older than it, and consumers find out that they missed changes
+        let mut log = ChangeLog {
+            next_seq: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_micros() as u64),
+            records: VecDeque::new(),
+        };
+
+        let path = config
+            .change_feed_persistent
+            .then(|| config.dir_path.join(CHANGES_FILENAME));
+        if let Some(ref path) = path {
+            _ = std::fs::remove_file(config.dir_path.join(CHANGES_TMP_FILENAME));
+            match std::fs::read(path) {
+                Ok(data) => {
+                    // a log that can't be loaded is discarded, like one that was lost in a crash
+                    if let Some(loaded) = Self::decode(&data) {
+                        log = loaded;
+                    }
+                    // the log is saved again when the store is dropped. until then it's only in memory, so a
+                    // crash must not leave this (stale) copy behind
+                    std::fs::remove_file(path)?;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        while log.records.len() > config.change_feed_capacity {
+            log.records.pop_front();
+        }
+
+        Ok(Some(Self {
+            capacity: config.change_feed_capacity,
+            path,
+            log: Mutex::new(log),
+        }))
+    }
+
+    fn decode(data: &[u8]) -> Option<ChangeLog> {
+        if data.len() < CHANGES_HEADER_SIZE || data[..CHANGES_MAGIC.len()] != CHANGES_MAGIC {
+            return None;
+        }
+        let (header, body) = data.split_at(CHANGES_HEADER_SIZE);
+        let checksum = SipHasher24::new().hash(body);
+        if header[8..16] != checksum.h1.to_le_bytes() || header[16..24] != checksum.h2.to_le_bytes()
+        {
+            return None;
+        }
+
+        let (next_seq, mut buf) = body.split_at_checked(size_of::<u64>())?;
+        let mut log = ChangeLog {
+            next_seq: u64::from_le_bytes(next_seq.try_into().unwrap()),
+            records: VecDeque::new(),
+        };
+        while !buf.is_empty() {
+            log.records.push_back(LoggedChange::decode(&mut buf)?);
+        }
+        (log.records.len() as u64 <= log.next_seq).then_some(log)
+    }
+
+    fn save(&self, path: &PathBuf) -> Result<()> {
+        let log = self.log.lock();
+        let mut body = log.next_seq.to_le_bytes().to_vec();
+        for record in log.records.iter() {
+            record.encode(&mut body);
+        }
+        let checksum = SipHasher24::new().hash(&body);
+
+        let tmp_filename = path.with_file_name(CHANGES_TMP_FILENAME);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&tmp_filename)?;
+        file.write_all(&CHANGES_MAGIC)?;
+        file.write_all(&checksum.h1.to_le_bytes())?;
+        file.write_all(&checksum.h2.to_le_bytes())?;
+        file.write_all(&body)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_filename, path)?;
+        Ok(())
+    }
+
+    fn push(&self, op: ChangeOp, full_key: &[u8]) {
+        let mut log = self.log.lock();
+        if log.records.len() >= self.capacity {
+            log.records.pop_front();
+        }
+        log.records.push_back(LoggedChange {
+            op,
+            full_key: full_key.to_vec(),
+        });
+        log.next_seq += 1;
+    }
+}
+
+impl Drop for ChangeFeed {
+    fn drop(&mut self) {
+        if let Some(path) = self.path.take() {
+            _ = self.save(&path);
+        }
+    }
+}
+
+impl CandyStore {
+    // records a change to the given full key in the change feed (if it's enabled and the key's namespace is
+    // reported)
+    pub(crate) fn record_change(&self, op: ChangeOp, full_key: &[u8]) {
+        let Some(ref feed) = self.change_feed else {
+            return;
+        };
+        if op == ChangeOp::Clear || classify(full_key).is_some() {
+            feed.push(op, full_key);
+        }
+    }
+
+    /// Returns the changes recorded since the given cursor (in the order they were recorded), along with the
+    /// cursor to poll with next time. This requires [crate::Config::change_feed_capacity] to be set, and only
+    /// the changes made after the store was opened (or, if [crate::Config::change_feed_persistent] is set,
+    /// since the feed was created) are recorded.
+    ///
+    /// Every write and removal of a key-value pair, typed entry, list, list element or queue records a
+    /// [ChangeRecord] (removals of missing entries don't), but records only tell which entries changed,
+    /// so consumers should read their current values from the store. Since records are appended after
+    /// the change is made, concurrent changes may be recorded in a different order than they were made,
+    /// but every change is recorded after it's visible.
+    ///
+    /// The feed holds the most recent [crate::Config::change_feed_capacity] records. If the records
+    /// following `cursor` were already dropped (e.g., the consumer fell behind, or the store crashed), this
+    /// returns [CandyError::ChangesLost], after which the consumer should resynchronize with the store
+    /// (e.g., by iterating over it), and continue from [Self::changes_cursor], taken before doing so
+    pub fn changes_since(&self, cursor: ChangeCursor) -> Result<(Vec<ChangeRecord>, ChangeCursor)> {
+        let Some(ref feed) = self.change_feed else {
+            return Err(anyhow!(CandyError::NotEnabled("change feeds")));
+        };
+        let log = feed.log.lock();
+        let oldest = log.oldest_seq();
+        if cursor.0 < oldest || cursor.0 > log.next_seq {
+            return Err(anyhow!(CandyError::ChangesLost {
+                cursor: cursor.0,
+                oldest,
+            }));
+        }
+
+        let records = log
+            .records
+            .iter()
+            .zip(oldest..)
+            .skip((cursor.0 - oldest) as usize)
+            .map(|(record, seq)| {
+                let (kind, key) = classify(&record.full_key).unwrap_or((ChangeKind::Kv, &[]));
+                ChangeRecord {
+                    seq,
+                    op: record.op,
+                    kind,
+                    key: key.to_vec(),
+                }
+            })
+            .collect();
+        Ok((records, ChangeCursor(log.next_seq)))
+    }
+
+    /// Returns the cursor that follows the most recent change, i.e., polling with it returns only changes
+    /// made from now on. See [Self::changes_since]
+    pub fn changes_cursor(&self) -> Result<ChangeCursor> {
+        let Some(ref feed) = self.change_feed else {
+            return Err(anyhow!(CandyError::NotEnabled("change feeds")));
+        };
+        Ok(ChangeCursor(feed.log.lock().next_seq))
+    }
+}
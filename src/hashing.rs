@@ -6,6 +6,17 @@ use bytemuck::{Pod, Zeroable};
 
 pub type HashSeed = [u8; 16];
 
+/// A function that hashes a key (with the given seed) into 128 bits, which determine the key's shard, row and
+/// signature. See [crate::Config::key_hasher]
+pub type KeyHasher = fn(seed: &HashSeed, key: &[u8]) -> (u64, u64);
+
+/// The default [KeyHasher], SipHash-2-4 (128 bit). Being a keyed PRF, it distributes any key pattern (including
+/// sequential integers) uniformly across shards and rows
+pub fn siphash_key_hasher(seed: &HashSeed, key: &[u8]) -> (u64, u64) {
+    let h = SipHasher24::new_with_key(seed).hash(key);
+    (h.h1, h.h2)
+}
+
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Pod, Zeroable, Hash)]
 #[repr(transparent)]
 pub(crate) struct PartedHash(u64);
@@ -28,8 +39,14 @@ pub(crate) const INVALID_SIG: u32 = 0;
 pub static mut HASH_BITS_TO_KEEP: u64 = u64::MAX; // which bits to keep from the hash - for testing collisions
 
 impl PartedHash {
+    #[allow(dead_code)]
     pub fn new(seed: &HashSeed, buf: &[u8]) -> Self {
-        Self::from_hash(SipHasher24::new_with_key(&seed).hash(buf))
+        Self::with_hasher(siphash_key_hasher, seed, buf)
+    }
+
+    pub fn with_hasher(hasher: KeyHasher, seed: &HashSeed, buf: &[u8]) -> Self {
+        let (h1, h2) = hasher(seed, buf);
+        Self::from_hash(Hash128 { h1, h2 })
     }
 
     #[inline]
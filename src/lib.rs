@@ -43,6 +43,8 @@
 //! }
 //! ```
 
+mod batch;
+mod changes;
 mod hashing;
 mod lists;
 mod queues;
@@ -52,22 +54,91 @@ mod stats;
 mod store;
 mod typed;
 
-pub use hashing::HashSeed;
-pub use lists::{ListCompactionParams, ListIterator};
+pub use batch::WriteBatch;
+/// Derives [CandyTypedKey] (see there)
+pub use candystore_derive::CandyTypedKey;
+pub use changes::{ChangeCursor, ChangeKind, ChangeOp, ChangeRecord};
+/// The serialization crate used by the typed wrappers, whose `Encode` and `Decode` traits keys and values
+/// implement (usually by deriving them)
+pub use databuf;
+pub use hashing::{siphash_key_hasher, HashSeed, KeyHasher};
+pub use lists::{
+    ListBatch, ListCompactionParams, ListCompactionStats, ListCursor, ListDebugInfo, ListIterator,
+    ListPage, MaintenanceHandle,
+};
+pub use queues::{QueueHealth, QueueSnapshot, QueueState};
 pub use stats::Stats;
-pub use store::{CandyStore, GetOrCreateStatus, ReplaceStatus, SetStatus};
-pub use typed::{CandyTypedDeque, CandyTypedKey, CandyTypedList, CandyTypedStore};
+pub use store::{
+    BigValueReader, BigValueWriter, CandyStore, CompactionReport, GetOrCreateStatus, ReplaceStatus,
+    SetStatus, ShardStat, StoreCursor,
+};
+pub use typed::{
+    register_value_tag, CandyFixedStore, CandyTypedDeque, CandyTypedIndex, CandyTypedKey,
+    CandyTypedList, CandyTypedListBatch, CandyTypedPriorityDeque, CandyTypedStore, ChangeEvent,
+};
 
 use std::fmt::{Display, Formatter};
 
 #[cfg(feature = "whitebox_testing")]
 pub use hashing::HASH_BITS_TO_KEEP;
 
+/// The errors specific to CandyStore. All APIs return [anyhow::Error], so to tell these apart, use
+/// `err.downcast_ref::<CandyError>()` and match on the result. Errors of the underlying file operations
+/// are [std::io::Error]s (and can be downcast as such), and missing keys are never errors (APIs return
+/// `None` instead)
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum CandyError {
     KeyTooLong(usize),
     ValueTooLong(usize),
     EntryCannotFitInShard(usize, usize),
+    TypeIdCollision(u32, &'static str, &'static str),
+    BatchInterrupted(usize),
+    /// a typed key or value could not be decoded (e.g., it was written by a different type)
+    DecodeFailed(String),
+    /// an entry was stored differently than the API that's reading it expects
+    WrongType(&'static str),
+    /// the data on disk is corrupt
+    Corruption(String),
+    /// the operation requires a feature that's disabled in the [Config]
+    NotEnabled(&'static str),
+    /// the operation would overwrite an existing entity (e.g., renaming a list to an existing one)
+    AlreadyExists(&'static str),
+    /// a value is larger than [Config::max_inline_value] (and smaller than [MAX_VALUE_SIZE], see
+    /// [CandyError::ValueTooLong]). Such values should be stored with [CandyStore::set_big] instead
+    ValueTooLarge {
+        size: usize,
+        limit: usize,
+    },
+    /// a typed value was stored with a different value type than the one reading it (see
+    /// [Config::typed_value_tags]). Holds the tags of the expected and of the found types
+    WrongValueType {
+        expected: u32,
+        found: u32,
+    },
+    /// no tag was registered for the given value type (see [register_value_tag]), which is required when
+    /// [Config::typed_value_tags] is enabled and by [CandyFixedStore]
+    ValueTagNotRegistered(&'static str),
+    /// the value tag is already registered (see [register_value_tag]) for the first type, so it can't be
+    /// registered for the second
+    ValueTagCollision(u32, &'static str, &'static str),
+    /// the store was written with an on-disk format version this version of the crate can't read.
+    /// [CandyStore::open_and_migrate] upgrades stores for which a safe migration exists. This is also
+    /// returned if typed values were stored with a different layout than the configured one (see
+    /// [Config::typed_entry_seq], [Config::typed_value_tags] and [Config::value_codec]), in which case
+    /// `found` and `supported` are the recorded and the configured layouts
+    IncompatibleFormat {
+        found: u64,
+        supported: u64,
+    },
+    /// the change feed no longer holds the records following the given cursor (see
+    /// [CandyStore::changes_since]). Holds the cursor's sequence number and that of the oldest record held
+    ChangesLost {
+        cursor: u64,
+        oldest: u64,
+    },
+    /// an optimistic update (see [CandyTypedStore::update]) kept conflicting with concurrent writers. Holds
+    /// the number of retries
+    TooManyRetries(usize),
 }
 
 impl Display for CandyError {
@@ -78,6 +149,47 @@ impl Display for CandyError {
             Self::EntryCannotFitInShard(sz, max) => {
                 write!(f, "entry too big ({sz}) for a single shard file ({max})")
             }
+            Self::TypeIdCollision(type_id, first, second) => {
+                write!(
+                    f,
+                    "TYPE_ID 0x{type_id:08x} is used by both {first} and {second}"
+                )
+            }
+            Self::BatchInterrupted(done) => {
+                write!(f, "batch interrupted after {done} items were processed")
+            }
+            Self::DecodeFailed(reason) => write!(f, "decoding failed: {reason}"),
+            Self::WrongType(expected) => write!(f, "entry was not stored by {expected}"),
+            Self::Corruption(reason) => write!(f, "corruption: {reason}"),
+            Self::NotEnabled(feature) => write!(f, "{feature} are not enabled"),
+            Self::AlreadyExists(what) => write!(f, "{what} already exists"),
+            Self::ValueTooLarge { size, limit } => {
+                write!(f, "value too large ({size}) for inline storage ({limit})")
+            }
+            Self::WrongValueType { expected, found } => write!(
+                f,
+                "value type tag mismatch (expected 0x{expected:08x}, found 0x{found:08x})"
+            ),
+            Self::ValueTagNotRegistered(name) => {
+                write!(f, "no value tag is registered for {name}")
+            }
+            Self::ValueTagCollision(tag, first, second) => {
+                write!(
+                    f,
+                    "value tag 0x{tag:08x} is used by both {first} and {second}"
+                )
+            }
+            Self::IncompatibleFormat { found, supported } => write!(
+                f,
+                "incompatible on-disk format version {found} (supported version is {supported})"
+            ),
+            Self::ChangesLost { cursor, oldest } => write!(
+                f,
+                "changes since {cursor} were lost (the oldest change held is {oldest})"
+            ),
+            Self::TooManyRetries(retries) => {
+                write!(f, "update conflicted with other writers {retries} times")
+            }
         }
     }
 }
@@ -86,6 +198,73 @@ impl std::error::Error for CandyError {}
 
 pub type Result<T> = anyhow::Result<T>;
 
+/// Whether (and how) typed stores keep a sequence number with each entry. See [Config::typed_entry_seq]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntrySeq {
+    /// no sequence numbers are kept
+    Disabled,
+    /// a counter that's persisted in the store, so it keeps increasing across restarts
+    Counter,
+    /// the wall-clock time (microseconds since the UNIX epoch) of the write. This is only as monotonic
+    /// as the system's clock
+    Timestamp,
+}
+
+/// How shard files are laid out in the store's directory. See [Config::shard_file_layout]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShardFileLayout {
+    /// all shard files are kept directly in the store's directory
+    Flat,
+    /// shard files are spread across (up to 256) subdirectories, named after the first two hex digits of the
+    /// shard's span (e.g., `shard_ab00-ac00` is kept in `ab/`), so that no directory holds more than 256 of them
+    Nested,
+}
+
+/// A compression codec for typed values. See [Config::value_codec]. The codec is just a pair of functions,
+/// so any compression library can be plugged in, e.g.
+///
+/// ```ignore
+/// ValueCodec {
+///     compress: |data| lz4_flex::compress_prepend_size(data),
+///     decompress: |data| Ok(lz4_flex::decompress_size_prepended(data)?),
+/// }
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct ValueCodec {
+    /// compresses the given (encoded) value
+    pub compress: fn(data: &[u8]) -> Vec<u8>,
+    /// decompresses what `compress` returned. Errors are not propagated, the entry is simply taken to be
+    /// uncompressed
+    pub decompress: fn(data: &[u8]) -> Result<Vec<u8>>,
+}
+
+/// Receives notifications about store operations, e.g., for exporting metrics. Register one by setting
+/// [Config::observer]. All methods have empty default implementations, so implement only what you need.
+///
+/// Observers are notified of the underlying store operations, so a single list, queue or typed operation
+/// may be reported as several gets/sets/removes. Methods are called synchronously on the calling thread,
+/// so they should be quick (e.g., incrementing counters or recording into a histogram)
+#[cfg(feature = "observer")]
+pub trait Observer: Send + Sync {
+    /// called after every lookup, whether the key was found or not
+    fn on_get(&self, _elapsed: std::time::Duration) {}
+    /// called after a lookup of a key that does not exist
+    fn on_miss(&self) {}
+    /// called after every insertion or update
+    fn on_set(&self, _elapsed: std::time::Duration) {}
+    /// called after every removal, whether the key existed or not
+    fn on_remove(&self, _elapsed: std::time::Duration) {}
+    /// called when a big value (see [CandyStore::set_big]) of the given length is written
+    fn on_big_value(&self, _len: usize) {}
+}
+
+#[cfg(feature = "observer")]
+impl std::fmt::Debug for dyn Observer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Observer")
+    }
+}
+
 /// The configuration options for CandyStore. Comes with sane defaults, feel free to use them
 #[derive(Debug, Clone)]
 pub struct Config {
@@ -95,8 +274,21 @@ pub struct Config {
     pub min_compaction_threashold: u32,
     /// just some entropy, not so important unless you fear DoS
     pub hash_seed: HashSeed,
-    /// hint for creating number of shards accordingly)
+    /// the function used to hash keys (along with `hash_seed`). Like `hash_seed`, this must never change for
+    /// an existing store. The default (SipHash) distributes all key patterns uniformly, so a custom hasher is
+    /// mostly useful for trading distribution quality for speed
+    pub key_hasher: KeyHasher,
+    /// hint for creating number of shards accordingly). Pre-splitting the store saves the splits that would
+    /// otherwise take place while it fills up, at the cost of more shard files (file descriptors, and
+    /// mmapped headers) upfront. See also [Self::initial_num_shards]
     pub expected_number_of_keys: usize,
+    /// the minimal number of shards to create when the store is created (rounded up to a power of two,
+    /// and capped at 65536), regardless of [Self::expected_number_of_keys]. 0 means it's derived only from
+    /// [Self::expected_number_of_keys]. More shards mean less contention between writers (each shard has its
+    /// own lock) and fewer splits under load, but each shard costs a file descriptor and ~400KB of (mmapped)
+    /// header, and [CandyStore::merge_small_shards] will not merge below this number.
+    /// This only affects newly-created stores; existing stores keep the shards they have on disk
+    pub initial_num_shards: u32,
     /// number of keyed locks for concurrent list ops
     pub max_concurrent_list_ops: u32,
     /// whether or not to truncate up shard files to their max size (spare files)
@@ -107,10 +299,64 @@ pub struct Config {
     pub mlock_headers: bool,
     /// number of background compaction threads
     pub num_compaction_threads: usize,
+    /// whether or not entries of [CandyTypedStore] carry a sequence number (8 bytes per entry), which is
+    /// updated on every write (see [CandyTypedStore::get_with_seq]). This determines the on-disk format of
+    /// typed values, so it must not be changed for an existing store: the store records whether sequence
+    /// numbers are kept, and [CandyStore::open] fails with [CandyError::IncompatibleFormat] if that changes.
+    /// Switching between [EntrySeq::Counter] and [EntrySeq::Timestamp] is allowed
+    pub typed_entry_seq: EntrySeq,
+    /// whether or not entries of [CandyTypedStore] carry a tag of their value type (4 bytes per entry), so
+    /// that reading them with a different value type (e.g., two wrappers that share `K` but not `V`) fails
+    /// with [CandyError::WrongValueType] rather than with an obscure decoding error (or silently returning
+    /// garbage). Every value type needs a tag, registered with [register_value_tag] (the builtin key types,
+    /// such as integers and `String`, come registered), and writing or reading a type without one fails with
+    /// [CandyError::ValueTagNotRegistered].
+    /// Like [Self::typed_entry_seq], this determines the on-disk format, so it must not be changed for an
+    /// existing store (which is checked on open)
+    pub typed_value_tags: bool,
+    /// the maximal size of values stored inline by [CandyStore::set] and the rest of the key-value APIs
+    /// (including those of [CandyTypedStore]), above which they fail with [CandyError::ValueTooLarge]. This can
+    /// be used to catch values that should have been stored with [CandyStore::set_big]. Values larger than
+    /// [MAX_VALUE_SIZE] are always rejected (with [CandyError::ValueTooLong]), and lists and queues are not
+    /// affected by this limit
+    pub max_inline_value: usize,
+    /// the (serialized) value size above which [CandyTypedStore::set_auto] stores values as big values
+    /// (see [CandyStore::set_big]) rather than inline. Values larger than [MAX_VALUE_SIZE] (or than
+    /// [Self::max_inline_value]) are always stored as big values
+    pub auto_big_value_threshold: usize,
+    /// the codec used to compress the values of [CandyTypedStore] (see [Self::value_compression_threshold]),
+    /// or `None` to store them as they are. Compressed entries are tagged, so entries written before the codec
+    /// was set are still read correctly, but once it's set, it must remain set (and able to decompress what
+    /// it had compressed) as long as compressed entries exist. The store records that a codec was set, and
+    /// [CandyStore::open] fails with [CandyError::IncompatibleFormat] if it's no longer set (until the store
+    /// is cleared)
+    pub value_codec: Option<ValueCodec>,
+    /// the (serialized) value size from which [CandyTypedStore] values are compressed with
+    /// [Self::value_codec]. Values that don't shrink are stored uncompressed. Unlike the codec, this may be
+    /// changed freely. Big values are never compressed
+    pub value_compression_threshold: usize,
+    /// how shard files are laid out in the store's directory (see [ShardFileLayout]). Some filesystems
+    /// perform poorly with many files in a single directory, which is what a large store ends up with
+    /// under the default ([ShardFileLayout::Flat]). Shard files are found on open regardless of the layout
+    /// that created them, and are moved to match this layout, so it can be changed for an existing store
+    pub shard_file_layout: ShardFileLayout,
+    /// the number of most recent changes held by the change feed (see [CandyStore::changes_since]), or 0 to
+    /// disable it. Every change costs a record (holding its key) in memory
+    pub change_feed_capacity: usize,
+    /// whether or not the change feed is saved (to a file in the store's directory) when the store is
+    /// dropped and loaded when it's opened, so consumers can continue from their cursors across restarts.
+    /// Changes made since the store was opened are lost in a crash (and consumers will be told so)
+    pub change_feed_persistent: bool,
+    /// the number of times [CandyTypedStore::update] retries when the value changed between reading it and
+    /// writing the updated one, before failing with [CandyError::TooManyRetries]
+    pub max_update_retries: usize,
     /// optionally delay modifying operations before for the given duration before flushing data to disk,
     /// to ensure reboot consistency
     #[cfg(feature = "flush_aggregation")]
     pub flush_aggregation_delay: Option<std::time::Duration>,
+    /// an observer to notify of store operations (see [Observer])
+    #[cfg(feature = "observer")]
+    pub observer: Option<std::sync::Arc<dyn Observer>>,
 }
 
 impl Default for Config {
@@ -119,14 +365,28 @@ impl Default for Config {
             max_shard_size: 64 * 1024 * 1024,
             min_compaction_threashold: 8 * 1024 * 1024,
             hash_seed: *b"kOYLu0xvq2WtzcKJ",
+            key_hasher: siphash_key_hasher,
             expected_number_of_keys: 0,
+            initial_num_shards: 0,
             max_concurrent_list_ops: 64,
             truncate_up: true,
             clear_on_unsupported_version: false,
             mlock_headers: false,
             num_compaction_threads: 4,
+            typed_entry_seq: EntrySeq::Disabled,
+            typed_value_tags: false,
+            max_inline_value: MAX_VALUE_SIZE,
+            auto_big_value_threshold: MAX_VALUE_SIZE,
+            value_codec: None,
+            value_compression_threshold: 256,
+            shard_file_layout: ShardFileLayout::Flat,
+            change_feed_capacity: 0,
+            change_feed_persistent: false,
+            max_update_retries: 100,
             #[cfg(feature = "flush_aggregation")]
             flush_aggregation_delay: None,
+            #[cfg(feature = "observer")]
+            observer: None,
         }
     }
 }
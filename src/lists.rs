@@ -1,14 +1,22 @@
-use std::ops::Range;
+use std::{
+    cmp::Ordering,
+    ops::Range,
+    sync::{Arc, Weak},
+    thread::JoinHandle,
+    time::Duration,
+};
 
 use crate::{
     hashing::PartedHash,
     shard::{InsertMode, KVPair},
     store::{CHAIN_NAMESPACE, ITEM_NAMESPACE, LIST_NAMESPACE},
-    CandyStore, GetOrCreateStatus, ReplaceStatus, Result, SetStatus,
+    CandyError, CandyStore, GetOrCreateStatus, ReplaceStatus, Result, SetStatus,
+    MAX_TOTAL_KEY_SIZE, MAX_TOTAL_VALUE_SIZE,
 };
+use anyhow::{bail, ensure};
 
 use bytemuck::{bytes_of, from_bytes, Pod, Zeroable};
-use parking_lot::MutexGuard;
+use parking_lot::{Mutex, MutexGuard};
 
 #[derive(Clone, Copy, Pod, Zeroable)]
 #[repr(C)]
@@ -38,6 +46,10 @@ impl List {
     fn is_empty(&self) -> bool {
         self.head_idx == self.tail_idx
     }
+    fn needs_compaction(&self, params: ListCompactionParams) -> bool {
+        self.span_len() >= params.min_length
+            && (self.holes() as f64) >= (self.span_len() as f64) * params.min_holes_ratio
+    }
 }
 
 #[derive(Debug, Clone, Copy, Pod, Zeroable)]
@@ -48,7 +60,7 @@ struct ChainKey {
     namespace: u8,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct ListCompactionParams {
     pub min_length: u64,
     pub min_holes_ratio: f64,
@@ -63,6 +75,204 @@ impl Default for ListCompactionParams {
     }
 }
 
+/// The outcome of compacting a list, as returned by [CandyStore::compact_list]. Compaction does not remove
+/// items, only the holes between them (left by removing items from the middle of the list), so the list's
+/// span (items and holes) shrinks to its number of items
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ListCompactionStats {
+    /// whether the list was compacted. If false (the list does not exist or did not meet the
+    /// [ListCompactionParams] thresholds), the rest of the fields describe the list as it is
+    pub compacted: bool,
+    /// the number of items in the list (which compaction does not change)
+    pub num_items: u64,
+    /// the span of the list (items and holes) before compacting
+    pub span_len_before: u64,
+    /// the span of the list (items and holes) after compacting
+    pub span_len_after: u64,
+    /// the number of bytes rewritten by compaction (the items and their index entries). The previous versions
+    /// of these entries become wasted space in their shards, to be reclaimed by shard compaction
+    pub bytes_rewritten: usize,
+}
+
+impl ListCompactionStats {
+    /// the number of holes that were removed
+    pub fn holes_removed(&self) -> u64 {
+        self.span_len_before - self.span_len_after
+    }
+}
+
+/// The internal structure of a list, as returned by [CandyStore::dump_list_internals], for diagnosing lists
+/// whose iteration and length disagree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListDebugInfo {
+    /// The index of the head element (inclusive), as recorded in the list's header
+    pub head_idx: u64,
+    /// The index past the tail element (exclusive), as recorded in the list's header
+    pub tail_idx: u64,
+    /// The number of elements recorded in the list's header (i.e., [CandyStore::list_len])
+    pub recorded_len: usize,
+    /// The number of elements actually found between `head_idx` and `tail_idx` (i.e., the ones iteration yields)
+    pub present_len: usize,
+    /// Maximal runs of indices that have no link to an element. These are left by removing elements from the
+    /// middle of the list (see [CandyStore::compact_list]), so they are not an inconsistency by themselves
+    pub holes: Vec<Range<u64>>,
+    /// Indices that have a link to an element that does not exist (or that does not point back at the index)
+    pub dangling: Vec<u64>,
+}
+
+impl ListDebugInfo {
+    /// Returns true if no inconsistencies were found: the recorded length matches the elements found, and
+    /// there are no dangling links
+    pub fn is_consistent(&self) -> bool {
+        self.recorded_len == self.present_len && self.dangling.is_empty()
+    }
+}
+
+/// A handle for operating on a single list while holding its lock, passed to the closure of
+/// [CandyStore::batch_in_list]. Operations are applied as they are made (there is no rollback), but no other
+/// locking operation on the list can interleave with them
+pub struct ListBatch<'a> {
+    store: &'a CandyStore,
+    list_ph: PartedHash,
+    list_key: Vec<u8>,
+    _guard: MutexGuard<'a, ()>,
+}
+
+impl ListBatch<'_> {
+    /// Same as [CandyStore::get_from_list], on the batch's list. Sees the changes made by the batch so far
+    pub fn get<B: AsRef<[u8]> + ?Sized>(&self, item_key: &B) -> Result<Option<Vec<u8>>> {
+        let (_, item_key) = self
+            .store
+            .make_item_key(self.list_ph, item_key.as_ref().to_owned());
+        let Some(mut val) = self.store.get_raw(&item_key)? else {
+            return Ok(None);
+        };
+        val.truncate(val.len() - size_of::<u64>());
+        Ok(Some(val))
+    }
+
+    /// Same as [CandyStore::set_in_list], on the batch's list
+    pub fn set<B1: AsRef<[u8]> + ?Sized, B2: AsRef<[u8]> + ?Sized>(
+        &mut self,
+        item_key: &B1,
+        val: &B2,
+    ) -> Result<SetStatus> {
+        let (item_ph, item_key) = self
+            .store
+            .make_item_key(self.list_ph, item_key.as_ref().to_owned());
+        match self.store._insert_to_list_locked(
+            self.list_ph,
+            &self.list_key,
+            item_ph,
+            &item_key,
+            val.as_ref().to_owned(),
+            InsertMode::Set,
+        )? {
+            InsertToListStatus::Created(_v) => Ok(SetStatus::CreatedNew),
+            InsertToListStatus::Replaced(v) => Ok(SetStatus::PrevValue(v)),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Same as [CandyStore::remove_from_list], on the batch's list
+    pub fn remove<B: AsRef<[u8]> + ?Sized>(&mut self, item_key: &B) -> Result<Option<Vec<u8>>> {
+        let (_, item_key) = self
+            .store
+            .make_item_key(self.list_ph, item_key.as_ref().to_owned());
+        self.store
+            ._remove_from_list_locked(self.list_ph, &self.list_key, &item_key)
+    }
+}
+
+// a maintenance thread, shared by its handle and the store, either of which may stop and join it
+pub(crate) struct MaintenanceThread(Mutex<MaintenanceThreadState>);
+
+struct MaintenanceThreadState {
+    stop_tx: Option<crossbeam_channel::Sender<()>>,
+    thread: Option<JoinHandle<Result<()>>>,
+    // the outcome of the thread once it was joined, so that the handle can report it even if the store
+    // joined it
+    outcome: Option<std::thread::Result<Result<()>>>,
+}
+
+impl MaintenanceThread {
+    pub(crate) fn stop_and_join(&self) {
+        let mut guard = self.0.lock();
+        // dropping the sender wakes the thread up
+        guard.stop_tx.take();
+        let Some(th) = guard.thread.take() else {
+            return;
+        };
+        if th.thread().id() == std::thread::current().id() {
+            // the store was dropped by the thread itself (when a pass released the last reference), which
+            // can't join itself. It exits right away, since it was stopped
+            guard.thread = Some(th);
+            return;
+        }
+        guard.outcome = Some(th.join());
+    }
+}
+
+/// A handle to the background maintenance thread spawned by [CandyStore::spawn_maintenance]. Dropping the handle
+/// stops the thread (waiting for its current pass to finish), and so does [Self::stop], which also reports the
+/// error that stopped the thread, if any. Dropping ignores such errors, including a panic of the thread.
+///
+/// The thread is also stopped and joined when the store is dropped, so it never outlives the store. Until then,
+/// callers must keep the handle for as long as maintenance should run
+pub struct MaintenanceHandle {
+    thread: Arc<MaintenanceThread>,
+}
+
+impl MaintenanceHandle {
+    /// Returns true if the thread is still running. The thread exits on its own if a pass fails or once the
+    /// store is dropped
+    pub fn is_running(&self) -> bool {
+        self.thread
+            .0
+            .lock()
+            .thread
+            .as_ref()
+            .is_some_and(|th| !th.is_finished())
+    }
+
+    /// Stops the thread and waits for it to exit. Returns the error that made it exit early, if any
+    pub fn stop(self) -> Result<()> {
+        self.thread.stop_and_join();
+        match self.thread.0.lock().outcome.take() {
+            None => Ok(()),
+            Some(Err(e)) => std::panic::resume_unwind(e),
+            Some(Ok(res)) => res,
+        }
+    }
+}
+
+impl Drop for MaintenanceHandle {
+    fn drop(&mut self) {
+        // a panic is not propagated here, as we may already be unwinding
+        self.thread.stop_and_join();
+    }
+}
+
+/// An opaque position in a list, as returned by [CandyStore::list_page], that can be used to fetch the next page.
+/// Cursors can be persisted or handed to clients via [Self::cookie] and [Self::from_cookie]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ListCursor(u64);
+
+impl ListCursor {
+    /// Returns the cookie of this cursor, from which it can be reconstructed using [Self::from_cookie]
+    pub fn cookie(&self) -> u64 {
+        self.0
+    }
+
+    /// Reconstructs a cursor from the cookie returned by [Self::cookie]
+    pub fn from_cookie(cookie: u64) -> Self {
+        Self(cookie)
+    }
+}
+
+/// A page of list elements, along with the cursor of the next page (see [CandyStore::list_page])
+pub type ListPage<T> = (Vec<T>, Option<ListCursor>);
+
 pub struct ListIterator<'a> {
     store: &'a CandyStore,
     list_key: Vec<u8>,
@@ -129,33 +339,69 @@ impl CandyStore {
 
     fn make_list_key(&self, mut list_key: Vec<u8>) -> (PartedHash, Vec<u8>) {
         list_key.extend_from_slice(LIST_NAMESPACE);
-        (PartedHash::new(&self.config.hash_seed, &list_key), list_key)
+        (self.config.hash_key(&list_key), list_key)
     }
 
     fn make_item_key(&self, list_ph: PartedHash, mut item_key: Vec<u8>) -> (PartedHash, Vec<u8>) {
         item_key.extend_from_slice(bytes_of(&list_ph));
         item_key.extend_from_slice(ITEM_NAMESPACE);
-        (PartedHash::new(&self.config.hash_seed, &item_key), item_key)
+        (self.config.hash_key(&item_key), item_key)
     }
 
     pub(crate) fn lock_list(&self, list_ph: PartedHash) -> MutexGuard<()> {
         self.keyed_locks[(list_ph.signature() & self.keyed_locks_mask) as usize].lock()
     }
 
+    /// locks the keyed locks of all given hashes, in a deadlock-safe order (sorted by lock index). Hashes that
+    /// map to the same lock are only locked once
+    pub(crate) fn lock_ordered(&self, phs: &[PartedHash]) -> Vec<MutexGuard<'_, ()>> {
+        let mut indices = phs
+            .iter()
+            .map(|ph| (ph.signature() & self.keyed_locks_mask) as usize)
+            .collect::<Vec<_>>();
+        indices.sort();
+        indices.dedup();
+        indices
+            .into_iter()
+            .map(|idx| self.keyed_locks[idx].lock())
+            .collect()
+    }
+
     fn _insert_to_list(
         &self,
         list_key: Vec<u8>,
         item_key: Vec<u8>,
-        mut val: Vec<u8>,
+        val: Vec<u8>,
         mode: InsertMode,
     ) -> Result<InsertToListStatus> {
         let (list_ph, list_key) = self.make_list_key(list_key);
         let (item_ph, item_key) = self.make_item_key(list_ph, item_key);
 
         let _guard = self.lock_list(list_ph);
+        self._insert_to_list_locked(list_ph, &list_key, item_ph, &item_key, val, mode)
+    }
+
+    fn _insert_to_list_locked(
+        &self,
+        list_ph: PartedHash,
+        list_key: &[u8],
+        item_ph: PartedHash,
+        item_key: &[u8],
+        mut val: Vec<u8>,
+        mode: InsertMode,
+    ) -> Result<InsertToListStatus> {
+        // fail before touching the list, so that a too-long item won't leave a dangling index behind
+        ensure!(
+            item_key.len() <= MAX_TOTAL_KEY_SIZE,
+            CandyError::KeyTooLong(item_key.len())
+        );
+        ensure!(
+            val.len() + size_of::<u64>() <= MAX_TOTAL_VALUE_SIZE,
+            CandyError::ValueTooLong(val.len())
+        );
 
         // if the item already exists, it's already part of the list. just update it and preserve the index
-        if let Some(mut existing_val) = self.get_raw(&item_key)? {
+        if let Some(mut existing_val) = self.get_raw(item_key)? {
             match mode {
                 InsertMode::GetOrCreate => {
                     existing_val.truncate(existing_val.len() - size_of::<u64>());
@@ -176,7 +422,7 @@ impl CandyStore {
             }
 
             val.extend_from_slice(&existing_val[existing_val.len() - size_of::<u64>()..]);
-            self.replace_raw(&item_key, &val, None)?;
+            self.replace_raw(item_key, &val, None)?;
             existing_val.truncate(existing_val.len() - size_of::<u64>());
             return Ok(InsertToListStatus::Replaced(existing_val));
         }
@@ -188,7 +434,7 @@ impl CandyStore {
 
         // get of create the list
         let res = self.get_or_create_raw(
-            &list_key,
+            list_key,
             bytes_of(&List {
                 head_idx: Self::FIRST_LIST_IDX,
                 tail_idx: Self::FIRST_LIST_IDX + 1,
@@ -211,7 +457,7 @@ impl CandyStore {
 
                 // create item
                 val.extend_from_slice(bytes_of(&Self::FIRST_LIST_IDX));
-                self.set_raw(&item_key, &val)?;
+                self.set_raw(item_key, &val)?;
             }
             crate::GetOrCreateStatus::ExistingValue(list_bytes) => {
                 let mut list = *from_bytes::<List>(&list_bytes);
@@ -221,7 +467,7 @@ impl CandyStore {
 
                 // update list
                 list.num_items += 1;
-                self.set_raw(&list_key, bytes_of(&list))?;
+                self.set_raw(list_key, bytes_of(&list))?;
 
                 // create chain
                 self.set_raw(
@@ -235,7 +481,7 @@ impl CandyStore {
 
                 // create item
                 val.extend_from_slice(bytes_of(&idx));
-                self.set_raw(&item_key, &val)?;
+                self.set_raw(item_key, &val)?;
             }
         }
 
@@ -267,7 +513,8 @@ impl CandyStore {
 
     /// Like [Self::set_in_list] but "promotes" the element to the tail of the list: it's basically a
     /// remove + insert operation. This can be usede to implement LRUs, where older elements are at the
-    /// beginning and newer ones at the end.
+    /// beginning and newer ones at the end. Like [Self::set_in_list], returns the previous value if the
+    /// element had existed (in which case it was moved to the tail).
     ///
     /// Note: **not crash-safe**
     pub fn set_in_list_promoting<
@@ -296,16 +543,323 @@ impl CandyStore {
         val: Vec<u8>,
         promote: bool,
     ) -> Result<SetStatus> {
-        if promote {
-            self.owned_remove_from_list(list_key.clone(), item_key.clone())?;
-        }
+        let promoted = if promote {
+            self.owned_remove_from_list(list_key.clone(), item_key.clone())?
+        } else {
+            None
+        };
         match self._insert_to_list(list_key, item_key, val, InsertMode::Set)? {
-            InsertToListStatus::Created(_v) => Ok(SetStatus::CreatedNew),
+            InsertToListStatus::Created(_v) => match promoted {
+                // the element had existed before we removed it, so this is a replacement as far as the caller
+                // is concerned
+                Some(prev) => Ok(SetStatus::PrevValue(prev)),
+                None => Ok(SetStatus::CreatedNew),
+            },
             InsertToListStatus::Replaced(v) => Ok(SetStatus::PrevValue(v)),
             _ => unreachable!(),
         }
     }
 
+    /// Like [Self::set_in_list], but also returns the length of the list after the insertion. Both are done under
+    /// the list's lock, so no other operation on this list can take place in between (e.g., for rate-limiting,
+    /// where a burst of concurrent inserts must not all observe the length from before)
+    pub fn set_in_list_and_len<
+        B1: AsRef<[u8]> + ?Sized,
+        B2: AsRef<[u8]> + ?Sized,
+        B3: AsRef<[u8]> + ?Sized,
+    >(
+        &self,
+        list_key: &B1,
+        item_key: &B2,
+        val: &B3,
+    ) -> Result<(SetStatus, usize)> {
+        self.owned_set_in_list_and_len(
+            list_key.as_ref().to_owned(),
+            item_key.as_ref().to_owned(),
+            val.as_ref().to_owned(),
+        )
+    }
+
+    /// Owned version of [Self::set_in_list_and_len]
+    pub fn owned_set_in_list_and_len(
+        &self,
+        list_key: Vec<u8>,
+        item_key: Vec<u8>,
+        val: Vec<u8>,
+    ) -> Result<(SetStatus, usize)> {
+        let (list_ph, list_key) = self.make_list_key(list_key);
+        let (item_ph, item_key) = self.make_item_key(list_ph, item_key);
+
+        let _guard = self.lock_list(list_ph);
+        let status = match self._insert_to_list_locked(
+            list_ph,
+            &list_key,
+            item_ph,
+            &item_key,
+            val,
+            InsertMode::Set,
+        )? {
+            InsertToListStatus::Created(_v) => SetStatus::CreatedNew,
+            InsertToListStatus::Replaced(v) => SetStatus::PrevValue(v),
+            _ => unreachable!(),
+        };
+        let len = match self.get_raw(&list_key)? {
+            Some(list_bytes) => from_bytes::<List>(&list_bytes).num_items as usize,
+            None => 0,
+        };
+        Ok((status, len))
+    }
+
+    /// Sets all the given items in the list, in order, under a single acquisition of the list's lock (so no other
+    /// operation on this list can interleave with them). New items are appended to the tail in the given order,
+    /// and existing items are updated in place, like [Self::set_in_list]. Returns the number of items set.
+    ///
+    /// If an error occurs mid-way, the items set so far remain in the list, and the returned error can be
+    /// downcast to [CandyError::BatchInterrupted], holding their number
+    pub fn extend_list<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        list_key: &B,
+        items: impl IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+    ) -> Result<usize> {
+        let (list_ph, list_key) = self.make_list_key(list_key.as_ref().to_owned());
+        let _guard = self.lock_list(list_ph);
+
+        let mut count = 0;
+        for (item_key, val) in items {
+            let (item_ph, item_key) = self.make_item_key(list_ph, item_key);
+            if let Err(e) = self._insert_to_list_locked(
+                list_ph,
+                &list_key,
+                item_ph,
+                &item_key,
+                val,
+                InsertMode::Set,
+            ) {
+                return Err(e.context(CandyError::BatchInterrupted(count)));
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Runs `f` on a [ListBatch] of the list, which can get, set and remove items, all under a single hold of the
+    /// list's lock. The lock is held for the whole duration of `f`, so no other operation that takes the list's
+    /// lock (sets, removes, pops, iteration, etc.) can interleave with the batch, and such operations observe
+    /// either none or all of its changes. Note that [Self::get_from_list] does not take the lock, so it may
+    /// observe a batch half-way. Keep `f` short, and beware of deadlocks: `f` must not operate on the list
+    /// (or on any list sharing its lock) other than through the batch.
+    ///
+    /// Changes are applied as they are made, so if `f` (or one of the operations) fails mid-way, the changes
+    /// made so far remain in the list. Returns whatever `f` returned
+    pub fn batch_in_list<B: AsRef<[u8]> + ?Sized, T>(
+        &self,
+        list_key: &B,
+        f: impl FnOnce(&mut ListBatch) -> Result<T>,
+    ) -> Result<T> {
+        self.owned_batch_in_list(list_key.as_ref().to_owned(), f)
+    }
+
+    /// Owned version of [Self::batch_in_list]
+    pub fn owned_batch_in_list<T>(
+        &self,
+        list_key: Vec<u8>,
+        f: impl FnOnce(&mut ListBatch) -> Result<T>,
+    ) -> Result<T> {
+        f(&mut self.lock_list_for_batch(list_key))
+    }
+
+    pub(crate) fn lock_list_for_batch(&self, list_key: Vec<u8>) -> ListBatch<'_> {
+        let (list_ph, list_key) = self.make_list_key(list_key);
+        ListBatch {
+            store: self,
+            list_ph,
+            list_key,
+            _guard: self.lock_list(list_ph),
+        }
+    }
+
+    /// Inserts an element `item_key` into the list `list_key`, positioning it before the first element whose value
+    /// is greater than `val`, according to `cmp(val, existing_val)`. If the list is kept sorted by always inserting
+    /// using this function, it will remain sorted (elements that compare equal retain their insertion order).
+    /// If the item already exists, it is removed first and then repositioned according to its new value.
+    ///
+    /// This is an O(n) operation, as it scans the list to find the position, and then shifts the following
+    /// elements by one. The list is locked during the whole operation.
+    ///
+    /// Note: **not crash-safe**
+    pub fn insert_sorted_in_list<
+        B1: AsRef<[u8]> + ?Sized,
+        B2: AsRef<[u8]> + ?Sized,
+        B3: AsRef<[u8]> + ?Sized,
+    >(
+        &self,
+        list_key: &B1,
+        item_key: &B2,
+        val: &B3,
+        cmp: impl FnMut(&[u8], &[u8]) -> Result<Ordering>,
+    ) -> Result<()> {
+        self.owned_insert_sorted_in_list(
+            list_key.as_ref().to_owned(),
+            item_key.as_ref().to_owned(),
+            val.as_ref().to_owned(),
+            cmp,
+        )
+    }
+
+    /// Owned version of [Self::insert_sorted_in_list]
+    pub fn owned_insert_sorted_in_list(
+        &self,
+        list_key: Vec<u8>,
+        item_key: Vec<u8>,
+        mut val: Vec<u8>,
+        mut cmp: impl FnMut(&[u8], &[u8]) -> Result<Ordering>,
+    ) -> Result<()> {
+        let (list_ph, list_key) = self.make_list_key(list_key);
+        let (item_ph, item_key) = self.make_item_key(list_ph, item_key);
+
+        let _guard = self.lock_list(list_ph);
+
+        // reposition an existing item
+        self._remove_from_list_locked(list_ph, &list_key, &item_key)?;
+
+        let mut list = match self.get_raw(&list_key)? {
+            Some(list_bytes) => *from_bytes::<List>(&list_bytes),
+            None => List {
+                head_idx: Self::FIRST_LIST_IDX,
+                tail_idx: Self::FIRST_LIST_IDX,
+                num_items: 0,
+            },
+        };
+
+        // find the first element that's greater than the new one
+        let mut insert_idx = list.tail_idx;
+        let mut prev_idx = None;
+        for idx in list.head_idx..list.tail_idx {
+            let Some((_, _, v)) = self.get_from_list_at_index(list_ph, idx, true)? else {
+                continue;
+            };
+            if cmp(&val, &v)? == Ordering::Less {
+                insert_idx = idx;
+                break;
+            }
+            prev_idx = Some(idx);
+        }
+
+        if insert_idx == list.tail_idx {
+            // append
+            list.tail_idx += 1;
+        } else if insert_idx == list.head_idx {
+            // prepend
+            list.head_idx -= 1;
+            insert_idx = list.head_idx;
+        } else if prev_idx.is_none_or(|prev_idx| prev_idx + 1 < insert_idx) {
+            // there's a hole right before the greater element, use it
+            insert_idx -= 1;
+        } else {
+            // shift all following elements by one, starting from the tail
+            for idx in (insert_idx..list.tail_idx).rev() {
+                let Some((ph, full_k, mut full_v)) =
+                    self.get_from_list_at_index(list_ph, idx, false)?
+                else {
+                    continue;
+                };
+                self.set_raw(
+                    bytes_of(&ChainKey {
+                        list_ph,
+                        idx: idx + 1,
+                        namespace: CHAIN_NAMESPACE,
+                    }),
+                    bytes_of(&ph),
+                )?;
+                let offset = full_v.len() - size_of::<u64>();
+                full_v[offset..].copy_from_slice(bytes_of(&(idx + 1)));
+                self.set_raw(&full_k, &full_v)?;
+                self.remove_raw(bytes_of(&ChainKey {
+                    list_ph,
+                    idx,
+                    namespace: CHAIN_NAMESPACE,
+                }))?;
+            }
+            list.tail_idx += 1;
+        }
+
+        // update list
+        list.num_items += 1;
+        self.set_raw(&list_key, bytes_of(&list))?;
+
+        // create chain
+        self.set_raw(
+            bytes_of(&ChainKey {
+                list_ph,
+                idx: insert_idx,
+                namespace: CHAIN_NAMESPACE,
+            }),
+            bytes_of(&item_ph),
+        )?;
+
+        // create item
+        val.extend_from_slice(bytes_of(&insert_idx));
+        self.set_raw(&item_key, &val)?;
+
+        Ok(())
+    }
+
+    /// Searches the list `list_key` for an element using binary search, as [slice::binary_search_by] does:
+    /// `f` is called with the values of elements and returns whether each is less than, equal to or greater
+    /// than the target. Returns the key and value of a matching element, or `None` if there's none (if several
+    /// elements match, any one of them may be returned).
+    ///
+    /// The list must be sorted consistently with `f` (e.g., by always inserting with
+    /// [Self::insert_sorted_in_list] using the same ordering), otherwise the result is unspecified.
+    ///
+    /// Elements are accessed by their position (index) in the list, so this takes O(log n) lookups, as long as
+    /// the list has few holes (see [Self::compact_list_if_needed]). Holes are skipped linearly. The list is
+    /// locked during the whole operation.
+    pub fn binary_search_in_list<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        list_key: &B,
+        f: impl FnMut(&[u8]) -> Result<Ordering>,
+    ) -> Result<Option<KVPair>> {
+        self.owned_binary_search_in_list(list_key.as_ref().to_owned(), f)
+    }
+
+    /// Owned version of [Self::binary_search_in_list]
+    pub fn owned_binary_search_in_list(
+        &self,
+        list_key: Vec<u8>,
+        mut f: impl FnMut(&[u8]) -> Result<Ordering>,
+    ) -> Result<Option<KVPair>> {
+        self._operate_on_list(list_key, None, |list_ph, _, list| {
+            let mut lo = list.head_idx;
+            let mut hi = list.tail_idx;
+            while lo < hi {
+                let mid = lo + (hi - lo) / 2;
+
+                // find the first element at or after mid (skipping holes)
+                let mut probe = mid;
+                let mut found = None;
+                while probe < hi {
+                    found = self.get_from_list_at_index(list_ph, probe, true)?;
+                    if found.is_some() {
+                        break;
+                    }
+                    probe += 1;
+                }
+                let Some((_, k, v)) = found else {
+                    hi = mid;
+                    continue;
+                };
+
+                match f(&v)? {
+                    Ordering::Equal => return Ok(Some((k, v))),
+                    Ordering::Less => lo = probe + 1,
+                    Ordering::Greater => hi = mid,
+                }
+            }
+            Ok(None)
+        })
+    }
+
     /// Like [Self::set_in_list], but will only replace (update) an existing item, i.e., it will never create the
     /// key
     pub fn replace_in_list<
@@ -402,6 +956,38 @@ impl CandyStore {
         Ok(Some(val))
     }
 
+    /// Checks which of the given `item_keys` belong to list `list_key`, returning the position (in `item_keys`)
+    /// of the first one that does, or None if none of them do. All candidates are checked under the list's
+    /// lock, so the answer is consistent with respect to concurrent list operations. This is an O(m) operation,
+    /// where m is the number of candidates
+    pub fn list_contains_any<B1: AsRef<[u8]> + ?Sized, B2: AsRef<[u8]> + ?Sized>(
+        &self,
+        list_key: &B1,
+        item_keys: &[&B2],
+    ) -> Result<Option<usize>> {
+        self.owned_list_contains_any(
+            list_key.as_ref().to_owned(),
+            item_keys.iter().map(|k| k.as_ref().to_owned()).collect(),
+        )
+    }
+
+    /// Owned version of [Self::list_contains_any]
+    pub fn owned_list_contains_any(
+        &self,
+        list_key: Vec<u8>,
+        item_keys: Vec<Vec<u8>>,
+    ) -> Result<Option<usize>> {
+        let (list_ph, _) = self.make_list_key(list_key);
+        let _guard = self.lock_list(list_ph);
+        for (i, item_key) in item_keys.into_iter().enumerate() {
+            let (_, item_key) = self.make_item_key(list_ph, item_key);
+            if self.get_raw(&item_key)?.is_some() {
+                return Ok(Some(i));
+            }
+        }
+        Ok(None)
+    }
+
     /// Removes a element from the list, identified by `list_key` and `item_key. The element can be
     /// at any position in the list, not just the head or the tail, but in this case, it will create a "hole".
     /// This means that iterations will go over the missing element's index every time, until the list is compacted.
@@ -425,8 +1011,60 @@ impl CandyStore {
         let (_, item_key) = self.make_item_key(list_ph, item_key);
 
         let _guard = self.lock_list(list_ph);
+        self._remove_from_list_locked(list_ph, &list_key, &item_key)
+    }
+
+    /// Same as [Self::remove_from_list], but only removes the element if its current value equals `expected_val`.
+    /// Returns true if the element was removed, false if it does not exist or has a different value. The
+    /// comparison and the removal are done under the list's lock, so they are atomic with respect to other
+    /// list operations
+    pub fn remove_from_list_if<
+        B1: AsRef<[u8]> + ?Sized,
+        B2: AsRef<[u8]> + ?Sized,
+        B3: AsRef<[u8]> + ?Sized,
+    >(
+        &self,
+        list_key: &B1,
+        item_key: &B2,
+        expected_val: &B3,
+    ) -> Result<bool> {
+        self.owned_remove_from_list_if(
+            list_key.as_ref().to_owned(),
+            item_key.as_ref().to_owned(),
+            expected_val.as_ref(),
+        )
+    }
+
+    /// Owned version of [Self::remove_from_list_if]
+    pub fn owned_remove_from_list_if(
+        &self,
+        list_key: Vec<u8>,
+        item_key: Vec<u8>,
+        expected_val: &[u8],
+    ) -> Result<bool> {
+        let (list_ph, list_key) = self.make_list_key(list_key);
+        let (_, item_key) = self.make_item_key(list_ph, item_key);
+
+        let _guard = self.lock_list(list_ph);
+        let Some(existing_val) = self.get_raw(&item_key)? else {
+            return Ok(false);
+        };
+        if &existing_val[..existing_val.len() - size_of::<u64>()] != expected_val {
+            return Ok(false);
+        }
+        Ok(self
+            ._remove_from_list_locked(list_ph, &list_key, &item_key)?
+            .is_some())
+    }
 
-        let Some(mut existing_val) = self.get_raw(&item_key)? else {
+    // the caller must hold the list lock
+    fn _remove_from_list_locked(
+        &self,
+        list_ph: PartedHash,
+        list_key: &[u8],
+        item_key: &[u8],
+    ) -> Result<Option<Vec<u8>>> {
+        let Some(mut existing_val) = self.get_raw(item_key)? else {
             return Ok(None);
         };
 
@@ -438,7 +1076,7 @@ impl CandyStore {
         existing_val.truncate(existing_val.len() - size_of::<u64>());
 
         // update list, if the item was the head/tail
-        if let Some(list_bytes) = self.get_raw(&list_key)? {
+        if let Some(list_bytes) = self.get_raw(list_key)? {
             let mut list = *from_bytes::<List>(&list_bytes);
 
             list.num_items -= 1;
@@ -451,9 +1089,9 @@ impl CandyStore {
                 }
             }
             if list.is_empty() {
-                self.remove_raw(&list_key)?;
+                self.remove_raw(list_key)?;
             } else {
-                self.set_raw(&list_key, bytes_of(&list))?;
+                self.set_raw(list_key, bytes_of(&list))?;
             }
         }
 
@@ -465,7 +1103,7 @@ impl CandyStore {
         }))?;
 
         // remove item
-        self.remove_raw(&item_key)?;
+        self.remove_raw(item_key)?;
 
         Ok(Some(existing_val))
     }
@@ -510,7 +1148,7 @@ impl CandyStore {
     /// You should call this function every so often if you're removing elements from lists at random locations.
     /// The function takes parameters that control when to compact: the list has to be of a minimal length and
     /// have a minimal holes-to-length ratio. The default values are expected to be okay for most use cases.
-    /// Returns true if the list was compacted, false otherwise.
+    /// Returns true if the list was compacted, false otherwise. See also [Self::compact_list].
     ///
     /// Note: **Not crash-safe**
     pub fn compact_list_if_needed<B: AsRef<[u8]> + ?Sized>(
@@ -518,18 +1156,34 @@ impl CandyStore {
         list_key: &B,
         params: ListCompactionParams,
     ) -> Result<bool> {
+        Ok(self.compact_list(list_key, params)?.compacted)
+    }
+
+    /// Same as [Self::compact_list_if_needed], but returns statistics about the compaction (or about the list,
+    /// if it was not compacted), which can be used to tune the [ListCompactionParams] thresholds.
+    ///
+    /// Note: **Not crash-safe**
+    pub fn compact_list<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        list_key: &B,
+        params: ListCompactionParams,
+    ) -> Result<ListCompactionStats> {
         let (list_ph, list_key) = self.make_list_key(list_key.as_ref().to_owned());
         let _guard = self.lock_list(list_ph);
 
         let Some(list_bytes) = self.get_raw(&list_key)? else {
-            return Ok(false);
+            return Ok(ListCompactionStats::default());
         };
         let list = *from_bytes::<List>(&list_bytes);
-        if list.span_len() < params.min_length {
-            return Ok(false);
-        }
-        if (list.holes() as f64) < (list.span_len() as f64) * params.min_holes_ratio {
-            return Ok(false);
+        let mut stats = ListCompactionStats {
+            compacted: false,
+            num_items: list.num_items,
+            span_len_before: list.span_len(),
+            span_len_after: list.span_len(),
+            bytes_rewritten: 0,
+        };
+        if !list.needs_compaction(params) {
+            return Ok(stats);
         }
 
         let mut new_idx = list.tail_idx;
@@ -554,6 +1208,8 @@ impl CandyStore {
             let offset = full_v.len() - size_of::<u64>();
             full_v[offset..].copy_from_slice(bytes_of(&new_idx));
             self.set_raw(&full_k, &full_v)?;
+            stats.bytes_rewritten +=
+                size_of::<ChainKey>() + size_of::<PartedHash>() + full_k.len() + full_v.len();
 
             // remove old chain
             self.remove_raw(bytes_of(&ChainKey {
@@ -580,6 +1236,207 @@ impl CandyStore {
             )?;
         }
 
+        stats.compacted = true;
+        stats.span_len_after = new_idx - list.tail_idx;
+        Ok(stats)
+    }
+
+    /// Returns true if the list meets the given thresholds, i.e., if [Self::compact_list_if_needed] would compact
+    /// it now, without compacting it. Returns false if the list does not exist
+    pub fn list_needs_compaction<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        list_key: &B,
+        params: ListCompactionParams,
+    ) -> Result<bool> {
+        let (list_ph, list_key) = self.make_list_key(list_key.as_ref().to_owned());
+        let _guard = self.lock_list(list_ph);
+
+        let Some(list_bytes) = self.get_raw(&list_key)? else {
+            return Ok(false);
+        };
+        Ok(from_bytes::<List>(&list_bytes).needs_compaction(params))
+    }
+
+    /// Returns the internal structure of the list (its header, and the links from its indices to its elements),
+    /// along with the inconsistencies found, for diagnosing a list whose iteration and length disagree. A list
+    /// that does not exist is reported as an empty, consistent one.
+    ///
+    /// Note: this fetches every index in the list's span (including holes), so it's meant for debugging only
+    pub fn dump_list_internals<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        list_key: &B,
+    ) -> Result<ListDebugInfo> {
+        let (list_ph, list_key) = self.make_list_key(list_key.as_ref().to_owned());
+        let _guard = self.lock_list(list_ph);
+
+        let list = match self.get_raw(&list_key)? {
+            Some(list_bytes) => *from_bytes::<List>(&list_bytes),
+            None => List {
+                head_idx: Self::FIRST_LIST_IDX,
+                tail_idx: Self::FIRST_LIST_IDX,
+                num_items: 0,
+            },
+        };
+
+        let mut info = ListDebugInfo {
+            head_idx: list.head_idx,
+            tail_idx: list.tail_idx,
+            recorded_len: list.num_items as usize,
+            present_len: 0,
+            holes: vec![],
+            dangling: vec![],
+        };
+        for idx in list.head_idx..list.tail_idx {
+            let chain_key = ChainKey {
+                list_ph,
+                idx,
+                namespace: CHAIN_NAMESPACE,
+            };
+            if self.get_raw(bytes_of(&chain_key))?.is_none() {
+                if let Some(hole) = info.holes.last_mut().filter(|hole| hole.end == idx) {
+                    hole.end += 1;
+                } else {
+                    info.holes.push(idx..idx + 1);
+                }
+            } else if self.get_from_list_at_index(list_ph, idx, false)?.is_some() {
+                info.present_len += 1;
+            } else {
+                info.dangling.push(idx);
+            }
+        }
+        Ok(info)
+    }
+
+    /// Compacts all the lists in the store that meet the given thresholds (see [Self::compact_list_if_needed]),
+    /// returning the number of lists compacted. This scans the entire store to find the lists, and takes the
+    /// lock of every list it compacts (one at a time).
+    ///
+    /// Note: **Not crash-safe**
+    pub fn compact_lists_if_needed(&self, params: ListCompactionParams) -> Result<usize> {
+        // collect the lists first, since compacting them while iterating may cause entries to move
+        let mut list_keys = vec![];
+        for res in self.iter_raw_keys() {
+            let mut k = res?;
+            if k.ends_with(LIST_NAMESPACE) {
+                k.truncate(k.len() - LIST_NAMESPACE.len());
+                list_keys.push(k);
+            }
+        }
+
+        let mut compacted = 0;
+        for list_key in list_keys {
+            if self.compact_list(&list_key, params)?.compacted {
+                compacted += 1;
+            }
+        }
+        Ok(compacted)
+    }
+
+    /// Spawns a background thread that calls [Self::compact_lists_if_needed] every `interval`, so that lists
+    /// don't have to be compacted manually. The thread runs until the returned [MaintenanceHandle] is dropped
+    /// (or stopped), until the store is dropped, or until a pass fails. The thread does not keep the store
+    /// alive, and dropping the store stops it and waits for its current pass to finish.
+    ///
+    /// Note that every pass scans the entire store and takes the locks of the lists it compacts, so it may
+    /// briefly contend with foreground operations on these lists
+    pub fn spawn_maintenance(
+        self: &Arc<Self>,
+        interval: Duration,
+        params: ListCompactionParams,
+    ) -> MaintenanceHandle {
+        let (stop_tx, stop_rx) = crossbeam_channel::bounded::<()>(0);
+        let store: Weak<Self> = Arc::downgrade(self);
+        let thread = std::thread::spawn(move || {
+            // only a timeout means we should keep running, a disconnect means we were stopped
+            while let Err(crossbeam_channel::RecvTimeoutError::Timeout) =
+                stop_rx.recv_timeout(interval)
+            {
+                let Some(store) = store.upgrade() else {
+                    break;
+                };
+                store.compact_lists_if_needed(params)?;
+            }
+            Ok(())
+        });
+
+        let thread = Arc::new(MaintenanceThread(Mutex::new(MaintenanceThreadState {
+            stop_tx: Some(stop_tx),
+            thread: Some(thread),
+            outcome: None,
+        })));
+        let mut threads = self.maintenance_threads.lock();
+        threads.retain(|th| th.strong_count() > 0);
+        threads.push(Arc::downgrade(&thread));
+        MaintenanceHandle { thread }
+    }
+
+    /// Reverses the order of the elements of the given list, so that the tail becomes the head and vice versa.
+    /// Returns false if the list does not exist. The list is rewritten under its lock, in a single critical section
+    /// (which also compacts it, as in [Self::compact_list]), so other list operations see either the old order
+    /// or the new one. Iterators that are already in progress, however, fetch elements one at a time, and may
+    /// miss elements that were moved by the reversal.
+    ///
+    /// Note: reversing invalidates existing cursors (see [Self::list_page]).
+    ///
+    /// Note: **Not crash-safe**
+    pub fn reverse_list<B: AsRef<[u8]> + ?Sized>(&self, list_key: &B) -> Result<bool> {
+        self.owned_reverse_list(list_key.as_ref().to_owned())
+    }
+
+    /// Owned version of [Self::reverse_list]
+    pub fn owned_reverse_list(&self, list_key: Vec<u8>) -> Result<bool> {
+        let (list_ph, list_key) = self.make_list_key(list_key);
+        let _guard = self.lock_list(list_ph);
+
+        let Some(list_bytes) = self.get_raw(&list_key)? else {
+            return Ok(false);
+        };
+        let list = *from_bytes::<List>(&list_bytes);
+
+        // move the elements (from tail to head) past the current tail, exactly like compaction does
+        let mut new_idx = list.tail_idx;
+        for idx in (list.head_idx..list.tail_idx).rev() {
+            let Some((item_ph, full_k, mut full_v)) =
+                self.get_from_list_at_index(list_ph, idx, false)?
+            else {
+                continue;
+            };
+
+            self.set_raw(
+                bytes_of(&ChainKey {
+                    idx: new_idx,
+                    list_ph,
+                    namespace: CHAIN_NAMESPACE,
+                }),
+                bytes_of(&item_ph),
+            )?;
+
+            let offset = full_v.len() - size_of::<u64>();
+            full_v[offset..].copy_from_slice(bytes_of(&new_idx));
+            self.set_raw(&full_k, &full_v)?;
+
+            self.remove_raw(bytes_of(&ChainKey {
+                idx,
+                list_ph,
+                namespace: CHAIN_NAMESPACE,
+            }))?;
+
+            new_idx += 1;
+        }
+
+        if list.tail_idx == new_idx {
+            self.remove_raw(&list_key)?;
+        } else {
+            self.set_raw(
+                &list_key,
+                bytes_of(&List {
+                    head_idx: list.tail_idx,
+                    tail_idx: new_idx,
+                    num_items: new_idx - list.tail_idx,
+                }),
+            )?;
+        }
+
         Ok(true)
     }
 
@@ -621,6 +1478,51 @@ impl CandyStore {
         }
     }
 
+    /// Returns up to `limit` elements of the list (from head to tail), starting at `cursor` (or at the head of
+    /// the list if `None`), along with the cursor of the next page, or `None` if the end of the list was
+    /// reached. Unlike paging by offset, cursors are not affected by removals of elements, or by insertions
+    /// (which go to the tail), so resuming from a cursor never repeats or skips elements.
+    ///
+    /// Note: compacting the list ([Self::compact_list_if_needed]) invalidates existing cursors, and promoted
+    /// elements (see [Self::set_in_list_promoting]) move to the tail, so they may be returned again
+    pub fn list_page<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        list_key: &B,
+        cursor: Option<ListCursor>,
+        limit: usize,
+    ) -> Result<ListPage<KVPair>> {
+        self.owned_list_page(list_key.as_ref().to_owned(), cursor, limit)
+    }
+
+    /// Owned version of [Self::list_page]
+    pub fn owned_list_page(
+        &self,
+        list_key: Vec<u8>,
+        cursor: Option<ListCursor>,
+        limit: usize,
+    ) -> Result<ListPage<KVPair>> {
+        let (list_ph, list_key) = self.make_list_key(list_key);
+        let list = {
+            let _guard = self.lock_list(list_ph);
+            let Some(list_bytes) = self.get_raw(&list_key)? else {
+                return Ok((vec![], None));
+            };
+            *from_bytes::<List>(&list_bytes)
+        };
+
+        let mut idx = cursor.map_or(list.head_idx, |c| c.0.max(list.head_idx));
+        let mut items = vec![];
+        while idx < list.tail_idx && items.len() < limit {
+            if let Some((_, k, v)) = self.get_from_list_at_index(list_ph, idx, true)? {
+                items.push((k, v));
+            }
+            idx += 1;
+        }
+
+        let next = (idx < list.tail_idx).then_some(ListCursor(idx));
+        Ok((items, next))
+    }
+
     /// Discards the given list, removing all elements it contains and dropping the list itself.
     /// This is more efficient than iteration + removal of each element.
     pub fn discard_list<B: AsRef<[u8]> + ?Sized>(&self, list_key: &B) -> Result<bool> {
@@ -652,6 +1554,71 @@ impl CandyStore {
         Ok(true)
     }
 
+    /// Renames the list `from` to `to`, keeping all its elements (and their order). Returns false if `from`
+    /// does not exist, and fails with [CandyError::AlreadyExists] if `to` already exists.
+    ///
+    /// Note that items are keyed by (a hash of) their list's key, so this moves every item of the list, i.e.,
+    /// it's O(n) in the length of the list, but avoids decoding and re-inserting each item.
+    ///
+    /// Note: **not crash-safe**. If your program crashes while renaming, the items will be split between the two
+    /// lists
+    pub fn rename_list<B1: AsRef<[u8]> + ?Sized, B2: AsRef<[u8]> + ?Sized>(
+        &self,
+        from: &B1,
+        to: &B2,
+    ) -> Result<bool> {
+        self.owned_rename_list(from.as_ref().to_owned(), to.as_ref().to_owned())
+    }
+
+    /// Owned version of [Self::rename_list]
+    pub fn owned_rename_list(&self, from: Vec<u8>, to: Vec<u8>) -> Result<bool> {
+        let (from_ph, from_key) = self.make_list_key(from);
+        let (to_ph, to_key) = self.make_list_key(to);
+        let _guards = self.lock_ordered(&[from_ph, to_ph]);
+
+        let Some(list_bytes) = self.get_raw(&from_key)? else {
+            return Ok(false);
+        };
+        if from_key == to_key {
+            return Ok(true);
+        }
+        if self.get_raw(&to_key)?.is_some() {
+            bail!(CandyError::AlreadyExists("list"));
+        }
+
+        // the new list takes the same indices, so the items keep their order (and holes)
+        self.set_raw(&to_key, &list_bytes)?;
+
+        let list = *from_bytes::<List>(&list_bytes);
+        for idx in list.head_idx..list.tail_idx {
+            let Some((_, full_key, val)) = self.get_from_list_at_index(from_ph, idx, false)? else {
+                continue;
+            };
+            let item_key = full_key[..full_key.len() - Self::LIST_KEY_SUFFIX_LEN].to_owned();
+            let (item_ph, new_item_key) = self.make_item_key(to_ph, item_key);
+
+            // the value still carries the index, which remains the same
+            self.set_raw(&new_item_key, &val)?;
+            self.set_raw(
+                bytes_of(&ChainKey {
+                    list_ph: to_ph,
+                    idx,
+                    namespace: CHAIN_NAMESPACE,
+                }),
+                bytes_of(&item_ph),
+            )?;
+            self.remove_raw(bytes_of(&ChainKey {
+                list_ph: from_ph,
+                idx,
+                namespace: CHAIN_NAMESPACE,
+            }))?;
+            self.remove_raw(&full_key)?;
+        }
+        self.remove_raw(&from_key)?;
+
+        Ok(true)
+    }
+
     /// Returns the first (head) element of the list
     pub fn peek_list_head<B: AsRef<[u8]> + ?Sized>(&self, list_key: &B) -> Result<Option<KVPair>> {
         self.owned_peek_list_head(list_key.as_ref().to_owned())
@@ -783,6 +1750,23 @@ impl CandyStore {
         Ok(from_bytes::<List>(&list_bytes).num_items as usize)
     }
 
+    /// Returns the total size (in bytes) of the keys and values of the list's elements, as they were given
+    /// by the user (i.e., not accounting for the internal bookkeeping). This scans the whole list, so it's
+    /// an O(n) operation
+    pub fn list_size_bytes<B: AsRef<[u8]> + ?Sized>(&self, list_key: &B) -> Result<usize> {
+        self.owned_list_size_bytes(list_key.as_ref().to_owned())
+    }
+
+    /// Owned version of [Self::list_size_bytes]
+    pub fn owned_list_size_bytes(&self, list_key: Vec<u8>) -> Result<usize> {
+        let mut size = 0;
+        for res in self.owned_iter_list(list_key) {
+            let (k, v) = res?;
+            size += k.len() + v.len();
+        }
+        Ok(size)
+    }
+
     /// iterate over the given list and retain all elements for which the predicate returns `true`. In other
     /// words, drop all other elements. This operation is not crash safe, and holds the list locked during the
     /// whole iteration, so no other gets/sets/deletes can be done in by other threads on this list while
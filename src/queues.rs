@@ -2,11 +2,11 @@ use std::ops::Range;
 
 use crate::{
     hashing::PartedHash,
-    store::{QUEUE_ITEM_NAMESPACE, QUEUE_NAMESPACE},
-    CandyStore,
+    store::{QUEUE_ITEM_NAMESPACE, QUEUE_NAMESPACE, QUEUE_NEXT_IDX_NAMESPACE},
+    CandyError, CandyStore,
 };
-use anyhow::Result;
-use bytemuck::{bytes_of, checked::from_bytes_mut, from_bytes, Pod, Zeroable};
+use anyhow::{anyhow, ensure, Result};
+use bytemuck::{bytes_of, pod_read_unaligned, Pod, Zeroable};
 
 #[derive(Clone, Copy, Pod, Zeroable)]
 #[repr(C)]
@@ -14,9 +14,44 @@ struct Queue {
     head_idx: u64, // inclusive
     tail_idx: u64, // exclusive
     num_items: u64,
+    // the index past the highest one ever pushed to the tail. It's ahead of `tail_idx` after popping from the
+    // tail, so that the popped indices are not reused
+    next_idx: u64,
 }
 
+// the header of queues written by older versions, which did not have `next_idx`
+const LEGACY_QUEUE_SIZE: usize = 3 * size_of::<u64>();
+
 impl Queue {
+    // an empty queue, whose first element will get the given index
+    fn empty_at(idx: u64) -> Self {
+        Self {
+            head_idx: idx,
+            tail_idx: idx,
+            num_items: 0,
+            next_idx: idx,
+        }
+    }
+
+    fn from_header(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() == LEGACY_QUEUE_SIZE {
+            let legacy: [u64; 3] = pod_read_unaligned(bytes);
+            return Ok(Self {
+                head_idx: legacy[0],
+                tail_idx: legacy[1],
+                num_items: legacy[2],
+                next_idx: legacy[1],
+            });
+        }
+        if bytes.len() != size_of::<Self>() {
+            return Err(anyhow!(CandyError::Corruption(format!(
+                "bad queue header length {}",
+                bytes.len()
+            ))));
+        }
+        Ok(pod_read_unaligned(bytes))
+    }
+
     #[allow(dead_code)]
     fn span_len(&self) -> u64 {
         self.tail_idx - self.head_idx
@@ -25,11 +60,59 @@ impl Queue {
     fn holes(&self) -> u64 {
         self.span_len() - self.num_items
     }
+    #[allow(dead_code)]
     fn is_empty(&self) -> bool {
         self.head_idx == self.tail_idx
     }
 }
 
+/// The result of checking a queue's header against the elements actually stored, as returned by
+/// [CandyStore::verify_queue]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueHealth {
+    /// The range (indices) recorded in the queue's header
+    pub range: Range<usize>,
+    /// The number of elements recorded in the queue's header (i.e., [CandyStore::queue_len])
+    pub recorded_len: usize,
+    /// The number of elements actually found within `range`
+    pub present_len: usize,
+    /// Maximal runs of indices within `range` that have no element
+    pub gaps: Vec<Range<usize>>,
+}
+
+impl QueueHealth {
+    /// Returns true if the recorded length matches the elements found and there are no gaps. Note that
+    /// [CandyStore::remove_from_queue] leaves gaps by design, so a queue with gaps is not necessarily
+    /// corrupted, while a length mismatch always indicates an inconsistency
+    pub fn is_healthy(&self) -> bool {
+        self.recorded_len == self.present_len && self.gaps.is_empty()
+    }
+}
+
+/// The state of a queue, as returned by [CandyStore::queue_state]. All fields are read together, so they are
+/// consistent with each other
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct QueueState {
+    /// The index of the head element (inclusive), as in [CandyStore::queue_range]
+    pub head_idx: usize,
+    /// The index past the tail element (exclusive), as in [CandyStore::queue_range]
+    pub tail_idx: usize,
+    /// The number of elements in the queue, as in [CandyStore::queue_len]. This may be smaller than
+    /// `tail_idx - head_idx` if elements were removed from the middle of the queue
+    pub len: usize,
+}
+
+/// A point-in-time copy of a queue, as returned by [CandyStore::snapshot_queue], that can be restored (into the
+/// same store or another one) by [CandyStore::restore_queue]. `T` is the type of the elements (raw bytes, or
+/// the value type of a [crate::CandyTypedDeque])
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueSnapshot<T = Vec<u8>> {
+    /// The range (indices) of the queue, as returned by [CandyStore::queue_range]
+    pub range: Range<usize>,
+    /// The elements of the queue and their indices, from head to tail
+    pub items: Vec<(usize, T)>,
+}
+
 enum QueuePos {
     Head,
     Tail,
@@ -39,17 +122,20 @@ pub struct QueueIterator<'a> {
     store: &'a CandyStore,
     queue_key: Vec<u8>,
     range: Option<Range<u64>>,
+    // the first index to consider (indices before the queue's head are skipped anyway)
+    start_idx: u64,
     fwd: bool,
 }
 
-impl<'a> Iterator for QueueIterator<'a> {
-    type Item = Result<(usize, Vec<u8>)>;
-    fn next(&mut self) -> Option<Self::Item> {
+impl<'a> QueueIterator<'a> {
+    // yields the next existing element from the front (or back) of the remaining range. the range is shared
+    // by both ends, so they never yield the same index and stop once they meet
+    fn next_from(&mut self, front: bool) -> Option<Result<(usize, Vec<u8>)>> {
         if self.range.is_none() {
             match self.store.fetch_queue(&self.queue_key) {
                 Ok(queue) => match queue {
                     Some(queue) => {
-                        self.range = Some(queue.head_idx..queue.tail_idx);
+                        self.range = Some(queue.head_idx.max(self.start_idx)..queue.tail_idx);
                     }
                     None => return None,
                 },
@@ -58,7 +144,7 @@ impl<'a> Iterator for QueueIterator<'a> {
         }
 
         loop {
-            let idx = if self.fwd {
+            let idx = if front {
                 self.range.as_mut().unwrap().next()
             } else {
                 self.range.as_mut().unwrap().next_back()
@@ -83,6 +169,13 @@ impl<'a> Iterator for QueueIterator<'a> {
             }
         }
     }
+}
+
+impl<'a> Iterator for QueueIterator<'a> {
+    type Item = Result<(usize, Vec<u8>)>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_from(self.fwd)
+    }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
         if let Some(ref range) = self.range {
@@ -93,16 +186,19 @@ impl<'a> Iterator for QueueIterator<'a> {
     }
 }
 
+impl<'a> DoubleEndedIterator for QueueIterator<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.next_from(!self.fwd)
+    }
+}
+
 impl CandyStore {
     const FIRST_QUEUE_IDX: u64 = 0x8000_0000_0000_0000;
 
     fn make_queue_key(&self, queue_key: &[u8]) -> (PartedHash, Vec<u8>) {
         let mut full_queue_key = queue_key.to_owned();
         full_queue_key.extend_from_slice(QUEUE_NAMESPACE);
-        (
-            PartedHash::new(&self.config.hash_seed, &queue_key),
-            full_queue_key,
-        )
+        (self.config.hash_key(&queue_key), full_queue_key)
     }
     fn make_queue_item_key(&self, queue_key: &[u8], idx: u64) -> Vec<u8> {
         let mut item_key = queue_key.to_owned();
@@ -110,41 +206,84 @@ impl CandyStore {
         item_key.extend_from_slice(QUEUE_ITEM_NAMESPACE);
         item_key
     }
+    fn queue_next_idx_key() -> Vec<u8> {
+        let mut key = b"queue_next_idx".to_vec();
+        key.extend_from_slice(QUEUE_NEXT_IDX_NAMESPACE);
+        key
+    }
+
+    // the index at which queues that do not exist (e.g., ones that drained) start when they're created. It's a
+    // single, store-wide index that drained queues raise past their last index (see store_queue_locked), so the
+    // indices of a drained queue are not reused when it's recreated, while nothing is left behind per queue
+    fn queue_next_idx(&self) -> Result<u64> {
+        let Some(bytes) = self.get_raw(&Self::queue_next_idx_key())? else {
+            return Ok(Self::FIRST_QUEUE_IDX);
+        };
+        let bytes: [u8; 8] = bytes
+            .try_into()
+            .map_err(|_| anyhow!(CandyError::Corruption("bad queue index".into())))?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    // the header of the queue, or an empty queue if it does not exist. The caller must hold the queue's lock
+    fn load_queue_locked(&self, full_queue_key: &[u8]) -> Result<Queue> {
+        match self.get_raw(full_queue_key)? {
+            Some(queue_bytes) => Queue::from_header(&queue_bytes),
+            None => Ok(Queue::empty_at(self.queue_next_idx()?)),
+        }
+    }
+
+    // writes back the queue's header, or removes it if the queue has drained (raising the store-wide next index
+    // past the queue's indices). The caller must hold the queue's lock
+    fn store_queue_locked(&self, full_queue_key: &[u8], queue: &Queue) -> Result<()> {
+        if queue.num_items > 0 {
+            self.set_raw(full_queue_key, bytes_of(queue))?;
+        } else {
+            let next_idx = queue.next_idx.max(queue.tail_idx);
+            {
+                let _guard = self.queue_next_idx_lock.lock();
+                if self.queue_next_idx()? < next_idx {
+                    self.set_raw(&Self::queue_next_idx_key(), &next_idx.to_le_bytes())?;
+                }
+            }
+            self.remove_raw(full_queue_key)?;
+        }
+        Ok(())
+    }
 
     fn _push_to_queue(&self, queue_key: &[u8], val: &[u8], pos: QueuePos) -> Result<usize> {
         let (queue_ph, full_queue_key) = self.make_queue_key(queue_key);
         let _guard = self.lock_list(queue_ph);
+        self._push_to_queue_locked(queue_key, &full_queue_key, val, pos)
+    }
 
-        let status = self.get_or_create_raw(
-            &full_queue_key,
-            bytes_of(&Queue {
-                head_idx: Self::FIRST_QUEUE_IDX,
-                tail_idx: Self::FIRST_QUEUE_IDX + 1,
-                num_items: 1,
-            })
-            .to_owned(),
-        )?;
-
-        let item_idx = match status {
-            crate::GetOrCreateStatus::CreatedNew(_) => Self::FIRST_QUEUE_IDX,
-            crate::GetOrCreateStatus::ExistingValue(mut queue_bytes) => {
-                let queue = from_bytes_mut::<Queue>(&mut queue_bytes);
-                let item_idx = match pos {
-                    QueuePos::Head => {
-                        queue.head_idx -= 1;
-                        queue.head_idx
-                    }
-                    QueuePos::Tail => {
-                        let item_idx = queue.tail_idx;
-                        queue.tail_idx += 1;
-                        item_idx
-                    }
-                };
-                queue.num_items += 1;
-                self.set_raw(&full_queue_key, &queue_bytes)?;
+    fn _push_to_queue_locked(
+        &self,
+        queue_key: &[u8],
+        full_queue_key: &[u8],
+        val: &[u8],
+        pos: QueuePos,
+    ) -> Result<usize> {
+        let mut queue = self.load_queue_locked(full_queue_key)?;
+        let item_idx = match pos {
+            // the first element of a queue is placed at its next index, whichever end it's pushed to
+            QueuePos::Head if queue.num_items > 0 => {
+                queue.head_idx -= 1;
+                queue.head_idx
+            }
+            _ => {
+                // indices that were popped from the tail are skipped
+                let item_idx = queue.tail_idx.max(queue.next_idx);
+                if queue.num_items == 0 {
+                    queue.head_idx = item_idx;
+                }
+                queue.tail_idx = item_idx + 1;
+                queue.next_idx = item_idx + 1;
                 item_idx
             }
         };
+        queue.num_items += 1;
+        self.set_raw(full_queue_key, bytes_of(&queue))?;
 
         self.set_raw(&self.make_queue_item_key(queue_key, item_idx), val)?;
         Ok(item_idx as usize)
@@ -168,14 +307,80 @@ impl CandyStore {
         self._push_to_queue(queue_key.as_ref(), val.as_ref(), QueuePos::Tail)
     }
 
+    /// Same as [Self::push_to_queue_tail], but evicts elements from the head of the queue so that it holds at most
+    /// `max_len` elements, returning the index and value of the evicted element, if any. The push and the eviction
+    /// are atomic. Normally at most one element is evicted, but if the queue held more than `max_len` elements
+    /// (e.g., `max_len` was lowered), all excess elements are evicted, and the last one of them (the one closest
+    /// to the new head) is returned. `max_len` must be positive.
+    ///
+    /// See [Self::try_push_to_queue_tail] for a bounded push that rejects elements instead of evicting
+    pub fn push_to_queue_tail_bounded<B1: AsRef<[u8]> + ?Sized, B2: AsRef<[u8]> + ?Sized>(
+        &self,
+        queue_key: &B1,
+        val: &B2,
+        max_len: usize,
+    ) -> Result<Option<(usize, Vec<u8>)>> {
+        ensure!(max_len > 0, "max_len must be positive");
+        let queue_key = queue_key.as_ref();
+        let (queue_ph, full_queue_key) = self.make_queue_key(queue_key);
+        let _guard = self.lock_list(queue_ph);
+
+        self._push_to_queue_locked(queue_key, &full_queue_key, val.as_ref(), QueuePos::Tail)?;
+
+        let mut evicted = None;
+        while let Some(queue_bytes) = self.get_raw(&full_queue_key)? {
+            if Queue::from_header(&queue_bytes)?.num_items as usize <= max_len {
+                break;
+            }
+            let Some(iv) = self._pop_queue_locked(queue_key, &full_queue_key, QueuePos::Head)?
+            else {
+                break;
+            };
+            evicted = Some(iv);
+        }
+        Ok(evicted)
+    }
+
+    /// Same as [Self::push_to_queue_tail], but only if the queue holds fewer than `max_len` elements. Returns
+    /// the element's index, or `None` (without pushing) if the queue is full. The check and the push are
+    /// atomic, so concurrent producers can use this to apply backpressure without exceeding `max_len`
+    pub fn try_push_to_queue_tail<B1: AsRef<[u8]> + ?Sized, B2: AsRef<[u8]> + ?Sized>(
+        &self,
+        queue_key: &B1,
+        val: &B2,
+        max_len: usize,
+    ) -> Result<Option<usize>> {
+        let queue_key = queue_key.as_ref();
+        let (queue_ph, full_queue_key) = self.make_queue_key(queue_key);
+        let _guard = self.lock_list(queue_ph);
+
+        let len = match self.get_raw(&full_queue_key)? {
+            Some(queue_bytes) => Queue::from_header(&queue_bytes)?.num_items as usize,
+            None => 0,
+        };
+        if len >= max_len {
+            return Ok(None);
+        }
+        self._push_to_queue_locked(queue_key, &full_queue_key, val.as_ref(), QueuePos::Tail)
+            .map(Some)
+    }
+
     fn _pop_queue(&self, queue_key: &[u8], pos: QueuePos) -> Result<Option<(usize, Vec<u8>)>> {
         let (queue_ph, full_queue_key) = self.make_queue_key(queue_key);
         let _guard = self.lock_list(queue_ph);
+        self._pop_queue_locked(queue_key, &full_queue_key, pos)
+    }
 
-        let Some(mut queue_bytes) = self.get_raw(&full_queue_key)? else {
+    fn _pop_queue_locked(
+        &self,
+        queue_key: &[u8],
+        full_queue_key: &[u8],
+        pos: QueuePos,
+    ) -> Result<Option<(usize, Vec<u8>)>> {
+        let Some(queue_bytes) = self.get_raw(full_queue_key)? else {
             return Ok(None);
         };
-        let queue = from_bytes_mut::<Queue>(&mut queue_bytes);
+        let mut queue = Queue::from_header(&queue_bytes)?;
         let mut res = None;
 
         match pos {
@@ -203,16 +408,43 @@ impl CandyStore {
             }
         }
 
-        if queue.is_empty() {
-            self.remove_raw(&full_queue_key)?;
-        } else {
-            self.set_raw(&full_queue_key, &queue_bytes)?;
-        }
-
+        self.store_queue_locked(full_queue_key, &queue)?;
         Ok(res)
     }
 
-    /// Removes and returns the head element and its index of the queue, or None if the queue is empty
+    /// Atomically pops the head element of the queue `from_queue_key` and pushes it to the tail of the queue
+    /// `to_queue_key`, returning the element's index in the destination queue and its value. Returns `None`
+    /// (leaving the destination untouched) if the source queue is empty. Both queues are locked (in a
+    /// deadlock-safe order) for the duration of the operation, so no other queue operation can observe the
+    /// element in neither (or both) of the queues.
+    ///
+    /// Note: **not crash-safe**. If your program crashes mid-transfer, the element may be lost
+    pub fn transfer_queue_head<B1: AsRef<[u8]> + ?Sized, B2: AsRef<[u8]> + ?Sized>(
+        &self,
+        from_queue_key: &B1,
+        to_queue_key: &B2,
+    ) -> Result<Option<(usize, Vec<u8>)>> {
+        let (from_queue_key, to_queue_key) = (from_queue_key.as_ref(), to_queue_key.as_ref());
+        let (from_ph, full_from_key) = self.make_queue_key(from_queue_key);
+        let (to_ph, full_to_key) = self.make_queue_key(to_queue_key);
+        let _guards = self.lock_ordered(&[from_ph, to_ph]);
+
+        let Some((_, val)) =
+            self._pop_queue_locked(from_queue_key, &full_from_key, QueuePos::Head)?
+        else {
+            return Ok(None);
+        };
+        let idx = self._push_to_queue_locked(to_queue_key, &full_to_key, &val, QueuePos::Tail)?;
+        Ok(Some((idx, val)))
+    }
+
+    /// Removes and returns the head element and its index of the queue, or None if the queue is empty.
+    ///
+    /// Indices are persisted, and are not reused or reset when the queue drains (a drained queue is removed, and
+    /// the index that new queues start at is raised past its indices): elements pushed to the tail always get an index greater than that of any element
+    /// that was ever pushed to the tail of this queue (even if it was popped from the tail or removed), so when
+    /// used as a FIFO (push to tail, pop from head), the indices form a strictly increasing sequence that
+    /// survives restarts. Only [Self::discard_queue] does not keep the indices
     pub fn pop_queue_head_with_idx<B: AsRef<[u8]> + ?Sized>(
         &self,
         queue_key: &B,
@@ -230,6 +462,75 @@ impl CandyStore {
             .map(|iv| iv.1))
     }
 
+    /// Removes and returns the head element of the queue (along with its index) only if `pred` returns true for
+    /// its value, leaving it in place otherwise. Returns None if the queue is empty or if `pred` returned false.
+    /// The element is examined and popped under the queue's lock, so no other consumer can pop it in between
+    /// (e.g., for delay queues, where the head is popped only once its scheduled time has passed)
+    pub fn pop_queue_head_if<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        queue_key: &B,
+        pred: impl FnOnce(&[u8]) -> Result<bool>,
+    ) -> Result<Option<(usize, Vec<u8>)>> {
+        let queue_key = queue_key.as_ref();
+        let (queue_ph, full_queue_key) = self.make_queue_key(queue_key);
+        let _guard = self.lock_list(queue_ph);
+
+        let Some(queue_bytes) = self.get_raw(&full_queue_key)? else {
+            return Ok(None);
+        };
+        let mut queue = Queue::from_header(&queue_bytes)?;
+
+        for idx in queue.head_idx..queue.tail_idx {
+            let item_key = self.make_queue_item_key(queue_key, idx);
+            let Some(v) = self.get_raw(&item_key)? else {
+                continue;
+            };
+            if !pred(&v)? {
+                return Ok(None);
+            }
+            self.remove_raw(&item_key)?;
+            queue.head_idx = idx + 1;
+            queue.num_items -= 1;
+            self.store_queue_locked(&full_queue_key, &queue)?;
+            return Ok(Some((idx as usize, v)));
+        }
+
+        Ok(None)
+    }
+
+    /// Removes and returns up to `n` elements from the head of the queue, along with their indices (fewer if
+    /// the queue drains). The elements are popped under a single lock acquisition, so concurrent consumers
+    /// cannot interleave within the batch
+    pub fn pop_queue_head_batch<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        queue_key: &B,
+        n: usize,
+    ) -> Result<Vec<(usize, Vec<u8>)>> {
+        let queue_key = queue_key.as_ref();
+        let (queue_ph, full_queue_key) = self.make_queue_key(queue_key);
+        let _guard = self.lock_list(queue_ph);
+
+        let Some(queue_bytes) = self.get_raw(&full_queue_key)? else {
+            return Ok(vec![]);
+        };
+        let mut queue = Queue::from_header(&queue_bytes)?;
+
+        let mut res = Vec::with_capacity(n.min(queue.num_items as usize));
+        while res.len() < n && queue.head_idx < queue.tail_idx {
+            let idx = queue.head_idx;
+            queue.head_idx += 1;
+            if let Some(v) = self.remove_raw(&self.make_queue_item_key(queue_key, idx))? {
+                res.push((idx as usize, v));
+                queue.num_items -= 1;
+            }
+        }
+
+        if n > 0 {
+            self.store_queue_locked(&full_queue_key, &queue)?;
+        }
+        Ok(res)
+    }
+
     /// Removes and returns the tail element and its index of the queue, or None if the queue is empty
     pub fn pop_queue_tail_with_idx<B: AsRef<[u8]> + ?Sized>(
         &self,
@@ -266,26 +567,124 @@ impl CandyStore {
             return Ok(None);
         };
 
-        if let Some(mut queue_bytes) = self.get_raw(&full_queue_key)? {
-            let queue = from_bytes_mut::<Queue>(&mut queue_bytes);
+        if let Some(queue_bytes) = self.get_raw(&full_queue_key)? {
+            let mut queue = Queue::from_header(&queue_bytes)?;
             if queue.head_idx == idx {
                 queue.head_idx += 1;
             }
+            // the queue's next index stays ahead, so the index will not be reused
             if queue.tail_idx == idx + 1 {
                 queue.tail_idx -= 1;
             }
             queue.num_items -= 1;
-            if queue.is_empty() {
-                self.remove_raw(&full_queue_key)?;
-            } else {
-                self.set_raw(&full_queue_key, &queue_bytes)?;
-            }
+            self.store_queue_locked(&full_queue_key, &queue)?;
         }
 
         Ok(Some(val))
     }
 
-    /// Discards the queue (dropping all elements in contains). Returns true if it had existed before, false otherwise
+    /// Rewrites the elements of the queue in place, under the queue's lock: `func` is called with the index and
+    /// value of every element (from head to tail), and returns the element's new value, or None to leave it as
+    /// is. Indices are preserved, so consumers' positions remain valid. Returns the number of elements visited.
+    ///
+    /// Note: this is not atomic - if `func` fails (or the process crashes) mid-way, the elements rewritten so
+    /// far keep their new values
+    pub fn update_queue_items<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        queue_key: &B,
+        mut func: impl FnMut(usize, &[u8]) -> Result<Option<Vec<u8>>>,
+    ) -> Result<usize> {
+        let queue_key = queue_key.as_ref();
+        let (queue_ph, full_queue_key) = self.make_queue_key(queue_key);
+        let _guard = self.lock_list(queue_ph);
+
+        let Some(queue_bytes) = self.get_raw(&full_queue_key)? else {
+            return Ok(0);
+        };
+        let queue = Queue::from_header(&queue_bytes)?;
+
+        let mut count = 0;
+        for idx in queue.head_idx..queue.tail_idx {
+            let item_key = self.make_queue_item_key(queue_key, idx);
+            let Some(val) = self.get_raw(&item_key)? else {
+                continue;
+            };
+            if let Some(new_val) = func(idx as usize, &val)? {
+                self.set_raw(&item_key, &new_val)?;
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    // removes elements from the given end of the queue, until at most `keep` elements remain. returns the number
+    // of elements removed. like popping, the removed indices will not be reused
+    fn _truncate_queue(&self, queue_key: &[u8], keep: usize, pos: QueuePos) -> Result<usize> {
+        let (queue_ph, full_queue_key) = self.make_queue_key(queue_key);
+        let _guard = self.lock_list(queue_ph);
+
+        let Some(queue_bytes) = self.get_raw(&full_queue_key)? else {
+            return Ok(0);
+        };
+        let mut queue = Queue::from_header(&queue_bytes)?;
+        let mut removed = 0;
+
+        while queue.num_items > keep as u64 && queue.head_idx < queue.tail_idx {
+            let idx = match pos {
+                QueuePos::Head => {
+                    queue.head_idx += 1;
+                    queue.head_idx - 1
+                }
+                QueuePos::Tail => {
+                    queue.tail_idx -= 1;
+                    queue.tail_idx
+                }
+            };
+            if self
+                .remove_raw(&self.make_queue_item_key(queue_key, idx))?
+                .is_some()
+            {
+                queue.num_items -= 1;
+                removed += 1;
+            }
+        }
+
+        if removed > 0 {
+            self.store_queue_locked(&full_queue_key, &queue)?;
+        }
+        Ok(removed)
+    }
+
+    /// Removes all elements of the queue, returning the number of elements removed. Unlike [Self::discard_queue],
+    /// this keeps the queue's indices, so elements pushed to the tail afterwards will get indices greater than
+    /// those of the removed elements (see [Self::pop_queue_head_with_idx])
+    pub fn clear_queue<B: AsRef<[u8]> + ?Sized>(&self, queue_key: &B) -> Result<usize> {
+        self._truncate_queue(queue_key.as_ref(), 0, QueuePos::Head)
+    }
+
+    /// Removes elements from the head of the queue, keeping only the last (up to) `n` elements. Returns
+    /// the number of elements removed
+    pub fn truncate_queue_head<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        queue_key: &B,
+        n: usize,
+    ) -> Result<usize> {
+        self._truncate_queue(queue_key.as_ref(), n, QueuePos::Head)
+    }
+
+    /// Removes elements from the tail of the queue, keeping only the first (up to) `n` elements. Returns
+    /// the number of elements removed. Like popping from the tail, the removed indices will not be reused
+    pub fn truncate_queue_tail<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        queue_key: &B,
+        n: usize,
+    ) -> Result<usize> {
+        self._truncate_queue(queue_key.as_ref(), n, QueuePos::Tail)
+    }
+
+    /// Discards the queue (dropping all elements in contains). Returns true if it had existed before (i.e., it had
+    /// elements), false otherwise. Unlike popping all elements, this does not raise the index that new queues start
+    /// at, so the queue's indices may be reused if it's recreated
     pub fn discard_queue<B: AsRef<[u8]> + ?Sized>(&self, queue_key: &B) -> Result<bool> {
         let queue_key = queue_key.as_ref();
         let (queue_ph, full_queue_key) = self.make_queue_key(queue_key);
@@ -294,7 +693,7 @@ impl CandyStore {
         let Some(queue_bytes) = self.get_raw(&full_queue_key)? else {
             return Ok(false);
         };
-        let queue = from_bytes::<Queue>(&queue_bytes);
+        let queue = Queue::from_header(&queue_bytes)?;
 
         for i in queue.head_idx..queue.tail_idx {
             self.remove_raw(&self.make_queue_item_key(queue_key, i as u64))?;
@@ -304,17 +703,195 @@ impl CandyStore {
         Ok(true)
     }
 
+    /// Returns a snapshot of the queue: its range and all of its elements, along with their indices. The queue
+    /// is locked while it's being copied, so the snapshot is consistent. A queue that does not exist yields an
+    /// empty snapshot (whose range starts at the index that new queues start at)
+    pub fn snapshot_queue<B: AsRef<[u8]> + ?Sized>(&self, queue_key: &B) -> Result<QueueSnapshot> {
+        let queue_key = queue_key.as_ref();
+        let (queue_ph, full_queue_key) = self.make_queue_key(queue_key);
+        let _guard = self.lock_list(queue_ph);
+
+        let queue = self.load_queue_locked(&full_queue_key)?;
+
+        let mut items = Vec::with_capacity(queue.num_items as usize);
+        for idx in queue.head_idx..queue.tail_idx {
+            if let Some(v) = self.get_raw(&self.make_queue_item_key(queue_key, idx))? {
+                items.push((idx as usize, v));
+            }
+        }
+        Ok(QueueSnapshot {
+            range: queue.head_idx as usize..queue.tail_idx as usize,
+            items,
+        })
+    }
+
+    /// Replaces the queue with the given snapshot (see [Self::snapshot_queue]), reproducing the original indices,
+    /// so that positions (e.g., consumer checkpoints) taken on the original queue remain valid, and elements that
+    /// are pushed later continue the original sequence. The snapshot's indices must be increasing and within
+    /// its range. An empty snapshot removes the queue, like draining it, so elements pushed later get indices
+    /// past the snapshot's range, but not necessarily right after it.
+    ///
+    /// Note: this is not an atomic (crash-safe) operation
+    pub fn restore_queue<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        queue_key: &B,
+        snapshot: &QueueSnapshot,
+    ) -> Result<()> {
+        let range = snapshot.range.start as u64..snapshot.range.end as u64;
+        ensure!(
+            range.start <= range.end,
+            "invalid queue snapshot: bad range {range:?}"
+        );
+        let mut prev = None;
+        for (idx, _) in snapshot.items.iter() {
+            let idx = *idx as u64;
+            ensure!(
+                range.contains(&idx) && prev.is_none_or(|prev| prev < idx),
+                "invalid queue snapshot: index {idx} is out of order or out of {range:?}"
+            );
+            prev = Some(idx);
+        }
+
+        let queue_key = queue_key.as_ref();
+        let (queue_ph, full_queue_key) = self.make_queue_key(queue_key);
+        let _guard = self.lock_list(queue_ph);
+
+        // drop the current elements, except for those that are about to be overwritten anyway
+        if let Some(queue_bytes) = self.get_raw(&full_queue_key)? {
+            let queue = Queue::from_header(&queue_bytes)?;
+            let mut restored = snapshot.items.iter().map(|(idx, _)| *idx as u64).peekable();
+            for idx in queue.head_idx..queue.tail_idx {
+                while restored.next_if(|r| *r < idx).is_some() {}
+                if restored.peek() != Some(&idx) {
+                    self.remove_raw(&self.make_queue_item_key(queue_key, idx))?;
+                }
+            }
+        }
+
+        for (idx, v) in snapshot.items.iter() {
+            self.set_raw(&self.make_queue_item_key(queue_key, *idx as u64), v)?;
+        }
+        // the next index is restored as well (rather than kept), so that the original sequence continues
+        self.store_queue_locked(
+            &full_queue_key,
+            &Queue {
+                head_idx: range.start,
+                tail_idx: range.end,
+                num_items: snapshot.items.len() as u64,
+                next_idx: range.end,
+            },
+        )?;
+        Ok(())
+    }
+
+    fn empty_queue_health(next_idx: u64) -> QueueHealth {
+        QueueHealth {
+            range: next_idx as usize..next_idx as usize,
+            recorded_len: 0,
+            present_len: 0,
+            gaps: vec![],
+        }
+    }
+
+    fn _verify_queue(&self, queue_key: &[u8], queue: &Queue) -> Result<QueueHealth> {
+        let mut present_len = 0;
+        let mut gaps: Vec<Range<usize>> = vec![];
+        for idx in queue.head_idx..queue.tail_idx {
+            if self
+                .get_raw(&self.make_queue_item_key(queue_key, idx))?
+                .is_some()
+            {
+                present_len += 1;
+            } else if let Some(gap) = gaps.last_mut().filter(|gap| gap.end == idx as usize) {
+                gap.end += 1;
+            } else {
+                gaps.push(idx as usize..idx as usize + 1);
+            }
+        }
+        Ok(QueueHealth {
+            range: queue.head_idx as usize..queue.tail_idx as usize,
+            recorded_len: queue.num_items as usize,
+            present_len,
+            gaps,
+        })
+    }
+
+    /// Checks that the queue's header is consistent with the elements actually stored: that every index in
+    /// [Self::queue_range] has an element, and that [Self::queue_len] matches the number of elements found.
+    /// A crash in the middle of a push (or [Self::extend_queue]) may leave the header pointing at elements that
+    /// were never written; use [Self::repair_queue] to fix that. A queue that does not exist is reported as
+    /// an empty, healthy one
+    ///
+    /// Note: this fetches every index in the queue's range, so it's meant to be used sparingly (e.g., on startup
+    /// after an unclean shutdown)
+    pub fn verify_queue<B: AsRef<[u8]> + ?Sized>(&self, queue_key: &B) -> Result<QueueHealth> {
+        let queue_key = queue_key.as_ref();
+        let (queue_ph, full_queue_key) = self.make_queue_key(queue_key);
+        let _guard = self.lock_list(queue_ph);
+
+        let Some(queue_bytes) = self.get_raw(&full_queue_key)? else {
+            return Ok(Self::empty_queue_health(self.queue_next_idx()?));
+        };
+        self._verify_queue(queue_key, &Queue::from_header(&queue_bytes)?)
+    }
+
+    /// Repairs the queue's header: the range is trimmed to start at the first present element and end after the
+    /// last one, and the length is set to the number of elements actually found. Gaps in the middle of the range
+    /// are left as they are, as they are skipped by iteration and popping anyway. Returns the health of the queue
+    /// after the repair
+    pub fn repair_queue<B: AsRef<[u8]> + ?Sized>(&self, queue_key: &B) -> Result<QueueHealth> {
+        let queue_key = queue_key.as_ref();
+        let (queue_ph, full_queue_key) = self.make_queue_key(queue_key);
+        let _guard = self.lock_list(queue_ph);
+
+        let Some(queue_bytes) = self.get_raw(&full_queue_key)? else {
+            return Ok(Self::empty_queue_health(self.queue_next_idx()?));
+        };
+        let mut queue = Queue::from_header(&queue_bytes)?;
+        let mut health = self._verify_queue(queue_key, &queue)?;
+        if health.is_healthy() {
+            return Ok(health);
+        }
+
+        if let Some(first) = health.gaps.first() {
+            if first.start == health.range.start {
+                queue.head_idx = first.end as u64;
+                health.gaps.remove(0);
+            }
+        }
+        if let Some(last) = health.gaps.last() {
+            if last.end == health.range.end {
+                // if the whole range was a gap, the head has already moved to the end
+                queue.tail_idx = (last.start as u64).max(queue.head_idx);
+                health.gaps.pop();
+            }
+        }
+        queue.num_items = health.present_len as u64;
+        health.range = queue.head_idx as usize..queue.tail_idx as usize;
+        health.recorded_len = health.present_len;
+        self.store_queue_locked(&full_queue_key, &queue)?;
+
+        Ok(health)
+    }
+
     fn fetch_queue(&self, queue_key: &[u8]) -> Result<Option<Queue>> {
         let queue_key = queue_key.as_ref();
         let (queue_ph, full_queue_key) = self.make_queue_key(queue_key);
         let _guard = self.lock_list(queue_ph);
         if let Some(queue_bytes) = self.get_raw(&full_queue_key)? {
-            Ok(Some(*from_bytes::<Queue>(&queue_bytes)))
+            Ok(Some(Queue::from_header(&queue_bytes)?))
         } else {
             Ok(None)
         }
     }
 
+    // same as fetch_queue, but a queue that does not exist is returned as an empty one
+    fn fetch_queue_or_empty(&self, queue_key: &[u8]) -> Result<Queue> {
+        let (queue_ph, full_queue_key) = self.make_queue_key(queue_key);
+        let _guard = self.lock_list(queue_ph);
+        self.load_queue_locked(&full_queue_key)
+    }
+
     /// Extends the queue with elements from the given iterator. The queue will be created if it did not exist before,
     /// and elements are pushed at the tail-end of the queue. This is more efficient than calling
     /// [Self::push_to_queue_tail] in a loop
@@ -332,21 +909,14 @@ impl CandyStore {
         let (queue_ph, full_queue_key) = self.make_queue_key(queue_key);
         let _guard = self.lock_list(queue_ph);
 
-        let mut queue_bytes = &mut self
-            .get_or_create_raw(
-                &full_queue_key,
-                bytes_of(&Queue {
-                    head_idx: Self::FIRST_QUEUE_IDX,
-                    tail_idx: Self::FIRST_QUEUE_IDX,
-                    num_items: 0,
-                })
-                .to_owned(),
-            )?
-            .value();
-
-        let queue = from_bytes_mut::<Queue>(&mut queue_bytes);
-
-        let first_idx = queue.tail_idx;
+        let mut queue = self.load_queue_locked(&full_queue_key)?;
+
+        // indices that were popped from the tail are skipped
+        let first_idx = queue.tail_idx.max(queue.next_idx);
+        if queue.num_items == 0 {
+            queue.head_idx = first_idx;
+        }
+        queue.tail_idx = first_idx;
         for item in items {
             self.set_raw(
                 &self.make_queue_item_key(queue_key, queue.tail_idx),
@@ -357,7 +927,8 @@ impl CandyStore {
         }
 
         let indices = first_idx as usize..queue.tail_idx as usize;
-        self.set_raw(&full_queue_key, &queue_bytes)?;
+        queue.next_idx = queue.tail_idx;
+        self.store_queue_locked(&full_queue_key, &queue)?;
 
         Ok(indices)
     }
@@ -373,6 +944,35 @@ impl CandyStore {
         Ok(None)
     }
 
+    /// Returns (without removing) up to `n` elements from the head of the queue, along with their indices.
+    /// Unlike iterating, the elements are collected under a single lock acquisition, so they form a consistent
+    /// snapshot of the queue's front (e.g., for deciding on a batch boundary before popping)
+    pub fn peek_queue_head_batch<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        queue_key: &B,
+        n: usize,
+    ) -> Result<Vec<(usize, Vec<u8>)>> {
+        let queue_key = queue_key.as_ref();
+        let (queue_ph, full_queue_key) = self.make_queue_key(queue_key);
+        let _guard = self.lock_list(queue_ph);
+
+        let Some(queue_bytes) = self.get_raw(&full_queue_key)? else {
+            return Ok(vec![]);
+        };
+        let queue = Queue::from_header(&queue_bytes)?;
+
+        let mut res = Vec::with_capacity(n.min(queue.num_items as usize));
+        for idx in queue.head_idx..queue.tail_idx {
+            if res.len() >= n {
+                break;
+            }
+            if let Some(v) = self.get_raw(&self.make_queue_item_key(queue_key, idx))? {
+                res.push((idx as usize, v));
+            }
+        }
+        Ok(res)
+    }
+
     /// Returns (without removing) the head element of the queue, or None if the queue is empty
     pub fn peek_queue_head<B: AsRef<[u8]> + ?Sized>(
         &self,
@@ -416,6 +1016,25 @@ impl CandyStore {
             store: &self,
             queue_key: queue_key.as_ref().to_owned(),
             range: None,
+            start_idx: 0,
+            fwd: true,
+        }
+    }
+
+    /// Same as [Self::iter_queue], but starts at the first element whose index is `>= start_idx` (or at the
+    /// head of the queue, if `start_idx` precedes it), without going over the elements before it. This allows
+    /// resuming iteration from a checkpointed index (e.g., the index of the last element that was processed,
+    /// plus one)
+    pub fn iter_queue_from<'a, B: AsRef<[u8]> + ?Sized>(
+        &'a self,
+        queue_key: &B,
+        start_idx: usize,
+    ) -> QueueIterator<'a> {
+        QueueIterator {
+            store: &self,
+            queue_key: queue_key.as_ref().to_owned(),
+            range: None,
+            start_idx: start_idx as u64,
             fwd: true,
         }
     }
@@ -433,6 +1052,7 @@ impl CandyStore {
             store: &self,
             queue_key: queue_key.as_ref().to_owned(),
             range: None,
+            start_idx: 0,
             fwd: false,
         }
     }
@@ -445,11 +1065,21 @@ impl CandyStore {
         Ok(queue.num_items as usize)
     }
 
-    /// Returns a the range (indices) of the given queue or an empty range if the queue does not exist
+    /// Returns a the range (indices) of the given queue or an empty range (starting at the index the queue's first
+    /// element would get) if the queue does not exist
     pub fn queue_range<B: AsRef<[u8]> + ?Sized>(&self, queue_key: &B) -> Result<Range<usize>> {
-        let Some(queue) = self.fetch_queue(queue_key.as_ref())? else {
-            return Ok(Self::FIRST_QUEUE_IDX as usize..Self::FIRST_QUEUE_IDX as usize);
-        };
+        let queue = self.fetch_queue_or_empty(queue_key.as_ref())?;
         Ok(queue.head_idx as usize..queue.tail_idx as usize)
     }
+
+    /// Returns the range and the length of the given queue (as [Self::queue_range] and [Self::queue_len] do),
+    /// read together under the queue's lock, so they are consistent even under concurrent pushes and pops
+    pub fn queue_state<B: AsRef<[u8]> + ?Sized>(&self, queue_key: &B) -> Result<QueueState> {
+        let queue = self.fetch_queue_or_empty(queue_key.as_ref())?;
+        Ok(QueueState {
+            head_idx: queue.head_idx as usize,
+            tail_idx: queue.tail_idx as usize,
+            len: queue.num_items as usize,
+        })
+    }
 }
@@ -4,8 +4,11 @@ use std::{ops::Range, sync::Arc};
 
 use crate::shard::{CompactionThreadPool, InsertMode, InsertStatus, Shard};
 use crate::stats::InternalStats;
-use crate::Result;
-use crate::{hashing::PartedHash, store::InternalConfig};
+use crate::{
+    hashing::PartedHash,
+    store::{is_shard_subdir_name, InternalConfig},
+};
+use crate::{CandyError, Result, ShardFileLayout};
 
 fn consolidate_ranges(mut ranges: Vec<Range<u32>>) -> (Vec<Range<u32>>, Vec<Range<u32>>) {
     // we may encounter unfinished splits, where we have any combination of the bottom half, top half and
@@ -103,51 +106,56 @@ impl ShardRouter {
         threadpool: &Arc<CompactionThreadPool>,
     ) -> Result<Vec<Shard>> {
         let mut found_shards = vec![];
-        for res in std::fs::read_dir(&config.dir_path)? {
-            let entry = res?;
-            let filename = entry.file_name();
-            let Some(filename) = filename.to_str() else {
-                continue;
-            };
-            let Ok(filetype) = entry.file_type() else {
-                continue;
-            };
-            if !filetype.is_file() {
-                continue;
-            }
+        for (filename, path) in config.list_shard_files()? {
             if filename.starts_with("bottom_")
                 || filename.starts_with("top_")
                 || filename.starts_with("merge_")
             {
-                std::fs::remove_file(entry.path())?;
+                std::fs::remove_file(path)?;
                 continue;
-            } else if !filename.starts_with("shard_") {
+            } else if !filename.starts_with("shard_") && !filename.starts_with("compact_") {
                 continue;
             }
-            let Some((_, span)) = filename.split_once("_") else {
+            let Some((prefix, span)) = filename.split_once("_") else {
                 continue;
             };
             let Some((start, end)) = span.split_once("-") else {
                 continue;
             };
-            let start = u32::from_str_radix(start, 16).expect(filename);
-            let end = u32::from_str_radix(end, 16).expect(filename);
+            let start = u32::from_str_radix(start, 16).expect(&filename);
+            let end = u32::from_str_radix(end, 16).expect(&filename);
 
             ensure!(
                 start < end && end <= Self::END_OF_SHARDS,
-                "Bad span for {filename}"
+                CandyError::Corruption(format!("bad span for {filename}"))
             );
 
-            found_shards.push(start..end);
+            // the file may have been created under a different layout, move it to where we expect it
+            let expected_path = config.shard_file_path(prefix, start, end);
+            if path != expected_path {
+                config.create_shard_file_dir(&expected_path)?;
+                std::fs::rename(&path, &expected_path)?;
+            }
+
+            if prefix == "shard" {
+                found_shards.push(start..end);
+            }
+        }
+        if config.shard_file_layout == ShardFileLayout::Flat {
+            // remove subdirectories left empty by the move (this fails harmlessly for non-empty ones)
+            for res in std::fs::read_dir(&config.dir_path)? {
+                let entry = res?;
+                if entry.file_type().is_ok_and(|ft| ft.is_dir())
+                    && entry.file_name().to_str().is_some_and(is_shard_subdir_name)
+                {
+                    _ = std::fs::remove_dir(entry.path());
+                }
+            }
         }
 
         let (shards_to_keep, shards_to_remove) = consolidate_ranges(found_shards);
         for span in shards_to_remove {
-            std::fs::remove_file(
-                config
-                    .dir_path
-                    .join(format!("shard_{:04x}-{:04x}", span.start, span.end)),
-            )?;
+            std::fs::remove_file(config.shard_file_path("shard", span.start, span.end))?;
         }
 
         let mut shards = vec![];
@@ -172,13 +180,24 @@ impl ShardRouter {
     pub(crate) fn calc_num_shards(num_items: usize) -> u32 {
         Self::END_OF_SHARDS / Self::calc_step(num_items)
     }
+    fn calc_initial_step(config: &InternalConfig) -> u32 {
+        let step = Self::calc_step(config.expected_number_of_keys);
+        if config.initial_num_shards == 0 {
+            return step;
+        }
+        let num_shards = config
+            .initial_num_shards
+            .min(Self::END_OF_SHARDS)
+            .next_power_of_two();
+        step.min(Self::END_OF_SHARDS / num_shards)
+    }
 
     fn create_initial_shards(
         config: &Arc<InternalConfig>,
         stats: &Arc<InternalStats>,
         threadpool: &Arc<CompactionThreadPool>,
     ) -> Result<Vec<Shard>> {
-        let step = Self::calc_step(config.expected_number_of_keys);
+        let step = Self::calc_initial_step(config);
 
         let mut shards = vec![];
         let mut start = 0;
@@ -294,27 +313,85 @@ impl ShardRouter {
         }
     }
 
+    // like shared_op, but for the shards of two selectors (which may be the same shard). The routers along both
+    // paths are read-locked in a single descent, bottom before top, so that the shards can't be split (or merged)
+    // while `func` holds them
+    pub(crate) fn shared_op_pair<T>(
+        &self,
+        shard_selector1: u32,
+        shard_selector2: u32,
+        func: impl FnOnce(&Shard, &Shard) -> Result<T>,
+    ) -> Result<T> {
+        match &*self.node.read() {
+            ShardNode::Leaf(sh) => func(sh, sh),
+            ShardNode::Vertex(bottom, top) => {
+                match (
+                    shard_selector1 < bottom.span.end,
+                    shard_selector2 < bottom.span.end,
+                ) {
+                    (true, true) => bottom.shared_op_pair(shard_selector1, shard_selector2, func),
+                    (false, false) => top.shared_op_pair(shard_selector1, shard_selector2, func),
+                    (true, false) => bottom.shared_op(shard_selector1, |sh1| {
+                        top.shared_op(shard_selector2, |sh2| func(sh1, sh2))
+                    }),
+                    (false, true) => bottom.shared_op(shard_selector2, |sh2| {
+                        top.shared_op(shard_selector1, |sh1| func(sh1, sh2))
+                    }),
+                }
+            }
+        }
+    }
+
+    // splits the shard that holds the given selector (unless it was already split)
+    pub(crate) fn split_shard(&self, shard_selector: u32) -> Result<()> {
+        if let ShardNode::Vertex(bottom, top) = &*self.node.read() {
+            return if shard_selector < bottom.span.end {
+                bottom.split_shard(shard_selector)
+            } else {
+                top.split_shard(shard_selector)
+            };
+        }
+        self.split_leaf()
+    }
+
+    fn split_leaf(&self) -> Result<()> {
+        let mut guard = self.node.write();
+        let ShardNode::Leaf(sh) = &*guard else {
+            // already split
+            return Ok(());
+        };
+
+        let (bottom, top) = sh.split()?;
+
+        *guard = ShardNode::Vertex(
+            Arc::new(ShardRouter {
+                span: bottom.span.clone(),
+                config: self.config.clone(),
+                node: RwLock::new(ShardNode::Leaf(bottom)),
+                stats: self.stats.clone(),
+                threadpool: self.threadpool.clone(),
+            }),
+            Arc::new(ShardRouter {
+                span: top.span.clone(),
+                config: self.config.clone(),
+                node: RwLock::new(ShardNode::Leaf(top)),
+                stats: self.stats.clone(),
+                threadpool: self.threadpool.clone(),
+            }),
+        );
+        Ok(())
+    }
+
     pub(crate) fn clear(&self) -> Result<()> {
         let mut guard = self.node.write();
 
-        for res in std::fs::read_dir(&self.config.dir_path)? {
-            let entry = res?;
-            let filename = entry.file_name();
-            let Some(filename) = filename.to_str() else {
-                continue;
-            };
-            let Ok(filetype) = entry.file_type() else {
-                continue;
-            };
-            if !filetype.is_file() {
-                continue;
-            }
+        for (filename, path) in self.config.list_shard_files()? {
             if filename.starts_with("shard_")
                 || filename.starts_with("compact_")
                 || filename.starts_with("bottom_")
                 || filename.starts_with("top_")
             {
-                std::fs::remove_file(entry.path())?;
+                std::fs::remove_file(path)?;
             }
         }
 
@@ -359,31 +436,7 @@ impl ShardRouter {
 
             match res {
                 InsertStatus::SplitNeeded => {
-                    let mut guard = self.node.write();
-                    let ShardNode::Leaf(sh) = &*guard else {
-                        // already split
-                        continue;
-                    };
-
-                    let (bottom, top) = sh.split()?;
-
-                    *guard = ShardNode::Vertex(
-                        Arc::new(ShardRouter {
-                            span: bottom.span.clone(),
-                            config: self.config.clone(),
-                            node: RwLock::new(ShardNode::Leaf(bottom)),
-                            stats: self.stats.clone(),
-                            threadpool: self.threadpool.clone(),
-                        }),
-                        Arc::new(ShardRouter {
-                            span: top.span.clone(),
-                            config: self.config.clone(),
-                            node: RwLock::new(ShardNode::Leaf(top)),
-                            stats: self.stats.clone(),
-                            threadpool: self.threadpool.clone(),
-                        }),
-                    );
-
+                    self.split_leaf()?;
                     // retry
                 }
                 _ => {
@@ -467,8 +520,8 @@ impl ShardRouter {
             num_items += count;
         }
 
-        let needed_shards =
-            Self::calc_num_shards(num_items.max(self.config.expected_number_of_keys));
+        let needed_shards = (Self::END_OF_SHARDS / Self::calc_initial_step(&self.config))
+            .max(Self::calc_num_shards(num_items));
 
         if starting_num_shards <= needed_shards {
             return Ok(false);
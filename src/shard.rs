@@ -1,4 +1,4 @@
-use anyhow::bail;
+use anyhow::{bail, ensure};
 use bytemuck::{bytes_of_mut, Pod, Zeroable};
 use parking_lot::{Mutex, RwLock, RwLockWriteGuard};
 use std::{
@@ -17,12 +17,12 @@ use std::{
 
 use memmap::{MmapMut, MmapOptions};
 
-use crate::Result;
 use crate::{
     hashing::{PartedHash, INVALID_SIG},
     stats::InternalStats,
     store::InternalConfig,
 };
+use crate::{CandyError, Result};
 
 //
 // these numbers were chosen according to the simulation, as they allow for 90% utilization of the shard with
@@ -103,6 +103,13 @@ struct PageAligned<T>(T);
 pub(crate) const SHARD_FILE_MAGIC: [u8; 8] = *b"CandyStr";
 pub(crate) const SHARD_FILE_VERSION: u64 = 11;
 
+/// upgrades a shard file in place from the version it's keyed by to the next one
+type ShardMigration = fn(&Path) -> Result<()>;
+
+/// the safe in-place migrations, keyed by the version they upgrade from. Versions that have no entry here
+/// (i.e., all of them, so far) require recreating the store
+const SHARD_FILE_MIGRATIONS: &[(u64, ShardMigration)] = &[];
+
 #[derive(Clone, Copy, Default, Debug, Pod, Zeroable)]
 #[repr(C)]
 struct MetaHeader {
@@ -146,6 +153,14 @@ enum TryReplaceStatus<'a> {
     KeyExistsReplaced(Vec<u8>),
 }
 
+#[derive(Debug)]
+pub(crate) enum SwapStatus {
+    /// the previous values of both keys
+    Swapped(Option<Vec<u8>>, Option<Vec<u8>>),
+    /// nothing was written, as the shard with the given span start has to be split first
+    SplitNeeded(u32),
+}
+
 pub(crate) type KVPair = (Vec<u8>, Vec<u8>);
 
 struct MmapFile {
@@ -176,6 +191,7 @@ impl MmapFile {
     }
 
     fn create(filename: impl AsRef<Path>, config: &InternalConfig) -> Result<Self> {
+        config.create_shard_file_dir(filename.as_ref())?;
         let file = OpenOptions::new()
             .read(true)
             .write(true)
@@ -368,6 +384,53 @@ pub(crate) struct Shard {
     in_sync_agg_delay: std::sync::atomic::AtomicBool,
 }
 
+fn read_meta_header(filename: &Path) -> Result<Option<MetaHeader>> {
+    let mut file = File::open(filename)?;
+    let mut meta_header = MetaHeader::default();
+    let sz = file.read(bytes_of_mut(&mut meta_header))?;
+    if sz != size_of::<MetaHeader>() || meta_header.magic != SHARD_FILE_MAGIC {
+        return Ok(None);
+    }
+    Ok(Some(meta_header))
+}
+
+/// Brings the given shard file up to [SHARD_FILE_VERSION] by applying [SHARD_FILE_MIGRATIONS] in sequence.
+/// Returns whether the file was migrated. Files that aren't shard files at all (bad magic or too short) are
+/// left for [Shard::open] to deal with
+pub(crate) fn migrate_shard_file(filename: &Path) -> Result<bool> {
+    let Some(meta_header) = read_meta_header(filename)? else {
+        return Ok(false);
+    };
+    let found = meta_header.version;
+    let mut version = found;
+    while version < SHARD_FILE_VERSION {
+        let Some((_, migration)) = SHARD_FILE_MIGRATIONS.iter().find(|(v, _)| *v == version) else {
+            break;
+        };
+        migration(filename)?;
+        let Some(meta_header) = read_meta_header(filename)? else {
+            bail!(CandyError::Corruption(format!(
+                "{filename:?} lost its header while migrating from version {version}"
+            )));
+        };
+        ensure!(
+            meta_header.version > version,
+            CandyError::Corruption(format!(
+                "{filename:?} was not upgraded from version {version}"
+            ))
+        );
+        version = meta_header.version;
+    }
+    ensure!(
+        version == SHARD_FILE_VERSION,
+        CandyError::IncompatibleFormat {
+            found,
+            supported: SHARD_FILE_VERSION,
+        }
+    );
+    Ok(found != SHARD_FILE_VERSION)
+}
+
 impl Shard {
     pub(crate) const EXPECTED_CAPACITY: usize = (NUM_ROWS * ROW_WIDTH * 9) / 10; // ~ 29,500
 
@@ -378,9 +441,8 @@ impl Shard {
         stats: Arc<InternalStats>,
         threadpool: Arc<CompactionThreadPool>,
     ) -> Result<Self> {
-        let filename = config
-            .dir_path
-            .join(format!("shard_{:04x}-{:04x}", span.start, span.end));
+        let filename = config.shard_file_path("shard", span.start, span.end);
+        config.create_shard_file_dir(&filename)?;
         let mut file = OpenOptions::new()
             .create(true)
             .read(true)
@@ -399,6 +461,11 @@ impl Shard {
                 if config.clear_on_unsupported_version {
                     file.set_len(0)?;
                     file_size = 0;
+                } else if sz == size_of::<MetaHeader>() && meta_header.magic == SHARD_FILE_MAGIC {
+                    bail!(CandyError::IncompatibleFormat {
+                        found: meta_header.version,
+                        supported: SHARD_FILE_VERSION,
+                    });
                 } else {
                     bail!(
                         "{filename:?} unsupported magic={:?} version=0x{:016x} size={}",
@@ -414,7 +481,9 @@ impl Shard {
                     file.set_len(0)?;
                     file_size = 0;
                 } else {
-                    bail!("corrupt shard file (size={})", file_size);
+                    bail!(CandyError::Corruption(format!(
+                        "{filename:?} is too short (size={file_size})"
+                    )));
                 }
             }
         }
@@ -437,9 +506,7 @@ impl Shard {
 
         let mut mmap_file = MmapFile::new(file, config.mlock_headers)?;
 
-        let compacted_filename = config
-            .dir_path
-            .join(format!("compact_{:04x}-{:04x}", span.start, span.end));
+        let compacted_filename = config.shard_file_path("compact", span.start, span.end);
         if truncate {
             _ = std::fs::remove_file(compacted_filename);
         } else {
@@ -528,7 +595,7 @@ impl Shard {
                     "row={row_idx} col={target_col} sig={}",
                     target_row.signatures[target_col]
                 );
-                let ph = PartedHash::new(&config.hash_seed, &k);
+                let ph = config.hash_key(&k);
                 assert_eq!(ph.row_selector(), row_idx);
                 target_row.offsets_and_sizes[target_col] = target.write_kv(&stats, &k, &v)?;
                 std::sync::atomic::fence(Ordering::SeqCst);
@@ -565,14 +632,8 @@ impl Shard {
 
         let t0 = Instant::now();
 
-        let bottom_filename = self
-            .config
-            .dir_path
-            .join(format!("bottom_{:04x}-{:04x}", self.span.start, mid));
-        let top_filename = self
-            .config
-            .dir_path
-            .join(format!("top_{:04x}-{:04x}", mid, self.span.end));
+        let bottom_filename = self.config.shard_file_path("bottom", self.span.start, mid);
+        let top_filename = self.config.shard_file_path("top", mid, self.span.end);
 
         let bottom_file = MmapFile::create(&bottom_filename, &self.config)?;
         let top_file = MmapFile::create(&top_filename, &self.config)?;
@@ -587,7 +648,7 @@ impl Shard {
                 let (k, v) = files_guard
                     .0
                     .read_kv(&self.stats, src_row.offsets_and_sizes[col])?;
-                let ph = PartedHash::new(&self.config.hash_seed, &k);
+                let ph = self.config.hash_key(&k);
                 assert_eq!(row_idx, ph.row_selector());
 
                 let (file, col) = if ph.shard_selector() < mid {
@@ -612,20 +673,16 @@ impl Shard {
 
         std::fs::rename(
             bottom_filename,
-            self.config
-                .dir_path
-                .join(format!("shard_{:04x}-{:04x}", self.span.start, mid,)),
+            self.config.shard_file_path("shard", self.span.start, mid),
         )?;
         std::fs::rename(
             top_filename,
+            self.config.shard_file_path("shard", mid, self.span.end),
+        )?;
+        std::fs::remove_file(
             self.config
-                .dir_path
-                .join(format!("shard_{:04x}-{:04x}", mid, self.span.end)),
+                .shard_file_path("shard", self.span.start, self.span.end),
         )?;
-        std::fs::remove_file(self.config.dir_path.join(format!(
-            "shard_{:04x}-{:04x}",
-            self.span.start, self.span.end
-        )))?;
 
         self.stats.report_split(
             t0,
@@ -655,10 +712,9 @@ impl Shard {
         let bottom_files = bottom.files.write();
         let top_files = top.files.write();
 
-        let tmp_filename = bottom.config.dir_path.join(format!(
-            "merge_{:04x}-{:04x}",
-            bottom.span.start, top.span.end
-        ));
+        let tmp_filename = bottom
+            .config
+            .shard_file_path("merge", bottom.span.start, top.span.end);
         let mmap_file = MmapFile::create(&tmp_filename, &bottom.config)?;
 
         let combined = Shard::new(
@@ -681,7 +737,7 @@ impl Shard {
                     let (k, v) = files
                         .0
                         .read_kv(&combined.stats, src_row.offsets_and_sizes[src_col])?;
-                    let ph = PartedHash::new(&combined.config.hash_seed, &k);
+                    let ph = combined.config.hash_key(&k);
                     assert_eq!(row_idx, ph.row_selector());
 
                     let target_row = combined_files.0.row_mut(ph.row_selector());
@@ -709,18 +765,17 @@ impl Shard {
             }
         }
 
-        let dst_filename = combined.config.dir_path.join(format!(
-            "shard_{:04x}-{:04x}",
-            combined.span.start, combined.span.end
-        ));
-        let bottom_filename = combined.config.dir_path.join(format!(
-            "shard_{:04x}-{:04x}",
-            bottom.span.start, bottom.span.end
-        ));
+        let dst_filename =
+            combined
+                .config
+                .shard_file_path("shard", combined.span.start, combined.span.end);
+        let bottom_filename =
+            combined
+                .config
+                .shard_file_path("shard", bottom.span.start, bottom.span.end);
         let top_filename = combined
             .config
-            .dir_path
-            .join(format!("shard_{:04x}-{:04x}", top.span.start, top.span.end));
+            .shard_file_path("shard", top.span.start, top.span.end);
 
         std::fs::rename(tmp_filename, dst_filename)?;
         std::fs::remove_file(bottom_filename)?;
@@ -731,6 +786,19 @@ impl Shard {
         Ok(Some(combined))
     }
 
+    // the file that holds the given row: the compaction target if the row was already compacted, the source
+    // otherwise. The caller must hold the row's lock
+    fn row_file(files: &(MmapFile, Option<MmapFile>), row_idx: usize) -> &MmapFile {
+        match files.1 {
+            Some(ref target)
+                if row_idx < target.header().compacted_up_to.load(Ordering::Acquire) =>
+            {
+                target
+            }
+            _ => &files.0,
+        }
+    }
+
     fn operate_on_row<T>(
         &self,
         row_idx: usize,
@@ -738,15 +806,7 @@ impl Shard {
     ) -> Result<T> {
         let files_guard = self.files.read();
         let _row_guard = self.row_locks[row_idx].read();
-        let file = if let Some(ref target) = files_guard.1 {
-            if row_idx < target.header().compacted_up_to.load(Ordering::Acquire) {
-                target
-            } else {
-                &files_guard.0
-            }
-        } else {
-            &files_guard.0
-        };
+        let file = Self::row_file(&files_guard, row_idx);
 
         func(file, file.row(row_idx))
     }
@@ -758,18 +818,10 @@ impl Shard {
     ) -> Result<T> {
         let files_guard = self.files.read();
         let row_guard = self.row_locks[row_idx].write();
-        let file = if let Some(ref target) = files_guard.1 {
-            if row_idx < target.header().compacted_up_to.load(Ordering::Acquire) {
-                target
-            } else {
-                &files_guard.0
-            }
-        } else {
-            &files_guard.0
-        };
+        let file = Self::row_file(&files_guard, row_idx);
 
         func(
-            &file,
+            file,
             files_guard.1.is_some(),
             row_guard,
             file.row_mut(row_idx),
@@ -929,6 +981,35 @@ impl Shard {
         Ok(())
     }
 
+    // compacts the shard (waiting for the compaction to finish) if it has any wasted bytes, returning the
+    // write offsets before and after the compaction
+    pub(crate) fn compact(&self) -> Result<Option<(usize, usize)>> {
+        self.wait_for_compaction()?;
+        let (wasted_bytes, write_offset) = {
+            let files_guard = self.files.read();
+            let hdr = files_guard.0.header();
+            (
+                hdr.wasted_bytes.load(Ordering::Relaxed),
+                hdr.write_offset.load(Ordering::Relaxed),
+            )
+        };
+        if wasted_bytes == 0 {
+            return Ok(None);
+        }
+
+        self.begin_compaction(write_offset)?;
+        self.wait_for_compaction()?;
+
+        let new_write_offset = self
+            .files
+            .read()
+            .0
+            .header()
+            .write_offset
+            .load(Ordering::Relaxed);
+        Ok(Some((write_offset as usize, new_write_offset as usize)))
+    }
+
     fn begin_compaction(&self, min_write_offset: u64) -> Result<()> {
         let mut handle_guard = self.compaction_handle.lock();
         let mut files_guard = self.files.write();
@@ -957,14 +1038,12 @@ impl Shard {
         assert!(files_guard.1.is_none());
 
         let t0 = Instant::now();
-        let src_filename = self.config.dir_path.join(format!(
-            "shard_{:04x}-{:04x}",
-            self.span.start, self.span.end
-        ));
-        let target_filename = self.config.dir_path.join(format!(
-            "compact_{:04x}-{:04x}",
-            self.span.start, self.span.end
-        ));
+        let src_filename = self
+            .config
+            .shard_file_path("shard", self.span.start, self.span.end);
+        let target_filename =
+            self.config
+                .shard_file_path("compact", self.span.start, self.span.end);
         let target = MmapFile::create(&target_filename, &self.config)?;
         target.header().compacted_up_to.store(0, Ordering::Release);
         files_guard.1 = Some(target);
@@ -1100,6 +1179,163 @@ impl Shard {
         })
     }
 
+    // finds the key in the given row, returning its column and value. The caller must hold the row's lock
+    fn find_in_row(
+        &self,
+        file: &MmapFile,
+        row_idx: usize,
+        ph: PartedHash,
+        key: &[u8],
+    ) -> Result<Option<(usize, Vec<u8>)>> {
+        let row = file.row(row_idx);
+        let mut start = 0;
+        while let Some(idx) = row.lookup(ph.signature(), &mut start) {
+            let (k, v) = file.read_kv(&self.stats, row.offsets_and_sizes[idx])?;
+            if key == k {
+                return Ok(Some((idx, v)));
+            }
+        }
+        Ok(None)
+    }
+
+    // sets (or removes, if `val` is None) the key in the given row, where it's found at column `existing` (if
+    // it exists). The caller must hold the row's lock and make sure there's an empty column for a new key
+    fn put_in_row(
+        &self,
+        file: &MmapFile,
+        row_idx: usize,
+        ph: PartedHash,
+        key: &[u8],
+        existing: Option<&(usize, Vec<u8>)>,
+        val: Option<&[u8]>,
+    ) -> Result<()> {
+        let row = file.row_mut(row_idx);
+        match (existing, val) {
+            (None, None) => {}
+            (Some((idx, existing_val)), None) => {
+                row.signatures[*idx] = INVALID_SIG;
+                file.header().num_removals.fetch_add(1, Ordering::Relaxed);
+                file.header()
+                    .wasted_bytes
+                    .fetch_add((key.len() + existing_val.len()) as u64, Ordering::Relaxed);
+            }
+            (Some((idx, existing_val)), Some(val)) => {
+                if val != existing_val {
+                    row.offsets_and_sizes[*idx] = file.write_kv(&self.stats, key, val)?;
+                    file.header()
+                        .wasted_bytes
+                        .fetch_add((key.len() + existing_val.len()) as u64, Ordering::Relaxed);
+                    self.stats.num_updates.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            (None, Some(val)) => {
+                let mut start = 0;
+                let idx = row.lookup(INVALID_SIG, &mut start).unwrap();
+                row.offsets_and_sizes[idx] = file.write_kv(&self.stats, key, val)?;
+                std::sync::atomic::fence(Ordering::SeqCst);
+                row.signatures[idx] = ph.signature();
+                file.header().num_inserts.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        Ok(())
+    }
+
+    // exchanges the values of `key1` (in `sh1`) and `key2` (in `sh2`, which may be the same shard). The rows of
+    // both keys are locked for writing throughout, in (shard, row) order, so that concurrent swaps cannot
+    // deadlock. Nothing is written unless both keys fit, otherwise the shard that needs splitting is returned
+    pub(crate) fn swap(
+        sh1: &Shard,
+        ph1: PartedHash,
+        key1: &[u8],
+        sh2: &Shard,
+        ph2: PartedHash,
+        key2: &[u8],
+    ) -> Result<SwapStatus> {
+        let (row1, row2) = (ph1.row_selector(), ph2.row_selector());
+        if (sh2.span.start, row2) < (sh1.span.start, row1) {
+            return Ok(match Self::swap(sh2, ph2, key2, sh1, ph1, key1)? {
+                SwapStatus::Swapped(v2, v1) => SwapStatus::Swapped(v1, v2),
+                status => status,
+            });
+        }
+        let same_shard = std::ptr::eq(sh1, sh2);
+        let same_row = same_shard && row1 == row2;
+
+        let files_guard1 = sh1.files.read();
+        let _row_guard1 = sh1.row_locks[row1].write();
+        let files_guard2 = (!same_shard).then(|| sh2.files.read());
+        let _row_guard2 = (!same_row).then(|| sh2.row_locks[row2].write());
+        let files2 = files_guard2.as_deref().unwrap_or(&files_guard1);
+        let file1 = Self::row_file(&files_guard1, row1);
+        let file2 = Self::row_file(files2, row2);
+
+        let existing1 = sh1.find_in_row(file1, row1, ph1, key1)?;
+        let existing2 = sh2.find_in_row(file2, row2, ph2, key2)?;
+        let val1 = existing2.as_ref().map(|(_, v)| &v[..]);
+        let val2 = existing1.as_ref().map(|(_, v)| &v[..]);
+
+        // make sure both keys fit before writing anything
+        let entry_size = |key: &[u8], val: Option<&[u8]>| val.map_or(0, |v| key.len() + v.len());
+        let (size1, size2) = (entry_size(key1, val1), entry_size(key2, val2));
+        for size in [size1, size2] {
+            ensure!(
+                size <= sh1.config.max_shard_size as usize,
+                CandyError::EntryCannotFitInShard(size, sh1.config.max_shard_size as usize)
+            );
+        }
+        let needs_room = |files: &(MmapFile, Option<MmapFile>), size: usize| {
+            files.1.is_none()
+                && files.0.header().write_offset.load(Ordering::Relaxed) + size as u64
+                    > sh1.config.max_shard_size as u64
+        };
+        if same_shard {
+            if needs_room(&files_guard1, size1 + size2) {
+                return Ok(SwapStatus::SplitNeeded(sh1.span.start));
+            }
+        } else if needs_room(&files_guard1, size1) {
+            return Ok(SwapStatus::SplitNeeded(sh1.span.start));
+        } else if needs_room(files2, size2) {
+            return Ok(SwapStatus::SplitNeeded(sh2.span.start));
+        }
+        // a key that moves into a row needs an empty column there, unless the key that moves out of it is in
+        // the same row (it's removed first)
+        let has_room = |file: &MmapFile, row_idx: usize| {
+            let mut start = 0;
+            same_row || file.row(row_idx).lookup(INVALID_SIG, &mut start).is_some()
+        };
+        if existing1.is_none() && val1.is_some() && !has_room(file1, row1) {
+            return Ok(SwapStatus::SplitNeeded(sh1.span.start));
+        }
+        if existing2.is_none() && val2.is_some() && !has_room(file2, row2) {
+            return Ok(SwapStatus::SplitNeeded(sh2.span.start));
+        }
+
+        // removals first, to free the columns for the insertions
+        if val1.is_none() {
+            sh1.put_in_row(file1, row1, ph1, key1, existing1.as_ref(), None)?;
+        }
+        if val2.is_none() {
+            sh2.put_in_row(file2, row2, ph2, key2, existing2.as_ref(), None)?;
+        }
+        if val1.is_some() {
+            sh1.put_in_row(file1, row1, ph1, key1, existing1.as_ref(), val1)?;
+        }
+        if val2.is_some() {
+            sh2.put_in_row(file2, row2, ph2, key2, existing2.as_ref(), val2)?;
+        }
+
+        let status = SwapStatus::Swapped(existing1.map(|(_, v)| v), existing2.map(|(_, v)| v));
+        #[cfg(feature = "flush_aggregation")]
+        {
+            drop((_row_guard2, files_guard2, _row_guard1, files_guard1));
+            sh1.flush_aggregation()?;
+            if !same_shard {
+                sh2.flush_aggregation()?;
+            }
+        }
+        Ok(status)
+    }
+
     pub(crate) fn get_stats(&self) -> Result<ShardStats> {
         self.wait_for_compaction()?;
         let files_guard = self.files.read();
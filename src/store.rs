@@ -1,16 +1,28 @@
 use anyhow::{anyhow, bail, ensure};
-use bytemuck::{bytes_of, from_bytes};
+use bytemuck::pod_read_unaligned;
 use fslock::LockFile;
 use parking_lot::Mutex;
+use rand::Rng;
 use std::{
+    ops::Range,
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Weak,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use crate::{
-    hashing::{HashSeed, PartedHash},
+    changes::{ChangeFeed, ChangeOp},
+    hashing::{HashSeed, KeyHasher, PartedHash},
+    lists::MaintenanceThread,
+    queues::QueueIterator,
     router::ShardRouter,
-    shard::{CompactionThreadPool, InsertMode, InsertStatus, KVPair},
+    shard::{
+        migrate_shard_file, CompactionThreadPool, InsertMode, InsertStatus, KVPair, Shard,
+        SwapStatus,
+    },
     Stats, MAX_KEY_SIZE, MAX_TOTAL_VALUE_SIZE,
 };
 use crate::{
@@ -18,7 +30,10 @@ use crate::{
     stats::InternalStats,
 };
 
-use crate::{CandyError, Config, Result, MAX_TOTAL_KEY_SIZE, MAX_VALUE_SIZE};
+use crate::{
+    CandyError, Config, EntrySeq, Result, ShardFileLayout, ValueCodec, MAX_TOTAL_KEY_SIZE,
+    MAX_VALUE_SIZE,
+};
 
 pub(crate) const USER_NAMESPACE: &[u8] = &[1];
 pub(crate) const TYPED_NAMESPACE: &[u8] = &[2];
@@ -27,6 +42,20 @@ pub(crate) const ITEM_NAMESPACE: &[u8] = &[4];
 pub(crate) const CHAIN_NAMESPACE: u8 = 5;
 pub(crate) const QUEUE_NAMESPACE: &[u8] = &[6];
 pub(crate) const QUEUE_ITEM_NAMESPACE: &[u8] = &[7];
+pub(crate) const SEQ_NAMESPACE: &[u8] = &[8];
+pub(crate) const TYPED_NS_NAMESPACE: &[u8] = &[9];
+pub(crate) const TYPED_INDEX_NAMESPACE: &[u8] = &[10];
+pub(crate) const TYPED_PRIORITY_NAMESPACE: &[u8] = &[11];
+pub(crate) const TYPED_FIXED_NAMESPACE: &[u8] = &[12];
+pub(crate) const QUEUE_NEXT_IDX_NAMESPACE: &[u8] = &[13];
+
+// the persisted counter is advanced in blocks, so that only one in SEQ_BLOCK sequence numbers requires a write
+const SEQ_BLOCK: u64 = 64 * 1024;
+
+/// whether `name` is that of a subdirectory of [ShardFileLayout::Nested]
+pub(crate) fn is_shard_subdir_name(name: &str) -> bool {
+    name.len() == 2 && name.bytes().all(|b| b.is_ascii_hexdigit())
+}
 
 #[derive(Debug, Clone)]
 pub(crate) struct InternalConfig {
@@ -34,14 +63,85 @@ pub(crate) struct InternalConfig {
     pub max_shard_size: u32,
     pub min_compaction_threashold: u32,
     pub hash_seed: HashSeed,
+    pub key_hasher: KeyHasher,
     pub expected_number_of_keys: usize,
+    pub initial_num_shards: u32,
     pub max_concurrent_list_ops: u32,
     pub truncate_up: bool,
     pub clear_on_unsupported_version: bool,
     pub mlock_headers: bool,
     pub num_compaction_threads: usize,
+    pub typed_entry_seq: EntrySeq,
+    pub typed_value_tags: bool,
+    pub max_inline_value: usize,
+    pub auto_big_value_threshold: usize,
+    pub value_codec: Option<ValueCodec>,
+    pub value_compression_threshold: usize,
+    pub shard_file_layout: ShardFileLayout,
+    pub change_feed_capacity: usize,
+    pub change_feed_persistent: bool,
+    pub max_update_retries: usize,
     #[cfg(feature = "flush_aggregation")]
     pub flush_aggregation_delay: Option<std::time::Duration>,
+    #[cfg(feature = "observer")]
+    pub observer: Option<Arc<dyn crate::Observer>>,
+}
+
+impl InternalConfig {
+    pub(crate) fn hash_key(&self, key: &[u8]) -> PartedHash {
+        PartedHash::with_hasher(self.key_hasher, &self.hash_seed, key)
+    }
+
+    /// the path of a shard's file (`prefix` is `shard`, or one of the temporary file prefixes), according
+    /// to the configured [ShardFileLayout]
+    pub(crate) fn shard_file_path(&self, prefix: &str, start: u32, end: u32) -> PathBuf {
+        let filename = format!("{prefix}_{start:04x}-{end:04x}");
+        match self.shard_file_layout {
+            ShardFileLayout::Flat => self.dir_path.join(filename),
+            ShardFileLayout::Nested => self
+                .dir_path
+                .join(format!("{:02x}", start >> 8))
+                .join(filename),
+        }
+    }
+
+    /// creates the subdirectory that's about to hold the given shard file, if needed
+    pub(crate) fn create_shard_file_dir(&self, path: &Path) -> Result<()> {
+        if self.shard_file_layout == ShardFileLayout::Nested {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// lists the files that may belong to shards (in the store's directory and in the subdirectories of
+    /// [ShardFileLayout::Nested]), regardless of the configured layout
+    pub(crate) fn list_shard_files(&self) -> Result<Vec<(String, PathBuf)>> {
+        let mut files = vec![];
+        let mut dirs = vec![self.dir_path.clone()];
+        while let Some(dir) = dirs.pop() {
+            let is_root = dir == self.dir_path;
+            for res in std::fs::read_dir(&dir)? {
+                let entry = res?;
+                let filename = entry.file_name();
+                let Some(filename) = filename.to_str() else {
+                    continue;
+                };
+                let Ok(filetype) = entry.file_type() else {
+                    continue;
+                };
+                if filetype.is_dir() {
+                    if is_root && is_shard_subdir_name(filename) {
+                        dirs.push(entry.path());
+                    }
+                } else if filetype.is_file() {
+                    files.push((filename.to_owned(), entry.path()));
+                }
+            }
+        }
+        Ok(files)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -98,6 +198,45 @@ impl GetOrCreateStatus {
         }
     }
 }
+/// The statistics of a single shard, as returned by [CandyStore::shard_stats]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShardStat {
+    /// the range of shard selectors (the top bits of key hashes) that this shard holds
+    pub span: Range<u32>,
+    /// the number of entries in the shard, including internal ones (of lists, queues, etc.)
+    pub num_entries: usize,
+    /// the number of bytes written to the shard's data section (excluding its header), including wasted ones
+    pub occupied_bytes: usize,
+    /// the number of bytes taken by removed or overwritten entries, to be reclaimed by compaction
+    pub wasted_bytes: usize,
+}
+
+impl ShardStat {
+    /// the number of bytes taken by live entries
+    pub fn data_bytes(&self) -> usize {
+        self.occupied_bytes - self.wasted_bytes
+    }
+}
+
+/// The result of [CandyStore::compact]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompactionReport {
+    /// the number of shards that had wasted space, and were compacted
+    pub num_compacted_shards: usize,
+    /// the total size of the data in the compacted shards, before compacting them
+    pub bytes_before: usize,
+    /// the total size of the data in the compacted shards, after compacting them
+    pub bytes_after: usize,
+}
+
+impl CompactionReport {
+    /// the number of bytes reclaimed by the compaction. Since other operations may write to a shard while it's
+    /// being compacted, a shard may end up larger than it started; such growth counts as nothing freed
+    pub fn bytes_freed(&self) -> usize {
+        self.bytes_before.saturating_sub(self.bytes_after)
+    }
+}
+
 /// The CandyStore object. Note that it's fully sync'ed, so can be shared between threads using `Arc`
 pub struct CandyStore {
     pub(crate) root: ShardRouter,
@@ -107,7 +246,50 @@ pub struct CandyStore {
     pub(crate) keyed_locks: Vec<Mutex<()>>,
     _lockfile: LockFile,
     stats: Arc<InternalStats>,
+    // (next, reserved) sequence numbers, for EntrySeq::Counter
+    seq: Mutex<(u64, u64)>,
+    // serializes batch commits, which share the journal file
+    pub(crate) journal_lock: Mutex<()>,
+    // serializes raising the store-wide index that queues start at (see CandyStore::queue_next_idx)
+    pub(crate) queue_next_idx_lock: Mutex<()>,
+    // the maintenance threads spawned by spawn_maintenance, which are stopped when the store is dropped
+    pub(crate) maintenance_threads: Mutex<Vec<Weak<MaintenanceThread>>>,
+    // bumped by every clear, so that typed read caches know to drop their entries
+    clear_epoch: AtomicU64,
+    pub(crate) change_feed: Option<ChangeFeed>,
     //threadpool: Arc<CompactionThreadPool>,
+    // must come last, so that the directory is removed only after the shards were dropped
+    _remove_dir_on_drop: Option<RemoveDirOnDrop>,
+}
+
+const IN_MEMORY_DIR_PREFIX: &str = "candy-in-memory-";
+
+// removes the store's directory when dropped, for stores opened by [CandyStore::open_in_memory]
+struct RemoveDirOnDrop(PathBuf);
+
+impl Drop for RemoveDirOnDrop {
+    fn drop(&mut self) {
+        _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// An opaque position in the store (the shard and the position within it), as returned by
+/// [crate::CandyTypedStore::keys_page], that can be used to fetch the next page. Cursors can be persisted or
+/// handed to clients via [Self::cookie] and [Self::from_cookie]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StoreCursor(u64);
+
+impl StoreCursor {
+    /// Returns the cookie of this cursor, from which it can be reconstructed using [Self::from_cookie].
+    /// This is the same cookie as [CandyStoreIterator::cookie]
+    pub fn cookie(&self) -> u64 {
+        self.0
+    }
+
+    /// Reconstructs a cursor from the cookie returned by [Self::cookie]
+    pub fn from_cookie(cookie: u64) -> Self {
+        Self(cookie)
+    }
 }
 
 /// An iterator over a CandyStore. Note that it's safe to modify (insert/delete) keys while iterating,
@@ -203,15 +385,112 @@ impl<'a> Iterator for CandyStoreIterator<'a> {
     }
 }
 
+// the file recording the layout of typed values (see typed_value_layout), which is checked on open
+const TYPED_LAYOUT_FILENAME: &str = "typed_layout";
+const TYPED_LAYOUT_TMP_FILENAME: &str = "typed_layout.tmp";
+const TYPED_LAYOUT_MAGIC: [u8; 8] = *b"CandyTVL";
+
+// the bits of the typed value layout
+const LAYOUT_ENTRY_SEQ: u64 = 1;
+const LAYOUT_VALUE_TAGS: u64 = 2;
+const LAYOUT_COMPRESSION: u64 = 4;
+
+impl InternalConfig {
+    // the config options that determine how typed values are stored on disk. Counters and timestamps are
+    // stored alike, so only whether sequence numbers are kept matters
+    fn typed_value_layout(&self) -> u64 {
+        let mut layout = 0;
+        if self.typed_entry_seq != EntrySeq::Disabled {
+            layout |= LAYOUT_ENTRY_SEQ;
+        }
+        if self.typed_value_tags {
+            layout |= LAYOUT_VALUE_TAGS;
+        }
+        if self.value_codec.is_some() {
+            layout |= LAYOUT_COMPRESSION;
+        }
+        layout
+    }
+
+    fn load_typed_value_layout(&self) -> Result<Option<u64>> {
+        let data = match std::fs::read(self.dir_path.join(TYPED_LAYOUT_FILENAME)) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let Some(layout) = data.strip_prefix(&TYPED_LAYOUT_MAGIC[..]) else {
+            bail!(CandyError::Corruption("bad typed layout file".into()));
+        };
+        let layout: [u8; 8] = layout
+            .try_into()
+            .map_err(|_| anyhow!(CandyError::Corruption("bad typed layout file".into())))?;
+        Ok(Some(u64::from_le_bytes(layout)))
+    }
+
+    pub(crate) fn save_typed_value_layout(&self, layout: u64) -> Result<()> {
+        let tmp_filename = self.dir_path.join(TYPED_LAYOUT_TMP_FILENAME);
+        let mut data = TYPED_LAYOUT_MAGIC.to_vec();
+        data.extend_from_slice(&layout.to_le_bytes());
+        std::fs::write(&tmp_filename, data)?;
+        std::fs::rename(&tmp_filename, self.dir_path.join(TYPED_LAYOUT_FILENAME))?;
+        Ok(())
+    }
+
+    // checks that the configured layout of typed values matches the recorded one (recording it for new stores,
+    // and for stores created by older versions). Enabling compression is allowed, as uncompressed values are
+    // still read correctly, but disabling it is not. Returns false if the layouts do not match
+    fn check_typed_value_layout(&self) -> Result<bool> {
+        let layout = self.typed_value_layout();
+        let Some(found) = self.load_typed_value_layout()? else {
+            self.save_typed_value_layout(layout)?;
+            return Ok(true);
+        };
+        if found & !LAYOUT_COMPRESSION != layout & !LAYOUT_COMPRESSION
+            || (found & LAYOUT_COMPRESSION != 0 && layout & LAYOUT_COMPRESSION == 0)
+        {
+            if self.clear_on_unsupported_version {
+                return Ok(false);
+            }
+            bail!(CandyError::IncompatibleFormat {
+                found,
+                supported: layout,
+            });
+        }
+        if found != layout {
+            self.save_typed_value_layout(layout)?;
+        }
+        Ok(true)
+    }
+}
+
 impl CandyStore {
     /// Opens or creates a new CandyStore.
     /// * dir_path - the directory where shards will be kept
     /// * config - the configuration options for the store
+    ///
+    /// Every shard file records the on-disk format version it was created with, and the store records the
+    /// layout of typed values (see [Config::typed_entry_seq], [Config::typed_value_tags] and
+    /// [Config::value_codec]). Opening a store that was written with a different format or layout fails with
+    /// [CandyError::IncompatibleFormat] (unless [Config::clear_on_unsupported_version] is set, in which case
+    /// it's cleared)
     pub fn open(dir_path: impl AsRef<Path>, config: Config) -> Result<Self> {
+        Self::open_impl(dir_path.as_ref(), config, false)
+    }
+
+    /// Same as [Self::open], but first upgrades shard files written with an older on-disk format in place,
+    /// if a safe migration exists for their version. Otherwise fails with [CandyError::IncompatibleFormat]
+    /// and leaves the files untouched. Files that are already up to date are not modified
+    pub fn open_and_migrate(dir_path: impl AsRef<Path>, config: Config) -> Result<Self> {
+        Self::open_impl(dir_path.as_ref(), config, true)
+    }
+
+    fn open_impl(dir_path: &Path, config: Config, migrate: bool) -> Result<Self> {
         let config = Arc::new(InternalConfig {
-            dir_path: dir_path.as_ref().to_path_buf(),
+            dir_path: dir_path.to_path_buf(),
             expected_number_of_keys: config.expected_number_of_keys,
+            initial_num_shards: config.initial_num_shards,
             hash_seed: config.hash_seed,
+            key_hasher: config.key_hasher,
             max_concurrent_list_ops: config.max_concurrent_list_ops,
             max_shard_size: config.max_shard_size,
             min_compaction_threashold: config.min_compaction_threashold,
@@ -219,8 +498,20 @@ impl CandyStore {
             clear_on_unsupported_version: config.clear_on_unsupported_version,
             mlock_headers: config.mlock_headers,
             num_compaction_threads: config.num_compaction_threads,
+            typed_entry_seq: config.typed_entry_seq,
+            typed_value_tags: config.typed_value_tags,
+            max_inline_value: config.max_inline_value,
+            auto_big_value_threshold: config.auto_big_value_threshold,
+            value_codec: config.value_codec,
+            value_compression_threshold: config.value_compression_threshold,
+            shard_file_layout: config.shard_file_layout,
+            change_feed_capacity: config.change_feed_capacity,
+            change_feed_persistent: config.change_feed_persistent,
+            max_update_retries: config.max_update_retries,
             #[cfg(feature = "flush_aggregation")]
             flush_aggregation_delay: config.flush_aggregation_delay,
+            #[cfg(feature = "observer")]
+            observer: config.observer,
         });
 
         std::fs::create_dir_all(dir_path)?;
@@ -255,6 +546,16 @@ impl CandyStore {
             );
         }
 
+        let layout_matches = config.check_typed_value_layout()?;
+
+        if migrate {
+            for (filename, path) in config.list_shard_files()? {
+                if filename.starts_with("shard_") {
+                    migrate_shard_file(&path)?;
+                }
+            }
+        }
+
         let mut num_keyed_locks = config.max_concurrent_list_ops.max(4);
         if !num_keyed_locks.is_power_of_two() {
             num_keyed_locks = 1 << (num_keyed_locks.ilog2() + 1);
@@ -268,16 +569,141 @@ impl CandyStore {
         let stats = Arc::new(InternalStats::default());
         let threadpool = Arc::new(CompactionThreadPool::new(config.num_compaction_threads));
         let root = ShardRouter::new(config.clone(), stats.clone(), threadpool.clone())?;
+        let change_feed = ChangeFeed::open(&config)?;
 
-        Ok(Self {
+        let store = Self {
             config,
             root,
             keyed_locks_mask: num_keyed_locks - 1,
             keyed_locks,
             _lockfile: lockfile,
             stats,
+            seq: Mutex::new((0, 0)),
+            journal_lock: Mutex::new(()),
+            queue_next_idx_lock: Mutex::new(()),
+            maintenance_threads: Mutex::new(vec![]),
+            clear_epoch: AtomicU64::new(0),
+            change_feed,
             //threadpool,
-        })
+            _remove_dir_on_drop: None,
+        };
+
+        if store.config.typed_entry_seq == EntrySeq::Counter {
+            // skip whatever remained of the last reserved block
+            let reserved = store.load_reserved_seq()?;
+            *store.seq.lock() = (reserved, reserved);
+        }
+
+        if !layout_matches {
+            store.clear()?;
+        }
+
+        // complete a batch commit that was interrupted by a crash
+        store.replay_journal()?;
+
+        Ok(store)
+    }
+
+    /// Opens a new, empty CandyStore that lives in memory and is discarded when dropped, e.g., for tests.
+    /// This is the same store (with the same semantics for big values, lists, queues, etc.), only its shards
+    /// are kept in a fresh `candy-in-memory-*` directory under `/dev/shm`, which is RAM-backed. The directory
+    /// is removed when the store is dropped.
+    ///
+    /// Note: where `/dev/shm` does not exist (e.g., on non-Linux systems), the directory is created under the
+    /// system's temp directory instead, so the store is only as "in memory" as that directory is. If a process
+    /// dies without dropping its in-memory stores, their directories are left behind; such leftovers (whose
+    /// stores are no longer locked by any process) are removed by the next call to this function
+    pub fn open_in_memory(config: Config) -> Result<Self> {
+        let shm = Path::new("/dev/shm");
+        let parent = if shm.is_dir() {
+            shm.to_path_buf()
+        } else {
+            std::env::temp_dir()
+        };
+        Self::remove_stale_in_memory_dirs(&parent);
+
+        let dir_path = parent.join(format!(
+            "{IN_MEMORY_DIR_PREFIX}{:016x}",
+            rand::random::<u64>()
+        ));
+        let guard = RemoveDirOnDrop(dir_path.clone());
+        let mut store = Self::open(&dir_path, config)?;
+        store._remove_dir_on_drop = Some(guard);
+        Ok(store)
+    }
+
+    // removes the directories of in-memory stores whose process died. A live store holds the lock file of
+    // its directory, so a directory whose lock can be taken is stale. This is best-effort, errors are ignored
+    fn remove_stale_in_memory_dirs(parent: &Path) {
+        let Ok(entries) = std::fs::read_dir(parent) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            if !entry
+                .file_name()
+                .to_string_lossy()
+                .starts_with(IN_MEMORY_DIR_PREFIX)
+            {
+                continue;
+            }
+            // skip recently-created directories, which may belong to stores that are being opened right now
+            // (before they take the lock)
+            let is_recent = entry
+                .metadata()
+                .and_then(|md| md.modified())
+                .map(|t| {
+                    t.elapsed()
+                        .map_or(true, |age| age < Duration::from_secs(60))
+                })
+                .unwrap_or(true);
+            let lockfilename = entry.path().join(".lock");
+            if is_recent || !lockfilename.exists() {
+                continue;
+            }
+            let Ok(mut lockfile) = LockFile::open(&lockfilename) else {
+                continue;
+            };
+            if let Ok(true) = lockfile.try_lock() {
+                _ = std::fs::remove_dir_all(entry.path());
+            }
+        }
+    }
+
+    fn seq_key() -> Vec<u8> {
+        let mut key = b"seq".to_vec();
+        key.extend_from_slice(SEQ_NAMESPACE);
+        key
+    }
+
+    fn load_reserved_seq(&self) -> Result<u64> {
+        let Some(bytes) = self.get_raw(&Self::seq_key())? else {
+            return Ok(0);
+        };
+        let bytes: [u8; 8] = bytes
+            .try_into()
+            .map_err(|_| anyhow!(CandyError::Corruption("bad sequence number".into())))?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    /// returns the sequence number for a typed entry that's being written, according to [Config::typed_entry_seq]
+    pub(crate) fn next_seq(&self) -> Result<u64> {
+        match self.config.typed_entry_seq {
+            EntrySeq::Disabled => bail!(CandyError::NotEnabled("sequence numbers")),
+            EntrySeq::Counter => {
+                let mut guard = self.seq.lock();
+                let (next, reserved) = &mut *guard;
+                if *next >= *reserved {
+                    self.set_raw(&Self::seq_key(), &(*next + SEQ_BLOCK).to_le_bytes())?;
+                    *reserved = *next + SEQ_BLOCK;
+                }
+                *next += 1;
+                Ok(*next - 1)
+            }
+            EntrySeq::Timestamp => Ok(SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_micros() as u64),
+        }
     }
 
     /// returns the directory where shards are kept
@@ -292,19 +718,60 @@ impl CandyStore {
         Ok(())
     }
 
-    /// Clears the store (erasing all keys), and removing all shard files
+    /// Clears the store (erasing all keys), and removing all shard files. This erases everything - the entries
+    /// of all typed stores, lists and queues (including the queues' index counters, so indices start over), big
+    /// values, and the journal of an incomplete [crate::WriteBatch] - leaving an empty store that can be used
+    /// right away. Read caches of typed stores (see [crate::CandyTypedStore::new_cached]) are dropped as well.
+    ///
+    /// Note: this should not be called while other operations are in flight; their effects may or may not
+    /// survive the clear
     pub fn clear(&self) -> Result<()> {
+        // wait for an in-progress batch commit, so it won't be applied (or replayed) over the cleared store
+        let _guard = self.journal_lock.lock();
         self.root.clear()?;
+        self.discard_journal()?;
+        // no typed values remain, so the configured layout can be used from now on
+        self.config
+            .save_typed_value_layout(self.config.typed_value_layout())?;
+        self.clear_epoch.fetch_add(1, Ordering::SeqCst);
+        self.record_change(ChangeOp::Clear, &[]);
         self.stats.clear();
+        // the persisted counter was erased, so the next sequence number must persist it again
+        {
+            let mut guard = self.seq.lock();
+            guard.1 = guard.0;
+        }
 
         Ok(())
     }
 
-    pub(crate) fn ensure_sizes(key: &[u8], val: &[u8]) -> Result<()> {
+    /// Removes all entries of all kinds, leaving an empty but usable store. This is the same as [Self::clear],
+    /// under a name that makes the full wipe explicit (e.g., for test teardown or a "factory reset").
+    ///
+    /// Note: this should not be called while other operations are in flight
+    pub fn clear_all(&self) -> Result<()> {
+        self.clear()
+    }
+
+    pub(crate) fn clear_epoch(&self) -> u64 {
+        self.clear_epoch.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn ensure_sizes(&self, key: &[u8], val: &[u8]) -> Result<()> {
         ensure!(key.len() <= MAX_KEY_SIZE, CandyError::KeyTooLong(key.len()));
+        self.ensure_inline_value_size(val.len())
+    }
+
+    // values of the key-value APIs (as opposed to those of lists and queues) are also limited by
+    // Config::max_inline_value
+    pub(crate) fn ensure_inline_value_size(&self, len: usize) -> Result<()> {
+        ensure!(len <= MAX_VALUE_SIZE, CandyError::ValueTooLong(len));
         ensure!(
-            val.len() <= MAX_VALUE_SIZE,
-            CandyError::ValueTooLong(val.len())
+            len <= self.config.max_inline_value,
+            CandyError::ValueTooLarge {
+                size: len,
+                limit: self.config.max_inline_value
+            }
         );
 
         Ok(())
@@ -322,13 +789,27 @@ impl CandyStore {
     }
 
     pub(crate) fn get_raw(&self, full_key: &[u8]) -> Result<Option<Vec<u8>>> {
-        let ph = PartedHash::new(&self.config.hash_seed, full_key);
-        self.root
-            .shared_op(ph.shard_selector(), |sh| sh.get(ph, &full_key))
+        let ph = self.config.hash_key(full_key);
+        let op = || {
+            self.root
+                .shared_op(ph.shard_selector(), |sh| sh.get(ph, &full_key))
+        };
+        #[cfg(feature = "observer")]
+        if let Some(ref observer) = self.config.observer {
+            let t0 = std::time::Instant::now();
+            let res = op()?;
+            observer.on_get(t0.elapsed());
+            if res.is_none() {
+                observer.on_miss();
+            }
+            return Ok(res);
+        }
+        op()
     }
 
     /// Gets the value of a key from the store. If the key does not exist, `None` will be returned.
-    /// The data is fully-owned, no references are returned.
+    /// The data is fully-owned, no references are returned. The returned vector can be handed over to other
+    /// owned-buffer types without copying, e.g., `bytes::Bytes::from(vec)`.
     pub fn get<B: AsRef<[u8]> + ?Sized>(&self, key: &B) -> Result<Option<Vec<u8>>> {
         self.owned_get(key.as_ref().to_owned())
     }
@@ -348,10 +829,65 @@ impl CandyStore {
         Ok(self.get_raw(&self.make_user_key(key))?.is_some())
     }
 
+    // checks the existence of the given keys shard by shard, taking each shard once for all of its keys,
+    // returning the results in the given order
+    pub(crate) fn contains_many_raw(&self, full_keys: Vec<Vec<u8>>) -> Result<Vec<bool>> {
+        let mut keys = full_keys
+            .into_iter()
+            .enumerate()
+            .map(|(i, k)| (self.config.hash_key(&k), i, k))
+            .collect::<Vec<_>>();
+        keys.sort_by_key(|(ph, _, _)| ph.shard_selector());
+
+        let mut res = vec![false; keys.len()];
+        let mut pos = 0;
+        while pos < keys.len() {
+            pos = self.root.shared_op(keys[pos].0.shard_selector(), |sh| {
+                while let Some((ph, i, full_key)) = keys.get(pos) {
+                    if !sh.span.contains(&ph.shard_selector()) {
+                        break;
+                    }
+                    res[*i] = sh.get(*ph, full_key)?.is_some();
+                    pos += 1;
+                }
+                Ok(pos)
+            })?;
+        }
+        Ok(res)
+    }
+
+    /// Checks which of the given keys exist, returning a bool per key (in the given order). The lookups are
+    /// grouped by shard, so each shard is locked once for all of its keys, rather than once per key as when
+    /// calling [Self::contains] for each key
+    pub fn contains_many<B: AsRef<[u8]> + ?Sized>(&self, keys: &[&B]) -> Result<Vec<bool>> {
+        self.contains_many_raw(
+            keys.iter()
+                .map(|k| self.make_user_key(k.as_ref().to_owned()))
+                .collect(),
+        )
+    }
+
     pub(crate) fn remove_raw(&self, full_key: &[u8]) -> Result<Option<Vec<u8>>> {
-        let ph = PartedHash::new(&self.config.hash_seed, full_key);
-        self.root
-            .shared_op(ph.shard_selector(), |sh| sh.remove(ph, &full_key))
+        let ph = self.config.hash_key(full_key);
+        let op = || {
+            self.root
+                .shared_op(ph.shard_selector(), |sh| sh.remove(ph, &full_key))
+        };
+        #[cfg(feature = "observer")]
+        let op = || {
+            let Some(ref observer) = self.config.observer else {
+                return op();
+            };
+            let t0 = std::time::Instant::now();
+            let res = op()?;
+            observer.on_remove(t0.elapsed());
+            Ok(res)
+        };
+        let res = op()?;
+        if res.is_some() {
+            self.record_change(ChangeOp::Remove, full_key);
+        }
+        Ok(res)
     }
 
     /// Removes a key-value pair from the store, returning `None` if the key did not exist,
@@ -365,14 +901,53 @@ impl CandyStore {
         self.remove_raw(&self.make_user_key(key))
     }
 
-    pub(crate) fn insert_internal(
-        &self,
-        full_key: &[u8],
-        val: &[u8],
-        mode: InsertMode,
-    ) -> Result<InsertStatus> {
-        let ph = PartedHash::new(&self.config.hash_seed, full_key);
+    // removes the given keys shard by shard, taking each shard once for all of its keys, returning the number
+    // of keys that existed. On error, the error carries [CandyError::BatchInterrupted] as context
+    pub(crate) fn remove_many_raw(&self, full_keys: Vec<Vec<u8>>) -> Result<usize> {
+        let mut keys = full_keys
+            .into_iter()
+            .map(|k| (self.config.hash_key(&k), k))
+            .collect::<Vec<_>>();
+        keys.sort_by_key(|(ph, _)| ph.shard_selector());
+
+        let mut removed = 0;
+        let mut pos = 0;
+        while pos < keys.len() {
+            let res = self.root.shared_op(keys[pos].0.shard_selector(), |sh| {
+                while let Some((ph, full_key)) = keys.get(pos) {
+                    if !sh.span.contains(&ph.shard_selector()) {
+                        break;
+                    }
+                    if sh.remove(*ph, full_key)?.is_some() {
+                        self.record_change(ChangeOp::Remove, full_key);
+                        removed += 1;
+                    }
+                    pos += 1;
+                }
+                Ok(())
+            });
+            if let Err(e) = res {
+                return Err(e.context(CandyError::BatchInterrupted(removed)));
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Removes all the given keys, returning the number of keys that existed (and were removed). Keys are
+    /// processed grouped by shard, not in the given order, and each shard is locked once for all of its keys.
+    ///
+    /// This is not atomic: if an error occurs mid-batch, the keys removed so far remain removed, and the
+    /// returned error can be downcast to [CandyError::BatchInterrupted], holding the number of keys
+    /// removed before the failure. Since removing is idempotent, it's safe to retry the whole batch
+    pub fn remove_many<B: AsRef<[u8]> + ?Sized>(&self, keys: &[&B]) -> Result<usize> {
+        self.remove_many_raw(
+            keys.iter()
+                .map(|k| self.make_user_key(k.as_ref().to_owned()))
+                .collect(),
+        )
+    }
 
+    fn ensure_entry_fits(&self, full_key: &[u8], val: &[u8]) -> Result<()> {
         ensure!(
             full_key.len() <= MAX_TOTAL_KEY_SIZE,
             CandyError::KeyTooLong(full_key.len())
@@ -388,8 +963,35 @@ impl CandyStore {
                 self.config.max_shard_size as usize
             )));
         }
+        Ok(())
+    }
+
+    pub(crate) fn insert_internal(
+        &self,
+        full_key: &[u8],
+        val: &[u8],
+        mode: InsertMode,
+    ) -> Result<InsertStatus> {
+        let ph = self.config.hash_key(full_key);
+        self.ensure_entry_fits(full_key, val)?;
+
+        #[cfg(feature = "observer")]
+        let res = match self.config.observer {
+            Some(ref observer) => {
+                let t0 = std::time::Instant::now();
+                let res = self.root.insert(ph, full_key, val, mode)?;
+                observer.on_set(t0.elapsed());
+                res
+            }
+            None => self.root.insert(ph, full_key, val, mode)?,
+        };
+        #[cfg(not(feature = "observer"))]
+        let res = self.root.insert(ph, full_key, val, mode)?;
 
-        self.root.insert(ph, full_key, val, mode)
+        if matches!(res, InsertStatus::Added | InsertStatus::Replaced(_)) {
+            self.record_change(ChangeOp::Set, full_key);
+        }
+        Ok(res)
     }
 
     pub(crate) fn set_raw(&self, full_key: &[u8], val: &[u8]) -> Result<SetStatus> {
@@ -402,6 +1004,89 @@ impl CandyStore {
         }
     }
 
+    // sets the given key-value pairs shard by shard, taking each shard once for all of its pairs, returning the
+    // number of pairs written. `on_set` is called with the (original) index and status of every pair written,
+    // after the shard is released. On error, the error carries [CandyError::BatchInterrupted] as context
+    pub(crate) fn set_many_raw(
+        &self,
+        items: Vec<(Vec<u8>, Vec<u8>)>,
+        mut on_set: impl FnMut(usize, &[u8], &[u8], SetStatus) -> Result<()>,
+    ) -> Result<usize> {
+        let mut items = items
+            .into_iter()
+            .enumerate()
+            .map(|(i, (k, v))| (self.config.hash_key(&k), i, k, v))
+            .collect::<Vec<_>>();
+        // the sort is stable, so repeated keys (which share a shard) are still written in the given order
+        items.sort_by_key(|(ph, _, _, _)| ph.shard_selector());
+
+        let mut written = 0;
+        let mut pos = 0;
+        while pos < items.len() {
+            // write the pairs that belong to this shard, stopping at the first one that requires a split (which
+            // can't be done while the shard is held)
+            let mut statuses = vec![];
+            let group_res = self.root.shared_op(items[pos].0.shard_selector(), |sh| {
+                while let Some((ph, _, full_key, val)) = items.get(pos + statuses.len()) {
+                    if !sh.span.contains(&ph.shard_selector()) {
+                        return Ok(false);
+                    }
+                    self.ensure_entry_fits(full_key, val)?;
+                    #[cfg(feature = "observer")]
+                    let t0 = std::time::Instant::now();
+                    let status = sh.insert(*ph, full_key, val, InsertMode::Set)?;
+                    #[cfg(feature = "observer")]
+                    if let Some(ref observer) = self.config.observer {
+                        observer.on_set(t0.elapsed());
+                    }
+                    if matches!(status, InsertStatus::SplitNeeded) {
+                        return Ok(true);
+                    }
+                    statuses.push(status);
+                }
+                Ok(false)
+            });
+
+            for status in statuses {
+                let (_, i, ref full_key, ref val) = items[pos];
+                pos += 1;
+                if matches!(status, InsertStatus::Added | InsertStatus::Replaced(_)) {
+                    self.record_change(ChangeOp::Set, full_key);
+                }
+                let status = match status {
+                    InsertStatus::Added => SetStatus::CreatedNew,
+                    InsertStatus::Replaced(v) | InsertStatus::AlreadyExists(v) => {
+                        SetStatus::PrevValue(v)
+                    }
+                    InsertStatus::KeyDoesNotExist | InsertStatus::SplitNeeded => unreachable!(),
+                };
+                written += 1;
+                if let Err(e) = on_set(i, full_key, val, status) {
+                    return Err(e.context(CandyError::BatchInterrupted(written)));
+                }
+            }
+
+            let split_needed = match group_res {
+                Ok(split_needed) => split_needed,
+                Err(e) => return Err(e.context(CandyError::BatchInterrupted(written))),
+            };
+            if split_needed {
+                // go through the regular path, which splits the shard before writing the pair
+                let (_, i, ref full_key, ref val) = items[pos];
+                pos += 1;
+                let status = match self.set_raw(full_key, val) {
+                    Ok(status) => status,
+                    Err(e) => return Err(e.context(CandyError::BatchInterrupted(written))),
+                };
+                written += 1;
+                if let Err(e) = on_set(i, full_key, val, status) {
+                    return Err(e.context(CandyError::BatchInterrupted(written)));
+                }
+            }
+        }
+        Ok(written)
+    }
+
     /// Inserts a key-value pair, creating it or replacing an existing pair. Note that if the program crashed
     /// while or "right after" this operation, or if the operating system is unable to flush the page cache,
     /// you may lose some data. However, you will still be in a consistent state, where you will get a previous
@@ -409,7 +1094,10 @@ impl CandyStore {
     ///
     /// While this method is O(1) amortized, every so often it will trigger either a shard compaction or a
     /// shard split, which requires rewriting the whole shard. However, unlike LSM trees, this operation is
-    /// constant in size
+    /// constant in size.
+    ///
+    /// Keys and values can be any `AsRef<[u8]>`, so buffers such as `bytes::Bytes` can be stored as they are,
+    /// without converting them to vectors first
     pub fn set<B1: AsRef<[u8]> + ?Sized, B2: AsRef<[u8]> + ?Sized>(
         &self,
         key: &B1,
@@ -420,7 +1108,7 @@ impl CandyStore {
 
     /// Same as [Self::set], but the key passed owned to this function
     pub fn owned_set(&self, key: Vec<u8>, val: &[u8]) -> Result<SetStatus> {
-        Self::ensure_sizes(&key, &val)?;
+        self.ensure_sizes(&key, &val)?;
         self.set_raw(&self.make_user_key(key), val)
     }
 
@@ -464,7 +1152,7 @@ impl CandyStore {
         val: &[u8],
         expected_val: Option<&[u8]>,
     ) -> Result<ReplaceStatus> {
-        Self::ensure_sizes(&key, &val)?;
+        self.ensure_sizes(&key, &val)?;
         self.replace_raw(&self.make_user_key(key), val, expected_val)
     }
 
@@ -502,10 +1190,58 @@ impl CandyStore {
         key: Vec<u8>,
         default_val: Vec<u8>,
     ) -> Result<GetOrCreateStatus> {
-        Self::ensure_sizes(&key, &default_val)?;
+        self.ensure_sizes(&key, &default_val)?;
         self.get_or_create_raw(&self.make_user_key(key), default_val)
     }
 
+    /// Sets the key to the given value only if it does not exist (like `SETNX`), returning true if the key was
+    /// created by this call, or false if it had already existed (in which case its value is left intact). This
+    /// is atomic, so when called concurrently on the same key, exactly one of the callers wins.
+    ///
+    /// See also [Self::get_or_create]
+    pub fn set_if_absent<B1: AsRef<[u8]> + ?Sized, B2: AsRef<[u8]> + ?Sized>(
+        &self,
+        key: &B1,
+        val: &B2,
+    ) -> Result<bool> {
+        Ok(self
+            .owned_get_or_create(key.as_ref().to_owned(), val.as_ref().to_owned())?
+            .was_created())
+    }
+
+    pub(crate) fn swap_raw(&self, full_key1: &[u8], full_key2: &[u8]) -> Result<()> {
+        if full_key1 == full_key2 {
+            return Ok(());
+        }
+        let ph1 = self.config.hash_key(full_key1);
+        let ph2 = self.config.hash_key(full_key2);
+
+        // both rows are held locked (in a deadlock-safe order) while the values are exchanged, and nothing is
+        // written unless both keys fit, so a shard that's too full is split before retrying
+        loop {
+            let status = self.root.shared_op_pair(
+                ph1.shard_selector(),
+                ph2.shard_selector(),
+                |sh1, sh2| Shard::swap(sh1, ph1, full_key1, sh2, ph2, full_key2),
+            )?;
+            match status {
+                SwapStatus::SplitNeeded(shard_selector) => self.root.split_shard(shard_selector)?,
+                SwapStatus::Swapped(val1, val2) => {
+                    for (full_key, old, new) in
+                        [(full_key1, &val1, &val2), (full_key2, &val2, &val1)]
+                    {
+                        if new.is_some() {
+                            self.record_change(ChangeOp::Set, full_key);
+                        } else if old.is_some() {
+                            self.record_change(ChangeOp::Remove, full_key);
+                        }
+                    }
+                    return Ok(());
+                }
+            }
+        }
+    }
+
     /// Returns an iterator over the whole store (skipping lists or typed items)
     pub fn iter(&self) -> CandyStoreIterator {
         CandyStoreIterator::new(self, false, true)
@@ -523,6 +1259,10 @@ impl CandyStore {
         CandyStoreIterator::new(self, true, true)
     }
 
+    pub(crate) fn iter_raw_keys(&self) -> impl Iterator<Item = Result<Vec<u8>>> + use<'_> {
+        CandyStoreIterator::new(self, true, false).map(|res| res.map(|kv| kv.0))
+    }
+
     /// Returns an iterator starting from the specified cookie (obtained via [CandyStoreIterator::cookie])
     pub fn iter_from_cookie(&self, cookie: u64) -> CandyStoreIterator {
         CandyStoreIterator::from_cookie(self, cookie, false, true)
@@ -556,6 +1296,127 @@ impl CandyStore {
         stats
     }
 
+    /// Compacts all shards that have wasted space (left by removed or overwritten entries), returning how much
+    /// space was reclaimed. Shards are normally compacted only when they are written to and their wasted space
+    /// exceeds [Config::min_compaction_threashold], so this is useful after removing many entries (e.g., a
+    /// batch delete) that would otherwise not be followed by writes.
+    ///
+    /// Shards are compacted one at a time, in the background thread pool, and this function waits for them to
+    /// finish. Other operations may take place concurrently. Note that the shard files themselves only shrink
+    /// if [Config::truncate_up] is disabled; otherwise, their space is reused by future writes
+    pub fn compact(&self) -> Result<CompactionReport> {
+        let mut report = CompactionReport::default();
+        for (before, after) in self
+            .root
+            .call_on_all_shards(|sh| sh.compact())?
+            .into_iter()
+            .flatten()
+        {
+            report.num_compacted_shards += 1;
+            report.bytes_before += before;
+            report.bytes_after += after;
+        }
+        Ok(report)
+    }
+
+    /// Returns the span (range of shard selectors) and number of entries of every shard, ordered by span. Since
+    /// a key's shard is determined by its hash, this can be used to diagnose skew in the key distribution
+    /// (see [Config::key_hasher]). Note that entries include the internal ones (of lists, queues, etc.)
+    pub fn shard_loads(&self) -> Result<Vec<(Range<u32>, usize)>> {
+        self.root
+            .call_on_all_shards(|sh| Ok((sh.span.clone(), sh.get_stats()?.num_items())))
+    }
+
+    /// Same as [Self::shard_loads], but returns the full statistics of every shard (ordered by span), including
+    /// their approximate sizes. This is cheap (it reads the shards' headers) and is meant for diagnosing skew
+    pub fn shard_stats(&self) -> Result<Vec<ShardStat>> {
+        self.root.call_on_all_shards(|sh| {
+            let stats = sh.get_stats()?;
+            Ok(ShardStat {
+                span: sh.span.clone(),
+                num_entries: stats.num_items(),
+                occupied_bytes: stats.write_offset,
+                wasted_bytes: stats.wasted_bytes,
+            })
+        })
+    }
+
+    // returns a selector of every shard (the start of its span)
+    pub(crate) fn shard_selectors(&self) -> Result<Vec<u32>> {
+        self.root.call_on_all_shards(|sh| Ok(sh.span.start))
+    }
+
+    // appends the entries (including internal ones) of the given row, whose keys `filter` accepts, to `out`.
+    // values are only read for accepted entries
+    pub(crate) fn read_row_raw(
+        &self,
+        shard_selector: u32,
+        row_idx: usize,
+        filter: &mut impl FnMut(&[u8]) -> bool,
+        out: &mut Vec<KVPair>,
+    ) -> Result<()> {
+        self.root.shared_op(shard_selector, |sh| {
+            for entry_idx in 0..ROW_WIDTH {
+                let Some((k, _)) = sh.read_at(row_idx, entry_idx, false)? else {
+                    continue;
+                };
+                if !filter(&k) {
+                    continue;
+                }
+                if let Some(kv) = sh.read_at(row_idx, entry_idx, true)? {
+                    out.push(kv);
+                }
+            }
+            Ok(())
+        })
+    }
+
+    // calls `func` with the entries (including internal ones) of the shard that holds `shard_selector`, whose keys
+    // `filter` accepts, one row at a time. the shard is only locked while a row is read, not while `func` runs
+    pub(crate) fn scan_shard_raw(
+        &self,
+        shard_selector: u32,
+        mut filter: impl FnMut(&[u8]) -> bool,
+        mut func: impl FnMut(Vec<KVPair>) -> Result<()>,
+    ) -> Result<()> {
+        for row_idx in 0..NUM_ROWS {
+            let mut entries = vec![];
+            self.read_row_raw(shard_selector, row_idx, &mut filter, &mut entries)?;
+            if !entries.is_empty() {
+                func(entries)?;
+            }
+        }
+        Ok(())
+    }
+
+    // reads the entries (including internal ones) whose keys `filter` accepts, from randomly chosen rows (without
+    // repetition), until at least `min_entries` were collected or all rows were read. shards are chosen uniformly
+    // (rather than by their spans), so all entries are equally likely to be read, as keys are hashed uniformly
+    // into rows
+    pub(crate) fn sample_rows(
+        &self,
+        min_entries: usize,
+        mut filter: impl FnMut(&[u8]) -> bool,
+    ) -> Result<Vec<KVPair>> {
+        let selectors = self.shard_selectors()?;
+        let mut rows = (0..selectors.len() * NUM_ROWS).collect::<Vec<_>>();
+        let mut rng = rand::rng();
+        let mut res = vec![];
+
+        // a lazy Fisher-Yates shuffle of the rows
+        for i in 0..rows.len() {
+            if res.len() >= min_entries {
+                break;
+            }
+            let j = rng.random_range(i..rows.len());
+            rows.swap(i, j);
+            let (shard_idx, row_idx) = (rows[i] / NUM_ROWS, rows[i] % NUM_ROWS);
+            self.read_row_raw(selectors[shard_idx], row_idx, &mut filter, &mut res)?;
+        }
+
+        Ok(res)
+    }
+
     /// Merges small shards (shards with a used capacity of less than `max_fill_level`), `max_fill_level` should
     /// be a number between 0 and 0.5, the reasonable choice is 0.25.
     ///
@@ -563,14 +1424,38 @@ impl CandyStore {
     /// take place while merging is in progress). Only use it if you expect the number of items to be at half or
     /// less than what it was (i.e., after a peak period)
     ///
-    /// Note 2: merging will stop once we reach the number of shards required for [Config::expected_number_of_keys],
-    /// if configured
+    /// Note 2: merging will stop once we reach the number of shards required for [Config::expected_number_of_keys]
+    /// or [Config::initial_num_shards], if configured
     ///
     /// Returns true if any shards were merged, false otherwise
     pub fn merge_small_shards(&self, max_fill_level: f32) -> Result<bool> {
         self.root.merge_small_shards(max_fill_level)
     }
 
+    // the trailing chunk of a complete big item: the item's length followed by a marker, so that it can't be
+    // mistaken for a (short) chunk of data of an incomplete item
+    const BIG_VALUE_MARKER: &'static [u8] = b"\xcaBIGVAL\x01";
+
+    fn big_value_trailer(len: usize) -> Vec<u8> {
+        let mut trailer = (len as u64).to_le_bytes().to_vec();
+        trailer.extend_from_slice(Self::BIG_VALUE_MARKER);
+        trailer
+    }
+
+    // returns the length encoded in the trailing chunk of a big item with `num_chunks` chunks of data (all full,
+    // except the last), or `None` if it's not a valid trailer. Items written by older versions end with the
+    // (native-endian) length alone, and are accepted if their number of chunks matches it
+    fn parse_big_value_trailer(chunk: &[u8], num_chunks: usize) -> Option<usize> {
+        let len = if let Some(len) = chunk.strip_suffix(Self::BIG_VALUE_MARKER) {
+            u64::from_le_bytes(len.try_into().ok()?) as usize
+        } else if chunk.len() == size_of::<usize>() {
+            pod_read_unaligned::<usize>(chunk)
+        } else {
+            return None;
+        };
+        (len.div_ceil(MAX_VALUE_SIZE) == num_chunks).then_some(len)
+    }
+
     /// Sets a big item, whose value is unlimited in size. Behind the scenes the value is split into chunks
     /// and stored as a list. This makes this API non-atomic, i.e., crashing while writing a big value may later
     /// allow you to retrieve a partial result. It is up to the caller to add a length field or a checksum to make
@@ -584,7 +1469,11 @@ impl CandyStore {
     ) -> Result<bool> {
         let existed = self.discard_queue(key)?;
         self.extend_queue(key, val.as_ref().chunks(MAX_VALUE_SIZE))?;
-        self.push_to_queue_tail(key, bytes_of(&val.as_ref().len()))?;
+        self.push_to_queue_tail(key, &Self::big_value_trailer(val.as_ref().len()))?;
+        #[cfg(feature = "observer")]
+        if let Some(ref observer) = self.config.observer {
+            observer.on_big_value(val.as_ref().len());
+        }
         Ok(existed)
     }
 
@@ -598,7 +1487,7 @@ impl CandyStore {
             // last element should encode the byte length of the item - if it's missing or encodes a different length,
             // consider it corrupt and ignore this element
             if idx + 1 == range.end {
-                if chunk.len() == size_of::<usize>() && *from_bytes::<usize>(&chunk) == val.len() {
+                if Self::parse_big_value_trailer(&chunk, idx - range.start) == Some(val.len()) {
                     return Ok(Some(val));
                 }
             } else {
@@ -608,11 +1497,185 @@ impl CandyStore {
         Ok(None)
     }
 
+    /// Tests if a big item (see [Self::set_big]) exists, without reading its chunks: only the trailing length
+    /// chunk is read, so this is cheap regardless of the item's size. Returns false for keys stored as normal
+    /// values (e.g., by [Self::set]), and for incomplete big items (see [Self::get_big_stream])
+    pub fn contains_big<B: AsRef<[u8]> + ?Sized>(&self, key: &B) -> Result<bool> {
+        Ok(self.peek_big_len(key.as_ref())?.is_some())
+    }
+
+    // returns the index of the trailing length chunk of a big item, and the length it encodes. The last
+    // element should be the trailer written once the item is complete - if it's missing, the item is incomplete
+    fn peek_big_len(&self, key: &[u8]) -> Result<Option<(usize, usize)>> {
+        let range = self.queue_range(key)?;
+        let Some((idx, chunk)) = self.peek_queue_tail_with_idx(key)? else {
+            return Ok(None);
+        };
+        if idx + 1 != range.end {
+            return Ok(None);
+        }
+        Ok(Self::parse_big_value_trailer(&chunk, idx - range.start).map(|len| (idx, len)))
+    }
+
     /// Removes a big item by key. Returns true if the key had existed, false otherwise.
     /// See also [Self::set_big]
     pub fn remove_big(&self, key: &[u8]) -> Result<bool> {
         self.discard_queue(key)
     }
+
+    /// Same as [Self::set_big], but returns a writer (implementing [std::io::Write]) that stores the value
+    /// incrementally, so it never needs to be held in memory as a whole. The previous value (if any) is
+    /// discarded right away, and the new value becomes visible (to [Self::get_big]) only once
+    /// [BigValueWriter::finish] is called. Dropping the writer without finishing removes the chunks written so
+    /// far, leaving the key without a value.
+    pub fn set_big_stream<B: AsRef<[u8]> + ?Sized>(&self, key: &B) -> Result<BigValueWriter<'_>> {
+        self.discard_queue(key)?;
+        Ok(BigValueWriter {
+            store: self,
+            key: key.as_ref().to_owned(),
+            buf: Vec::with_capacity(MAX_VALUE_SIZE),
+            len: 0,
+            finished: false,
+        })
+    }
+
+    /// Same as [Self::get_big], but returns a reader (implementing [std::io::Read]) that fetches the underlying
+    /// chunks one at a time. Returns `None` if the key does not exist or if its value is incomplete
+    pub fn get_big_stream<B: AsRef<[u8]> + ?Sized>(
+        &self,
+        key: &B,
+    ) -> Result<Option<BigValueReader<'_>>> {
+        let key = key.as_ref();
+        let Some((idx, len)) = self.peek_big_len(key)? else {
+            return Ok(None);
+        };
+        Ok(Some(BigValueReader {
+            chunks: self.iter_queue(key),
+            len_idx: idx,
+            len,
+            chunk: vec![],
+            chunk_offset: 0,
+            bytes_read: 0,
+        }))
+    }
+}
+
+/// A writer of a big value, returned by [CandyStore::set_big_stream]. Data is buffered up to a single chunk and
+/// written to the store as chunks fill up (flushing does not write partial chunks). Call [Self::finish] once
+/// done writing.
+pub struct BigValueWriter<'a> {
+    store: &'a CandyStore,
+    key: Vec<u8>,
+    buf: Vec<u8>,
+    len: usize,
+    finished: bool,
+}
+
+impl BigValueWriter<'_> {
+    fn push_chunk(&mut self) -> Result<()> {
+        if !self.buf.is_empty() {
+            self.store.push_to_queue_tail(&self.key, &self.buf)?;
+            self.buf.clear();
+        }
+        Ok(())
+    }
+
+    /// Writes the remaining buffered data and the length of the value, making the value visible. Returns the
+    /// total length of the value
+    pub fn finish(mut self) -> Result<usize> {
+        self.push_chunk()?;
+        self.store
+            .push_to_queue_tail(&self.key, &CandyStore::big_value_trailer(self.len))?;
+        self.finished = true;
+        #[cfg(feature = "observer")]
+        if let Some(ref observer) = self.store.config.observer {
+            observer.on_big_value(self.len);
+        }
+        Ok(self.len)
+    }
+}
+
+impl std::io::Write for BigValueWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = buf.len().min(MAX_VALUE_SIZE - self.buf.len());
+        self.buf.extend_from_slice(&buf[..n]);
+        self.len += n;
+        if self.buf.len() == MAX_VALUE_SIZE {
+            self.push_chunk().map_err(std::io::Error::other)?;
+        }
+        Ok(n)
+    }
+
+    // only full chunks are written before finishing, so that the chunks of an incomplete value always add up
+    // to a multiple of the chunk size
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for BigValueWriter<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            // errors can't be reported here; the chunks are left behind, but the value remains incomplete
+            let _ = self.store.discard_queue(&self.key);
+        }
+    }
+}
+
+/// A reader of a big value, returned by [CandyStore::get_big_stream]. Fails with [std::io::ErrorKind::InvalidData]
+/// if the underlying chunks do not add up to the expected length (e.g., they were modified while reading)
+pub struct BigValueReader<'a> {
+    chunks: QueueIterator<'a>,
+    len_idx: usize,
+    len: usize,
+    chunk: Vec<u8>,
+    chunk_offset: usize,
+    bytes_read: usize,
+}
+
+impl BigValueReader<'_> {
+    /// The total length of the value
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the value is empty (zero-length)
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl std::io::Read for BigValueReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.chunk_offset >= self.chunk.len() {
+            let next = match self.chunks.next() {
+                Some(Ok((idx, _))) if idx >= self.len_idx => None,
+                Some(Ok((_, chunk))) => Some(chunk),
+                Some(Err(e)) => return Err(std::io::Error::other(e)),
+                None => None,
+            };
+            let Some(chunk) = next else {
+                if self.bytes_read != self.len {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!(
+                            "big value has {} bytes, expected {}",
+                            self.bytes_read, self.len
+                        ),
+                    ));
+                }
+                return Ok(0);
+            };
+            self.chunk = chunk;
+            self.chunk_offset = 0;
+        }
+
+        let n = buf.len().min(self.chunk.len() - self.chunk_offset);
+        buf[..n].copy_from_slice(&self.chunk[self.chunk_offset..self.chunk_offset + n]);
+        self.chunk_offset += n;
+        self.bytes_read += n;
+        Ok(n)
+    }
 }
 
 // impl Drop for CandyStore {
@@ -620,3 +1683,13 @@ impl CandyStore {
 //         _ = self.threadpool.terminate();
 //     }
 // }
+
+impl Drop for CandyStore {
+    fn drop(&mut self) {
+        for th in self.maintenance_threads.get_mut().drain(..) {
+            if let Some(th) = th.upgrade() {
+                th.stop_and_join();
+            }
+        }
+    }
+}
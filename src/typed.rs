@@ -1,15 +1,72 @@
-use anyhow::anyhow;
-use bytemuck::bytes_of;
-use std::{borrow::Borrow, marker::PhantomData, ops::Range, sync::Arc};
+use anyhow::{anyhow, bail};
+use bytemuck::{bytes_of, Pod, Zeroable};
+use parking_lot::Mutex;
+use siphasher::sip128::SipHasher24;
+use std::{
+    borrow::{Borrow, Cow},
+    cmp::Ordering,
+    collections::{hash_map::Entry, BTreeMap, HashMap},
+    hash::Hash,
+    marker::PhantomData,
+    ops::{Range, RangeBounds},
+    panic::AssertUnwindSafe,
+    sync::{
+        atomic::{self, AtomicBool, AtomicUsize},
+        Arc, LazyLock,
+    },
+};
 
 use crate::{
-    store::{ReplaceStatus, SetStatus, TYPED_NAMESPACE},
-    CandyStore, ListCompactionParams,
+    store::{
+        CandyStoreIterator, ReplaceStatus, SetStatus, TYPED_FIXED_NAMESPACE, TYPED_INDEX_NAMESPACE,
+        TYPED_NAMESPACE, TYPED_NS_NAMESPACE, TYPED_PRIORITY_NAMESPACE,
+    },
+    BigValueReader, BigValueWriter, CandyError, CandyStore, EntrySeq, GetOrCreateStatus, ListBatch,
+    ListCompactionParams, ListCompactionStats, ListCursor, ListDebugInfo, ListPage, QueueHealth,
+    QueueSnapshot, QueueState, StoreCursor, WriteBatch, MAX_VALUE_SIZE,
 };
 
+#[cfg(feature = "rayon")]
+use crate::shard::NUM_ROWS;
 use crate::Result;
 use databuf::{config::num::LE, DecodeOwned, Encode};
+use rand::seq::SliceRandom;
 
+/// The trait of keys of the typed wrappers (see [CandyTypedStore]). Besides the builtin types and tuples
+/// of them, it can be implemented for structs that implement the `databuf` traits:
+///
+/// ```
+/// use candystore::databuf::{Decode, Encode};
+/// use candystore::CandyTypedKey;
+///
+/// #[derive(Encode, Decode)]
+/// struct MyKey {
+///     x: u32,
+///     y: String,
+/// }
+///
+/// impl CandyTypedKey for MyKey {
+///     const TYPE_ID: u32 = 0x3476a551;
+/// }
+/// ```
+///
+/// or by deriving it, giving the `TYPE_ID` as an attribute. Fieldless (C-like) enums need nothing else, as
+/// the derive also implements `Encode` and `Decode` for them, encoding the variant's discriminant (so
+/// adding variants is safe, but changing the discriminants of existing ones is not):
+///
+/// ```
+/// use candystore::CandyTypedKey;
+///
+/// #[derive(CandyTypedKey)]
+/// #[candy(type_id = 0x8e5d_2c41)]
+/// enum Color {
+///     Red,
+///     Green,
+///     Blue = 7,
+/// }
+/// ```
+///
+/// while the rest (structs and enums with fields) must derive the `databuf` traits themselves
 pub trait CandyTypedKey: Encode + DecodeOwned {
     /// a random number that remains consistent (unlike [std::any::TypeId]), so that `MyPair(u32, u32)`
     /// is different from `YourPair(u32, u32)`
@@ -42,8 +99,203 @@ typed_builtin!(String, 15);
 typed_builtin!(Vec<u8>, 16);
 typed_builtin!(uuid::Bytes, 17);
 
+// mixes the element TYPE_IDs (and the arity) with FNV-1a, so that `(u32, u64)`, `(u64, u32)` and `(u32, u64, u8)`
+// all get different TYPE_IDs
+const fn combine_type_ids(ids: &[u32]) -> u32 {
+    let mut h: u32 = 0x811c_9dc5;
+    let mut i = 0;
+    while i < ids.len() {
+        let bytes = ids[i].to_le_bytes();
+        let mut j = 0;
+        while j < bytes.len() {
+            h ^= bytes[j] as u32;
+            h = h.wrapping_mul(0x0100_0193);
+            j += 1;
+        }
+        i += 1;
+    }
+    h ^ (ids.len() as u32)
+}
+
+// tuples are encoded as the concatenation of their elements' encodings
+macro_rules! typed_tuple {
+    ($($t:ident),+) => {
+        impl<$($t: CandyTypedKey),+> CandyTypedKey for ($($t,)+) {
+            const TYPE_ID: u32 = combine_type_ids(&[$($t::TYPE_ID),+]);
+        }
+    };
+}
+
+typed_tuple!(A);
+typed_tuple!(A, B);
+typed_tuple!(A, B, C);
+typed_tuple!(A, B, C, D);
+typed_tuple!(A, B, C, D, E);
+typed_tuple!(A, B, C, D, E, F);
+
+// maps the name of each value type (which is only used as a lookup key within the process, never persisted) to
+// the tag registered for it, and each tag back to its type's name. The builtin key types are registered with
+// their TYPE_IDs
+type ValueTagRegistry = (HashMap<&'static str, u32>, HashMap<u32, &'static str>);
+
+static VALUE_TAG_REGISTRY: LazyLock<Mutex<ValueTagRegistry>> = LazyLock::new(|| {
+    fn builtin<T: CandyTypedKey>(registry: &mut ValueTagRegistry) {
+        let name = std::any::type_name::<T>();
+        registry.0.insert(name, T::TYPE_ID);
+        registry.1.insert(T::TYPE_ID, name);
+    }
+    let mut registry = (HashMap::new(), HashMap::new());
+    builtin::<u8>(&mut registry);
+    builtin::<u16>(&mut registry);
+    builtin::<u32>(&mut registry);
+    builtin::<u64>(&mut registry);
+    builtin::<u128>(&mut registry);
+    builtin::<i8>(&mut registry);
+    builtin::<i16>(&mut registry);
+    builtin::<i32>(&mut registry);
+    builtin::<i64>(&mut registry);
+    builtin::<i128>(&mut registry);
+    builtin::<bool>(&mut registry);
+    builtin::<usize>(&mut registry);
+    builtin::<isize>(&mut registry);
+    builtin::<char>(&mut registry);
+    builtin::<String>(&mut registry);
+    builtin::<Vec<u8>>(&mut registry);
+    builtin::<uuid::Bytes>(&mut registry);
+    Mutex::new(registry)
+});
+
+/// Registers the tag that's stored with every value of type `V` when [crate::Config::typed_value_tags] is
+/// enabled, which must be done before values of `V` are written or read. The tag is also part of the keys of
+/// [CandyFixedStore], which requires it regardless of the config. Like [CandyTypedKey::TYPE_ID], the
+/// tag is persisted, so it must remain the same across builds and versions of the program, and every value
+/// type needs a distinct tag. The builtin key types (integers, `String`, `Vec<u8>`, etc.) come registered
+/// with their TYPE_IDs, and a type that implements [CandyTypedKey] can be registered with its `TYPE_ID`.
+///
+/// Registering a type again with the same tag does nothing. This fails with [CandyError::ValueTagCollision]
+/// if the tag is already registered for a different type, and with [CandyError::AlreadyExists] if the type
+/// is already registered with a different tag
+pub fn register_value_tag<V: ?Sized>(tag: u32) -> Result<()> {
+    let name = std::any::type_name::<V>();
+    let mut guard = VALUE_TAG_REGISTRY.lock();
+    let (tags, names) = &mut *guard;
+    if let Some(&other) = names.get(&tag) {
+        if other != name {
+            bail!(CandyError::ValueTagCollision(tag, other, name));
+        }
+    }
+    match tags.entry(name) {
+        Entry::Vacant(e) => {
+            e.insert(tag);
+            names.insert(tag, name);
+        }
+        Entry::Occupied(e) => {
+            if *e.get() != tag {
+                bail!(CandyError::AlreadyExists(
+                    "a different value tag for this type"
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+// the tag stored with each typed value when Config::typed_value_tags is enabled (see register_value_tag)
+fn value_type_tag<V: ?Sized>() -> Result<u32> {
+    let name = std::any::type_name::<V>();
+    VALUE_TAG_REGISTRY
+        .lock()
+        .0
+        .get(name)
+        .copied()
+        .ok_or_else(|| anyhow!(CandyError::ValueTagNotRegistered(name)))
+}
+
+// maps each TYPE_ID to the name of the first type that used it
+static TYPE_ID_REGISTRY: LazyLock<Mutex<HashMap<u32, &'static str>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn register_type_id<K: CandyTypedKey>() -> Result<()> {
+    let name = std::any::type_name::<K>();
+    match TYPE_ID_REGISTRY.lock().entry(K::TYPE_ID) {
+        Entry::Vacant(e) => {
+            e.insert(name);
+        }
+        Entry::Occupied(e) => {
+            if *e.get() != name {
+                return Err(anyhow!(CandyError::TypeIdCollision(
+                    K::TYPE_ID,
+                    e.get(),
+                    name
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+// only debug builds pay for the registry in the infallible constructors
+fn debug_register_type_id<K: CandyTypedKey>() {
+    if cfg!(debug_assertions) {
+        if let Err(e) = register_type_id::<K>() {
+            panic!("{e}");
+        }
+    }
+}
+
+// the first byte of entries stored by CandyTypedStore::set_auto
+const AUTO_TAG_INLINE: u8 = 0;
+const AUTO_TAG_BIG: u8 = 1;
+
+// the first byte of values compressed with Config::value_codec. It's followed by the compressed value and the
+// checksum of the uncompressed one, so an uncompressed value that happens to start with this byte will (with
+// overwhelming probability) fail to decompress or to match the checksum, and is then read as it is
+const COMPRESSED_TAG: u8 = 0xc7;
+const COMPRESSED_OVERHEAD: usize = 1 + size_of::<u64>();
+
+fn compressed_checksum(vbytes: &[u8]) -> u64 {
+    SipHasher24::new().hash(vbytes).h1
+}
+
+// decodes a `T` from the reader, turning panics into errors, so that corrupt (or malicious) data is reported
+// as [CandyError::DecodeFailed] instead of bringing down the reader. Panics may come from custom `Decode`
+// impls, or from collections whose corrupt length overflows their capacity. Note that databuf preallocates
+// collections according to their encoded length (up to 2^30 elements), so a corrupt length may still cause a
+// large (short-lived) allocation before decoding fails
+fn decode_guarded<T: DecodeOwned>(reader: &mut &[u8]) -> Result<T> {
+    match std::panic::catch_unwind(AssertUnwindSafe(|| T::decode::<LE>(reader))) {
+        Ok(res) => res.map_err(|e| anyhow!(CandyError::DecodeFailed(e.to_string()))),
+        Err(payload) => {
+            let reason = if let Some(s) = payload.downcast_ref::<&str>() {
+                s.to_string()
+            } else if let Some(s) = payload.downcast_ref::<String>() {
+                s.clone()
+            } else {
+                "unknown".into()
+            };
+            Err(anyhow!(CandyError::DecodeFailed(format!(
+                "decoding panicked: {reason}"
+            ))))
+        }
+    }
+}
+
 fn from_bytes<T: DecodeOwned>(bytes: &[u8]) -> Result<T> {
-    T::from_bytes::<LE>(bytes).map_err(|e| anyhow!(e))
+    let mut reader = bytes;
+    decode_guarded(&mut reader)
+}
+
+// like from_bytes, but also fails if the value has trailing bytes (e.g., it was encoded by another type)
+fn from_bytes_exact<T: DecodeOwned>(bytes: &[u8]) -> Result<T> {
+    let mut reader = bytes;
+    let val = decode_guarded::<T>(&mut reader)?;
+    if !reader.is_empty() {
+        return Err(anyhow!(CandyError::DecodeFailed(format!(
+            "{} trailing bytes",
+            reader.len()
+        ))));
+    }
+    Ok(val)
 }
 
 /// Typed stores are wrappers around an underlying [CandyStore], that serialize keys and values (using [databuf]).
@@ -56,704 +308,3637 @@ fn from_bytes<T: DecodeOwned>(bytes: &[u8]) -> Result<T> {
 /// * All APIs take keys and values by-ref, because they will serialize them, so taking owned values doesn't
 ///   make sense
 /// * [CandyStore::iter] will skip typed items, since it's meaningless to interpret them without the wrapper
+/// * Empty keys (e.g., an empty `String` or `Vec<u8>`) are valid keys like any other, and since the key's
+///   `TYPE_ID` is part of the stored key, empty keys of different types are distinct
+/// * Keys and values are taken as `&Q` where `K: Borrow<Q>` (or `V: Borrow<Q>`), so a `String` key or value
+///   can be passed as a plain `&str` (and a `Vec<T>` as a `&[T]`), which encode exactly like their owned
+///   counterparts. A `Cow<str>` can be passed as `&*cow`
+/// * Wrappers are `Send + Sync` (as long as `K` and `V` are) and cheap to clone, and any number of them may be
+///   used concurrently over the same store: each operation is as atomic as the underlying [CandyStore]
+///   operation. Wrappers whose keys have different `TYPE_ID`s (or live in different namespaces) never see each
+///   other's entries, while wrappers with the same `K` (and namespace) share their entries, exactly like
+///   clones of a single wrapper do. See also [CandyStore::typed]
+/// * Entries that fail to decode (e.g., because they were written by another type, or are corrupt) are
+///   reported as [CandyError::DecodeFailed] by all typed wrappers, and never panic, even if the `Decode` impl
+///   does. Note that a corrupt collection length may still make the decoder allocate a large buffer up front
+///
+/// Example:
+/// ```
+/// use std::sync::Arc;
+/// use candystore::{CandyStore, CandyTypedStore, Config, Result};
+///
+/// fn main() -> Result<()> {
+///     let db = Arc::new(CandyStore::open("/tmp/candy-typed-dir", Config::default())?);
+///     let typed = CandyTypedStore::<String, String>::new(db);
+///
+///     typed.set("hello", "world")?;
+///     assert_eq!(typed.get("hello")?, Some("world".to_owned()));
+///     assert_eq!(typed.get_or_create("hello", "ignored")?, "world");
+///     assert_eq!(typed.replace("hello", "mundo", Some("world"))?, Some("world".to_owned()));
+///     assert_eq!(typed.remove("hello")?, Some("mundo".to_owned()));
+///     Ok(())
+/// }
+/// ```
 pub struct CandyTypedStore<K, V> {
     store: Arc<CandyStore>,
+    // appended to every encoded key: the namespace (if any), `K::TYPE_ID` and the typed namespace byte
+    key_suffix: Arc<[u8]>,
+    on_change: Option<OnChangeFn<K, V>>,
+    cache: Option<Arc<ReadCache<V>>>,
+    normalizer: Option<KeyNormalizerFn<K>>,
     _phantom: PhantomData<(K, V)>,
 }
 
-impl<K, V> Clone for CandyTypedStore<K, V> {
-    fn clone(&self) -> Self {
+/// A change made to an entry of a [CandyTypedStore], as passed to the callback registered by
+/// [CandyTypedStore::with_on_change]. `old` is `None` if the entry was created, and `new` is `None`
+/// if it was removed
+pub struct ChangeEvent<'a, K, V> {
+    pub key: &'a K,
+    pub old: Option<&'a V>,
+    pub new: Option<&'a V>,
+}
+
+type OnChangeFn<K, V> = Arc<dyn Fn(ChangeEvent<K, V>) + Send + Sync>;
+type KeyNormalizerFn<K> = Arc<dyn Fn(&K) -> K + Send + Sync>;
+
+struct ReadCacheInner<V> {
+    // full key -> (value, last-use tick)
+    entries: HashMap<Vec<u8>, (Arc<V>, u64)>,
+    // last-use tick -> full key, oldest first
+    lru: BTreeMap<u64, Vec<u8>>,
+    tick: u64,
+    // bumped on every invalidation, so that a value read before an invalidation won't be inserted after it
+    generation: u64,
+    // the store's clear epoch the entries belong to (see [CandyStore::clear])
+    clear_epoch: u64,
+}
+
+// the LRU cache of decoded values used by [CandyTypedStore::get_cached]
+struct ReadCache<V> {
+    capacity: usize,
+    inner: Mutex<ReadCacheInner<V>>,
+}
+
+impl<V> ReadCache<V> {
+    fn new(capacity: usize) -> Self {
         Self {
-            store: self.store.clone(),
-            _phantom: Default::default(),
+            capacity,
+            inner: Mutex::new(ReadCacheInner {
+                entries: HashMap::new(),
+                lru: BTreeMap::new(),
+                tick: 0,
+                generation: 0,
+                clear_epoch: 0,
+            }),
+        }
+    }
+
+    // returns the cached value (marking it as recently used), or the current generation on a miss. all entries
+    // are dropped if the store was cleared since they were cached
+    fn get(&self, kbytes: &[u8], clear_epoch: u64) -> std::result::Result<Arc<V>, u64> {
+        let mut guard = self.inner.lock();
+        let inner = &mut *guard;
+        if inner.clear_epoch != clear_epoch {
+            inner.clear_epoch = clear_epoch;
+            inner.generation += 1;
+            inner.entries.clear();
+            inner.lru.clear();
+        }
+        inner.tick += 1;
+        let Some((val, tick)) = inner.entries.get_mut(kbytes) else {
+            return Err(inner.generation);
+        };
+        let key = inner.lru.remove(tick).unwrap_or_default();
+        *tick = inner.tick;
+        inner.lru.insert(inner.tick, key);
+        Ok(val.clone())
+    }
+
+    fn insert(&self, kbytes: Vec<u8>, val: Arc<V>, generation: u64) {
+        let mut guard = self.inner.lock();
+        let inner = &mut *guard;
+        if inner.generation != generation {
+            return;
+        }
+        inner.tick += 1;
+        if let Some((_, tick)) = inner.entries.insert(kbytes.clone(), (val, inner.tick)) {
+            inner.lru.remove(&tick);
+        }
+        inner.lru.insert(inner.tick, kbytes);
+        while inner.entries.len() > self.capacity {
+            let Some((_, oldest)) = inner.lru.pop_first() else {
+                break;
+            };
+            inner.entries.remove(&oldest);
         }
     }
+
+    fn invalidate(&self, kbytes: &[u8]) {
+        let mut inner = self.inner.lock();
+        inner.generation += 1;
+        if let Some((_, tick)) = inner.entries.remove(kbytes) {
+            inner.lru.remove(&tick);
+        }
+    }
+
+    fn invalidate_all(&self) {
+        let mut inner = self.inner.lock();
+        inner.generation += 1;
+        inner.entries.clear();
+        inner.lru.clear();
+    }
 }
 
-impl<K, V> CandyTypedStore<K, V>
-where
-    K: CandyTypedKey,
-    V: Encode + DecodeOwned,
-{
-    /// Constructs a typed wrapper over a CandyStore
-    pub fn new(store: Arc<CandyStore>) -> Self {
+impl<K, V> Clone for CandyTypedStore<K, V> {
+    fn clone(&self) -> Self {
         Self {
-            store,
+            store: self.store.clone(),
+            key_suffix: self.key_suffix.clone(),
+            on_change: self.on_change.clone(),
+            cache: self.cache.clone(),
+            normalizer: self.normalizer.clone(),
             _phantom: Default::default(),
         }
     }
+}
 
-    fn make_key<Q: ?Sized + Encode>(key: &Q) -> Vec<u8>
+impl CandyStore {
+    /// Returns a [CandyTypedStore] over this store (sharing the same `Arc`). This is the same as
+    /// [CandyTypedStore::new], and can be called any number of times, from any thread - all typed stores over
+    /// the same store can be used concurrently
+    pub fn typed<K, V>(self: &Arc<Self>) -> CandyTypedStore<K, V>
     where
-        K: Borrow<Q>,
+        K: CandyTypedKey,
+        V: Encode + DecodeOwned,
     {
-        let mut kbytes = key.to_bytes::<LE>();
-        kbytes.extend_from_slice(bytes_of(&K::TYPE_ID));
-        kbytes.extend_from_slice(TYPED_NAMESPACE);
-        kbytes
+        CandyTypedStore::new(self.clone())
     }
+}
 
-    /// Same as [CandyStore::contains] but serializes the key
-    pub fn contains<Q: ?Sized + Encode>(&self, key: &Q) -> Result<bool>
-    where
-        K: Borrow<Q>,
-    {
-        Ok(self.store.get_raw(&Self::make_key(key))?.is_some())
+// the layout of typed values (compressed if configured, followed by the value type tag and sequence number, if
+// enabled), shared by the stores that keep their values in it
+trait TypedValues<V: Encode + DecodeOwned> {
+    fn raw_store(&self) -> &CandyStore;
+
+    fn has_seq(&self) -> bool {
+        self.raw_store().config.typed_entry_seq != EntrySeq::Disabled
     }
 
-    /// Same as [CandyStore::get] but serializes the key and deserializes the value
-    pub fn get<Q: ?Sized + Encode>(&self, key: &Q) -> Result<Option<V>>
-    where
-        K: Borrow<Q>,
-    {
-        let kbytes = Self::make_key(key);
-        if let Some(vbytes) = self.store.get_raw(&kbytes)? {
-            Ok(Some(from_bytes::<V>(&vbytes)?))
-        } else {
-            Ok(None)
+    fn has_tag(&self) -> bool {
+        self.raw_store().config.typed_value_tags
+    }
+
+    // splits a stored value into the encoded value, its value type tag and its sequence number (each
+    // only if enabled). The layout is `value ++ tag ++ seq`
+    fn split_trailer<'b>(&self, vbytes: &'b [u8]) -> Result<(&'b [u8], Option<u32>, Option<u64>)> {
+        let (mut vbytes, mut tag, mut seq) = (vbytes, None, None);
+        if self.has_seq() {
+            let Some(offset) = vbytes.len().checked_sub(size_of::<u64>()) else {
+                return Err(anyhow!(CandyError::Corruption(
+                    "value is too short to hold a sequence number".into()
+                )));
+            };
+            seq = Some(u64::from_le_bytes(vbytes[offset..].try_into().unwrap()));
+            vbytes = &vbytes[..offset];
         }
+        if self.has_tag() {
+            let Some(offset) = vbytes.len().checked_sub(size_of::<u32>()) else {
+                return Err(anyhow!(CandyError::Corruption(
+                    "value is too short to hold a type tag".into()
+                )));
+            };
+            tag = Some(u32::from_le_bytes(vbytes[offset..].try_into().unwrap()));
+            vbytes = &vbytes[..offset];
+        }
+        Ok((vbytes, tag, seq))
     }
 
-    /// Same as [CandyStore::replace] but serializes the key and the value
-    pub fn replace<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
-        &self,
-        key: &Q1,
-        val: &Q2,
-        expected_val: Option<&Q2>,
-    ) -> Result<Option<V>>
-    where
-        K: Borrow<Q1>,
-        V: Borrow<Q2>,
-    {
-        let kbytes = Self::make_key(key);
-        let vbytes = val.to_bytes::<LE>();
-        let ebytes = expected_val.map(|ev| ev.to_bytes::<LE>()).unwrap_or(vec![]);
-        match self
-            .store
-            .replace_raw(&kbytes, &vbytes, expected_val.map(|_| &*ebytes))?
-        {
-            ReplaceStatus::DoesNotExist => Ok(None),
-            ReplaceStatus::PrevValue(v) => Ok(Some(from_bytes::<V>(&v)?)),
-            ReplaceStatus::WrongValue(_) => Ok(None),
+    // splits a stored value into the encoded value and its sequence number (if enabled), verifying that
+    // it was stored as a `V` (if value tags are enabled)
+    fn split_seq<'b>(&self, vbytes: &'b [u8]) -> Result<(&'b [u8], Option<u64>)> {
+        let (vbytes, tag, seq) = self.split_trailer(vbytes)?;
+        if let Some(found) = tag {
+            let expected = value_type_tag::<V>()?;
+            if found != expected {
+                bail!(CandyError::WrongValueType { expected, found });
+            }
         }
+        Ok((vbytes, seq))
     }
 
-    /// Same as [CandyStore::set] but serializes the key and the value.
-    pub fn set<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
-        &self,
-        key: &Q1,
-        val: &Q2,
-    ) -> Result<Option<V>>
-    where
-        K: Borrow<Q1>,
-        V: Borrow<Q2>,
-    {
-        let kbytes = Self::make_key(key);
-        let vbytes = val.to_bytes::<LE>();
-        match self.store.set_raw(&kbytes, &vbytes)? {
-            SetStatus::CreatedNew => Ok(None),
-            SetStatus::PrevValue(v) => Ok(Some(from_bytes::<V>(&v)?)),
+    // appends the value type tag of `T` (if enabled)
+    fn append_tag<T: ?Sized>(&self, vbytes: &mut Vec<u8>) -> Result<()> {
+        if self.has_tag() {
+            vbytes.extend_from_slice(&value_type_tag::<T>()?.to_le_bytes());
         }
+        Ok(())
     }
 
-    /// Same as [CandyStore::get_or_create] but serializes the key and the default value
-    pub fn get_or_create<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
-        &self,
-        key: &Q1,
-        default_val: &Q2,
-    ) -> Result<V>
-    where
-        K: Borrow<Q1>,
-        V: Borrow<Q2>,
-    {
-        let kbytes = Self::make_key(key);
-        Ok(from_bytes::<V>(
-            &self
-                .store
-                .get_or_create_raw(&kbytes, default_val.to_bytes::<LE>())?
-                .value(),
-        )?)
+    fn compresses(&self) -> bool {
+        self.raw_store().config.value_codec.is_some()
     }
 
-    /// Same as [CandyStore::remove] but serializes the key
-    pub fn remove<Q: ?Sized + Encode>(&self, k: &Q) -> Result<Option<V>>
-    where
-        K: Borrow<Q>,
-    {
-        let kbytes = Self::make_key(k);
-        if let Some(vbytes) = self.store.remove_raw(&kbytes)? {
-            Ok(Some(from_bytes::<V>(&vbytes)?))
-        } else {
-            Ok(None)
+    // compresses the encoded value (if a codec is configured, it's large enough and it actually shrinks)
+    fn compress_val(&self, vbytes: Vec<u8>) -> Vec<u8> {
+        let Some(codec) = self.raw_store().config.value_codec else {
+            return vbytes;
+        };
+        if vbytes.len() < self.raw_store().config.value_compression_threshold {
+            return vbytes;
+        }
+        let compressed = (codec.compress)(&vbytes);
+        if compressed.len() + COMPRESSED_OVERHEAD >= vbytes.len() {
+            return vbytes;
         }
+        let mut framed = Vec::with_capacity(compressed.len() + COMPRESSED_OVERHEAD);
+        framed.push(COMPRESSED_TAG);
+        framed.extend_from_slice(&compressed);
+        framed.extend_from_slice(&compressed_checksum(&vbytes).to_le_bytes());
+        framed
     }
 
-    /// Same as [CandyStore::get_big] but serializes the key and deserializes the value
-    pub fn get_big<Q: ?Sized + Encode>(&self, key: &Q) -> Result<Option<V>>
-    where
-        K: Borrow<Q>,
-    {
-        let kbytes = Self::make_key(key);
-        if let Some(vbytes) = self.store.get_big(&kbytes)? {
-            Ok(Some(from_bytes::<V>(&vbytes)?))
-        } else {
-            Ok(None)
+    // the inverse of compress_val: returns the encoded value, whether or not it was stored compressed
+    fn decompress_val<'b>(&self, vbytes: &'b [u8]) -> Cow<'b, [u8]> {
+        let Some(codec) = self.raw_store().config.value_codec else {
+            return Cow::Borrowed(vbytes);
+        };
+        if vbytes.first() != Some(&COMPRESSED_TAG) || vbytes.len() < COMPRESSED_OVERHEAD {
+            return Cow::Borrowed(vbytes);
+        }
+        let (compressed, checksum) = vbytes[1..].split_at(vbytes.len() - COMPRESSED_OVERHEAD);
+        match (codec.decompress)(compressed) {
+            Ok(decompressed)
+                if compressed_checksum(&decompressed).to_le_bytes()[..] == *checksum =>
+            {
+                Cow::Owned(decompressed)
+            }
+            _ => Cow::Borrowed(vbytes),
         }
     }
 
-    /// Same as [CandyStore::set_big] but serializes the key and the value.
-    pub fn set_big<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
-        &self,
-        key: &Q1,
-        val: &Q2,
-    ) -> Result<bool>
-    where
-        K: Borrow<Q1>,
-        V: Borrow<Q2>,
-    {
-        let kbytes = Self::make_key(key);
-        let vbytes = val.to_bytes::<LE>();
-        self.store.set_big(&kbytes, &vbytes)
+    // encodes the value as a `T`: compressed (if needed), size-checked and followed by the value type tag of `T`
+    // (if enabled). The sequence number is left to the caller
+    fn encode_val_as<T: ?Sized, Q: ?Sized + Encode>(&self, val: &Q) -> Result<Vec<u8>> {
+        let mut vbytes = self.compress_val(val.to_bytes::<LE>());
+        self.raw_store().ensure_inline_value_size(vbytes.len())?;
+        self.append_tag::<T>(&mut vbytes)?;
+        Ok(vbytes)
     }
 
-    /// Same as [CandyStore::remove_big] but serializes the key
-    pub fn remove_big<Q: ?Sized + Encode>(&self, k: &Q) -> Result<bool>
-    where
-        K: Borrow<Q>,
-    {
-        let kbytes = Self::make_key(k);
-        self.store.remove_big(&kbytes)
+    fn encode_val<Q: ?Sized + Encode>(&self, val: &Q) -> Result<Vec<u8>> {
+        let mut vbytes = self.encode_val_as::<V, Q>(val)?;
+        if self.has_seq() {
+            vbytes.extend_from_slice(&self.raw_store().next_seq()?.to_le_bytes());
+        }
+        Ok(vbytes)
     }
-}
 
-/// A wrapper around [CandyStore] that exposes the list API in a typed manner. See [CandyTypedStore] for more
-/// info
-pub struct CandyTypedList<L, K, V> {
-    store: Arc<CandyStore>,
-    _phantom: PhantomData<(L, K, V)>,
+    fn decode_val(&self, vbytes: &[u8]) -> Result<V> {
+        from_bytes::<V>(&self.decompress_val(self.split_seq(vbytes)?.0))
+    }
 }
 
-impl<L, K, V> Clone for CandyTypedList<L, K, V> {
-    fn clone(&self) -> Self {
-        Self {
-            store: self.store.clone(),
-            _phantom: Default::default(),
-        }
+impl<K, V> TypedValues<V> for CandyTypedStore<K, V>
+where
+    V: Encode + DecodeOwned,
+{
+    fn raw_store(&self) -> &CandyStore {
+        &self.store
     }
 }
 
-impl<L, K, V> CandyTypedList<L, K, V>
+impl<K, V> CandyTypedStore<K, V>
 where
-    L: CandyTypedKey,
-    K: Encode + DecodeOwned,
+    K: CandyTypedKey,
     V: Encode + DecodeOwned,
 {
-    /// Constructs a [CandyTypedList] over an existing [CandyStore]
+    /// Constructs a typed wrapper over a CandyStore. In debug builds, this panics if `K::TYPE_ID` was
+    /// already used by a different key type (see [Self::new_checked])
     pub fn new(store: Arc<CandyStore>) -> Self {
+        debug_register_type_id::<K>();
+        Self::with_key_suffix(store, None)
+    }
+
+    // the constructor shared by all others, which are responsible for registering the type ID
+    fn with_key_suffix(store: Arc<CandyStore>, ns: Option<&str>) -> Self {
         Self {
             store,
-            _phantom: PhantomData,
+            key_suffix: Self::make_key_suffix(ns),
+            on_change: None,
+            cache: None,
+            normalizer: None,
+            _phantom: Default::default(),
         }
     }
 
-    fn make_list_key<Q: ?Sized + Encode>(list_key: &Q) -> Vec<u8>
-    where
-        L: Borrow<Q>,
-    {
-        let mut kbytes = list_key.to_bytes::<LE>();
-        kbytes.extend_from_slice(bytes_of(&L::TYPE_ID));
-        kbytes
+    /// Same as [Self::new], but the entries of this store live in the given namespace: they are disjoint from
+    /// the entries of the same type in other namespaces (as well as from those created by [Self::new]), so
+    /// `K` can be reused across logical tables (e.g., per tenant). Methods that scan the store, like
+    /// [Self::iter], [Self::len] and [Self::clear], only cover the store's namespace
+    pub fn new_in_namespace(store: Arc<CandyStore>, ns: &str) -> Self {
+        debug_register_type_id::<K>();
+        Self::with_key_suffix(store, Some(ns))
     }
 
-    /// Tests if the given typed `item_key` exists in this list (identified by `list_key`)
-    pub fn contains<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
-        &self,
-        list_key: &Q1,
-        item_key: &Q2,
-    ) -> Result<bool>
-    where
-        L: Borrow<Q1>,
-        K: Borrow<Q2>,
-    {
-        let list_key = Self::make_list_key(list_key);
-        let item_key = item_key.to_bytes::<LE>();
-        Ok(self
-            .store
-            .owned_get_from_list(list_key, item_key)?
-            .is_some())
+    /// Same as [Self::new_in_namespace], but returns [CandyError::TypeIdCollision] like [Self::new_checked]
+    pub fn new_in_namespace_checked(store: Arc<CandyStore>, ns: &str) -> Result<Self> {
+        register_type_id::<K>()?;
+        Ok(Self::with_key_suffix(store, Some(ns)))
     }
 
-    /// Same as [CandyStore::get_from_list], but `list_key` and `item_key` are typed
-    pub fn get<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
-        &self,
-        list_key: &Q1,
-        item_key: &Q2,
-    ) -> Result<Option<V>>
-    where
-        L: Borrow<Q1>,
-        K: Borrow<Q2>,
+    /// Same as [Self::new], but `on_change` is called whenever an entry is created, overwritten or removed,
+    /// with the old and new values (e.g., for maintaining a secondary index). The callback is called
+    /// synchronously, after the change was made but before the operation returns, so it should be quick.
+    /// Note that it's not called under any lock, so callbacks of concurrent changes to the same key may
+    /// be called in any order.
+    ///
+    /// The callback is called by [Self::set], [Self::replace], [Self::remove], [Self::remove_many],
+    /// [Self::clear], [Self::set_auto], [Self::remove_auto], and by [Self::get_or_create],
+    /// [Self::get_or_create_status] and [Self::set_if_absent] when they create the entry. Other operations
+    /// ([Self::swap], [Self::migrate], [Self::set_big] and the rest of the big value methods) do not call it.
+    /// The callback only applies to this wrapper (and its clones), not to other wrappers over the same store
+    pub fn with_on_change(
+        store: Arc<CandyStore>,
+        on_change: impl Fn(ChangeEvent<K, V>) + Send + Sync + 'static,
+    ) -> Self {
+        let mut this = Self::new(store);
+        this.on_change = Some(Arc::new(on_change));
+        this
+    }
+
+    /// Same as [Self::with_on_change], but returns [CandyError::TypeIdCollision] like [Self::new_checked]
+    pub fn with_on_change_checked(
+        store: Arc<CandyStore>,
+        on_change: impl Fn(ChangeEvent<K, V>) + Send + Sync + 'static,
+    ) -> Result<Self> {
+        let mut this = Self::new_checked(store)?;
+        this.on_change = Some(Arc::new(on_change));
+        Ok(this)
+    }
+
+    /// Same as [Self::new], but with a read cache of up to `capacity` decoded values, used by
+    /// [Self::get_cached]. Cached values are invalidated by every write this wrapper (or its clones) makes
+    /// to their keys, but not by writes made through other wrappers or directly through the [CandyStore],
+    /// which may leave stale values in the cache. Panics if `capacity` is 0
+    pub fn new_cached(store: Arc<CandyStore>, capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity must be positive");
+        let mut this = Self::new(store);
+        this.cache = Some(Arc::new(ReadCache::new(capacity)));
+        this
+    }
+
+    /// Same as [Self::new_cached], but returns [CandyError::TypeIdCollision] like [Self::new_checked]
+    pub fn new_cached_checked(store: Arc<CandyStore>, capacity: usize) -> Result<Self> {
+        assert!(capacity > 0, "capacity must be positive");
+        let mut this = Self::new_checked(store)?;
+        this.cache = Some(Arc::new(ReadCache::new(capacity)));
+        Ok(this)
+    }
+
+    /// Same as [Self::new], but every key is passed through `normalizer` before it's used, by all the methods that
+    /// take keys, so that different forms of a key map to the same entry (e.g., lowercasing the keys for
+    /// case-insensitive lookups). Entries are stored under their normalized keys, so methods that yield keys
+    /// (e.g., [Self::iter]) yield normalized keys. The normalizer only applies to this wrapper (and its clones),
+    /// and it should be idempotent and consistent across runs, or existing entries will not be found
+    pub fn new_with_normalizer(
+        store: Arc<CandyStore>,
+        normalizer: impl Fn(&K) -> K + Send + Sync + 'static,
+    ) -> Self {
+        let mut this = Self::new(store);
+        this.normalizer = Some(Arc::new(normalizer));
+        this
+    }
+
+    /// Same as [Self::new_with_normalizer], but returns [CandyError::TypeIdCollision] like [Self::new_checked]
+    pub fn new_with_normalizer_checked(
+        store: Arc<CandyStore>,
+        normalizer: impl Fn(&K) -> K + Send + Sync + 'static,
+    ) -> Result<Self> {
+        let mut this = Self::new_checked(store)?;
+        this.normalizer = Some(Arc::new(normalizer));
+        Ok(this)
+    }
+
+    fn invalidate(&self, kbytes: &[u8]) {
+        if let Some(ref cache) = self.cache {
+            cache.invalidate(kbytes);
+        }
+    }
+
+    fn invalidate_all(&self) {
+        if let Some(ref cache) = self.cache {
+            cache.invalidate_all();
+        }
+    }
+
+    fn notify(&self, kbytes: &[u8], old: Option<&V>, new_vbytes: Option<&[u8]>) -> Result<()> {
+        if self.on_change.is_none() {
+            self.invalidate(kbytes);
+            return Ok(());
+        }
+        let new = match new_vbytes {
+            Some(vbytes) => Some(self.decode_val(vbytes)?),
+            None => None,
+        };
+        self.notify_decoded(kbytes, old, new.as_ref())
+    }
+
+    // same as notify, but takes the new value decoded
+    fn notify_decoded(&self, kbytes: &[u8], old: Option<&V>, new: Option<&V>) -> Result<()> {
+        self.invalidate(kbytes);
+        let Some(ref on_change) = self.on_change else {
+            return Ok(());
+        };
+        let key = from_bytes::<K>(&kbytes[..kbytes.len() - self.key_suffix.len()])?;
+        on_change(ChangeEvent {
+            key: &key,
+            old,
+            new,
+        });
+        Ok(())
+    }
+
+    // removes the given full keys, notifying on each one (if needed)
+    fn remove_full_keys(&self, full_keys: Vec<Vec<u8>>) -> Result<usize> {
+        if self.on_change.is_none() {
+            for full_key in full_keys.iter() {
+                self.invalidate(full_key);
+            }
+            return self.store.remove_many_raw(full_keys);
+        }
+        let mut removed = 0;
+        for full_key in full_keys {
+            let res = self.store.remove_raw(&full_key).and_then(|vbytes| {
+                let Some(vbytes) = vbytes else {
+                    return Ok(false);
+                };
+                let old = self.decode_val(&vbytes)?;
+                self.notify(&full_key, Some(&old), None)?;
+                Ok(true)
+            });
+            match res {
+                Ok(true) => removed += 1,
+                Ok(false) => {}
+                Err(e) => return Err(e.context(CandyError::BatchInterrupted(removed))),
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Same as [Self::new], but returns [CandyError::TypeIdCollision] if `K::TYPE_ID` was already used by a
+    /// different key type, regardless of the build type
+    pub fn new_checked(store: Arc<CandyStore>) -> Result<Self> {
+        register_type_id::<K>()?;
+        Ok(Self::with_key_suffix(store, None))
+    }
+
+    fn make_key_suffix(ns: Option<&str>) -> Arc<[u8]> {
+        let mut suffix = vec![];
+        match ns {
+            None => {
+                suffix.extend_from_slice(bytes_of(&K::TYPE_ID));
+                suffix.extend_from_slice(TYPED_NAMESPACE);
+            }
+            Some(ns) => {
+                // the length follows the namespace, so that no namespace's suffix is a suffix of another's
+                suffix.extend_from_slice(ns.as_bytes());
+                suffix.extend_from_slice(bytes_of(&(ns.len() as u16)));
+                suffix.extend_from_slice(bytes_of(&K::TYPE_ID));
+                suffix.extend_from_slice(TYPED_NS_NAMESPACE);
+            }
+        }
+        suffix.into()
+    }
+
+    fn make_key<Q: ?Sized + Encode>(&self, key: &Q) -> Result<Vec<u8>>
+    where
+        K: Borrow<Q>,
     {
-        let list_key = Self::make_list_key(list_key);
-        let item_key = item_key.to_bytes::<LE>();
-        if let Some(vbytes) = self.store.owned_get_from_list(list_key, item_key)? {
-            Ok(Some(from_bytes::<V>(&vbytes)?))
+        let mut kbytes = Vec::new();
+        self.make_key_into(key, &mut kbytes)?;
+        Ok(kbytes)
+    }
+
+    /// Encodes the full key (as stored in the underlying [CandyStore]) of `key` into `buf`, which is cleared first.
+    /// This lets loops reuse a scratch buffer instead of allocating a key per iteration; a buffer with enough
+    /// capacity is never reallocated.
+    ///
+    /// If the store has a key normalizer (see [Self::new_with_normalizer]), the key is normalized first. This
+    /// fails if `key` can't be decoded as a `K`, which may only happen if its borrowed form encodes differently
+    pub fn make_key_into<Q: ?Sized + Encode>(&self, key: &Q, buf: &mut Vec<u8>) -> Result<()>
+    where
+        K: Borrow<Q>,
+    {
+        buf.clear();
+        if let Some(ref normalizer) = self.normalizer {
+            // writing into a Vec cannot fail
+            key.encode::<LE>(buf).unwrap();
+            let key = normalizer(&from_bytes_exact::<K>(buf)?);
+            buf.clear();
+            Self::encode_key_into(&key, &self.key_suffix, buf);
+        } else {
+            Self::encode_key_into(key, &self.key_suffix, buf);
+        }
+        Ok(())
+    }
+
+    fn encode_key_into<Q: ?Sized + Encode>(key: &Q, suffix: &[u8], buf: &mut Vec<u8>) {
+        // the in-memory size of the key is exact for plain fixed-size keys, and otherwise just a starting point
+        // that the encoder grows as needed, so the key is encoded only once
+        buf.reserve(std::mem::size_of_val(key) + suffix.len());
+        // writing into a Vec cannot fail
+        key.encode::<LE>(buf).unwrap();
+        buf.extend_from_slice(suffix);
+    }
+
+    /// Iterates over all entries of this typed store (i.e., all entries whose key type has `K::TYPE_ID`),
+    /// deserializing the keys and values. Same as [CandyStore::iter], it's safe to modify the store while
+    /// iterating, but the results may or may not include these changes
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = Result<(K, V)>> + 'a {
+        self.iter_with_seq().map(|res| res.map(|(k, v, _)| (k, v)))
+    }
+
+    /// Same as [Self::iter], but also yields the sequence number of each entry (see [Self::get_with_seq]).
+    /// Sequence numbers are `None` unless [crate::Config::typed_entry_seq] is enabled
+    pub fn iter_with_seq<'a>(&'a self) -> impl Iterator<Item = Result<(K, V, Option<u64>)>> + 'a {
+        let suffix = self.key_suffix.clone();
+        self.store.iter_raw().filter_map(move |res| match res {
+            Err(e) => Some(Err(e)),
+            Ok((mut k, v)) => {
+                if !k.ends_with(&suffix) {
+                    return None;
+                }
+                k.truncate(k.len() - suffix.len());
+                Some(from_bytes::<K>(&k).and_then(|key| {
+                    let (v, seq) = self.split_seq(&v)?;
+                    Ok((key, from_bytes::<V>(&self.decompress_val(v))?, seq))
+                }))
+            }
+        })
+    }
+
+    /// Returns up to `limit` keys of this typed store, starting at `cursor` (or at the beginning if `None`), along
+    /// with the cursor of the next page, or `None` if the end of the store was reached. Unlike [Self::iter],
+    /// nothing is held between pages, so the cursor can be kept (e.g., across HTTP requests) while the store is
+    /// being modified.
+    ///
+    /// Consistency is the same as [CandyStore::iter]: keys that are added or removed between pages may or may
+    /// not appear, and if a shard is split or compacted between pages, keys that moved may be returned again or
+    /// skipped
+    pub fn keys_page(
+        &self,
+        cursor: Option<StoreCursor>,
+        limit: usize,
+    ) -> Result<(Vec<K>, Option<StoreCursor>)> {
+        let mut iter = CandyStoreIterator::from_cookie(
+            &self.store,
+            cursor.map_or(0, |c| c.cookie()),
+            true,
+            false,
+        );
+        let mut keys = Vec::with_capacity(limit.min(1024));
+        loop {
+            let cookie = iter.cookie();
+            let Some(res) = iter.next() else {
+                return Ok((keys, None));
+            };
+            let mut k = res?.0;
+            if !k.ends_with(&self.key_suffix) {
+                continue;
+            }
+            if keys.len() >= limit {
+                // there's at least one more key, resume from it
+                return Ok((keys, Some(StoreCursor::from_cookie(cookie))));
+            }
+            k.truncate(k.len() - self.key_suffix.len());
+            keys.push(from_bytes::<K>(&k)?);
+        }
+    }
+
+    /// Returns the distribution of the serialized sizes of the inline values of this typed store (big values are
+    /// not included), which can help choose [crate::Config::max_inline_value] and decide which values should be
+    /// stored with [Self::set_big]. Values are counted by their (stored, i.e., possibly compressed) length, without
+    /// decoding them.
+    ///
+    /// `bounds` are the inclusive upper bounds of the buckets (they are sorted and deduplicated), and the result
+    /// holds a `(bucket_upper_bound, count)` pair for each of them, followed by a `(usize::MAX, count)` pair for
+    /// the values that are larger than all bounds
+    pub fn value_size_histogram(&self, bounds: &[usize]) -> Result<Vec<(usize, usize)>> {
+        let mut hist = bounds.iter().map(|&b| (b, 0usize)).collect::<Vec<_>>();
+        hist.sort();
+        hist.dedup();
+        if hist.last().is_none_or(|(b, _)| *b != usize::MAX) {
+            hist.push((usize::MAX, 0));
+        }
+
+        for res in self.store.iter_raw() {
+            let (k, v) = res?;
+            if !k.ends_with(&self.key_suffix) {
+                continue;
+            }
+            let len = self.split_trailer(&v)?.0.len();
+            let idx = hist.partition_point(|(b, _)| *b < len);
+            hist[idx].1 += 1;
+        }
+
+        Ok(hist)
+    }
+
+    /// Returns up to `n` entries of this typed store (fewer only if it holds fewer), sampled from randomly chosen
+    /// parts of the store, so that properties of a large store can be estimated without scanning all of it.
+    ///
+    /// Note: the sample is approximate, and not a uniform random sample. Every entry is equally likely to be
+    /// sampled, but entries are read in clusters (keys are hashed into rows, and whole rows are read), so entries
+    /// are not sampled independently of each other. Also note that the store is scanned until enough entries of
+    /// this typed store are found, so if they are a small fraction of the store, much of it may be scanned
+    pub fn sample(&self, n: usize) -> Result<Vec<(K, V)>> {
+        if n == 0 {
+            return Ok(vec![]);
+        }
+        let mut entries = self
+            .store
+            .sample_rows(n, |k| k.ends_with(&self.key_suffix))?;
+        // the last row read may have added more entries than needed
+        entries.shuffle(&mut rand::rng());
+        entries.truncate(n);
+
+        let mut res = Vec::with_capacity(entries.len());
+        for (mut k, v) in entries {
+            k.truncate(k.len() - self.key_suffix.len());
+            res.push((from_bytes::<K>(&k)?, self.decode_val(&v)?));
+        }
+        Ok(res)
+    }
+
+    /// Calls `func` with every entry of this typed store, like [Self::iter], but scans the shards of the store
+    /// concurrently, on `num_threads` threads (or one per CPU if 0), and decodes the entries on these threads.
+    /// Each shard is only locked while one of its rows is read (not while decoding or calling `func`), so the
+    /// scan does not block writers. Consistency is the same as [Self::iter].
+    ///
+    /// `func` is called concurrently, in no particular order. The scan stops at the first error (of decoding or
+    /// returned by `func`), which is returned, although entries already being processed on other threads may
+    /// still be passed to `func`
+    pub fn par_for_each(
+        &self,
+        num_threads: usize,
+        func: impl Fn(K, V) -> Result<()> + Sync,
+    ) -> Result<()>
+    where
+        K: Send + Sync,
+        V: Send + Sync,
+    {
+        let num_threads = if num_threads == 0 {
+            std::thread::available_parallelism().map_or(1, |n| n.get())
+        } else {
+            num_threads
+        };
+        let selectors = self.store.shard_selectors()?;
+        let next_shard = AtomicUsize::new(0);
+        let failed = AtomicBool::new(false);
+
+        let scan_shards = || -> Result<()> {
+            while !failed.load(atomic::Ordering::Relaxed) {
+                let Some(&selector) =
+                    selectors.get(next_shard.fetch_add(1, atomic::Ordering::Relaxed))
+                else {
+                    break;
+                };
+                let res = self.store.scan_shard_raw(
+                    selector,
+                    |k| k.ends_with(&self.key_suffix),
+                    |entries| {
+                        for (mut k, v) in entries {
+                            if failed.load(atomic::Ordering::Relaxed) {
+                                break;
+                            }
+                            k.truncate(k.len() - self.key_suffix.len());
+                            func(from_bytes::<K>(&k)?, self.decode_val(&v)?)?;
+                        }
+                        Ok(())
+                    },
+                );
+                if res.is_err() {
+                    failed.store(true, atomic::Ordering::Relaxed);
+                    return res;
+                }
+            }
+            Ok(())
+        };
+
+        std::thread::scope(|s| {
+            let handles = (0..num_threads.min(selectors.len()))
+                .map(|_| s.spawn(scan_shards))
+                .collect::<Vec<_>>();
+            let mut res = Ok(());
+            for handle in handles {
+                let thread_res = match handle.join() {
+                    Ok(thread_res) => thread_res,
+                    Err(e) => std::panic::resume_unwind(e),
+                };
+                if res.is_ok() {
+                    res = thread_res;
+                }
+            }
+            res
+        })
+    }
+
+    /// Same as [Self::iter], but returns a rayon `ParallelIterator`, which scans the shards of the store (and the
+    /// rows of each shard) concurrently on rayon's thread pool, decoding the entries on its threads. Each shard
+    /// is only read-locked while one of its rows is read, so the scan is not serialized on a single lock and
+    /// does not block writers. Consistency is the same as [Self::iter], and entries come in no particular
+    /// order. Only available with the `rayon` feature (see [Self::par_for_each] otherwise)
+    #[cfg(feature = "rayon")]
+    pub fn par_iter<'a>(&'a self) -> impl rayon::iter::ParallelIterator<Item = Result<(K, V)>> + 'a
+    where
+        K: Send + Sync,
+        V: Send + Sync,
+    {
+        use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+        // failing to list the shards is reported as the only item
+        let (selectors, err) = match self.store.shard_selectors() {
+            Ok(selectors) => (selectors, None),
+            Err(e) => (vec![], Some(Err(e))),
+        };
+        let entries = selectors
+            .into_par_iter()
+            .flat_map(|selector| {
+                (0..NUM_ROWS)
+                    .into_par_iter()
+                    .map(move |row_idx| (selector, row_idx))
+            })
+            .flat_map_iter(move |(selector, row_idx)| {
+                let mut entries = vec![];
+                let row_err = self
+                    .store
+                    .read_row_raw(
+                        selector,
+                        row_idx,
+                        &mut |k| k.ends_with(&self.key_suffix),
+                        &mut entries,
+                    )
+                    .err()
+                    .map(Err);
+                entries
+                    .into_iter()
+                    .map(move |(mut k, v)| {
+                        k.truncate(k.len() - self.key_suffix.len());
+                        Ok((from_bytes::<K>(&k)?, self.decode_val(&v)?))
+                    })
+                    .chain(row_err)
+            });
+        err.into_par_iter().chain(entries)
+    }
+
+    /// Same as [Self::iter], but yields batches of up to `chunk` entries. No lock is held between batches
+    /// (nor between entries of the same batch), so writers are not starved during long scans.
+    ///
+    /// Consistency is the same as [CandyStore::iter]: entries that are added or removed mid-scan may or may
+    /// not appear. An error ends the current batch, dropping the entries collected so far in it
+    pub fn iter_chunked<'a>(
+        &'a self,
+        chunk: usize,
+    ) -> impl Iterator<Item = Result<Vec<(K, V)>>> + 'a {
+        let chunk = chunk.max(1);
+        let mut iter = self.iter();
+        std::iter::from_fn(move || {
+            let mut batch = Vec::with_capacity(chunk);
+            for res in iter.by_ref() {
+                match res {
+                    Ok(kv) => batch.push(kv),
+                    Err(e) => return Some(Err(e)),
+                }
+                if batch.len() >= chunk {
+                    break;
+                }
+            }
+            if batch.is_empty() {
+                None
+            } else {
+                Some(Ok(batch))
+            }
+        })
+    }
+
+    /// Same as [Self::iter], but yields the entries sorted by their encoded keys, so the order is deterministic
+    /// (e.g., for reproducible dumps). The keys are collected and sorted up front (so they are held in memory),
+    /// and the values are fetched lazily: entries that are removed while iterating are skipped, and entries
+    /// added while iterating are not returned
+    pub fn iter_sorted<'a>(&'a self) -> Result<impl Iterator<Item = Result<(K, V)>> + 'a> {
+        let mut full_keys = self.full_keys()?;
+        full_keys.sort();
+        Ok(full_keys.into_iter().filter_map(move |full_key| {
+            let vbytes = match self.store.get_raw(&full_key) {
+                Ok(Some(vbytes)) => vbytes,
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e)),
+            };
+            let kbytes = &full_key[..full_key.len() - self.key_suffix.len()];
+            Some(from_bytes::<K>(kbytes).and_then(|key| Ok((key, self.decode_val(&vbytes)?))))
+        }))
+    }
+
+    /// Iterates over the keys of this typed store, yielding those for which `pred` returns true. Only the keys
+    /// are read and decoded, values are never touched, so this is cheaper than filtering [Self::iter] when the
+    /// predicate only inspects the key (e.g., for finding stale keys to remove). Consistency is the same as
+    /// [CandyStore::iter]
+    pub fn filter_keys<'a>(
+        &'a self,
+        mut pred: impl FnMut(&K) -> bool + 'a,
+    ) -> impl Iterator<Item = Result<K>> + 'a {
+        self.store.iter_raw_keys().filter_map(move |res| {
+            let k = match res {
+                Ok(k) => k,
+                Err(e) => return Some(Err(e)),
+            };
+            if !k.ends_with(&self.key_suffix) {
+                return None;
+            }
+            match from_bytes::<K>(&k[..k.len() - self.key_suffix.len()]) {
+                Ok(key) => pred(&key).then_some(Ok(key)),
+                Err(e) => Some(Err(e)),
+            }
+        })
+    }
+
+    /// Iterates over the entries whose keys fall in the given range (e.g., `start..end` of timestamps). Since
+    /// the store is hash-based, keys are not ordered on disk, so this scans all keys of this typed store (as
+    /// [Self::filter_keys] does, reading only the values of the keys in range), and yields the entries in no
+    /// particular order. Consistency is the same as [CandyStore::iter]
+    pub fn range<'a>(
+        &'a self,
+        range: impl RangeBounds<K> + 'a,
+    ) -> impl Iterator<Item = Result<(K, V)>> + 'a
+    where
+        K: PartialOrd,
+    {
+        self.filter_keys(move |k| range.contains(k))
+            .filter_map(|res| {
+                let key = match res {
+                    Ok(key) => key,
+                    Err(e) => return Some(Err(e)),
+                };
+                // the entry may have been removed since its key was read
+                self.get(&key).transpose().map(|res| res.map(|v| (key, v)))
+            })
+    }
+
+    /// Collects all entries of this typed store into a [HashMap]. This is meant as a test utility, for asserting
+    /// on (or snapshotting) the full contents of the store -- it scans the entire underlying store, so keep it
+    /// out of hot paths
+    pub fn to_hashmap(&self) -> Result<HashMap<K, V>>
+    where
+        K: Hash + Eq,
+    {
+        self.iter().collect()
+    }
+
+    // the full keys of all entries of this typed store
+    fn full_keys(&self) -> Result<Vec<Vec<u8>>> {
+        let mut full_keys = vec![];
+        for res in self.store.iter_raw_keys() {
+            let k = res?;
+            if k.ends_with(&self.key_suffix) {
+                full_keys.push(k);
+            }
+        }
+        Ok(full_keys)
+    }
+
+    /// Returns the number of entries in this typed store. This scans the entire underlying store, so it's an
+    /// expensive operation
+    pub fn len(&self) -> Result<usize> {
+        let mut count = 0;
+        for res in self.store.iter_raw_keys() {
+            if res?.ends_with(&self.key_suffix) {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Returns true if this typed store has no entries. Like [Self::len], this may scan the entire underlying store
+    pub fn is_empty(&self) -> Result<bool> {
+        for res in self.store.iter_raw_keys() {
+            if res?.ends_with(&self.key_suffix) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Removes all entries of this typed store (and only them), returning the number of entries removed. Big
+    /// values (see [Self::set_big]) are not removed. This scans the entire underlying store, and is not atomic:
+    /// entries that are added concurrently may or may not be removed
+    pub fn clear(&self) -> Result<usize> {
+        self.remove_full_keys(self.full_keys()?)
+    }
+
+    /// Migrates the values of this store from `V` to `V2`, e.g., after `V` has gained a field. Every entry is
+    /// decoded as `V` and passed to `f`: if it returns `Some(new_val)`, the entry is rewritten (under the same key)
+    /// with the new value, and if it returns `None`, the entry is removed. Afterwards, the entries can be accessed
+    /// via a `CandyTypedStore<K, V2>`. Returns the number of entries that were migrated (rewritten or removed).
+    ///
+    /// Entries that fail to decode as `V` (or have trailing bytes) are considered already-migrated and are
+    /// skipped, so it's safe to call this function again if it was interrupted. Note that this relies on the
+    /// encodings of `V` and `V2` being distinguishable.
+    ///
+    /// Note: **not crash-safe** (as a whole), and concurrent writers of `V` values may race with the migration
+    pub fn migrate<V2: Encode>(&self, mut f: impl FnMut(K, V) -> Option<V2>) -> Result<usize> {
+        let suffix = &self.key_suffix;
+        let own_tag = if self.has_tag() {
+            // fail before rewriting anything if `V2` has no tag
+            value_type_tag::<V2>()?;
+            Some(value_type_tag::<V>()?)
+        } else {
+            None
+        };
+
+        // collect the keys first, since rewriting entries while iterating may cause us to visit them twice
+        let full_keys = self.full_keys()?;
+
+        let mut count = 0;
+        for full_key in full_keys {
+            let Some(vbytes) = self.store.get_raw(&full_key)? else {
+                continue;
+            };
+            let (vbytes, tag, seq) = self.split_trailer(&vbytes)?;
+            if tag.is_some() && tag != own_tag {
+                // already migrated
+                continue;
+            }
+            let Ok(val) = from_bytes_exact::<V>(&self.decompress_val(vbytes)) else {
+                // already migrated
+                continue;
+            };
+            let key = from_bytes::<K>(&full_key[..full_key.len() - suffix.len()])?;
+            match f(key, val) {
+                Some(new_val) => {
+                    // the entry keeps its sequence number, as its logical value did not change
+                    let mut new_vbytes = self.encode_val_as::<V2, V2>(&new_val)?;
+                    if let Some(seq) = seq {
+                        new_vbytes.extend_from_slice(&seq.to_le_bytes());
+                    }
+                    self.store.set_raw(&full_key, &new_vbytes)?;
+                }
+                None => {
+                    self.store.remove_raw(&full_key)?;
+                }
+            }
+            count += 1;
+        }
+
+        self.invalidate_all();
+        Ok(count)
+    }
+
+    /// Same as [CandyStore::contains] but serializes the key
+    pub fn contains<Q: ?Sized + Encode>(&self, key: &Q) -> Result<bool>
+    where
+        K: Borrow<Q>,
+    {
+        Ok(self.store.get_raw(&self.make_key(key)?)?.is_some())
+    }
+
+    /// Same as [CandyStore::contains_many] but serializes the keys. Values are never decoded
+    pub fn contains_many<Q: ?Sized + Encode>(&self, keys: &[&Q]) -> Result<Vec<bool>>
+    where
+        K: Borrow<Q>,
+    {
+        let full_keys = keys
+            .iter()
+            .map(|k| self.make_key(*k))
+            .collect::<Result<_>>()?;
+        self.store.contains_many_raw(full_keys)
+    }
+
+    /// Same as [CandyStore::get] but serializes the key and deserializes the value
+    pub fn get<Q: ?Sized + Encode>(&self, key: &Q) -> Result<Option<V>>
+    where
+        K: Borrow<Q>,
+    {
+        let kbytes = self.make_key(key)?;
+        if let Some(vbytes) = self.store.get_raw(&kbytes)? {
+            Ok(Some(self.decode_val(&vbytes)?))
         } else {
             Ok(None)
         }
     }
 
-    fn _set<Q1: ?Sized + Encode, Q2: ?Sized + Encode, Q3: ?Sized + Encode>(
+    /// Same as [Self::get], but returns a shared value from the read cache (see [Self::new_cached]) if it's
+    /// there, saving the read and the decoding. Otherwise the value is read, decoded and cached (missing keys
+    /// are not cached). Without a read cache, this is [Self::get] with the value wrapped in an `Arc`
+    pub fn get_cached<Q: ?Sized + Encode>(&self, key: &Q) -> Result<Option<Arc<V>>>
+    where
+        K: Borrow<Q>,
+    {
+        let Some(ref cache) = self.cache else {
+            return Ok(self.get(key)?.map(Arc::new));
+        };
+        let kbytes = self.make_key(key)?;
+        let generation = match cache.get(&kbytes, self.store.clear_epoch()) {
+            Ok(val) => return Ok(Some(val)),
+            Err(generation) => generation,
+        };
+        let Some(vbytes) = self.store.get_raw(&kbytes)? else {
+            return Ok(None);
+        };
+        let val = Arc::new(self.decode_val(&vbytes)?);
+        cache.insert(kbytes, val.clone(), generation);
+        Ok(Some(val))
+    }
+
+    /// Returns the stored (encoded) value of the given key, without attempting to decode it. This is useful for
+    /// inspecting or repairing entries that fail to decode as `V` (e.g., after a schema change), where [Self::get]
+    /// would return an error. The sequence number and the value type tag (if enabled) are stripped (and the tag
+    /// is not verified), and compressed values (see [crate::Config::value_codec]) are decompressed
+    pub fn get_raw_bytes<Q: ?Sized + Encode>(&self, key: &Q) -> Result<Option<Vec<u8>>>
+    where
+        K: Borrow<Q>,
+    {
+        let kbytes = self.make_key(key)?;
+        let Some(vbytes) = self.store.get_raw(&kbytes)? else {
+            return Ok(None);
+        };
+        Ok(Some(
+            self.decompress_val(self.split_trailer(&vbytes)?.0)
+                .into_owned(),
+        ))
+    }
+
+    /// Same as [Self::get], but also returns the entry's sequence number, which is updated whenever the entry
+    /// is written. This requires [crate::Config::typed_entry_seq] to be enabled, and can be used to find
+    /// the least-recently written entries (e.g., for LRU eviction) by scanning with [Self::iter_with_seq].
+    ///
+    /// Note: [Self::swap] moves the sequence numbers along with the values, and big values ([Self::set_big])
+    /// do not carry sequence numbers
+    pub fn get_with_seq<Q: ?Sized + Encode>(&self, key: &Q) -> Result<Option<(V, u64)>>
+    where
+        K: Borrow<Q>,
+    {
+        if !self.has_seq() {
+            bail!(CandyError::NotEnabled("sequence numbers"));
+        }
+        let kbytes = self.make_key(key)?;
+        let Some(vbytes) = self.store.get_raw(&kbytes)? else {
+            return Ok(None);
+        };
+        let (vbytes, seq) = self.split_seq(&vbytes)?;
+        Ok(Some((
+            from_bytes::<V>(&self.decompress_val(vbytes))?,
+            seq.unwrap_or_default(),
+        )))
+    }
+
+    /// Same as [CandyStore::replace] but serializes the key and the value
+    pub fn replace<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
         &self,
-        list_key: &Q1,
-        item_key: &Q2,
-        val: &Q3,
-        promote: bool,
+        key: &Q1,
+        val: &Q2,
+        expected_val: Option<&Q2>,
     ) -> Result<Option<V>>
     where
-        L: Borrow<Q1>,
-        K: Borrow<Q2>,
-        V: Borrow<Q3>,
+        K: Borrow<Q1>,
+        V: Borrow<Q2>,
     {
-        let list_key = Self::make_list_key(list_key);
-        let item_key = item_key.to_bytes::<LE>();
-        let val = val.to_bytes::<LE>();
+        let kbytes = self.make_key(key)?;
+        let vbytes = self.encode_val(val)?;
+        let ebytes = expected_val.map(|ev| ev.to_bytes::<LE>()).unwrap_or(vec![]);
+        if (self.has_seq() || self.compresses()) && expected_val.is_some() {
+            // the stored value carries a sequence number (or may be compressed), so we can't compare it
+            // directly. instead, we compare-and-swap the full stored value, as long as its value part is the
+            // expected one
+            loop {
+                let Some(existing) = self.store.get_raw(&kbytes)? else {
+                    return Ok(None);
+                };
+                if *self.decompress_val(self.split_seq(&existing)?.0) != *ebytes {
+                    return Ok(None);
+                }
+                match self.store.replace_raw(&kbytes, &vbytes, Some(&existing))? {
+                    ReplaceStatus::DoesNotExist => return Ok(None),
+                    ReplaceStatus::PrevValue(v) => {
+                        let old = self.decode_val(&v)?;
+                        self.notify(&kbytes, Some(&old), Some(&vbytes))?;
+                        return Ok(Some(old));
+                    }
+                    ReplaceStatus::WrongValue(_) => continue,
+                }
+            }
+        }
+        // without sequence numbers, the stored value is the encoded value followed by its (fixed) type tag
+        let mut ebytes = ebytes;
+        self.append_tag::<V>(&mut ebytes)?;
         match self
             .store
-            .owned_set_in_list(list_key, item_key, val, promote)?
+            .replace_raw(&kbytes, &vbytes, expected_val.map(|_| &*ebytes))?
         {
-            SetStatus::CreatedNew => Ok(None),
-            SetStatus::PrevValue(v) => Ok(Some(from_bytes::<V>(&v)?)),
+            ReplaceStatus::DoesNotExist => Ok(None),
+            ReplaceStatus::PrevValue(v) => {
+                let old = self.decode_val(&v)?;
+                self.notify(&kbytes, Some(&old), Some(&vbytes))?;
+                Ok(Some(old))
+            }
+            ReplaceStatus::WrongValue(_) => Ok(None),
+        }
+    }
+
+    /// Updates the value of an existing key with the value returned by `f`, returning both the old and the new
+    /// values, or None (without calling `f`) if the key does not exist. This saves a separate [Self::get] when
+    /// the previous value is needed as well.
+    ///
+    /// The update is done under a keyed lock (the same ones that lists use), so it is atomic with respect to
+    /// other calls of `replace_with` on the same key, but not with respect to other writers (e.g., a concurrent
+    /// [Self::set] may be overwritten). Since the lock is held while `f` runs, `f` must not access the store
+    pub fn replace_with<Q: ?Sized + Encode>(
+        &self,
+        key: &Q,
+        f: impl FnOnce(&V) -> V,
+    ) -> Result<Option<(V, V)>>
+    where
+        K: Borrow<Q>,
+    {
+        let kbytes = self.make_key(key)?;
+        let guard = self.store.lock_list(self.store.config.hash_key(&kbytes));
+
+        let Some(old_vbytes) = self.store.get_raw(&kbytes)? else {
+            return Ok(None);
+        };
+        let old = self.decode_val(&old_vbytes)?;
+        let new = f(&old);
+        let vbytes = self.encode_val(&new)?;
+        self.store.set_raw(&kbytes, &vbytes)?;
+        drop(guard);
+
+        self.notify(&kbytes, Some(&old), Some(&vbytes))?;
+        Ok(Some((old, new)))
+    }
+
+    /// Updates the value of the key (creating it if it does not exist) with the value returned by `f`, which
+    /// is given the current value (or `None`), returning the value written. Unlike [Self::replace_with], no
+    /// lock is held: the current value is read, the new one is computed, and it's written only if the value
+    /// has not changed in the meantime (compare-and-swap). Otherwise, this starts over, so `f` may be called
+    /// more than once, and should not have side effects.
+    ///
+    /// Fails with [CandyError::TooManyRetries] if the value changed under every one of
+    /// [crate::Config::max_update_retries] retries (i.e., under heavy contention on the key)
+    pub fn update<Q: ?Sized + Encode>(
+        &self,
+        key: &Q,
+        mut f: impl FnMut(Option<V>) -> V,
+    ) -> Result<V>
+    where
+        K: Borrow<Q>,
+    {
+        let kbytes = self.make_key(key)?;
+        let max_retries = self.store.config.max_update_retries;
+        for _ in 0..=max_retries {
+            let existing = self.store.get_raw(&kbytes)?;
+            let old = match existing {
+                Some(ref vbytes) => Some(self.decode_val(vbytes)?),
+                None => None,
+            };
+            let new = f(old);
+            let vbytes = self.encode_val(&new)?;
+
+            let written = match existing {
+                Some(ref existing) => matches!(
+                    self.store.replace_raw(&kbytes, &vbytes, Some(existing))?,
+                    ReplaceStatus::PrevValue(_)
+                ),
+                None => self
+                    .store
+                    .get_or_create_raw(&kbytes, vbytes.clone())?
+                    .was_created(),
+            };
+            if written {
+                // `old` was moved into `f`, so it's decoded again for the callback
+                let old = match existing {
+                    Some(ref vbytes) if self.on_change.is_some() => Some(self.decode_val(vbytes)?),
+                    _ => None,
+                };
+                self.notify(&kbytes, old.as_ref(), Some(&vbytes))?;
+                return Ok(new);
+            }
         }
+        bail!(CandyError::TooManyRetries(max_retries))
+    }
+
+    /// Same as [CandyStore::set] but serializes the key and the value.
+    pub fn set<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
+        &self,
+        key: &Q1,
+        val: &Q2,
+    ) -> Result<Option<V>>
+    where
+        K: Borrow<Q1>,
+        V: Borrow<Q2>,
+    {
+        let kbytes = self.make_key(key)?;
+        let vbytes = self.encode_val(val)?;
+        let old = match self.store.set_raw(&kbytes, &vbytes)? {
+            SetStatus::CreatedNew => None,
+            SetStatus::PrevValue(v) => Some(self.decode_val(&v)?),
+        };
+        self.notify(&kbytes, old.as_ref(), Some(&vbytes))?;
+        Ok(old)
+    }
+
+    /// Sets all the given entries, returning the number of entries written. This is meant for the initial
+    /// load of a store (e.g., bootstrapping from a dataset), not for use concurrently with readers: the entries
+    /// are written grouped by shard rather than in the given order, so readers may observe any subset of them.
+    /// All entries are serialized up front, so they are held in memory at once.
+    ///
+    /// This is not atomic: if an error occurs mid-way, the returned error can be downcast to
+    /// [CandyError::BatchInterrupted], holding the number of entries written before the failure
+    pub fn bulk_load(&self, items: impl IntoIterator<Item = (K, V)>) -> Result<usize> {
+        if self.on_change.is_some() {
+            // go through set, so that the callback is called on every entry
+            let mut written = 0;
+            for (k, v) in items {
+                if let Err(e) = self.set(&k, &v) {
+                    return Err(e.context(CandyError::BatchInterrupted(written)));
+                }
+                written += 1;
+            }
+            return Ok(written);
+        }
+
+        let mut encoded = vec![];
+        for (k, v) in items {
+            encoded.push((self.make_key(&k)?, self.encode_val(&v)?));
+        }
+        self.store.set_many_raw(encoded, |_, kbytes, _, _| {
+            self.invalidate(kbytes);
+            Ok(())
+        })
+    }
+
+    /// Sets all the given entries, returning the previous value of each (or None if it did not exist), in the
+    /// given order. Entries are written grouped by shard (rather than in the given order) to minimize lock
+    /// acquisitions and shard switches, but if a key repeats, its entries are written in the given order (so
+    /// the last one wins, and each gets the previous one's value). The on-change callback is called for every
+    /// entry.
+    ///
+    /// This is not atomic: each entry is written atomically, but readers may observe any subset of the batch.
+    /// If an error occurs mid-way, the entries written so far remain written (and there's no telling which
+    /// ones they are), and the returned error can be downcast to [CandyError::BatchInterrupted], holding the
+    /// number of entries written before the failure. For per-entry error handling, use [Self::set] in a loop
+    pub fn set_many(&self, entries: impl IntoIterator<Item = (K, V)>) -> Result<Vec<Option<V>>> {
+        let mut encoded = vec![];
+        for (k, v) in entries {
+            encoded.push((self.make_key(&k)?, self.encode_val(&v)?));
+        }
+
+        let mut prevs = Vec::with_capacity(encoded.len());
+        prevs.resize_with(encoded.len(), || None);
+        self.store
+            .set_many_raw(encoded, |i, kbytes, vbytes, status| {
+                if let SetStatus::PrevValue(v) = status {
+                    prevs[i] = Some(self.decode_val(&v)?);
+                }
+                self.notify(kbytes, prevs[i].as_ref(), Some(vbytes))
+            })?;
+        Ok(prevs)
+    }
+
+    /// Stages a [Self::set] of the given key and value in the batch, to be applied when it's committed (see
+    /// [WriteBatch]). The on-change callback is not called for staged writes
+    pub fn stage_set<'a, Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
+        &self,
+        batch: &mut WriteBatch<'a>,
+        key: &Q1,
+        val: &Q2,
+    ) -> Result<()>
+    where
+        K: Borrow<Q1>,
+        V: Borrow<Q2>,
+        V: 'a,
+    {
+        let kbytes = self.make_key(key)?;
+        batch.set_raw(kbytes.clone(), self.encode_val(val)?)?;
+        if let Some(cache) = self.cache.clone() {
+            batch.on_commit(move || cache.invalidate(&kbytes));
+        }
+        Ok(())
+    }
+
+    /// Stages a [Self::remove] of the given key in the batch, to be applied when it's committed (see
+    /// [WriteBatch]). The on-change callback is not called for staged writes
+    pub fn stage_remove<'a, Q: ?Sized + Encode>(
+        &self,
+        batch: &mut WriteBatch<'a>,
+        key: &Q,
+    ) -> Result<()>
+    where
+        K: Borrow<Q>,
+        V: 'a,
+    {
+        let kbytes = self.make_key(key)?;
+        batch.remove_raw(kbytes.clone())?;
+        if let Some(cache) = self.cache.clone() {
+            batch.on_commit(move || cache.invalidate(&kbytes));
+        }
+        Ok(())
+    }
+
+    /// Same as [CandyStore::get_or_create] but serializes the key and the default value
+    pub fn get_or_create<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
+        &self,
+        key: &Q1,
+        default_val: &Q2,
+    ) -> Result<V>
+    where
+        K: Borrow<Q1>,
+        V: Borrow<Q2>,
+    {
+        Ok(self.get_or_create_status(key, default_val)?.0)
+    }
+
+    /// Same as [Self::get_or_create], but also returns whether the entry was created by this call (`true`)
+    /// or had already existed (`false`)
+    pub fn get_or_create_status<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
+        &self,
+        key: &Q1,
+        default_val: &Q2,
+    ) -> Result<(V, bool)>
+    where
+        K: Borrow<Q1>,
+        V: Borrow<Q2>,
+    {
+        let kbytes = self.make_key(key)?;
+        let status = self
+            .store
+            .get_or_create_raw(&kbytes, self.encode_val(default_val)?)?;
+        let created = status.was_created();
+        let vbytes = status.value();
+        if created {
+            self.notify(&kbytes, None, Some(&vbytes))?;
+        }
+        Ok((self.decode_val(&vbytes)?, created))
+    }
+
+    /// Returns the value of the key, or `V::default()` if it does not exist. This does not write to the store
+    /// (the default is not persisted), see [Self::get_or_insert_default] for that
+    pub fn get_or_default<Q: ?Sized + Encode>(&self, key: &Q) -> Result<V>
+    where
+        K: Borrow<Q>,
+        V: Default,
+    {
+        Ok(self.get(key)?.unwrap_or_default())
+    }
+
+    /// Same as [Self::get_or_create] with `V::default()` as the default value, i.e., if the key does not exist,
+    /// `V::default()` is written to the store (and returned)
+    pub fn get_or_insert_default<Q: ?Sized + Encode>(&self, key: &Q) -> Result<V>
+    where
+        K: Borrow<Q>,
+        V: Default,
+    {
+        self.get_or_create(key, &V::default())
+    }
+
+    /// Same as [CandyStore::set_if_absent] but serializes the key and the value. Returns true if this call
+    /// created the key, without decoding the existing value otherwise
+    pub fn set_if_absent<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
+        &self,
+        key: &Q1,
+        val: &Q2,
+    ) -> Result<bool>
+    where
+        K: Borrow<Q1>,
+        V: Borrow<Q2>,
+    {
+        let kbytes = self.make_key(key)?;
+        let status = self
+            .store
+            .get_or_create_raw(&kbytes, self.encode_val(val)?)?;
+        if let GetOrCreateStatus::CreatedNew(ref vbytes) = status {
+            self.notify(&kbytes, None, Some(vbytes))?;
+        }
+        Ok(status.was_created())
+    }
+
+    /// Same as [CandyStore::remove] but serializes the key
+    pub fn remove<Q: ?Sized + Encode>(&self, k: &Q) -> Result<Option<V>>
+    where
+        K: Borrow<Q>,
+    {
+        let kbytes = self.make_key(k)?;
+        if let Some(vbytes) = self.store.remove_raw(&kbytes)? {
+            let old = self.decode_val(&vbytes)?;
+            self.notify(&kbytes, Some(&old), None)?;
+            Ok(Some(old))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Same as [Self::remove], but only returns whether the key existed, without decoding its previous value
+    /// (unless an on-change callback is registered, see [Self::with_on_change], which needs it)
+    pub fn delete<Q: ?Sized + Encode>(&self, k: &Q) -> Result<bool>
+    where
+        K: Borrow<Q>,
+    {
+        if self.on_change.is_some() {
+            return Ok(self.remove(k)?.is_some());
+        }
+        let kbytes = self.make_key(k)?;
+        let removed = self.store.remove_raw(&kbytes)?.is_some();
+        self.invalidate(&kbytes);
+        Ok(removed)
+    }
+
+    /// Removes all the given keys, returning the number of keys that existed. See [CandyStore::remove_many]
+    /// for the (non-)atomicity guarantees and error reporting
+    pub fn remove_many<Q: ?Sized + Encode>(&self, keys: &[&Q]) -> Result<usize>
+    where
+        K: Borrow<Q>,
+    {
+        let full_keys = keys
+            .iter()
+            .map(|k| self.make_key(*k))
+            .collect::<Result<_>>()?;
+        self.remove_full_keys(full_keys)
+    }
+
+    /// Exchanges the values of the two given keys. If only one of them exists, its value is moved to the
+    /// other key (and it is removed), and if neither exists, this is a no-op.
+    ///
+    /// The swap is atomic: the shard rows of both keys are locked (in a deadlock-safe order, sorted by shard)
+    /// throughout, so concurrent writes to either key take place either before or after the swap (and are
+    /// never lost), and nothing is written unless both keys fit. Note that it is not crash-safe
+    pub fn swap<Q: ?Sized + Encode>(&self, key1: &Q, key2: &Q) -> Result<()>
+    where
+        K: Borrow<Q>,
+    {
+        let kbytes1 = self.make_key(key1)?;
+        let kbytes2 = self.make_key(key2)?;
+        self.store.swap_raw(&kbytes1, &kbytes2)?;
+        self.invalidate(&kbytes1);
+        self.invalidate(&kbytes2);
+        Ok(())
+    }
+
+    /// Copies the value of `src` to `dst`, without decoding and re-encoding it. Returns false (and does
+    /// nothing) if `src` does not exist. An existing `dst` is overwritten, just like [Self::set] (including
+    /// getting a new sequence number, if enabled, and notifying the on-change callback)
+    pub fn copy<Q: ?Sized + Encode>(&self, src: &Q, dst: &Q) -> Result<bool>
+    where
+        K: Borrow<Q>,
+    {
+        let Some(src_vbytes) = self.store.get_raw(&self.make_key(src)?)? else {
+            return Ok(false);
+        };
+        let mut vbytes = self.split_seq(&src_vbytes)?.0.to_vec();
+        self.append_tag::<V>(&mut vbytes)?;
+        if self.has_seq() {
+            vbytes.extend_from_slice(&self.store.next_seq()?.to_le_bytes());
+        }
+
+        let kbytes = self.make_key(dst)?;
+        let old = match self.store.set_raw(&kbytes, &vbytes)? {
+            SetStatus::CreatedNew => None,
+            SetStatus::PrevValue(v) if self.on_change.is_some() => Some(self.decode_val(&v)?),
+            SetStatus::PrevValue(_) => None,
+        };
+        self.notify(&kbytes, old.as_ref(), Some(&vbytes))?;
+        Ok(true)
+    }
+
+    /// Same as [CandyStore::contains_big] but serializes the key
+    pub fn contains_big<Q: ?Sized + Encode>(&self, key: &Q) -> Result<bool>
+    where
+        K: Borrow<Q>,
+    {
+        self.store.contains_big(&self.make_key(key)?)
+    }
+
+    /// Same as [CandyStore::get_big] but serializes the key and deserializes the value
+    pub fn get_big<Q: ?Sized + Encode>(&self, key: &Q) -> Result<Option<V>>
+    where
+        K: Borrow<Q>,
+    {
+        let kbytes = self.make_key(key)?;
+        if let Some(vbytes) = self.store.get_big(&kbytes)? {
+            Ok(Some(from_bytes::<V>(&vbytes)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Same as [CandyStore::set_big] but serializes the key and the value.
+    pub fn set_big<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
+        &self,
+        key: &Q1,
+        val: &Q2,
+    ) -> Result<bool>
+    where
+        K: Borrow<Q1>,
+        V: Borrow<Q2>,
+    {
+        let kbytes = self.make_key(key)?;
+        let vbytes = val.to_bytes::<LE>();
+        self.store.set_big(&kbytes, &vbytes)
+    }
+
+    /// Stores the value either inline (like [Self::set]) or as a big value (like [Self::set_big]), according to
+    /// its serialized size and [crate::Config::auto_big_value_threshold]. The entry is tagged with the mechanism
+    /// that was used, so it must be read by [Self::get_auto] and removed by [Self::remove_auto] (and not by
+    /// the non-auto variants). Returns true if the key had existed before, false otherwise.
+    ///
+    /// Note: **not crash-safe** when switching between the two mechanisms
+    pub fn set_auto<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
+        &self,
+        key: &Q1,
+        val: &Q2,
+    ) -> Result<bool>
+    where
+        K: Borrow<Q1>,
+        V: Borrow<Q2>,
+    {
+        let kbytes = self.make_key(key)?;
+        // the old value must be read before a big value replaces it
+        let old = match self.on_change {
+            Some(_) => self.get_auto_full_key(&kbytes)?,
+            None => None,
+        };
+        let vbytes = val.to_bytes::<LE>();
+        let new = match self.on_change {
+            Some(_) => Some(from_bytes::<V>(&vbytes)?),
+            None => None,
+        };
+        let inline_overhead = 1
+            + if self.has_tag() { size_of::<u32>() } else { 0 }
+            + if self.has_seq() { size_of::<u64>() } else { 0 };
+        let threshold = self
+            .store
+            .config
+            .auto_big_value_threshold
+            .min(self.store.config.max_inline_value)
+            .min(MAX_VALUE_SIZE - inline_overhead);
+
+        let mut tagged = vec![];
+        if vbytes.len() > threshold {
+            self.store.set_big(&kbytes, &vbytes)?;
+            tagged.push(AUTO_TAG_BIG);
+        } else {
+            // inline values are compressed like those stored by set (big values never are)
+            tagged.push(AUTO_TAG_INLINE);
+            tagged.extend_from_slice(&self.compress_val(vbytes));
+        }
+        self.append_tag::<V>(&mut tagged)?;
+        if self.has_seq() {
+            tagged.extend_from_slice(&self.store.next_seq()?.to_le_bytes());
+        }
+
+        let existed = match self.store.set_raw(&kbytes, &tagged)? {
+            SetStatus::CreatedNew => false,
+            SetStatus::PrevValue(prev) => {
+                if tagged[0] == AUTO_TAG_INLINE && prev.first() == Some(&AUTO_TAG_BIG) {
+                    self.store.remove_big(&kbytes)?;
+                }
+                true
+            }
+        };
+        self.notify_decoded(&kbytes, old.as_ref(), new.as_ref())?;
+        Ok(existed)
+    }
+
+    /// Gets a value that was stored by [Self::set_auto], from whichever mechanism was used to store it
+    pub fn get_auto<Q: ?Sized + Encode>(&self, key: &Q) -> Result<Option<V>>
+    where
+        K: Borrow<Q>,
+    {
+        self.get_auto_full_key(&self.make_key(key)?)
+    }
+
+    fn get_auto_full_key(&self, kbytes: &[u8]) -> Result<Option<V>> {
+        let Some(tagged) = self.store.get_raw(kbytes)? else {
+            return Ok(None);
+        };
+        match tagged.first() {
+            Some(&AUTO_TAG_INLINE) => Ok(Some(self.decode_val(&tagged[1..])?)),
+            Some(&AUTO_TAG_BIG) => {
+                // only verifies the value type tag (if enabled), the value itself is stored separately
+                self.split_seq(&tagged[1..])?;
+                match self.store.get_big(kbytes)? {
+                    Some(vbytes) => Ok(Some(from_bytes::<V>(&vbytes)?)),
+                    None => Ok(None),
+                }
+            }
+            _ => Err(anyhow!(CandyError::WrongType("set_auto"))),
+        }
+    }
+
+    /// Removes a value that was stored by [Self::set_auto]. Returns true if the key had existed, false otherwise
+    pub fn remove_auto<Q: ?Sized + Encode>(&self, key: &Q) -> Result<bool>
+    where
+        K: Borrow<Q>,
+    {
+        let kbytes = self.make_key(key)?;
+        // the old value must be read before a big value is removed
+        let old = match self.on_change {
+            Some(_) => self.get_auto_full_key(&kbytes)?,
+            None => None,
+        };
+        let Some(tagged) = self.store.remove_raw(&kbytes)? else {
+            return Ok(false);
+        };
+        if tagged.first() == Some(&AUTO_TAG_BIG) {
+            self.store.remove_big(&kbytes)?;
+        }
+        self.notify_decoded(&kbytes, old.as_ref(), None)?;
+        Ok(true)
+    }
+
+    /// Same as [CandyStore::set_big_stream] but serializes the key. The data written should be the
+    /// serialization of a `V`, if it is to be read later by [Self::get_big]
+    pub fn set_big_stream<Q: ?Sized + Encode>(&self, key: &Q) -> Result<BigValueWriter<'_>>
+    where
+        K: Borrow<Q>,
+    {
+        self.store.set_big_stream(&self.make_key(key)?)
+    }
+
+    /// Same as [CandyStore::get_big_stream] but serializes the key. The reader returns the serialized value
+    pub fn get_big_stream<Q: ?Sized + Encode>(&self, key: &Q) -> Result<Option<BigValueReader<'_>>>
+    where
+        K: Borrow<Q>,
+    {
+        self.store.get_big_stream(&self.make_key(key)?)
+    }
+
+    /// Same as [CandyStore::remove_big] but serializes the key
+    pub fn remove_big<Q: ?Sized + Encode>(&self, k: &Q) -> Result<bool>
+    where
+        K: Borrow<Q>,
+    {
+        let kbytes = self.make_key(k)?;
+        self.store.remove_big(&kbytes)
+    }
+}
+
+/// Helpers for stores of optional values (`V = Option<T>`), which can tell a key that's known to have no value
+/// (stored as `None`, e.g., a negative cache entry) from a key that was never set. For such stores, [Self::get]
+/// returns `Ok(None)` if the key does not exist, `Ok(Some(None))` if it's null and `Ok(Some(Some(val)))` if it
+/// has a value. Use [Self::get_flat] when the distinction does not matter.
+///
+/// Example:
+/// ```
+/// use std::sync::Arc;
+/// use candystore::{CandyStore, CandyTypedStore, Config, Result};
+///
+/// fn main() -> Result<()> {
+///     let db = Arc::new(CandyStore::open("/tmp/candy-nullable-dir", Config::default())?);
+///     let emails = CandyTypedStore::<String, Option<String>>::new(db);
+///
+///     emails.set("alice", &Some("alice@example.com".to_owned()))?;
+///     emails.set_null("bob")?;
+///
+///     assert_eq!(emails.get("bob")?, Some(None));
+///     assert!(emails.is_null("bob")?);
+///     assert_eq!(emails.get_flat("bob")?, None);
+///     assert_eq!(emails.get("carol")?, None);
+///     assert!(!emails.is_null("carol")?);
+///     assert_eq!(emails.get_flat("alice")?, Some("alice@example.com".to_owned()));
+///     Ok(())
+/// }
+/// ```
+impl<K, T> CandyTypedStore<K, Option<T>>
+where
+    K: CandyTypedKey,
+    Option<T>: Encode + DecodeOwned,
+{
+    /// Same as [Self::get], but collapses a missing key and a null value into `None`
+    pub fn get_flat<Q: ?Sized + Encode>(&self, key: &Q) -> Result<Option<T>>
+    where
+        K: Borrow<Q>,
+    {
+        Ok(self.get(key)?.flatten())
+    }
+
+    /// Sets the key to null (`None`), marking it as known to have no value. Returns the previous value, like
+    /// [Self::set]
+    pub fn set_null<Q: ?Sized + Encode>(&self, key: &Q) -> Result<Option<Option<T>>>
+    where
+        K: Borrow<Q>,
+    {
+        self.set(key, &None)
+    }
+
+    /// Returns true if the key exists and is null, false if it has a value or does not exist
+    pub fn is_null<Q: ?Sized + Encode>(&self, key: &Q) -> Result<bool>
+    where
+        K: Borrow<Q>,
+    {
+        Ok(matches!(self.get(key)?, Some(None)))
+    }
+}
+
+/// Helpers for stores of byte buffers (`V = Vec<u8>`), which take and return other buffer types, like
+/// `bytes::Bytes`, directly. [Self::set_bytes] takes any `AsRef<[u8]>` and encodes it as it is (it's stored
+/// exactly like the equivalent `Vec<u8>`), and [Self::get_bytes] hands the decoded vector over to any
+/// `From<Vec<u8>>` type, which is free for `Bytes` (and `Box<[u8]>`), so no copy is made on either side
+/// beyond the encoding and decoding themselves.
+///
+/// Example:
+/// ```
+/// use std::sync::Arc;
+/// use candystore::{CandyStore, CandyTypedStore, Config, Result};
+///
+/// fn main() -> Result<()> {
+///     let db = Arc::new(CandyStore::open("/tmp/candy-bytes-dir", Config::default())?);
+///     let responses = CandyTypedStore::<String, Vec<u8>>::new(db);
+///
+///     let body: Box<[u8]> = b"<html></html>".as_slice().into();
+///     responses.set_bytes("/index.html", &body)?;
+///
+///     let cached: Box<[u8]> = responses.get_bytes("/index.html")?.unwrap();
+///     assert_eq!(cached, body);
+///     Ok(())
+/// }
+/// ```
+impl<K> CandyTypedStore<K, Vec<u8>>
+where
+    K: CandyTypedKey,
+{
+    /// Same as [Self::set], but takes the value as any byte buffer (e.g., `bytes::Bytes`), without converting it
+    /// to a vector first
+    pub fn set_bytes<Q: ?Sized + Encode, B: ?Sized + AsRef<[u8]>>(
+        &self,
+        key: &Q,
+        val: &B,
+    ) -> Result<Option<Vec<u8>>>
+    where
+        K: Borrow<Q>,
+    {
+        self.set(key, val.as_ref())
+    }
+
+    /// Same as [Self::get], but returns the value as any buffer type that can take over a vector (e.g.,
+    /// `bytes::Bytes`)
+    pub fn get_bytes<Q: ?Sized + Encode, B: From<Vec<u8>>>(&self, key: &Q) -> Result<Option<B>>
+    where
+        K: Borrow<Q>,
+    {
+        Ok(self.get(key)?.map(B::from))
+    }
+}
+
+/// A typed [ListBatch], passed to the closure of [CandyTypedList::batch_in_list]
+pub struct CandyTypedListBatch<'a, K, V> {
+    batch: ListBatch<'a>,
+    _phantom: PhantomData<(K, V)>,
+}
+
+impl<K, V> CandyTypedListBatch<'_, K, V>
+where
+    K: Encode + DecodeOwned,
+    V: Encode + DecodeOwned,
+{
+    /// Same as [ListBatch::get], but `item_key` is typed
+    pub fn get<Q: ?Sized + Encode>(&self, item_key: &Q) -> Result<Option<V>>
+    where
+        K: Borrow<Q>,
+    {
+        match self.batch.get(&item_key.to_bytes::<LE>())? {
+            Some(vbytes) => Ok(Some(from_bytes::<V>(&vbytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Same as [ListBatch::set], but `item_key` and `val` are typed. Returns the previous value, if any
+    pub fn set<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
+        &mut self,
+        item_key: &Q1,
+        val: &Q2,
+    ) -> Result<Option<V>>
+    where
+        K: Borrow<Q1>,
+        V: Borrow<Q2>,
+    {
+        match self
+            .batch
+            .set(&item_key.to_bytes::<LE>(), &val.to_bytes::<LE>())?
+        {
+            SetStatus::CreatedNew => Ok(None),
+            SetStatus::PrevValue(v) => Ok(Some(from_bytes::<V>(&v)?)),
+        }
+    }
+
+    /// Same as [ListBatch::remove], but `item_key` is typed
+    pub fn remove<Q: ?Sized + Encode>(&mut self, item_key: &Q) -> Result<Option<V>>
+    where
+        K: Borrow<Q>,
+    {
+        match self.batch.remove(&item_key.to_bytes::<LE>())? {
+            Some(vbytes) => Ok(Some(from_bytes::<V>(&vbytes)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// A wrapper around [CandyStore] that exposes the list API in a typed manner. See [CandyTypedStore] for more
+/// info
+///
+/// Example:
+/// ```
+/// use std::sync::Arc;
+/// use candystore::{CandyStore, CandyTypedList, Config, Result};
+///
+/// fn main() -> Result<()> {
+///     let db = Arc::new(CandyStore::open("/tmp/candy-typed-list-dir", Config::default())?);
+///     let typed = CandyTypedList::<String, String, String>::new(db);
+///
+///     typed.set("italian", "bye", "arrivederci")?;
+///     assert_eq!(typed.get_or_create("italian", "thanks", "grazie")?, "grazie");
+///     assert_eq!(typed.replace("italian", "bye", "ciao", None)?, Some("arrivederci".to_owned()));
+///     assert_eq!(typed.get("italian", "bye")?, Some("ciao".to_owned()));
+///     typed.discard("italian")?;
+///     Ok(())
+/// }
+/// ```
+pub struct CandyTypedList<L, K, V> {
+    store: Arc<CandyStore>,
+    _phantom: PhantomData<(L, K, V)>,
+}
+
+impl<L, K, V> Clone for CandyTypedList<L, K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+            _phantom: Default::default(),
+        }
+    }
+}
+
+impl<L, K, V> CandyTypedList<L, K, V>
+where
+    L: CandyTypedKey,
+    K: Encode + DecodeOwned,
+    V: Encode + DecodeOwned,
+{
+    /// Constructs a [CandyTypedList] over an existing [CandyStore]. In debug builds, this panics if
+    /// `L::TYPE_ID` was already used by a different key type (see [Self::new_checked])
+    pub fn new(store: Arc<CandyStore>) -> Self {
+        debug_register_type_id::<L>();
+        Self {
+            store,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Same as [Self::new], but returns [CandyError::TypeIdCollision] if `L::TYPE_ID` was already used by a
+    /// different key type, regardless of the build type
+    pub fn new_checked(store: Arc<CandyStore>) -> Result<Self> {
+        register_type_id::<L>()?;
+        Ok(Self {
+            store,
+            _phantom: PhantomData,
+        })
+    }
+
+    fn make_list_key<Q: ?Sized + Encode>(list_key: &Q) -> Vec<u8>
+    where
+        L: Borrow<Q>,
+    {
+        let mut kbytes = list_key.to_bytes::<LE>();
+        kbytes.extend_from_slice(bytes_of(&L::TYPE_ID));
+        kbytes
+    }
+
+    /// Tests if the given typed `item_key` exists in this list (identified by `list_key`)
+    pub fn contains<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
+        &self,
+        list_key: &Q1,
+        item_key: &Q2,
+    ) -> Result<bool>
+    where
+        L: Borrow<Q1>,
+        K: Borrow<Q2>,
+    {
+        let list_key = Self::make_list_key(list_key);
+        let item_key = item_key.to_bytes::<LE>();
+        Ok(self
+            .store
+            .owned_get_from_list(list_key, item_key)?
+            .is_some())
+    }
+
+    /// Tests if the given typed `item_key` exists in this list (identified by `list_key`) with exactly the given
+    /// value. The stored value is compared to the serialized `val` without being decoded, so this is cheaper than
+    /// [Self::get] and comparing (note that it compares encodings, so values that compare equal but encode
+    /// differently are considered different). Returns false if the item does not exist
+    pub fn contains_value<Q1: ?Sized + Encode, Q2: ?Sized + Encode, Q3: ?Sized + Encode>(
+        &self,
+        list_key: &Q1,
+        item_key: &Q2,
+        val: &Q3,
+    ) -> Result<bool>
+    where
+        L: Borrow<Q1>,
+        K: Borrow<Q2>,
+        V: Borrow<Q3>,
+    {
+        let list_key = Self::make_list_key(list_key);
+        let item_key = item_key.to_bytes::<LE>();
+        Ok(self
+            .store
+            .owned_get_from_list(list_key, item_key)?
+            .is_some_and(|vbytes| vbytes == val.to_bytes::<LE>()))
+    }
+
+    /// Returns the first of the given `item_keys` that exists in this list (identified by `list_key`), or None
+    /// if none of them do. See [CandyStore::list_contains_any]
+    pub fn contains_any<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
+        &self,
+        list_key: &Q1,
+        item_keys: &[&Q2],
+    ) -> Result<Option<K>>
+    where
+        L: Borrow<Q1>,
+        K: Borrow<Q2>,
+    {
+        let list_key = Self::make_list_key(list_key);
+        let mut item_keys = item_keys
+            .iter()
+            .map(|k| k.to_bytes::<LE>())
+            .collect::<Vec<_>>();
+        let Some(i) = self
+            .store
+            .owned_list_contains_any(list_key, item_keys.clone())?
+        else {
+            return Ok(None);
+        };
+        Ok(Some(from_bytes::<K>(&item_keys.swap_remove(i))?))
+    }
+
+    /// Same as [CandyStore::get_from_list], but `list_key` and `item_key` are typed
+    pub fn get<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
+        &self,
+        list_key: &Q1,
+        item_key: &Q2,
+    ) -> Result<Option<V>>
+    where
+        L: Borrow<Q1>,
+        K: Borrow<Q2>,
+    {
+        let list_key = Self::make_list_key(list_key);
+        let item_key = item_key.to_bytes::<LE>();
+        if let Some(vbytes) = self.store.owned_get_from_list(list_key, item_key)? {
+            Ok(Some(from_bytes::<V>(&vbytes)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn _set<Q1: ?Sized + Encode, Q2: ?Sized + Encode, Q3: ?Sized + Encode>(
+        &self,
+        list_key: &Q1,
+        item_key: &Q2,
+        val: &Q3,
+        promote: bool,
+    ) -> Result<Option<V>>
+    where
+        L: Borrow<Q1>,
+        K: Borrow<Q2>,
+        V: Borrow<Q3>,
+    {
+        let list_key = Self::make_list_key(list_key);
+        let item_key = item_key.to_bytes::<LE>();
+        let val = val.to_bytes::<LE>();
+        match self
+            .store
+            .owned_set_in_list(list_key, item_key, val, promote)?
+        {
+            SetStatus::CreatedNew => Ok(None),
+            SetStatus::PrevValue(v) => Ok(Some(from_bytes::<V>(&v)?)),
+        }
+    }
+
+    /// Same as [CandyStore::set_in_list], but `list_key`, `item_key` and `val` are typed
+    pub fn set<Q1: ?Sized + Encode, Q2: ?Sized + Encode, Q3: ?Sized + Encode>(
+        &self,
+        list_key: &Q1,
+        item_key: &Q2,
+        val: &Q3,
+    ) -> Result<Option<V>>
+    where
+        L: Borrow<Q1>,
+        K: Borrow<Q2>,
+        V: Borrow<Q3>,
+    {
+        self._set(list_key, item_key, val, false)
+    }
+
+    /// Same as [CandyStore::set_in_list_and_len], but `list_key`, `item_key` and `val` are typed. Returns
+    /// the length of the list after the insertion
+    pub fn set_and_len<Q1: ?Sized + Encode, Q2: ?Sized + Encode, Q3: ?Sized + Encode>(
+        &self,
+        list_key: &Q1,
+        item_key: &Q2,
+        val: &Q3,
+    ) -> Result<usize>
+    where
+        L: Borrow<Q1>,
+        K: Borrow<Q2>,
+        V: Borrow<Q3>,
+    {
+        let list_key = Self::make_list_key(list_key);
+        let item_key = item_key.to_bytes::<LE>();
+        let val = val.to_bytes::<LE>();
+        let (_, len) = self
+            .store
+            .owned_set_in_list_and_len(list_key, item_key, val)?;
+        Ok(len)
+    }
+
+    /// Same as [CandyStore::extend_list], but `list_key` and the items are typed. Items are serialized as they
+    /// are inserted, and new ones are appended in the given order
+    pub fn extend<Q: ?Sized + Encode>(
+        &self,
+        list_key: &Q,
+        items: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<usize>
+    where
+        L: Borrow<Q>,
+    {
+        let list_key = Self::make_list_key(list_key);
+        self.store.extend_list(
+            &list_key,
+            items
+                .into_iter()
+                .map(|(k, v)| (k.to_bytes::<LE>(), v.to_bytes::<LE>())),
+        )
+    }
+
+    /// Same as [CandyStore::batch_in_list], but `list_key` and the batch's items are typed. The list's lock is
+    /// held for the whole duration of `f`, so `f` must not operate on the list other than through the batch
+    pub fn batch_in_list<Q1: ?Sized + Encode, F, T>(&self, list_key: &Q1, f: F) -> Result<T>
+    where
+        L: Borrow<Q1>,
+        F: FnOnce(&mut CandyTypedListBatch<K, V>) -> Result<T>,
+    {
+        let mut batch = CandyTypedListBatch {
+            batch: self
+                .store
+                .lock_list_for_batch(Self::make_list_key(list_key)),
+            _phantom: PhantomData,
+        };
+        f(&mut batch)
+    }
+
+    /// Same as [WriteBatch::set_in_list], but `list_key`, `item_key` and `val` are typed
+    pub fn stage_set<Q1: ?Sized + Encode, Q2: ?Sized + Encode, Q3: ?Sized + Encode>(
+        &self,
+        batch: &mut WriteBatch,
+        list_key: &Q1,
+        item_key: &Q2,
+        val: &Q3,
+    ) -> Result<()>
+    where
+        L: Borrow<Q1>,
+        K: Borrow<Q2>,
+        V: Borrow<Q3>,
+    {
+        batch.set_in_list(
+            &Self::make_list_key(list_key),
+            &item_key.to_bytes::<LE>(),
+            &val.to_bytes::<LE>(),
+        )
+    }
+
+    /// Same as [WriteBatch::remove_from_list], but `list_key` and `item_key` are typed
+    pub fn stage_remove<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
+        &self,
+        batch: &mut WriteBatch,
+        list_key: &Q1,
+        item_key: &Q2,
+    ) -> Result<()>
+    where
+        L: Borrow<Q1>,
+        K: Borrow<Q2>,
+    {
+        batch.remove_from_list(&Self::make_list_key(list_key), &item_key.to_bytes::<LE>())
+    }
+
+    /// Same as [CandyStore::set_in_list_promoting], but `list_key`, `item_key` and `val` are typed
+    pub fn set_promoting<Q1: ?Sized + Encode, Q2: ?Sized + Encode, Q3: ?Sized + Encode>(
+        &self,
+        list_key: &Q1,
+        item_key: &Q2,
+        val: &Q3,
+    ) -> Result<Option<V>>
+    where
+        L: Borrow<Q1>,
+        K: Borrow<Q2>,
+        V: Borrow<Q3>,
+    {
+        self._set(list_key, item_key, val, true)
+    }
+
+    /// Pushes an item to the tail of the list (its newest end) without duplicating it: if `item_key` already
+    /// exists, the existing item is moved to the tail and its value is replaced by `val`, instead of adding
+    /// another item. Returns true if the item was newly inserted, false if an existing one was promoted.
+    ///
+    /// This is [Self::set_promoting], except for the return value. Note that items are deduplicated by
+    /// `item_key` (as each key appears at most once per list), not by value - to deduplicate by value, use the
+    /// value itself (or a digest of it) as the item key. For a "recently viewed" list, [Self::iter_backwards]
+    /// and [Self::peek_tail] yield the most recently pushed items first
+    pub fn push_unique<Q1: ?Sized + Encode, Q2: ?Sized + Encode, Q3: ?Sized + Encode>(
+        &self,
+        list_key: &Q1,
+        item_key: &Q2,
+        val: &Q3,
+    ) -> Result<bool>
+    where
+        L: Borrow<Q1>,
+        K: Borrow<Q2>,
+        V: Borrow<Q3>,
+    {
+        let list_key = Self::make_list_key(list_key);
+        let item_key = item_key.to_bytes::<LE>();
+        let val = val.to_bytes::<LE>();
+        Ok(self
+            .store
+            .owned_set_in_list(list_key, item_key, val, true)?
+            .was_created())
+    }
+
+    /// Same as [CandyStore::get_or_create_in_list], but `list_key`, `item_key` and `default_val` are typed
+    pub fn get_or_create<Q1: ?Sized + Encode, Q2: ?Sized + Encode, Q3: ?Sized + Encode>(
+        &self,
+        list_key: &Q1,
+        item_key: &Q2,
+        default_val: &Q3,
+    ) -> Result<V>
+    where
+        L: Borrow<Q1>,
+        K: Borrow<Q2>,
+        V: Borrow<Q3>,
+    {
+        Ok(self
+            .get_or_create_status(list_key, item_key, default_val)?
+            .0)
+    }
+
+    /// Same as [Self::get_or_create], but also returns whether the item was inserted by this call (`true`)
+    /// or had already existed (`false`)
+    pub fn get_or_create_status<Q1: ?Sized + Encode, Q2: ?Sized + Encode, Q3: ?Sized + Encode>(
+        &self,
+        list_key: &Q1,
+        item_key: &Q2,
+        default_val: &Q3,
+    ) -> Result<(V, bool)>
+    where
+        L: Borrow<Q1>,
+        K: Borrow<Q2>,
+        V: Borrow<Q3>,
+    {
+        let list_key = Self::make_list_key(list_key);
+        let item_key = item_key.to_bytes::<LE>();
+        let default_val = default_val.to_bytes::<LE>();
+        let status = self
+            .store
+            .owned_get_or_create_in_list(list_key, item_key, default_val)?;
+        let created = status.was_created();
+        Ok((from_bytes::<V>(&status.value())?, created))
+    }
+
+    /// Same as [CandyStore::insert_sorted_in_list], but `list_key`, `item_key` and `val` are typed, and `cmp`
+    /// compares the new value (first argument) with the existing ones
+    pub fn insert_sorted<Q1: ?Sized + Encode, Q2: ?Sized + Encode, Q3: ?Sized + Encode>(
+        &self,
+        list_key: &Q1,
+        item_key: &Q2,
+        val: &Q3,
+        cmp: impl Fn(&V, &V) -> Ordering,
+    ) -> Result<()>
+    where
+        L: Borrow<Q1>,
+        K: Borrow<Q2>,
+        V: Borrow<Q3>,
+    {
+        let list_key = Self::make_list_key(list_key);
+        let item_key = item_key.to_bytes::<LE>();
+        let val = val.to_bytes::<LE>();
+        let tval = from_bytes::<V>(&val)?;
+        self.store
+            .owned_insert_sorted_in_list(list_key, item_key, val, |_, existing| {
+                Ok(cmp(&tval, &from_bytes::<V>(existing)?))
+            })
+    }
+
+    /// Same as [CandyStore::binary_search_in_list], but `list_key` is typed and `f` is given the typed values.
+    /// The list must be sorted consistently with `f`, e.g., by always inserting with [Self::insert_sorted] using
+    /// the same ordering
+    pub fn binary_search_by<Q: ?Sized + Encode>(
+        &self,
+        list_key: &Q,
+        f: impl Fn(&V) -> Ordering,
+    ) -> Result<Option<(K, V)>>
+    where
+        L: Borrow<Q>,
+    {
+        let list_key = Self::make_list_key(list_key);
+        let Some((k, v)) = self
+            .store
+            .owned_binary_search_in_list(list_key, |v| Ok(f(&from_bytes::<V>(v)?)))?
+        else {
+            return Ok(None);
+        };
+        Ok(Some((from_bytes::<K>(&k)?, from_bytes::<V>(&v)?)))
+    }
+
+    /// Same as [CandyStore::replace_in_list], but `list_key`, `item_key` and `val` are typed
+    pub fn replace<Q1: ?Sized + Encode, Q2: ?Sized + Encode, Q3: ?Sized + Encode>(
+        &self,
+        list_key: &Q1,
+        item_key: &Q2,
+        val: &Q3,
+        expected_val: Option<&Q3>,
+    ) -> Result<Option<V>>
+    where
+        L: Borrow<Q1>,
+        K: Borrow<Q2>,
+        V: Borrow<Q3>,
+    {
+        let list_key = Self::make_list_key(list_key);
+        let item_key = item_key.to_bytes::<LE>();
+        let val = val.to_bytes::<LE>();
+        let ebytes = expected_val
+            .map(|ev| ev.to_bytes::<LE>())
+            .unwrap_or_default();
+        match self.store.owned_replace_in_list(
+            list_key,
+            item_key,
+            val,
+            expected_val.map(|_| &*ebytes),
+        )? {
+            ReplaceStatus::DoesNotExist => Ok(None),
+            ReplaceStatus::PrevValue(v) => Ok(Some(from_bytes::<V>(&v)?)),
+            ReplaceStatus::WrongValue(_) => Ok(None),
+        }
+    }
+
+    /// Same as [CandyStore::remove_from_list], but `list_key` and `item_key`  are typed
+    pub fn remove<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
+        &self,
+        list_key: &Q1,
+        item_key: &Q2,
+    ) -> Result<Option<V>>
+    where
+        L: Borrow<Q1>,
+        K: Borrow<Q2>,
+    {
+        let list_key = Self::make_list_key(list_key);
+        let item_key = item_key.to_bytes::<LE>();
+        if let Some(vbytes) = self.store.owned_remove_from_list(list_key, item_key)? {
+            Ok(Some(from_bytes::<V>(&vbytes)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Same as [CandyStore::remove_from_list_if], but `list_key`, `item_key` and `expected_val` are typed.
+    /// Returns false if the item does not exist or its value is not `expected_val`
+    pub fn remove_if<Q1: ?Sized + Encode, Q2: ?Sized + Encode, Q3: ?Sized + Encode>(
+        &self,
+        list_key: &Q1,
+        item_key: &Q2,
+        expected_val: &Q3,
+    ) -> Result<bool>
+    where
+        L: Borrow<Q1>,
+        K: Borrow<Q2>,
+        V: Borrow<Q3>,
+    {
+        let list_key = Self::make_list_key(list_key);
+        let item_key = item_key.to_bytes::<LE>();
+        let expected_val = expected_val.to_bytes::<LE>();
+        self.store
+            .owned_remove_from_list_if(list_key, item_key, &expected_val)
+    }
+
+    /// Same as [CandyStore::iter_list], but `list_key` is typed
+    pub fn iter<'a, Q: ?Sized + Encode>(
+        &'a self,
+        list_key: &Q,
+    ) -> impl Iterator<Item = Result<(K, V)>> + 'a
+    where
+        L: Borrow<Q>,
+    {
+        let list_key = Self::make_list_key(list_key);
+        self.store.owned_iter_list(list_key).map(|res| match res {
+            Err(e) => Err(e),
+            Ok((k, v)) => {
+                let key = from_bytes::<K>(&k)?;
+                let val = from_bytes::<V>(&v)?;
+                Ok((key, val))
+            }
+        })
+    }
+
+    /// Same as [CandyStore::list_page], but `list_key` is typed
+    pub fn page<Q: ?Sized + Encode>(
+        &self,
+        list_key: &Q,
+        cursor: Option<ListCursor>,
+        limit: usize,
+    ) -> Result<ListPage<(K, V)>>
+    where
+        L: Borrow<Q>,
+    {
+        let list_key = Self::make_list_key(list_key);
+        let (kvs, next) = self.store.owned_list_page(list_key, cursor, limit)?;
+        let mut items = Vec::with_capacity(kvs.len());
+        for (k, v) in kvs {
+            items.push((from_bytes::<K>(&k)?, from_bytes::<V>(&v)?));
+        }
+        Ok((items, next))
+    }
+
+    /// Same as [CandyStore::iter_list_backwards], but `list_key` is typed
+    pub fn iter_backwards<'a, Q: ?Sized + Encode>(
+        &'a self,
+        list_key: &Q,
+    ) -> impl Iterator<Item = Result<(K, V)>> + 'a
+    where
+        L: Borrow<Q>,
+    {
+        let list_key = Self::make_list_key(list_key);
+        self.store
+            .owned_iter_list_backwards(list_key)
+            .map(|res| match res {
+                Err(e) => Err(e),
+                Ok((k, v)) => {
+                    let key = from_bytes::<K>(&k)?;
+                    let val = from_bytes::<V>(&v)?;
+                    Ok((key, val))
+                }
+            })
+    }
+
+    /// Collects the items of the list (in order) into a `Vec`. The `Vec` is preallocated according to the list's
+    /// length, but since the list is not locked while it's being read, concurrent changes may be reflected
+    pub fn to_vec<Q: ?Sized + Encode>(&self, list_key: &Q) -> Result<Vec<(K, V)>>
+    where
+        L: Borrow<Q>,
+    {
+        let mut items = Vec::with_capacity(self.len(list_key)?);
+        for res in self.iter(list_key) {
+            items.push(res?);
+        }
+        Ok(items)
+    }
+
+    /// Same as [Self::to_vec], but only collects the values
+    pub fn values<Q: ?Sized + Encode>(&self, list_key: &Q) -> Result<Vec<V>>
+    where
+        L: Borrow<Q>,
+    {
+        let mut vals = Vec::with_capacity(self.len(list_key)?);
+        for res in self.store.owned_iter_list(Self::make_list_key(list_key)) {
+            vals.push(from_bytes::<V>(&res?.1)?);
+        }
+        Ok(vals)
+    }
+
+    /// Same as [Self::to_vec], but only collects the keys (the values are not decoded)
+    pub fn keys<Q: ?Sized + Encode>(&self, list_key: &Q) -> Result<Vec<K>>
+    where
+        L: Borrow<Q>,
+    {
+        let mut keys = Vec::with_capacity(self.len(list_key)?);
+        for res in self.store.owned_iter_list(Self::make_list_key(list_key)) {
+            keys.push(from_bytes::<K>(&res?.0)?);
+        }
+        Ok(keys)
+    }
+
+    /// Same as [CandyStore::discard_list], but `list_key` is typed
+    pub fn discard<Q: ?Sized + Encode>(&self, list_key: &Q) -> Result<bool>
+    where
+        L: Borrow<Q>,
+    {
+        let list_key = Self::make_list_key(list_key);
+        self.store.owned_discard_list(list_key)
+    }
+
+    /// Same as [CandyStore::rename_list], but `from` and `to` are typed
+    pub fn rename<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
+        &self,
+        from: &Q1,
+        to: &Q2,
+    ) -> Result<bool>
+    where
+        L: Borrow<Q1>,
+        L: Borrow<Q2>,
+    {
+        let from = Self::make_list_key(from);
+        let to = Self::make_list_key(to);
+        self.store.owned_rename_list(from, to)
+    }
+
+    /// Same as [CandyStore::compact_list_if_needed], but `list_key` is typed
+    pub fn compact_if_needed<Q: ?Sized + Encode>(
+        &self,
+        list_key: &Q,
+        params: ListCompactionParams,
+    ) -> Result<bool>
+    where
+        L: Borrow<Q>,
+    {
+        let list_key = Self::make_list_key(list_key);
+        self.store.compact_list_if_needed(&list_key, params)
+    }
+
+    /// Same as [CandyStore::list_needs_compaction], but `list_key` is typed
+    pub fn needs_compaction<Q: ?Sized + Encode>(
+        &self,
+        list_key: &Q,
+        params: ListCompactionParams,
+    ) -> Result<bool>
+    where
+        L: Borrow<Q>,
+    {
+        let list_key = Self::make_list_key(list_key);
+        self.store.list_needs_compaction(&list_key, params)
+    }
+
+    /// Same as [CandyStore::dump_list_internals], but `list_key` is typed
+    pub fn dump_internals<Q: ?Sized + Encode>(&self, list_key: &Q) -> Result<ListDebugInfo>
+    where
+        L: Borrow<Q>,
+    {
+        let list_key = Self::make_list_key(list_key);
+        self.store.dump_list_internals(&list_key)
+    }
+
+    /// Same as [CandyStore::compact_list], but `list_key` is typed
+    pub fn compact<Q: ?Sized + Encode>(
+        &self,
+        list_key: &Q,
+        params: ListCompactionParams,
+    ) -> Result<ListCompactionStats>
+    where
+        L: Borrow<Q>,
+    {
+        let list_key = Self::make_list_key(list_key);
+        self.store.compact_list(&list_key, params)
+    }
+
+    /// Same as [CandyStore::reverse_list], but `list_key` is typed
+    pub fn reverse<Q: ?Sized + Encode>(&self, list_key: &Q) -> Result<bool>
+    where
+        L: Borrow<Q>,
+    {
+        let list_key = Self::make_list_key(list_key);
+        self.store.owned_reverse_list(list_key)
+    }
+
+    /// Same as [CandyStore::pop_list_tail], but `list_key` is typed
+    pub fn pop_tail<Q: ?Sized + Encode>(&self, list_key: &Q) -> Result<Option<(K, V)>>
+    where
+        L: Borrow<Q>,
+    {
+        let list_key = Self::make_list_key(list_key);
+        let Some((k, v)) = self.store.owned_pop_list_tail(list_key)? else {
+            return Ok(None);
+        };
+        Ok(Some((from_bytes::<K>(&k)?, from_bytes::<V>(&v)?)))
+    }
+
+    /// Same as [CandyStore::pop_list_head], but `list_key` is typed
+    pub fn pop_head<Q: ?Sized + Encode>(&self, list_key: &Q) -> Result<Option<(K, V)>>
+    where
+        L: Borrow<Q>,
+    {
+        let list_key = Self::make_list_key(list_key);
+        let Some((k, v)) = self.store.owned_pop_list_head(list_key)? else {
+            return Ok(None);
+        };
+        Ok(Some((from_bytes::<K>(&k)?, from_bytes::<V>(&v)?)))
+    }
+
+    /// Same as [CandyStore::peek_list_tail], but `list_key` is typed
+    pub fn peek_tail<Q: ?Sized + Encode>(&self, list_key: &Q) -> Result<Option<(K, V)>>
+    where
+        L: Borrow<Q>,
+    {
+        let list_key = Self::make_list_key(list_key);
+        let Some((k, v)) = self.store.owned_peek_list_tail(list_key)? else {
+            return Ok(None);
+        };
+        Ok(Some((from_bytes::<K>(&k)?, from_bytes::<V>(&v)?)))
+    }
+
+    /// Same as [CandyStore::peek_list_head], but `list_key` is typed
+    pub fn peek_head<Q: ?Sized + Encode>(&self, list_key: &Q) -> Result<Option<(K, V)>>
+    where
+        L: Borrow<Q>,
+    {
+        let list_key = Self::make_list_key(list_key);
+        let Some((k, v)) = self.store.owned_peek_list_head(list_key)? else {
+            return Ok(None);
+        };
+        Ok(Some((from_bytes::<K>(&k)?, from_bytes::<V>(&v)?)))
+    }
+
+    /// Same as [Self::peek_tail], but returns only the item key. The value is not decoded (though it's still
+    /// read, as it's stored alongside the key), which saves decoding large values when only the key is needed
+    pub fn peek_tail_key<Q: ?Sized + Encode>(&self, list_key: &Q) -> Result<Option<K>>
+    where
+        L: Borrow<Q>,
+    {
+        let list_key = Self::make_list_key(list_key);
+        let Some((k, _)) = self.store.owned_peek_list_tail(list_key)? else {
+            return Ok(None);
+        };
+        Ok(Some(from_bytes::<K>(&k)?))
+    }
+
+    /// Same as [Self::peek_head], but returns only the item key. See [Self::peek_tail_key]
+    pub fn peek_head_key<Q: ?Sized + Encode>(&self, list_key: &Q) -> Result<Option<K>>
+    where
+        L: Borrow<Q>,
+    {
+        let list_key = Self::make_list_key(list_key);
+        let Some((k, _)) = self.store.owned_peek_list_head(list_key)? else {
+            return Ok(None);
+        };
+        Ok(Some(from_bytes::<K>(&k)?))
+    }
+
+    /// Same as [CandyStore::list_len], but `list_key` is typed
+    pub fn len<Q: ?Sized + Encode>(&self, list_key: &Q) -> Result<usize>
+    where
+        L: Borrow<Q>,
+    {
+        self.store.owned_list_len(Self::make_list_key(list_key))
+    }
+
+    /// Same as [CandyStore::list_size_bytes], but `list_key` is typed. The size is that of the serialized
+    /// item keys and values
+    pub fn size_bytes<Q: ?Sized + Encode>(&self, list_key: &Q) -> Result<usize>
+    where
+        L: Borrow<Q>,
+    {
+        self.store
+            .owned_list_size_bytes(Self::make_list_key(list_key))
+    }
+
+    /// Same as [CandyStore::retain_in_list], but `list_key` is typed
+    pub fn retain<Q: ?Sized + Encode>(
+        &self,
+        list_key: &Q,
+        mut func: impl FnMut(&K, &V) -> Result<bool>,
+    ) -> Result<()>
+    where
+        L: Borrow<Q>,
+    {
+        let list_key = Self::make_list_key(list_key);
+        self.store.owned_retain_in_list(list_key, |k, v| {
+            let tk = from_bytes::<K>(&k)?;
+            let tv = from_bytes::<V>(&v)?;
+            func(&tk, &tv)
+        })
+    }
+
+    /// Iterates over the list (from head to tail), calling `func` on each item, and returns the first
+    /// `Some` it produces. Iteration stops right there, so items past the match are neither fetched
+    /// nor decoded. Returns `None` if `func` returned `None` for all items
+    pub fn find_map<Q: ?Sized + Encode, T>(
+        &self,
+        list_key: &Q,
+        mut func: impl FnMut(&K, &V) -> Result<Option<T>>,
+    ) -> Result<Option<T>>
+    where
+        L: Borrow<Q>,
+    {
+        let list_key = Self::make_list_key(list_key);
+        for res in self.store.owned_iter_list(list_key) {
+            let (k, v) = res?;
+            let tk = from_bytes::<K>(&k)?;
+            let tv = from_bytes::<V>(&v)?;
+            if let Some(t) = func(&tk, &tv)? {
+                return Ok(Some(t));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// A wrapper around [CandyStore] that exposes the queue API in a typed manner. See [CandyTypedStore] for more
+/// info
+pub struct CandyTypedDeque<L, V> {
+    store: Arc<CandyStore>,
+    _phantom: PhantomData<(L, V)>,
+}
+
+impl<L, V> Clone for CandyTypedDeque<L, V> {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+            _phantom: Default::default(),
+        }
+    }
+}
+
+impl<L, V> CandyTypedDeque<L, V>
+where
+    L: CandyTypedKey,
+    V: Encode + DecodeOwned,
+{
+    /// Constructs a [CandyTypedDeque] over an existing [CandyStore]. In debug builds, this panics if
+    /// `L::TYPE_ID` was already used by a different key type (see [Self::new_checked])
+    pub fn new(store: Arc<CandyStore>) -> Self {
+        debug_register_type_id::<L>();
+        Self {
+            store,
+            _phantom: Default::default(),
+        }
+    }
+
+    /// Same as [Self::new], but returns [CandyError::TypeIdCollision] if `L::TYPE_ID` was already used by a
+    /// different key type, regardless of the build type
+    pub fn new_checked(store: Arc<CandyStore>) -> Result<Self> {
+        register_type_id::<L>()?;
+        Ok(Self {
+            store,
+            _phantom: Default::default(),
+        })
+    }
+
+    /// Pushes a value at the beginning (head) of the queue
+    pub fn push_head<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
+        &self,
+        queue_key: &Q1,
+        val: &Q2,
+    ) -> Result<()>
+    where
+        L: Borrow<Q1>,
+        V: Borrow<Q2>,
+    {
+        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        let val = val.to_bytes::<LE>();
+        self.store.push_to_queue_head(&queue_key, &val)?;
+        Ok(())
+    }
+
+    /// Pushes a value at the end (tail) of the queue
+    pub fn push_tail<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
+        &self,
+        queue_key: &Q1,
+        val: &Q2,
+    ) -> Result<()>
+    where
+        L: Borrow<Q1>,
+        V: Borrow<Q2>,
+    {
+        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        let val = val.to_bytes::<LE>();
+        self.store.push_to_queue_tail(&queue_key, &val)?;
+        Ok(())
+    }
+
+    /// Pushes a value at the end (tail) of the queue, but only if it holds fewer than `max_len` elements.
+    /// Returns false (without pushing) if the queue is full, so producers can apply backpressure instead of
+    /// dropping data. See [CandyStore::try_push_to_queue_tail]
+    pub fn try_push_tail<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
+        &self,
+        queue_key: &Q1,
+        val: &Q2,
+        max_len: usize,
+    ) -> Result<bool>
+    where
+        L: Borrow<Q1>,
+        V: Borrow<Q2>,
+    {
+        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        let val = val.to_bytes::<LE>();
+        Ok(self
+            .store
+            .try_push_to_queue_tail(&queue_key, &val, max_len)?
+            .is_some())
+    }
+
+    /// Pushes a value at the end (tail) of the queue, evicting values from the head so that it holds at most
+    /// `max_len` values, and returns the index and value of the evicted value, if any. See
+    /// [CandyStore::push_to_queue_tail_bounded]
+    pub fn push_tail_bounded<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
+        &self,
+        queue_key: &Q1,
+        val: &Q2,
+        max_len: usize,
+    ) -> Result<Option<(usize, V)>>
+    where
+        L: Borrow<Q1>,
+        V: Borrow<Q2>,
+    {
+        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        let val = val.to_bytes::<LE>();
+        let Some((idx, v)) = self
+            .store
+            .push_to_queue_tail_bounded(&queue_key, &val, max_len)?
+        else {
+            return Ok(None);
+        };
+        Ok(Some((idx, from_bytes::<V>(&v)?)))
+    }
+
+    /// Same as [Self::push_tail_bounded], but returns only the evicted value
+    pub fn push_tail_bounded_value<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
+        &self,
+        queue_key: &Q1,
+        val: &Q2,
+        max_len: usize,
+    ) -> Result<Option<V>>
+    where
+        L: Borrow<Q1>,
+        V: Borrow<Q2>,
+    {
+        Ok(self
+            .push_tail_bounded(queue_key, val, max_len)?
+            .map(|iv| iv.1))
+    }
+
+    /// Atomically pops the head of `from_queue` and pushes it to the tail of `to_queue`, returning the moved
+    /// value, or `None` if `from_queue` is empty (in which case `to_queue` is untouched). See
+    /// [CandyStore::transfer_queue_head]
+    pub fn transfer_head<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
+        &self,
+        from_queue: &Q1,
+        to_queue: &Q2,
+    ) -> Result<Option<V>>
+    where
+        L: Borrow<Q1>,
+        L: Borrow<Q2>,
+    {
+        let from_queue = CandyTypedList::<L, (), ()>::make_list_key(from_queue);
+        let to_queue = CandyTypedList::<L, (), ()>::make_list_key(to_queue);
+        let Some((_, vbytes)) = self.store.transfer_queue_head(&from_queue, &to_queue)? else {
+            return Ok(None);
+        };
+        Ok(Some(from_bytes::<V>(&vbytes)?))
+    }
+
+    /// Pops a value from the beginning (head) of the queue, along with its index. See
+    /// [CandyStore::pop_queue_head_with_idx] for the guarantees on indices
+    pub fn pop_head_with_idx<Q: ?Sized + Encode>(&self, queue_key: &Q) -> Result<Option<(usize, V)>>
+    where
+        L: Borrow<Q>,
+    {
+        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        let Some((idx, v)) = self.store.pop_queue_head_with_idx(&queue_key)? else {
+            return Ok(None);
+        };
+        Ok(Some((idx, from_bytes::<V>(&v)?)))
+    }
+
+    /// Pops the value at the beginning (head) of the queue only if `pred` returns true for it, leaving it in place
+    /// otherwise. See [CandyStore::pop_queue_head_if]
+    pub fn pop_head_if<Q: ?Sized + Encode>(
+        &self,
+        queue_key: &Q,
+        pred: impl FnOnce(&V) -> bool,
+    ) -> Result<Option<V>>
+    where
+        L: Borrow<Q>,
+    {
+        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        let mut head = None;
+        let popped = self.store.pop_queue_head_if(&queue_key, |vbytes| {
+            let v = from_bytes::<V>(vbytes)?;
+            let ok = pred(&v);
+            head = Some(v);
+            Ok(ok)
+        })?;
+        Ok(popped.and(head))
+    }
+
+    /// Pops a value from the beginning (head) of the queue
+    pub fn pop_head<Q: ?Sized + Encode>(&self, queue_key: &Q) -> Result<Option<V>>
+    where
+        L: Borrow<Q>,
+    {
+        Ok(self.pop_head_with_idx(queue_key)?.map(|iv| iv.1))
+    }
+
+    /// Pops up to `n` values from the beginning (head) of the queue, along with their indices, under a single
+    /// lock acquisition. See [CandyStore::pop_queue_head_batch]
+    pub fn pop_head_batch<Q: ?Sized + Encode>(
+        &self,
+        queue_key: &Q,
+        n: usize,
+    ) -> Result<Vec<(usize, V)>>
+    where
+        L: Borrow<Q>,
+    {
+        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        self.store
+            .pop_queue_head_batch(&queue_key, n)?
+            .into_iter()
+            .map(|(idx, v)| Ok((idx, from_bytes::<V>(&v)?)))
+            .collect()
+    }
+
+    /// Pops a value from the end (tail) of the queue
+    pub fn pop_tail_with_idx<Q: ?Sized + Encode>(&self, queue_key: &Q) -> Result<Option<(usize, V)>>
+    where
+        L: Borrow<Q>,
+    {
+        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        let Some((idx, v)) = self.store.pop_queue_tail_with_idx(&queue_key)? else {
+            return Ok(None);
+        };
+        Ok(Some((idx, from_bytes::<V>(&v)?)))
+    }
+
+    /// Pops a value from the end (tail) of the queue
+    pub fn pop_tail<Q: ?Sized + Encode>(&self, queue_key: &Q) -> Result<Option<V>>
+    where
+        L: Borrow<Q>,
+    {
+        Ok(self.pop_tail_with_idx(queue_key)?.map(|iv| iv.1))
+    }
+
+    /// Removes the element at the given index (as returned by [Self::iter], [Self::peek_head_with_idx], etc.),
+    /// returning its value or None if it did not exist. This leaves a hole in the queue,
+    /// which iteration skips over. See [CandyStore::remove_from_queue]
+    pub fn remove_by_idx<Q: ?Sized + Encode>(&self, queue_key: &Q, idx: usize) -> Result<Option<V>>
+    where
+        L: Borrow<Q>,
+    {
+        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        let Some(v) = self.store.remove_from_queue(&queue_key, idx)? else {
+            return Ok(None);
+        };
+        Ok(Some(from_bytes::<V>(&v)?))
+    }
+
+    /// Peek at the value from the beginning (head) of the queue and its index
+    pub fn peek_head_with_idx<Q: ?Sized + Encode>(
+        &self,
+        queue_key: &Q,
+    ) -> Result<Option<(usize, V)>>
+    where
+        L: Borrow<Q>,
+    {
+        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        let Some((idx, v)) = self.store.peek_queue_head_with_idx(&queue_key)? else {
+            return Ok(None);
+        };
+        Ok(Some((idx, from_bytes::<V>(&v)?)))
+    }
+
+    /// Removes all elements of the queue, returning the number of elements removed. Indices keep increasing
+    /// after a clear, i.e., elements pushed to the tail afterwards get indices greater than those of the removed
+    /// ones. See [CandyStore::clear_queue]
+    pub fn clear<Q: ?Sized + Encode>(&self, queue_key: &Q) -> Result<usize>
+    where
+        L: Borrow<Q>,
+    {
+        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        self.store.clear_queue(&queue_key)
+    }
+
+    /// Applies `func` to every value of the queue in place, under the queue's lock, returning the number of
+    /// values visited. Indices are preserved (values that `func` leaves unchanged are not rewritten). See
+    /// [CandyStore::update_queue_items] for the (non-)atomicity guarantees
+    pub fn map_in_place<Q: ?Sized + Encode>(
+        &self,
+        queue_key: &Q,
+        mut func: impl FnMut(&mut V),
+    ) -> Result<usize>
+    where
+        L: Borrow<Q>,
+    {
+        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        self.store.update_queue_items(&queue_key, |_, vbytes| {
+            let mut val = from_bytes::<V>(vbytes)?;
+            func(&mut val);
+            let new_vbytes = val.to_bytes::<LE>();
+            Ok((new_vbytes != vbytes).then_some(new_vbytes))
+        })
+    }
+
+    /// Folds the values of the queue (from head to tail) into an accumulator, starting with `init`, without
+    /// collecting them. The queue is walked under its lock, so the result reflects a single state of the queue.
+    /// Stops and returns the error on the first value that fails to decode
+    pub fn fold<Q: ?Sized + Encode, B>(
+        &self,
+        queue_key: &Q,
+        init: B,
+        mut f: impl FnMut(B, &V) -> B,
+    ) -> Result<B>
+    where
+        L: Borrow<Q>,
+    {
+        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        let mut acc = Some(init);
+        // never returns a new value, so nothing is rewritten
+        self.store.update_queue_items(&queue_key, |_, vbytes| {
+            let val = from_bytes::<V>(vbytes)?;
+            acc = acc.take().map(|acc| f(acc, &val));
+            Ok(None)
+        })?;
+        Ok(acc.expect("accumulator is always set"))
     }
 
-    /// Same as [CandyStore::set_in_list], but `list_key`, `item_key` and `val` are typed
-    pub fn set<Q1: ?Sized + Encode, Q2: ?Sized + Encode, Q3: ?Sized + Encode>(
-        &self,
-        list_key: &Q1,
-        item_key: &Q2,
-        val: &Q3,
-    ) -> Result<Option<V>>
+    /// Keeps only the last (up to) `n` elements of the queue, removing the rest from its head. Returns the
+    /// number of elements removed
+    pub fn truncate_head<Q: ?Sized + Encode>(&self, queue_key: &Q, n: usize) -> Result<usize>
     where
-        L: Borrow<Q1>,
-        K: Borrow<Q2>,
-        V: Borrow<Q3>,
+        L: Borrow<Q>,
     {
-        self._set(list_key, item_key, val, false)
+        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        self.store.truncate_queue_head(&queue_key, n)
     }
 
-    /// Same as [CandyStore::set_in_list_promoting], but `list_key`, `item_key` and `val` are typed
-    pub fn set_promoting<Q1: ?Sized + Encode, Q2: ?Sized + Encode, Q3: ?Sized + Encode>(
-        &self,
-        list_key: &Q1,
-        item_key: &Q2,
-        val: &Q3,
-    ) -> Result<Option<V>>
+    /// Keeps only the first (up to) `n` elements of the queue, removing the rest from its tail. Returns the
+    /// number of elements removed
+    pub fn truncate_tail<Q: ?Sized + Encode>(&self, queue_key: &Q, n: usize) -> Result<usize>
     where
-        L: Borrow<Q1>,
-        K: Borrow<Q2>,
-        V: Borrow<Q3>,
+        L: Borrow<Q>,
     {
-        self._set(list_key, item_key, val, true)
+        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        self.store.truncate_queue_tail(&queue_key, n)
     }
 
-    /// Same as [CandyStore::get_or_create_in_list], but `list_key`, `item_key` and `default_val` are typed
-    pub fn get_or_create<Q1: ?Sized + Encode, Q2: ?Sized + Encode, Q3: ?Sized + Encode>(
+    /// Peek at up to `n` values from the beginning (head) of the queue, along with their indices. See
+    /// [CandyStore::peek_queue_head_batch]
+    pub fn peek_head_batch<Q: ?Sized + Encode>(
         &self,
-        list_key: &Q1,
-        item_key: &Q2,
-        default_val: &Q3,
-    ) -> Result<V>
+        queue_key: &Q,
+        n: usize,
+    ) -> Result<Vec<(usize, V)>>
     where
-        L: Borrow<Q1>,
-        K: Borrow<Q2>,
+        L: Borrow<Q>,
     {
-        let list_key = Self::make_list_key(list_key);
-        let item_key = item_key.to_bytes::<LE>();
-        let default_val = default_val.to_bytes::<LE>();
-        let vbytes = self
-            .store
-            .owned_get_or_create_in_list(list_key, item_key, default_val)?
-            .value();
-        from_bytes::<V>(&vbytes)
+        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        self.store
+            .peek_queue_head_batch(&queue_key, n)?
+            .into_iter()
+            .map(|(idx, v)| Ok((idx, from_bytes::<V>(&v)?)))
+            .collect()
     }
 
-    /// Same as [CandyStore::replace_in_list], but `list_key`, `item_key` and `val` are typed
-    pub fn replace<Q1: ?Sized + Encode, Q2: ?Sized + Encode, Q3: ?Sized + Encode>(
-        &self,
-        list_key: &Q1,
-        item_key: &Q2,
-        val: &Q3,
-        expected_val: Option<&Q3>,
-    ) -> Result<Option<V>>
+    /// Peek at the value from the beginning (head) of the queue
+    pub fn peek_head<Q: ?Sized + Encode>(&self, queue_key: &Q) -> Result<Option<V>>
     where
-        L: Borrow<Q1>,
-        K: Borrow<Q2>,
-        V: Borrow<Q3>,
+        L: Borrow<Q>,
     {
-        let list_key = Self::make_list_key(list_key);
-        let item_key = item_key.to_bytes::<LE>();
-        let val = val.to_bytes::<LE>();
-        let ebytes = expected_val
-            .map(|ev| ev.to_bytes::<LE>())
-            .unwrap_or_default();
-        match self.store.owned_replace_in_list(
-            list_key,
-            item_key,
-            val,
-            expected_val.map(|_| &*ebytes),
-        )? {
-            ReplaceStatus::DoesNotExist => Ok(None),
-            ReplaceStatus::PrevValue(v) => Ok(Some(from_bytes::<V>(&v)?)),
-            ReplaceStatus::WrongValue(_) => Ok(None),
-        }
+        Ok(self.peek_head_with_idx(queue_key)?.map(|iv| iv.1))
     }
 
-    /// Same as [CandyStore::remove_from_list], but `list_key` and `item_key`  are typed
-    pub fn remove<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
+    /// Peek at the value from the end (tail) of the queue
+    pub fn peek_tail_with_idx<Q: ?Sized + Encode>(
         &self,
-        list_key: &Q1,
-        item_key: &Q2,
-    ) -> Result<Option<V>>
+        queue_key: &Q,
+    ) -> Result<Option<(usize, V)>>
     where
-        L: Borrow<Q1>,
-        K: Borrow<Q2>,
+        L: Borrow<Q>,
     {
-        let list_key = Self::make_list_key(list_key);
-        let item_key = item_key.to_bytes::<LE>();
-        if let Some(vbytes) = self.store.owned_remove_from_list(list_key, item_key)? {
-            Ok(Some(from_bytes::<V>(&vbytes)?))
-        } else {
-            Ok(None)
-        }
+        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        let Some((idx, v)) = self.store.peek_queue_tail_with_idx(&queue_key)? else {
+            return Ok(None);
+        };
+        Ok(Some((idx, from_bytes::<V>(&v)?)))
     }
 
-    /// Same as [CandyStore::iter_list], but `list_key` is typed
+    /// Peek at the value from the end (tail) of the queue
+    pub fn peek_tail<Q: ?Sized + Encode>(&self, queue_key: &Q) -> Result<Option<V>>
+    where
+        L: Borrow<Q>,
+    {
+        Ok(self.peek_tail_with_idx(queue_key)?.map(|iv| iv.1))
+    }
+
+    /// Iterates over the elements of the queue, from head to tail. The iterator is double-ended, so it can
+    /// also be consumed from the tail (e.g., using `.rev()`), and the two ends stop once they meet.
+    /// See also [CandyTypedList::iter]
     pub fn iter<'a, Q: ?Sized + Encode>(
         &'a self,
-        list_key: &Q,
-    ) -> impl Iterator<Item = Result<(K, V)>> + 'a
+        queue_key: &Q,
+    ) -> impl DoubleEndedIterator<Item = Result<(usize, V)>> + 'a
     where
         L: Borrow<Q>,
     {
-        let list_key = Self::make_list_key(list_key);
-        self.store.owned_iter_list(list_key).map(|res| match res {
+        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        self.store.iter_queue(&queue_key).map(|res| match res {
             Err(e) => Err(e),
-            Ok((k, v)) => {
-                let key = from_bytes::<K>(&k)?;
-                let val = from_bytes::<V>(&v)?;
-                Ok((key, val))
-            }
+            Ok((idx, v)) => Ok((idx, from_bytes::<V>(&v)?)),
         })
     }
 
-    /// Same as [CandyStore::iter_list_backwards], but `list_key` is typed
+    /// Same as [Self::iter], but starts at the first element whose index is `>= start_idx` (or at the head, if
+    /// `start_idx` precedes it), e.g., to resume consuming from a checkpointed index. See
+    /// [CandyStore::iter_queue_from]
+    pub fn iter_from<'a, Q: ?Sized + Encode>(
+        &'a self,
+        queue_key: &Q,
+        start_idx: usize,
+    ) -> impl DoubleEndedIterator<Item = Result<(usize, V)>> + 'a
+    where
+        L: Borrow<Q>,
+    {
+        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        self.store
+            .iter_queue_from(&queue_key, start_idx)
+            .map(|res| match res {
+                Err(e) => Err(e),
+                Ok((idx, v)) => Ok((idx, from_bytes::<V>(&v)?)),
+            })
+    }
+
+    /// Same as [Self::iter], but only yields the elements whose (decoded) value satisfies `pred`. Errors are
+    /// always yielded
+    pub fn iter_filter<'a, Q: ?Sized + Encode>(
+        &'a self,
+        queue_key: &Q,
+        mut pred: impl FnMut(&V) -> bool + 'a,
+    ) -> impl Iterator<Item = Result<(usize, V)>> + 'a
+    where
+        L: Borrow<Q>,
+    {
+        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        self.store
+            .iter_queue(&queue_key)
+            .filter_map(move |res| match res {
+                Err(e) => Some(Err(e)),
+                Ok((idx, v)) => match from_bytes::<V>(&v) {
+                    Ok(v) => pred(&v).then_some(Ok((idx, v))),
+                    Err(e) => Some(Err(e)),
+                },
+            })
+    }
+
+    /// Same as [Self::iter_filter], but `pred` is given the encoded value, and only the elements that
+    /// satisfy it are decoded. This is cheaper when the predicate can be decided on the raw bytes (e.g.,
+    /// by testing a prefix)
+    pub fn iter_filter_raw<'a, Q: ?Sized + Encode>(
+        &'a self,
+        queue_key: &Q,
+        mut pred: impl FnMut(&[u8]) -> bool + 'a,
+    ) -> impl Iterator<Item = Result<(usize, V)>> + 'a
+    where
+        L: Borrow<Q>,
+    {
+        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        self.store
+            .iter_queue(&queue_key)
+            .filter_map(move |res| match res {
+                Err(e) => Some(Err(e)),
+                Ok((idx, v)) => pred(&v).then(|| Ok((idx, from_bytes::<V>(&v)?))),
+            })
+    }
+
+    /// See [CandyTypedList::iter_backwards]
     pub fn iter_backwards<'a, Q: ?Sized + Encode>(
         &'a self,
-        list_key: &Q,
-    ) -> impl Iterator<Item = Result<(K, V)>> + 'a
+        queue_key: &Q,
+    ) -> impl DoubleEndedIterator<Item = Result<(usize, V)>> + 'a
     where
         L: Borrow<Q>,
     {
-        let list_key = Self::make_list_key(list_key);
+        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
         self.store
-            .owned_iter_list_backwards(list_key)
+            .iter_queue_backwards(&queue_key)
             .map(|res| match res {
                 Err(e) => Err(e),
-                Ok((k, v)) => {
-                    let key = from_bytes::<K>(&k)?;
-                    let val = from_bytes::<V>(&v)?;
-                    Ok((key, val))
-                }
+                Ok((idx, v)) => Ok((idx, from_bytes::<V>(&v)?)),
             })
     }
 
-    /// Same as [CandyStore::discard_list], but `list_key` is typed
-    pub fn discard<Q: ?Sized + Encode>(&self, list_key: &Q) -> Result<bool>
+    /// Collects the elements of the queue (and their indices), from head to tail
+    pub fn to_vec<Q: ?Sized + Encode>(&self, queue_key: &Q) -> Result<Vec<(usize, V)>>
+    where
+        L: Borrow<Q>,
+    {
+        let mut items = vec![];
+        self.peek_all_into(queue_key, &mut items)?;
+        Ok(items)
+    }
+
+    /// Same as [Self::to_vec], but clears and fills the given buffer instead of allocating a new one, so that
+    /// polling a queue repeatedly (e.g., for monitoring) can reuse the buffer's capacity. On error, the buffer
+    /// holds the elements collected so far
+    pub fn peek_all_into<Q: ?Sized + Encode>(
+        &self,
+        queue_key: &Q,
+        buf: &mut Vec<(usize, V)>,
+    ) -> Result<()>
+    where
+        L: Borrow<Q>,
+    {
+        buf.clear();
+        for res in self.iter(queue_key) {
+            buf.push(res?);
+        }
+        Ok(())
+    }
+
+    pub fn len<Q: ?Sized + Encode>(&self, queue_key: &Q) -> Result<usize>
+    where
+        L: Borrow<Q>,
+    {
+        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        self.store.queue_len(&queue_key)
+    }
+
+    pub fn range<Q: ?Sized + Encode>(&self, queue_key: &Q) -> Result<Range<usize>>
+    where
+        L: Borrow<Q>,
+    {
+        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        self.store.queue_range(&queue_key)
+    }
+
+    /// Same as [CandyStore::queue_state], but `queue_key` is typed
+    pub fn state<Q: ?Sized + Encode>(&self, queue_key: &Q) -> Result<QueueState>
+    where
+        L: Borrow<Q>,
+    {
+        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        self.store.queue_state(&queue_key)
+    }
+
+    /// Same as [CandyStore::verify_queue], but `queue_key` is typed
+    pub fn verify_queue<Q: ?Sized + Encode>(&self, queue_key: &Q) -> Result<QueueHealth>
+    where
+        L: Borrow<Q>,
+    {
+        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        self.store.verify_queue(&queue_key)
+    }
+
+    /// Same as [CandyStore::repair_queue], but `queue_key` is typed
+    pub fn repair_queue<Q: ?Sized + Encode>(&self, queue_key: &Q) -> Result<QueueHealth>
+    where
+        L: Borrow<Q>,
+    {
+        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        self.store.repair_queue(&queue_key)
+    }
+
+    /// Same as [CandyStore::snapshot_queue], but `queue_key` is typed and the values are deserialized
+    pub fn snapshot<Q: ?Sized + Encode>(&self, queue_key: &Q) -> Result<QueueSnapshot<V>>
+    where
+        L: Borrow<Q>,
+    {
+        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        let snapshot = self.store.snapshot_queue(&queue_key)?;
+        let mut items = Vec::with_capacity(snapshot.items.len());
+        for (idx, v) in snapshot.items {
+            items.push((idx, from_bytes::<V>(&v)?));
+        }
+        Ok(QueueSnapshot {
+            range: snapshot.range,
+            items,
+        })
+    }
+
+    /// Same as [CandyStore::restore_queue], but `queue_key` is typed and the values are serialized
+    pub fn restore<Q: ?Sized + Encode>(
+        &self,
+        queue_key: &Q,
+        snapshot: QueueSnapshot<V>,
+    ) -> Result<()>
+    where
+        L: Borrow<Q>,
+    {
+        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
+        let snapshot = QueueSnapshot {
+            range: snapshot.range,
+            items: snapshot
+                .items
+                .into_iter()
+                .map(|(idx, v)| (idx, v.to_bytes::<LE>()))
+                .collect(),
+        };
+        self.store.restore_queue(&queue_key, &snapshot)
+    }
+}
+
+/// A secondary index over [CandyStore], mapping attribute values (`A`) to sets of (primary) keys (`K`). Each
+/// attribute value is backed by a list whose items are the keys, so a key appears at most once per attribute
+/// value, and keys are yielded in insertion order. The index is separate from the lists of [CandyTypedList],
+/// even if they share the same key type. See [CandyTypedStore] for more info
+pub struct CandyTypedIndex<A, K> {
+    store: Arc<CandyStore>,
+    _phantom: PhantomData<(A, K)>,
+}
+
+impl<A, K> Clone for CandyTypedIndex<A, K> {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+            _phantom: Default::default(),
+        }
+    }
+}
+
+impl<A, K> CandyTypedIndex<A, K>
+where
+    A: CandyTypedKey,
+    K: Encode + DecodeOwned,
+{
+    /// Constructs a [CandyTypedIndex] over an existing [CandyStore]. In debug builds, this panics if
+    /// `A::TYPE_ID` was already used by a different key type (see [Self::new_checked])
+    pub fn new(store: Arc<CandyStore>) -> Self {
+        debug_register_type_id::<A>();
+        Self {
+            store,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Same as [Self::new], but returns [CandyError::TypeIdCollision] if `A::TYPE_ID` was already used by a
+    /// different key type, regardless of the build type
+    pub fn new_checked(store: Arc<CandyStore>) -> Result<Self> {
+        register_type_id::<A>()?;
+        Ok(Self {
+            store,
+            _phantom: PhantomData,
+        })
+    }
+
+    fn make_attr_key<Q: ?Sized + Encode>(attr: &Q) -> Vec<u8>
     where
-        L: Borrow<Q>,
+        A: Borrow<Q>,
     {
-        let list_key = Self::make_list_key(list_key);
-        self.store.owned_discard_list(list_key)
+        let mut kbytes = attr.to_bytes::<LE>();
+        kbytes.extend_from_slice(bytes_of(&A::TYPE_ID));
+        kbytes.extend_from_slice(TYPED_INDEX_NAMESPACE);
+        kbytes
     }
 
-    /// Same as [CandyStore::compact_list_if_needed], but `list_key` is typed
-    pub fn compact_if_needed<Q: ?Sized + Encode>(
+    /// Adds `key` to the set of keys of `attr`. Returns true if it was added, false if it was already there
+    pub fn insert<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
         &self,
-        list_key: &Q,
-        params: ListCompactionParams,
+        attr: &Q1,
+        key: &Q2,
     ) -> Result<bool>
     where
-        L: Borrow<Q>,
-    {
-        let list_key = Self::make_list_key(list_key);
-        self.store.compact_list_if_needed(&list_key, params)
-    }
-
-    /// Same as [CandyStore::pop_list_tail], but `list_key` is typed
-    pub fn pop_tail<Q: ?Sized + Encode>(&self, list_key: &Q) -> Result<Option<(K, V)>>
-    where
-        L: Borrow<Q>,
+        A: Borrow<Q1>,
+        K: Borrow<Q2>,
     {
-        let list_key = Self::make_list_key(list_key);
-        let Some((k, v)) = self.store.owned_pop_list_tail(list_key)? else {
-            return Ok(None);
-        };
-        Ok(Some((from_bytes::<K>(&k)?, from_bytes::<V>(&v)?)))
+        let attr_key = Self::make_attr_key(attr);
+        let status =
+            self.store
+                .owned_get_or_create_in_list(attr_key, key.to_bytes::<LE>(), vec![])?;
+        Ok(status.was_created())
     }
 
-    /// Same as [CandyStore::pop_list_head], but `list_key` is typed
-    pub fn pop_head<Q: ?Sized + Encode>(&self, list_key: &Q) -> Result<Option<(K, V)>>
+    /// Removes `key` from the set of keys of `attr`. Returns true if it was removed, false if it was not there
+    pub fn remove<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
+        &self,
+        attr: &Q1,
+        key: &Q2,
+    ) -> Result<bool>
     where
-        L: Borrow<Q>,
+        A: Borrow<Q1>,
+        K: Borrow<Q2>,
     {
-        let list_key = Self::make_list_key(list_key);
-        let Some((k, v)) = self.store.owned_pop_list_head(list_key)? else {
-            return Ok(None);
-        };
-        Ok(Some((from_bytes::<K>(&k)?, from_bytes::<V>(&v)?)))
+        let attr_key = Self::make_attr_key(attr);
+        Ok(self
+            .store
+            .owned_remove_from_list(attr_key, key.to_bytes::<LE>())?
+            .is_some())
     }
 
-    /// Same as [CandyStore::peek_list_tail], but `list_key` is typed
-    pub fn peek_tail<Q: ?Sized + Encode>(&self, list_key: &Q) -> Result<Option<(K, V)>>
+    /// Tests if `key` is in the set of keys of `attr`
+    pub fn contains<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
+        &self,
+        attr: &Q1,
+        key: &Q2,
+    ) -> Result<bool>
     where
-        L: Borrow<Q>,
+        A: Borrow<Q1>,
+        K: Borrow<Q2>,
     {
-        let list_key = Self::make_list_key(list_key);
-        let Some((k, v)) = self.store.owned_peek_list_tail(list_key)? else {
-            return Ok(None);
-        };
-        Ok(Some((from_bytes::<K>(&k)?, from_bytes::<V>(&v)?)))
+        let attr_key = Self::make_attr_key(attr);
+        Ok(self
+            .store
+            .owned_get_from_list(attr_key, key.to_bytes::<LE>())?
+            .is_some())
     }
 
-    /// Same as [CandyStore::peek_list_head], but `list_key` is typed
-    pub fn peek_head<Q: ?Sized + Encode>(&self, list_key: &Q) -> Result<Option<(K, V)>>
+    /// Iterates over the keys of `attr`, in insertion order
+    pub fn get<'a, Q: ?Sized + Encode>(&'a self, attr: &Q) -> impl Iterator<Item = Result<K>> + 'a
     where
-        L: Borrow<Q>,
+        A: Borrow<Q>,
     {
-        let list_key = Self::make_list_key(list_key);
-        let Some((k, v)) = self.store.owned_peek_list_head(list_key)? else {
-            return Ok(None);
-        };
-        Ok(Some((from_bytes::<K>(&k)?, from_bytes::<V>(&v)?)))
+        let attr_key = Self::make_attr_key(attr);
+        self.store
+            .owned_iter_list(attr_key)
+            .map(|res| from_bytes::<K>(&res?.0))
     }
 
-    /// Same as [CandyStore::list_len], but `list_key` is typed
-    pub fn len<Q: ?Sized + Encode>(&self, list_key: &Q) -> Result<usize>
+    /// Returns the number of keys of `attr`
+    pub fn len<Q: ?Sized + Encode>(&self, attr: &Q) -> Result<usize>
     where
-        L: Borrow<Q>,
+        A: Borrow<Q>,
     {
-        self.store.owned_list_len(Self::make_list_key(list_key))
+        self.store.owned_list_len(Self::make_attr_key(attr))
     }
 
-    /// Same as [CandyStore::retain_in_list], but `list_key` is typed
-    pub fn retain<Q: ?Sized + Encode>(
-        &self,
-        list_key: &Q,
-        mut func: impl FnMut(&K, &V) -> Result<bool>,
-    ) -> Result<()>
+    /// Removes all keys of `attr`, like [CandyStore::discard_list] does for lists
+    pub fn discard<Q: ?Sized + Encode>(&self, attr: &Q) -> Result<bool>
     where
-        L: Borrow<Q>,
+        A: Borrow<Q>,
     {
-        let list_key = Self::make_list_key(list_key);
-        self.store.owned_retain_in_list(list_key, |k, v| {
-            let tk = from_bytes::<K>(&k)?;
-            let tv = from_bytes::<V>(&v)?;
-            func(&tk, &tv)
-        })
+        self.store.owned_discard_list(Self::make_attr_key(attr))
     }
 }
 
-/// A wrapper around [CandyStore] that exposes the queue API in a typed manner. See [CandyTypedStore] for more
-/// info
-pub struct CandyTypedDeque<L, V> {
+/// A priority queue over [CandyStore]: each queue (identified by `L`) is made of `num_lanes` lanes, one per
+/// priority level, each of which is a regular queue (see [CandyStore::push_to_queue_tail]). Values are
+/// popped from the highest-priority lane that isn't empty, and in FIFO order within a lane. The lanes are
+/// separate from the queues of [CandyTypedDeque], even if they share the same key type. See
+/// [CandyTypedStore] for more info
+///
+/// Note: popping scans the lanes from the highest priority down without locking them together, so a value
+/// pushed to a higher lane while a pop is in progress may be popped after a lower-priority one
+pub struct CandyTypedPriorityDeque<L, V> {
     store: Arc<CandyStore>,
+    num_lanes: u8,
     _phantom: PhantomData<(L, V)>,
 }
 
-impl<L, V> Clone for CandyTypedDeque<L, V> {
+impl<L, V> Clone for CandyTypedPriorityDeque<L, V> {
     fn clone(&self) -> Self {
         Self {
             store: self.store.clone(),
+            num_lanes: self.num_lanes,
             _phantom: Default::default(),
         }
     }
 }
 
-impl<L, V> CandyTypedDeque<L, V>
+impl<L, V> CandyTypedPriorityDeque<L, V>
 where
     L: CandyTypedKey,
     V: Encode + DecodeOwned,
 {
-    pub fn new(store: Arc<CandyStore>) -> Self {
+    /// Constructs a [CandyTypedPriorityDeque] with `num_lanes` priority levels (`0..num_lanes`, where higher
+    /// is more urgent) over an existing [CandyStore]. The number of lanes is not persisted, so it must be the
+    /// same whenever the queues are accessed (values in lanes above it are never popped).
+    /// Panics if `num_lanes` is 0, and in debug builds, if `L::TYPE_ID` was already used by a different key
+    /// type (see [Self::new_checked])
+    pub fn new(store: Arc<CandyStore>, num_lanes: u8) -> Self {
+        assert!(num_lanes > 0, "num_lanes must be positive");
+        debug_register_type_id::<L>();
         Self {
             store,
+            num_lanes,
             _phantom: Default::default(),
         }
     }
 
-    /// Pushes a value at the beginning (head) of the queue
-    pub fn push_head<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
+    /// Same as [Self::new], but returns [CandyError::TypeIdCollision] if `L::TYPE_ID` was already used by a
+    /// different key type, regardless of the build type
+    pub fn new_checked(store: Arc<CandyStore>, num_lanes: u8) -> Result<Self> {
+        assert!(num_lanes > 0, "num_lanes must be positive");
+        register_type_id::<L>()?;
+        Ok(Self {
+            store,
+            num_lanes,
+            _phantom: Default::default(),
+        })
+    }
+
+    /// The number of priority levels of this deque
+    pub fn num_lanes(&self) -> u8 {
+        self.num_lanes
+    }
+
+    // the keys of the lanes of the given queue (i.e., the queue key followed by the priority), ordered from
+    // the highest priority down
+    fn make_lane_keys<Q: ?Sized + Encode>(
         &self,
-        queue_key: &Q1,
-        val: &Q2,
-    ) -> Result<()>
+        queue_key: &Q,
+    ) -> impl Iterator<Item = (u8, Vec<u8>)>
     where
-        L: Borrow<Q1>,
-        V: Borrow<Q2>,
+        L: Borrow<Q>,
     {
-        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
-        let val = val.to_bytes::<LE>();
-        self.store.push_to_queue_head(&queue_key, &val)?;
-        Ok(())
+        let mut kbytes = queue_key.to_bytes::<LE>();
+        kbytes.extend_from_slice(bytes_of(&L::TYPE_ID));
+        kbytes.extend_from_slice(TYPED_PRIORITY_NAMESPACE);
+        (0..self.num_lanes).rev().map(move |priority| {
+            let mut lane_key = kbytes.clone();
+            lane_key.push(priority);
+            (priority, lane_key)
+        })
     }
 
-    /// Pushes a value at the end (tail) of the queue
-    pub fn push_tail<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
+    /// Pushes a value to the tail of the lane of the given priority. Priorities beyond the number of lanes are
+    /// treated as the highest priority
+    pub fn push<Q1: ?Sized + Encode, Q2: ?Sized + Encode>(
         &self,
         queue_key: &Q1,
         val: &Q2,
+        priority: u8,
     ) -> Result<()>
     where
         L: Borrow<Q1>,
         V: Borrow<Q2>,
     {
-        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
-        let val = val.to_bytes::<LE>();
-        self.store.push_to_queue_tail(&queue_key, &val)?;
+        let priority = priority.min(self.num_lanes - 1);
+        let (_, lane_key) = self
+            .make_lane_keys(queue_key)
+            .nth((self.num_lanes - 1 - priority) as usize)
+            .unwrap();
+        self.store
+            .push_to_queue_tail(&lane_key, &val.to_bytes::<LE>())?;
         Ok(())
     }
 
-    /// Pops a value from the beginning (head) of the queue
-    pub fn pop_head_with_idx<Q: ?Sized + Encode>(&self, queue_key: &Q) -> Result<Option<(usize, V)>>
+    /// Pops the oldest value of the highest priority, along with its priority
+    pub fn pop_with_priority<Q: ?Sized + Encode>(&self, queue_key: &Q) -> Result<Option<(u8, V)>>
     where
         L: Borrow<Q>,
     {
-        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
-        let Some((idx, v)) = self.store.pop_queue_head_with_idx(&queue_key)? else {
-            return Ok(None);
-        };
-        Ok(Some((idx, from_bytes::<V>(&v)?)))
+        for (priority, lane_key) in self.make_lane_keys(queue_key) {
+            if let Some(v) = self.store.pop_queue_head(&lane_key)? {
+                return Ok(Some((priority, from_bytes::<V>(&v)?)));
+            }
+        }
+        Ok(None)
     }
 
-    /// Pops a value from the beginning (head) of the queue
-    pub fn pop_head<Q: ?Sized + Encode>(&self, queue_key: &Q) -> Result<Option<V>>
+    /// Pops the oldest value of the highest priority
+    pub fn pop<Q: ?Sized + Encode>(&self, queue_key: &Q) -> Result<Option<V>>
     where
         L: Borrow<Q>,
     {
-        Ok(self.pop_head_with_idx(queue_key)?.map(|iv| iv.1))
+        Ok(self.pop_with_priority(queue_key)?.map(|pv| pv.1))
     }
 
-    /// Pops a value from the end (tail) of the queue
-    pub fn pop_tail_with_idx<Q: ?Sized + Encode>(&self, queue_key: &Q) -> Result<Option<(usize, V)>>
+    /// Peeks at the value that [Self::pop_with_priority] would return, without removing it
+    pub fn peek_with_priority<Q: ?Sized + Encode>(&self, queue_key: &Q) -> Result<Option<(u8, V)>>
     where
         L: Borrow<Q>,
     {
-        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
-        let Some((idx, v)) = self.store.pop_queue_tail_with_idx(&queue_key)? else {
-            return Ok(None);
-        };
-        Ok(Some((idx, from_bytes::<V>(&v)?)))
+        for (priority, lane_key) in self.make_lane_keys(queue_key) {
+            if let Some(v) = self.store.peek_queue_head(&lane_key)? {
+                return Ok(Some((priority, from_bytes::<V>(&v)?)));
+            }
+        }
+        Ok(None)
     }
 
-    /// Pops a value from the end (tail) of the queue
-    pub fn pop_tail<Q: ?Sized + Encode>(&self, queue_key: &Q) -> Result<Option<V>>
+    /// Returns the total number of values in all lanes of the queue
+    pub fn len<Q: ?Sized + Encode>(&self, queue_key: &Q) -> Result<usize>
     where
         L: Borrow<Q>,
     {
-        Ok(self.pop_tail_with_idx(queue_key)?.map(|iv| iv.1))
+        let mut len = 0;
+        for (_, lane_key) in self.make_lane_keys(queue_key) {
+            len += self.store.queue_len(&lane_key)?;
+        }
+        Ok(len)
     }
 
-    /// Peek at the value from the beginning (head) of the queue and its index
-    pub fn peek_head_with_idx<Q: ?Sized + Encode>(
-        &self,
-        queue_key: &Q,
-    ) -> Result<Option<(usize, V)>>
+    /// Removes all values from all lanes of the queue, returning the number of values removed
+    pub fn clear<Q: ?Sized + Encode>(&self, queue_key: &Q) -> Result<usize>
     where
         L: Borrow<Q>,
     {
-        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
-        let Some((idx, v)) = self.store.peek_queue_head_with_idx(&queue_key)? else {
-            return Ok(None);
-        };
-        Ok(Some((idx, from_bytes::<V>(&v)?)))
+        let mut count = 0;
+        for (_, lane_key) in self.make_lane_keys(queue_key) {
+            count += self.store.clear_queue(&lane_key)?;
+        }
+        Ok(count)
     }
+}
 
-    /// Peek at the value from the beginning (head) of the queue
-    pub fn peek_head<Q: ?Sized + Encode>(&self, queue_key: &Q) -> Result<Option<V>>
-    where
-        L: Borrow<Q>,
-    {
-        Ok(self.peek_head_with_idx(queue_key)?.map(|iv| iv.1))
+// the length of the trailer of a CandyFixedStore key: the namespace, the value type tag and TYPED_FIXED_NAMESPACE
+const FIXED_TRAILER_LEN: usize = size_of::<u32>() + size_of::<u32>() + TYPED_FIXED_NAMESPACE.len();
+
+// the full key of a CandyFixedStore entry, laid out on the stack
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct FixedKey<const N: usize> {
+    key: [u8; N],
+    trailer: [u8; FIXED_TRAILER_LEN],
+}
+
+// safety: the struct is repr(C) and consists only of bytes, so it has no padding and any bit pattern is valid
+unsafe impl<const N: usize> Zeroable for FixedKey<N> {}
+unsafe impl<const N: usize> Pod for FixedKey<N> {}
+
+/// A typed wrapper over [CandyStore] for keys of a fixed size of `N` bytes (e.g., `u64::to_le_bytes()`), whose
+/// full keys are laid out on the stack, so unlike [CandyTypedStore], no allocation takes place for the key on
+/// every operation. Values are stored like those of [CandyTypedStore], so they are compressed, tagged and
+/// carry sequence numbers according to the [crate::Config].
+///
+/// Entries are keyed by the key bytes, a numeric namespace (see [Self::new_in_namespace]) and the value type
+/// tag of `V` (see [register_value_tag]), so fixed stores of different value types or namespaces are disjoint,
+/// as are they from the entries of [CandyTypedStore] (even for the same key bytes)
+pub struct CandyFixedStore<const N: usize, V> {
+    store: Arc<CandyStore>,
+    trailer: [u8; FIXED_TRAILER_LEN],
+    _phantom: PhantomData<V>,
+}
+
+impl<const N: usize, V> Clone for CandyFixedStore<N, V> {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+            trailer: self.trailer,
+            _phantom: Default::default(),
+        }
     }
+}
 
-    /// Peek at the value from the end (tail) of the queue
-    pub fn peek_tail_with_idx<Q: ?Sized + Encode>(
-        &self,
-        queue_key: &Q,
-    ) -> Result<Option<(usize, V)>>
-    where
-        L: Borrow<Q>,
-    {
-        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
-        let Some((idx, v)) = self.store.peek_queue_tail_with_idx(&queue_key)? else {
-            return Ok(None);
-        };
-        Ok(Some((idx, from_bytes::<V>(&v)?)))
+impl<const N: usize, V> TypedValues<V> for CandyFixedStore<N, V>
+where
+    V: Encode + DecodeOwned,
+{
+    fn raw_store(&self) -> &CandyStore {
+        &self.store
     }
+}
 
-    /// Peek at the value from the end (tail) of the queue
-    pub fn peek_tail<Q: ?Sized + Encode>(&self, queue_key: &Q) -> Result<Option<V>>
-    where
-        L: Borrow<Q>,
-    {
-        Ok(self.peek_tail_with_idx(queue_key)?.map(|iv| iv.1))
+impl<const N: usize, V> CandyFixedStore<N, V>
+where
+    V: Encode + DecodeOwned,
+{
+    /// Constructs a fixed-key wrapper over a CandyStore, in the default namespace (0). Fails with
+    /// [CandyError::ValueTagNotRegistered] if no value tag was registered for `V` (see [register_value_tag])
+    pub fn new(store: Arc<CandyStore>) -> Result<Self> {
+        Self::new_in_namespace(store, 0)
     }
 
-    /// See [CandyTypedList::iter]
-    pub fn iter<'a, Q: ?Sized + Encode>(
-        &'a self,
-        queue_key: &Q,
-    ) -> impl Iterator<Item = Result<(usize, V)>> + 'a
-    where
-        L: Borrow<Q>,
-    {
-        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
-        self.store.iter_queue(&queue_key).map(|res| match res {
-            Err(e) => Err(e),
-            Ok((idx, v)) => Ok((idx, from_bytes::<V>(&v).unwrap())),
+    /// Same as [Self::new], but the entries live in the given namespace, so fixed stores of the same `N` and
+    /// `V` in different namespaces are disjoint
+    pub fn new_in_namespace(store: Arc<CandyStore>, ns: u32) -> Result<Self> {
+        let mut trailer = [0u8; FIXED_TRAILER_LEN];
+        trailer[..4].copy_from_slice(&ns.to_le_bytes());
+        trailer[4..8].copy_from_slice(&value_type_tag::<V>()?.to_le_bytes());
+        trailer[8..].copy_from_slice(TYPED_FIXED_NAMESPACE);
+        Ok(Self {
+            store,
+            trailer,
+            _phantom: Default::default(),
         })
     }
 
-    /// See [CandyTypedList::iter_backwards]
-    pub fn iter_backwards<'a, Q: ?Sized + Encode>(
-        &'a self,
-        queue_key: &Q,
-    ) -> impl Iterator<Item = Result<(usize, V)>> + 'a
-    where
-        L: Borrow<Q>,
-    {
-        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
-        self.store
-            .iter_queue_backwards(&queue_key)
-            .map(|res| match res {
-                Err(e) => Err(e),
-                Ok((idx, v)) => Ok((idx, from_bytes::<V>(&v).unwrap())),
-            })
+    fn make_key(&self, key: &[u8; N]) -> FixedKey<N> {
+        FixedKey {
+            key: *key,
+            trailer: self.trailer,
+        }
     }
 
-    pub fn len<Q: ?Sized + Encode>(&self, queue_key: &Q) -> Result<usize>
-    where
-        L: Borrow<Q>,
-    {
-        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
-        self.store.queue_len(&queue_key)
+    /// Checks if the given key exists
+    pub fn contains(&self, key: &[u8; N]) -> Result<bool> {
+        Ok(self.store.get_raw(bytes_of(&self.make_key(key)))?.is_some())
     }
 
-    pub fn range<Q: ?Sized + Encode>(&self, queue_key: &Q) -> Result<Range<usize>>
+    /// Same as [CandyStore::get] but deserializes the value
+    pub fn get(&self, key: &[u8; N]) -> Result<Option<V>> {
+        match self.store.get_raw(bytes_of(&self.make_key(key)))? {
+            Some(vbytes) => Ok(Some(self.decode_val(&vbytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Same as [CandyStore::set] but serializes the value. Returns the previous value, if the key had existed
+    pub fn set<Q: ?Sized + Encode>(&self, key: &[u8; N], val: &Q) -> Result<Option<V>>
     where
-        L: Borrow<Q>,
+        V: Borrow<Q>,
     {
-        let queue_key = CandyTypedList::<L, (), ()>::make_list_key(queue_key);
-        self.store.queue_range(&queue_key)
+        let vbytes = self.encode_val(val)?;
+        match self.store.set_raw(bytes_of(&self.make_key(key)), &vbytes)? {
+            SetStatus::CreatedNew => Ok(None),
+            SetStatus::PrevValue(v) => Ok(Some(self.decode_val(&v)?)),
+        }
+    }
+
+    /// Same as [CandyStore::remove], but deserializes the removed value
+    pub fn remove(&self, key: &[u8; N]) -> Result<Option<V>> {
+        match self.store.remove_raw(bytes_of(&self.make_key(key)))? {
+            Some(vbytes) => Ok(Some(self.decode_val(&vbytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Iterates over all entries of this fixed store. See [CandyStore::iter] for the guarantees
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = Result<([u8; N], V)>> + 'a {
+        self.store.iter_raw().filter_map(|res| match res {
+            Err(e) => Some(Err(e)),
+            Ok((k, v)) => {
+                if k.len() != N + FIXED_TRAILER_LEN || !k.ends_with(&self.trailer) {
+                    return None;
+                }
+                let key: [u8; N] = k[..N].try_into().unwrap();
+                Some(self.decode_val(&v).map(|val| (key, val)))
+            }
+        })
     }
 }
@@ -0,0 +1,110 @@
+mod common;
+
+use std::sync::Arc;
+
+use candystore::{CandyError, CandyStore, CandyTypedList, CandyTypedStore, Config, Result};
+
+use crate::common::run_in_tempdir;
+
+#[test]
+fn test_batch_commit() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        db.set("gone", "soon")?;
+        db.set_in_list("mylist", "old", "item")?;
+
+        let typed = CandyTypedStore::<String, u32>::new_cached(db.clone(), 16);
+        typed.set("cached", &1)?;
+        assert_eq!(*typed.get_cached("cached")?.unwrap(), 1);
+        let typed_list = CandyTypedList::<String, u32, String>::new(db.clone());
+
+        let mut batch = db.batch();
+        assert!(batch.is_empty());
+        batch.set("hello", "world")?;
+        batch.remove("gone")?;
+        batch.set_in_list("mylist", "new", "item")?;
+        batch.remove_from_list("mylist", "old")?;
+        typed.stage_set(&mut batch, "cached", &2)?;
+        typed.stage_remove(&mut batch, "missing")?;
+        typed_list.stage_set(&mut batch, "tl", &7, "seven")?;
+        assert_eq!(batch.len(), 7);
+
+        // nothing is visible before the commit
+        assert_eq!(db.get("hello")?, None);
+        assert_eq!(typed.get("cached")?, Some(1));
+
+        batch.commit()?;
+
+        assert_eq!(db.get("hello")?, Some("world".into()));
+        assert_eq!(db.get("gone")?, None);
+        assert_eq!(db.get_from_list("mylist", "new")?, Some("item".into()));
+        assert_eq!(db.get_from_list("mylist", "old")?, None);
+        assert_eq!(*typed.get_cached("cached")?.unwrap(), 2);
+        assert_eq!(typed_list.get("tl", &7)?, Some("seven".into()));
+
+        // an empty batch is a no-op
+        db.batch().commit()?;
+
+        // size errors are reported at staging time
+        let mut batch = db.batch();
+        let e = batch.set(&vec![7u8; 0x4000], "x").unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<CandyError>(),
+            Some(CandyError::KeyTooLong(_))
+        ));
+        assert!(batch.is_empty());
+
+        Ok(())
+    })
+}
+
+#[cfg(feature = "whitebox_testing")]
+#[test]
+fn test_batch_replay_after_crash() -> Result<()> {
+    run_in_tempdir(|dir| {
+        {
+            let db = CandyStore::open(dir, Config::default())?;
+            db.set("gone", "soon")?;
+
+            let mut batch = db.batch();
+            batch.set("hello", "world")?;
+            batch.remove("gone")?;
+            batch.set_in_list("mylist", "item", "val")?;
+            // simulate a crash right after the journal was made durable
+            batch.commit_journal_only()?;
+
+            assert_eq!(db.get("hello")?, None);
+            assert_eq!(db.get("gone")?, Some("soon".into()));
+        }
+
+        let db = CandyStore::open(dir, Config::default())?;
+        assert_eq!(db.get("hello")?, Some("world".into()));
+        assert_eq!(db.get("gone")?, None);
+        assert_eq!(db.get_from_list("mylist", "item")?, Some("val".into()));
+        assert!(!std::path::Path::new(dir).join("journal").exists());
+
+        Ok(())
+    })
+}
+
+#[cfg(feature = "whitebox_testing")]
+#[test]
+fn test_batch_journal_discarded_by_clear() -> Result<()> {
+    run_in_tempdir(|dir| {
+        {
+            let db = CandyStore::open(dir, Config::default())?;
+            let mut batch = db.batch();
+            batch.set("hello", "world")?;
+            batch.commit_journal_only()?;
+
+            db.clear()?;
+        }
+
+        // the journal must not be replayed over the cleared store
+        let db = CandyStore::open(dir, Config::default())?;
+        assert_eq!(db.get("hello")?, None);
+
+        Ok(())
+    })
+}
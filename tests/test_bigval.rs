@@ -1,8 +1,12 @@
 mod common;
 
-use std::sync::Arc;
+use std::{
+    io::{Read, Write},
+    sync::{Arc, Mutex},
+};
 
-use candystore::{CandyStore, CandyTypedStore, Config, Result};
+use candystore::{CandyStore, CandyTypedStore, Config, Result, ValueCodec};
+use databuf::{config::num::LE, Encode};
 
 use crate::common::run_in_tempdir;
 
@@ -30,3 +34,225 @@ fn test_bigval() -> Result<()> {
         Ok(())
     })
 }
+
+#[test]
+fn test_bigval_stream() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let data = (0..300_000u32).map(|i| i as u8).collect::<Vec<_>>();
+        let mut writer = db.set_big_stream(b"mykey")?;
+        for chunk in data.chunks(7777) {
+            writer.write_all(chunk)?;
+        }
+        // not visible before finishing
+        assert_eq!(db.get_big(b"mykey")?, None);
+        assert!(db.get_big_stream(b"mykey")?.is_none());
+        assert_eq!(writer.finish()?, data.len());
+
+        assert_eq!(db.get_big(b"mykey")?, Some(data.clone()));
+
+        let mut reader = db.get_big_stream(b"mykey")?.unwrap();
+        assert_eq!(reader.len(), data.len());
+        let mut buf = vec![];
+        reader.read_to_end(&mut buf)?;
+        assert_eq!(buf, data);
+
+        // values written by set_big can be streamed as well
+        db.set_big(b"yourkey", &vec![0x99; 100_000])?;
+        let mut buf = vec![];
+        db.get_big_stream(b"yourkey")?
+            .unwrap()
+            .read_to_end(&mut buf)?;
+        assert_eq!(buf, vec![0x99; 100_000]);
+        assert!(db.get_big_stream(b"nokey")?.is_none());
+
+        // empty value
+        db.set_big_stream(b"empty")?.finish()?;
+        assert_eq!(db.get_big(b"empty")?, Some(vec![]));
+
+        let typed = CandyTypedStore::<String, Vec<u32>>::new(db);
+        let mut writer = typed.set_big_stream("hello")?;
+        writer.write_all(&vec![1u32, 2, 3].to_bytes::<LE>())?;
+        writer.finish()?;
+        assert_eq!(typed.get_big("hello")?, Some(vec![1, 2, 3]));
+        assert!(typed.get_big_stream("hello")?.is_some());
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_bigval_auto() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(
+            dir,
+            Config {
+                auto_big_value_threshold: 1000,
+                ..Default::default()
+            },
+        )?);
+
+        let typed = CandyTypedStore::<String, Vec<u8>>::new(db.clone());
+        let small = vec![1u8; 100];
+        let big = vec![2u8; 5000];
+        let huge = vec![3u8; 300_000];
+
+        assert!(!typed.set_auto("x", &small)?);
+        assert_eq!(typed.get_auto("x")?, Some(small.clone()));
+        // stored inline
+        assert_eq!(typed.get_big("x")?, None);
+
+        // switch to a big value and back
+        assert!(typed.set_auto("x", &big)?);
+        assert_eq!(typed.get_auto("x")?, Some(big.clone()));
+        assert_eq!(typed.get_big("x")?, Some(big.clone()));
+        assert!(typed.set_auto("x", &small)?);
+        assert_eq!(typed.get_auto("x")?, Some(small.clone()));
+        assert_eq!(typed.get_big("x")?, None);
+
+        typed.set_auto("y", &huge)?;
+        assert_eq!(typed.get_auto("y")?, Some(huge));
+
+        assert!(typed.remove_auto("y")?);
+        assert!(!typed.remove_auto("y")?);
+        assert_eq!(typed.get_auto("y")?, None);
+        assert_eq!(typed.get_big("y")?, None);
+        assert_eq!(typed.get_auto("z")?, None);
+
+        Ok(())
+    })
+}
+
+// replaces the trailing run of a repeated byte by the byte and the run's length, which is enough to compress the
+// values of the test
+fn compress_trailing_run(data: &[u8]) -> Vec<u8> {
+    let Some(&last) = data.last() else {
+        return vec![];
+    };
+    let run = data.iter().rev().take_while(|&&b| b == last).count();
+    let mut res = data[..data.len() - run].to_vec();
+    res.push(last);
+    res.extend_from_slice(&(run as u32).to_le_bytes());
+    res
+}
+
+fn decompress_trailing_run(data: &[u8]) -> Result<Vec<u8>> {
+    let Some(split) = data.len().checked_sub(5) else {
+        return Ok(vec![]);
+    };
+    let (prefix, run) = data.split_at(split);
+    let mut res = prefix.to_vec();
+    res.resize(
+        prefix.len() + u32::from_le_bytes(run[1..].try_into()?) as usize,
+        run[0],
+    );
+    Ok(res)
+}
+
+#[test]
+fn test_bigval_auto_on_change_and_compression() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(
+            dir,
+            Config {
+                auto_big_value_threshold: 1000,
+                value_codec: Some(ValueCodec {
+                    compress: compress_trailing_run,
+                    decompress: decompress_trailing_run,
+                }),
+                value_compression_threshold: 10,
+                ..Default::default()
+            },
+        )?);
+
+        let events = Arc::new(Mutex::new(vec![]));
+        let events2 = events.clone();
+        let typed = CandyTypedStore::<String, Vec<u8>>::with_on_change(db, move |ev| {
+            events2
+                .lock()
+                .unwrap()
+                .push((ev.old.map(|v| v.len()), ev.new.map(|v| v.len())));
+        });
+
+        let small = vec![1u8; 101];
+        let big = vec![2u8; 5001];
+        assert!(!typed.set_auto("x", &small)?);
+        assert_eq!(typed.get_auto("x")?, Some(small.clone()));
+        // stored compressed
+        assert_eq!(
+            typed.value_size_histogram(&[50])?,
+            vec![(50, 1), (usize::MAX, 0)]
+        );
+        assert!(typed.set_auto("x", &big)?);
+        assert_eq!(typed.get_auto("x")?, Some(big.clone()));
+        assert!(typed.remove_auto("x")?);
+        assert!(!typed.remove_auto("x")?);
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                (None, Some(101)),
+                (Some(101), Some(5001)),
+                (Some(5001), None)
+            ]
+        );
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_bigval_contains() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        assert!(!db.contains_big(b"mykey")?);
+        db.set_big(b"mykey", &vec![0x99; 1_000_000])?;
+        assert!(db.contains_big(b"mykey")?);
+
+        // normal values are not big values, even under the same key
+        db.set(b"plain", b"value")?;
+        assert!(!db.contains_big(b"plain")?);
+        db.set(b"mykey", b"value")?;
+        assert!(db.contains_big(b"mykey")?);
+
+        // incomplete values do not count, even if flushed, and are removed if they are never finished
+        let mut writer = db.set_big_stream(b"mykey")?;
+        writer.write_all(&[1, 2, 3])?;
+        assert!(!db.contains_big(b"mykey")?);
+        writer.flush()?;
+        assert!(!db.contains_big(b"mykey")?);
+        assert_eq!(db.get_big(b"mykey")?, None);
+        writer.finish()?;
+        assert!(db.contains_big(b"mykey")?);
+        assert_eq!(db.get_big(b"mykey")?, Some(vec![1, 2, 3]));
+
+        // a flushed chunk of data of the same length as the trailer is not mistaken for it
+        let mut writer = db.set_big_stream(b"other")?;
+        writer.write_all(&16usize.to_ne_bytes())?;
+        writer.write_all(&[0; 8])?;
+        writer.flush()?;
+        assert!(!db.contains_big(b"other")?);
+        drop(writer);
+        assert!(!db.contains_big(b"other")?);
+        assert_eq!(db.queue_len(b"other")?, 0);
+
+        let mut writer = db.set_big_stream(b"other")?;
+        writer.write_all(&vec![7; 300_000])?;
+        drop(writer);
+        assert_eq!(db.queue_len(b"other")?, 0);
+
+        db.remove_big(b"mykey")?;
+        assert!(!db.contains_big(b"mykey")?);
+
+        let typed = CandyTypedStore::<String, Vec<u32>>::new(db);
+        typed.set("hello", &vec![1, 2, 3])?;
+        assert!(!typed.contains_big("hello")?);
+        typed.set_big("hello", &vec![123456789; 100_000])?;
+        assert!(typed.contains_big("hello")?);
+        assert!(!typed.contains_big("world")?);
+
+        Ok(())
+    })
+}
@@ -0,0 +1,169 @@
+mod common;
+
+use std::sync::Arc;
+
+use candystore::{
+    CandyError, CandyFixedStore, CandyStore, CandyTypedKey, CandyTypedStore, ChangeCursor,
+    ChangeKind, ChangeOp, Config, Result,
+};
+
+use crate::common::run_in_tempdir;
+
+#[test]
+fn test_changes_since() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let config = Config {
+            change_feed_capacity: 100,
+            ..Default::default()
+        };
+        let db = Arc::new(CandyStore::open(dir, config)?);
+
+        let cursor = db.changes_cursor()?;
+        let (records, next) = db.changes_since(cursor)?;
+        assert!(records.is_empty());
+        assert_eq!(next, cursor);
+
+        db.set("hello", "world")?;
+        db.set("hello", "world")?;
+        db.remove("hello")?;
+        // removing a missing key is not a change
+        db.remove("hello")?;
+        db.set_in_list("mylist", "item", "val")?;
+        db.push_to_queue_tail("myqueue", "val")?;
+
+        let typed = CandyTypedStore::<String, u32>::new(db.clone());
+        typed.set("k", &7)?;
+        let typed_ns = CandyTypedStore::<String, u32>::new_in_namespace(db.clone(), "ns");
+        typed_ns.remove("k")?;
+        typed_ns.set("k", &8)?;
+        let fixed = CandyFixedStore::<4, u32>::new_in_namespace(db.clone(), 5)?;
+        fixed.set(b"fix!", &9)?;
+
+        let (records, next) = db.changes_since(cursor)?;
+        let summary = records
+            .iter()
+            .map(|r| (r.op, r.kind.clone()))
+            .collect::<Vec<_>>();
+        let type_id = <String as CandyTypedKey>::TYPE_ID;
+        assert_eq!(
+            summary,
+            vec![
+                (ChangeOp::Set, ChangeKind::Kv),
+                (ChangeOp::Set, ChangeKind::Kv),
+                (ChangeOp::Remove, ChangeKind::Kv),
+                (ChangeOp::Set, ChangeKind::List),
+                (ChangeOp::Set, ChangeKind::ListItem),
+                (ChangeOp::Set, ChangeKind::Queue),
+                (
+                    ChangeOp::Set,
+                    ChangeKind::Typed {
+                        type_id,
+                        namespace: None
+                    }
+                ),
+                (
+                    ChangeOp::Set,
+                    ChangeKind::Typed {
+                        type_id,
+                        namespace: Some("ns".into())
+                    }
+                ),
+                (
+                    ChangeOp::Set,
+                    ChangeKind::Fixed {
+                        type_id: <u32 as CandyTypedKey>::TYPE_ID,
+                        namespace: 5
+                    }
+                ),
+            ]
+        );
+        assert_eq!(records[0].key, b"hello");
+        assert_eq!(records[3].key, b"mylist");
+        assert_eq!(records[4].key, b"item");
+        assert_eq!(records[5].key, b"myqueue");
+        assert!(records[6].key.ends_with(b"k"));
+        assert_eq!(records[6].key, records[7].key);
+        assert_eq!(records[8].key, b"fix!");
+        assert!(records.windows(2).all(|w| w[1].seq == w[0].seq + 1));
+        assert_eq!(next.cookie(), records[8].seq + 1);
+
+        // polling again returns only the new records
+        assert!(db.changes_since(next)?.0.is_empty());
+        db.clear()?;
+        let (records, next) = db.changes_since(next)?;
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].op, ChangeOp::Clear);
+
+        // falling behind loses changes
+        for i in 0..150 {
+            db.set(&format!("key{i}"), "val")?;
+        }
+        let e = db.changes_since(next).unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<CandyError>(),
+            Some(CandyError::ChangesLost { .. })
+        ));
+        let e = db.changes_since(ChangeCursor::default()).unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<CandyError>(),
+            Some(CandyError::ChangesLost { .. })
+        ));
+        let (records, _) = db.changes_since(ChangeCursor::from_cookie(
+            db.changes_cursor()?.cookie() - 100,
+        ))?;
+        assert_eq!(records.len(), 100);
+        assert_eq!(records[99].key, b"key149");
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_persistent_change_feed() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let config = Config {
+            change_feed_capacity: 100,
+            change_feed_persistent: true,
+            ..Default::default()
+        };
+
+        let cursor = {
+            let db = CandyStore::open(dir, config.clone())?;
+            let cursor = db.changes_cursor()?;
+            db.set("hello", "world")?;
+            cursor
+        };
+
+        // the feed survives a restart
+        let db = CandyStore::open(dir, config.clone())?;
+        let (records, _) = db.changes_since(cursor)?;
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].key, b"hello");
+        drop(db);
+
+        // but a non-persistent feed starts over, and old cursors are reported as lost
+        let db = CandyStore::open(
+            dir,
+            Config {
+                change_feed_persistent: false,
+                ..config
+            },
+        )?;
+        let e = db.changes_since(cursor).unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<CandyError>(),
+            Some(CandyError::ChangesLost { .. })
+        ));
+
+        // and a disabled feed can't be polled
+        drop(db);
+        let db = CandyStore::open(dir, Config::default())?;
+        let e = db.changes_since(cursor).unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<CandyError>(),
+            Some(CandyError::NotEnabled(_))
+        ));
+
+        Ok(())
+    })
+}
@@ -0,0 +1,50 @@
+mod common;
+
+use std::sync::Arc;
+
+use candystore::{CandyStore, CandyTypedIndex, CandyTypedList, Config, Result};
+
+use crate::common::run_in_tempdir;
+
+#[test]
+fn test_typed_index() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        // city -> user ids
+        let by_city = CandyTypedIndex::<String, u64>::new(db.clone());
+        assert!(by_city.insert("paris", &1)?);
+        assert!(by_city.insert("paris", &2)?);
+        assert!(by_city.insert("paris", &3)?);
+        assert!(!by_city.insert("paris", &2)?);
+        assert!(by_city.insert("rome", &4)?);
+
+        assert_eq!(by_city.len("paris")?, 3);
+        assert_eq!(
+            by_city.get("paris").collect::<Result<Vec<_>>>()?,
+            vec![1, 2, 3]
+        );
+        assert!(by_city.contains("rome", &4)?);
+        assert!(!by_city.contains("rome", &1)?);
+
+        assert!(by_city.remove("paris", &2)?);
+        assert!(!by_city.remove("paris", &2)?);
+        assert_eq!(
+            by_city.get("paris").collect::<Result<Vec<_>>>()?,
+            vec![1, 3]
+        );
+        assert_eq!(by_city.get("berlin").count(), 0);
+
+        // the index does not share lists with typed lists of the same key type
+        let lists = CandyTypedList::<String, u64, ()>::new(db.clone());
+        assert_eq!(lists.len("paris")?, 0);
+        lists.set("paris", &100, &())?;
+        assert_eq!(by_city.len("paris")?, 2);
+
+        assert!(by_city.discard("paris")?);
+        assert_eq!(by_city.len("paris")?, 0);
+        assert_eq!(lists.len("paris")?, 1);
+
+        Ok(())
+    })
+}
@@ -1,10 +1,14 @@
 mod common;
 
-use std::sync::{atomic::AtomicUsize, Arc};
+use std::{
+    sync::{atomic::AtomicUsize, Arc},
+    time::{Duration, Instant},
+};
 
 use candystore::{
-    CandyStore, CandyTypedDeque, CandyTypedList, Config, GetOrCreateStatus, ListCompactionParams,
-    ReplaceStatus, Result, SetStatus,
+    CandyError, CandyStore, CandyTypedDeque, CandyTypedList, CandyTypedPriorityDeque, Config,
+    GetOrCreateStatus, ListCompactionParams, ListCompactionStats, ListCursor, QueueSnapshot,
+    QueueState, ReplaceStatus, Result, SetStatus,
 };
 
 use crate::common::run_in_tempdir;
@@ -123,6 +127,39 @@ fn test_typed_lists() -> Result<()> {
     })
 }
 
+#[test]
+fn test_typed_set_and_len() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let typed = CandyTypedList::<String, u64, u32>::new(db);
+        assert_eq!(typed.set_and_len("events", &1, &10)?, 1);
+        assert_eq!(typed.set_and_len("events", &2, &20)?, 2);
+        // updating an existing item does not change the length
+        assert_eq!(typed.set_and_len("events", &1, &11)?, 2);
+        assert_eq!(typed.get("events", &1)?, Some(11));
+
+        // concurrent inserts each observe a distinct length
+        let mut handles = vec![];
+        for t in 0..4u64 {
+            let typed = typed.clone();
+            handles.push(std::thread::spawn(move || {
+                (0..50u64)
+                    .map(|i| typed.set_and_len("burst", &(t * 1000 + i), &0).unwrap())
+                    .collect::<Vec<_>>()
+            }));
+        }
+        let mut lens = handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect::<Vec<_>>();
+        lens.sort();
+        assert_eq!(lens, (1..=200).collect::<Vec<_>>());
+
+        Ok(())
+    })
+}
+
 #[test]
 fn test_lists_multithreading() -> Result<()> {
     run_in_tempdir(|dir| {
@@ -280,6 +317,154 @@ fn test_typed_queue() -> Result<()> {
     })
 }
 
+#[test]
+fn test_typed_queue_remove_by_idx() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let queue = CandyTypedDeque::<String, u32>::new(db);
+        for i in 0..5 {
+            queue.push_tail("jobs", &i)?;
+        }
+
+        let idx = queue.iter("jobs").nth(2).unwrap()?.0;
+        assert_eq!(queue.remove_by_idx("jobs", idx)?, Some(2));
+        assert_eq!(queue.remove_by_idx("jobs", idx)?, None);
+        assert_eq!(queue.remove_by_idx("nonexistent", idx)?, None);
+        assert_eq!(queue.len("jobs")?, 4);
+
+        let items = queue
+            .iter("jobs")
+            .map(|res| res.unwrap().1)
+            .collect::<Vec<_>>();
+        assert_eq!(items, vec![0, 1, 3, 4]);
+
+        let (head_idx, _) = queue.peek_head_with_idx("jobs")?.unwrap();
+        assert_eq!(queue.remove_by_idx("jobs", head_idx)?, Some(0));
+        assert_eq!(queue.pop_head("jobs")?, Some(1));
+        assert_eq!(queue.pop_head("jobs")?, Some(3));
+        assert_eq!(queue.pop_head("jobs")?, Some(4));
+        assert_eq!(queue.pop_head("jobs")?, None);
+        assert_eq!(queue.len("jobs")?, 0);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_queue_peek_head_batch() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let queue = CandyTypedDeque::<String, u32>::new(db);
+        assert_eq!(queue.peek_head_batch("jobs", 10)?, vec![]);
+
+        for i in 0..10 {
+            queue.push_tail("jobs", &i)?;
+        }
+        let idx = queue.iter("jobs").nth(1).unwrap()?.0;
+        queue.remove_by_idx("jobs", idx)?;
+
+        let batch = queue.peek_head_batch("jobs", 3)?;
+        assert_eq!(
+            batch.iter().map(|(_, v)| *v).collect::<Vec<_>>(),
+            vec![0, 2, 3]
+        );
+        assert_eq!(batch[0], queue.peek_head_with_idx("jobs")?.unwrap());
+        assert_eq!(queue.len("jobs")?, 9);
+
+        assert_eq!(queue.peek_head_batch("jobs", 100)?.len(), 9);
+        assert_eq!(queue.peek_head_batch("jobs", 0)?, vec![]);
+
+        for _ in 0..batch.len() {
+            queue.pop_head("jobs")?;
+        }
+        assert_eq!(queue.peek_head("jobs")?, Some(4));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_queue_clear_and_truncate() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let queue = CandyTypedDeque::<String, u32>::new(db);
+        assert_eq!(queue.clear("jobs")?, 0);
+
+        for i in 0..10 {
+            queue.push_tail("jobs", &i)?;
+        }
+        assert_eq!(queue.truncate_head("jobs", 7)?, 3);
+        assert_eq!(queue.truncate_tail("jobs", 5)?, 2);
+        assert_eq!(queue.truncate_tail("jobs", 5)?, 0);
+        let items = queue
+            .iter("jobs")
+            .map(|res| res.unwrap().1)
+            .collect::<Vec<_>>();
+        assert_eq!(items, vec![3, 4, 5, 6, 7]);
+        assert_eq!(queue.len("jobs")?, 5);
+
+        let last_idx = queue.peek_tail_with_idx("jobs")?.unwrap().0;
+        assert_eq!(queue.clear("jobs")?, 5);
+        assert_eq!(queue.len("jobs")?, 0);
+        assert_eq!(queue.pop_head("jobs")?, None);
+
+        // indices keep increasing after a clear
+        queue.push_tail("jobs", &100)?;
+        let (idx, v) = queue.peek_head_with_idx("jobs")?.unwrap();
+        assert_eq!(v, 100);
+        assert!(idx > last_idx);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_queue_double_ended_iter() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let queue = CandyTypedDeque::<String, u32>::new(db);
+        for i in 0..7 {
+            queue.push_tail("q", &i)?;
+        }
+        let idx = queue.iter("q").nth(3).unwrap()?.0;
+        queue.remove_by_idx("q", idx)?;
+
+        let items = queue
+            .iter("q")
+            .rev()
+            .map(|res| res.unwrap().1)
+            .collect::<Vec<_>>();
+        assert_eq!(items, vec![6, 5, 4, 2, 1, 0]);
+
+        // consume from both ends, until they meet
+        let mut iter = queue.iter("q");
+        let mut front = vec![];
+        let mut back = vec![];
+        loop {
+            let Some(res) = iter.next() else { break };
+            front.push(res?.1);
+            let Some(res) = iter.next_back() else { break };
+            back.push(res?.1);
+        }
+        assert_eq!(front, vec![0, 1, 2]);
+        assert_eq!(back, vec![6, 5, 4]);
+        assert!(iter.next().is_none());
+
+        let items = queue
+            .iter_backwards("q")
+            .rev()
+            .map(|res| res.unwrap().1)
+            .collect::<Vec<_>>();
+        assert_eq!(items, vec![0, 1, 2, 4, 5, 6]);
+
+        Ok(())
+    })
+}
+
 #[test]
 fn test_rev_iter() -> Result<()> {
     run_in_tempdir(|dir| {
@@ -378,8 +563,13 @@ fn test_typed_promote() -> Result<()> {
         typed.set("mylist", &2, "yyy")?;
         assert_eq!(items(), &[1, 2, 3, 4]);
 
-        typed.set_promoting("mylist", &2, "zzz")?;
+        assert_eq!(
+            typed.set_promoting("mylist", &2, "zzz")?,
+            Some("yyy".to_owned())
+        );
         assert_eq!(items(), &[1, 3, 4, 2]);
+        assert_eq!(typed.set_promoting("mylist", &5, "zzz")?, None);
+        assert_eq!(typed.remove("mylist", &5)?, Some("zzz".to_owned()));
 
         typed.set_promoting("mylist", &1, "zzz")?;
         assert_eq!(items(), &[3, 4, 2, 1]);
@@ -514,3 +704,1500 @@ fn test_list_retain() -> Result<()> {
         Ok(())
     })
 }
+
+#[test]
+fn test_typed_insert_sorted() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+        let leaderboard = CandyTypedList::<String, String, u32>::new(db);
+
+        let items = || {
+            leaderboard
+                .iter("board")
+                .map(|res| res.unwrap())
+                .collect::<Vec<_>>()
+        };
+        let desc = |a: &u32, b: &u32| b.cmp(a);
+
+        leaderboard.insert_sorted("board", "alice", &50, desc)?;
+        leaderboard.insert_sorted("board", "bob", &70, desc)?;
+        leaderboard.insert_sorted("board", "carol", &10, desc)?;
+        leaderboard.insert_sorted("board", "dave", &60, desc)?;
+        leaderboard.insert_sorted("board", "erin", &60, desc)?;
+        assert_eq!(
+            items(),
+            vec![
+                ("bob".into(), 70),
+                ("dave".into(), 60),
+                ("erin".into(), 60),
+                ("alice".into(), 50),
+                ("carol".into(), 10)
+            ]
+        );
+
+        // repositioning an existing item
+        leaderboard.insert_sorted("board", "carol", &65, desc)?;
+        assert_eq!(
+            items().into_iter().map(|(k, _)| k).collect::<Vec<_>>(),
+            vec!["bob", "carol", "dave", "erin", "alice"]
+        );
+        assert_eq!(leaderboard.len("board")?, 5);
+
+        // fill a hole
+        leaderboard.remove("board", "dave")?;
+        leaderboard.insert_sorted("board", "frank", &61, desc)?;
+        assert_eq!(
+            items().into_iter().map(|(k, _)| k).collect::<Vec<_>>(),
+            vec!["bob", "carol", "frank", "erin", "alice"]
+        );
+
+        leaderboard.insert_sorted("board", "gina", &100, desc)?;
+        assert_eq!(leaderboard.peek_head("board")?, Some(("gina".into(), 100)));
+        assert_eq!(leaderboard.peek_tail("board")?, Some(("alice".into(), 50)));
+        assert_eq!(leaderboard.len("board")?, 6);
+
+        let rev = leaderboard
+            .iter_backwards("board")
+            .map(|res| res.unwrap().1)
+            .collect::<Vec<_>>();
+        assert_eq!(rev, vec![50, 60, 61, 65, 70, 100]);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_binary_search() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+        let sorted = CandyTypedList::<String, u32, u32>::new(db);
+
+        assert_eq!(sorted.binary_search_by("nums", |v| v.cmp(&5))?, None);
+
+        for i in (0..100).rev() {
+            sorted.insert_sorted("nums", &i, &(i * 2), |a, b| a.cmp(b))?;
+        }
+        for i in 0..100 {
+            assert_eq!(
+                sorted.binary_search_by("nums", |v| v.cmp(&(i * 2)))?,
+                Some((i, i * 2))
+            );
+            assert_eq!(
+                sorted.binary_search_by("nums", |v| v.cmp(&(i * 2 + 1)))?,
+                None
+            );
+        }
+
+        // holes are skipped
+        for i in (0..100).filter(|i| i % 3 != 0) {
+            sorted.remove("nums", &i)?;
+        }
+        for i in 0..100 {
+            assert_eq!(
+                sorted.binary_search_by("nums", |v| v.cmp(&(i * 2)))?,
+                (i % 3 == 0).then_some((i, i * 2)),
+                "i={i}"
+            );
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_contains_any() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let blocked = CandyTypedList::<String, u64, ()>::new(db.clone());
+        blocked.set("alice", &17, &())?;
+        blocked.set("alice", &42, &())?;
+
+        assert_eq!(blocked.contains_any("alice", &[&1, &42, &17])?, Some(42));
+        assert_eq!(blocked.contains_any("alice", &[&1, &2, &3])?, None);
+        assert_eq!(blocked.contains_any("alice", &[])?, None);
+        assert_eq!(blocked.contains_any("bob", &[&17])?, None);
+
+        db.set_in_list("carol", "x", "1")?;
+        assert_eq!(db.list_contains_any("carol", &["y", "x"])?, Some(1));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_list_size_bytes() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let typed = CandyTypedList::<String, u64, Vec<u8>>::new(db.clone());
+        assert_eq!(typed.size_bytes("quota")?, 0);
+
+        typed.set("quota", &1, &vec![0u8; 10])?;
+        typed.set("quota", &2, &vec![0u8; 20])?;
+        // u64 keys take 8 bytes, and the (short) vec's length prefix takes 1 byte
+        assert_eq!(typed.size_bytes("quota")?, 8 + 11 + 8 + 21);
+
+        typed.remove("quota", &1)?;
+        assert_eq!(typed.size_bytes("quota")?, 8 + 21);
+
+        db.set_in_list("raw", "abc", "defg")?;
+        assert_eq!(db.list_size_bytes("raw")?, 7);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_list_find_map() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let typed = CandyTypedList::<String, u32, String>::new(db.clone());
+        for i in 0..10 {
+            typed.set("xxx", &i, &format!("val{i}"))?;
+        }
+
+        let mut visited = 0;
+        let found = typed.find_map("xxx", |k, v| {
+            visited += 1;
+            Ok((*k == 3).then(|| v.clone()))
+        })?;
+        assert_eq!(found, Some("val3".to_owned()));
+        assert_eq!(visited, 4);
+
+        assert_eq!(typed.find_map("xxx", |_, _| Ok(None::<()>))?, None);
+        assert_eq!(typed.find_map("yyy", |_, _| Ok(Some(())))?, None);
+
+        let res = typed.find_map("xxx", |_, _| -> Result<Option<()>> {
+            Err(anyhow::anyhow!("stop"))
+        });
+        assert!(res.is_err());
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_list_dump_internals() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let list = CandyTypedList::<String, u32, String>::new(db);
+        let info = list.dump_internals("xxx")?;
+        assert!(info.is_consistent());
+        assert_eq!(info.head_idx, info.tail_idx);
+        assert_eq!(info.present_len, 0);
+
+        for i in 0..6 {
+            list.set("xxx", &i, &format!("val{i}"))?;
+        }
+        let info = list.dump_internals("xxx")?;
+        assert!(info.is_consistent());
+        assert_eq!(info.tail_idx - info.head_idx, 6);
+        assert_eq!(info.recorded_len, 6);
+        assert_eq!(info.present_len, 6);
+        assert!(info.holes.is_empty());
+
+        // removing from the middle leaves holes, which are consistent
+        list.remove("xxx", &2)?;
+        list.remove("xxx", &3)?;
+        let info2 = list.dump_internals("xxx")?;
+        assert!(info2.is_consistent());
+        assert_eq!(info2.present_len, 4);
+        assert_eq!(info2.holes, vec![info.head_idx + 2..info.head_idx + 4]);
+        assert_eq!(info2.present_len, list.iter("xxx").count());
+
+        list.compact_if_needed(
+            "xxx",
+            ListCompactionParams {
+                min_length: 1,
+                min_holes_ratio: 0.0,
+            },
+        )?;
+        let info3 = list.dump_internals("xxx")?;
+        assert!(info3.is_consistent());
+        assert!(info3.holes.is_empty());
+        assert_eq!(info3.tail_idx - info3.head_idx, 4);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_list_batch() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let list = CandyTypedList::<String, String, u32>::new(db);
+        let prev = list.batch_in_list("accounts", |batch| {
+            assert_eq!(batch.set("alice", &60)?, None);
+            assert_eq!(batch.set("bob", &40)?, None);
+            assert_eq!(batch.set("carol", &0)?, None);
+            assert_eq!(batch.get("bob")?, Some(40));
+            batch.remove("carol")
+        })?;
+        assert_eq!(prev, Some(0));
+        assert_eq!(
+            list.iter("accounts").collect::<Result<Vec<_>>>()?,
+            vec![("alice".to_owned(), 60), ("bob".to_owned(), 40)]
+        );
+
+        // transfers are never observed half-way by other batches
+        std::thread::scope(|s| {
+            for i in 0..4 {
+                let list = list.clone();
+                s.spawn(move || {
+                    for j in 0..100u32 {
+                        let (from, to) = if (i + j) % 2 == 0 {
+                            ("alice", "bob")
+                        } else {
+                            ("bob", "alice")
+                        };
+                        list.batch_in_list("accounts", |batch| {
+                            let from_balance = batch.get(from)?.unwrap();
+                            let to_balance = batch.get(to)?.unwrap();
+                            let amount = from_balance.min(7);
+                            batch.set(from, &(from_balance - amount))?;
+                            batch.set(to, &(to_balance + amount))?;
+                            Ok(())
+                        })
+                        .unwrap();
+                    }
+                });
+            }
+            for _ in 0..100 {
+                let total = list
+                    .batch_in_list("accounts", |batch| {
+                        Ok(batch.get("alice")?.unwrap() + batch.get("bob")?.unwrap())
+                    })
+                    .unwrap();
+                assert_eq!(total, 100);
+            }
+        });
+        assert_eq!(list.len("accounts")?, 2);
+
+        // changes made before an error remain
+        let e = list
+            .batch_in_list("accounts", |batch| -> Result<()> {
+                batch.set("dave", &1)?;
+                Err(CandyError::WrongType("dave").into())
+            })
+            .unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<CandyError>(),
+            Some(CandyError::WrongType(_))
+        ));
+        assert_eq!(list.get("accounts", "dave")?, Some(1));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_queue_verify_and_repair() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let queue = CandyTypedDeque::<String, u32>::new(db);
+        let health = queue.verify_queue("jobs")?;
+        assert!(health.is_healthy());
+        assert_eq!(health.present_len, 0);
+
+        for i in 0..6 {
+            queue.push_tail("jobs", &i)?;
+        }
+        let range = queue.range("jobs")?;
+        let health = queue.verify_queue("jobs")?;
+        assert!(health.is_healthy());
+        assert_eq!(health.range, range);
+        assert_eq!(health.recorded_len, 6);
+        assert_eq!(health.present_len, 6);
+
+        // remove the middle two and the last element. removing the last element trims the range, leaving a gap
+        // only inside of it
+        queue.remove_by_idx("jobs", range.start + 2)?;
+        queue.remove_by_idx("jobs", range.start + 3)?;
+        queue.remove_by_idx("jobs", range.start + 5)?;
+
+        let health = queue.verify_queue("jobs")?;
+        assert!(!health.is_healthy());
+        assert_eq!(health.range, range.start..range.start + 5);
+        assert_eq!(health.recorded_len, 3);
+        assert_eq!(health.present_len, 3);
+        assert_eq!(health.gaps, vec![range.start + 2..range.start + 4]);
+
+        let health = queue.repair_queue("jobs")?;
+        assert_eq!(health.range, range.start..range.start + 5);
+        assert_eq!(health.gaps, vec![range.start + 2..range.start + 4]);
+        assert_eq!(queue.range("jobs")?, health.range);
+        assert_eq!(queue.verify_queue("jobs")?, health);
+
+        let items = queue
+            .iter("jobs")
+            .map(|res| res.unwrap().1)
+            .collect::<Vec<_>>();
+        assert_eq!(items, vec![0, 1, 4]);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_list_rename() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let typed = CandyTypedList::<String, u32, String>::new(db.clone());
+        for i in 0..10 {
+            typed.set("2024-01", &i, &format!("val{i}"))?;
+        }
+        typed.remove("2024-01", &3)?;
+        typed.set("2024-02", &100, &"other".to_owned())?;
+
+        assert!(!typed.rename("nonexistent", "2024-03")?);
+        let err = typed.rename("2024-01", "2024-02").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<CandyError>(),
+            Some(CandyError::AlreadyExists(_))
+        ));
+        assert_eq!(typed.len("2024-01")?, 9);
+
+        assert!(typed.rename("2024-01", "archive")?);
+        assert_eq!(typed.len("2024-01")?, 0);
+        assert_eq!(typed.iter("2024-01").count(), 0);
+        assert_eq!(typed.get("2024-01", &5)?, None);
+
+        assert_eq!(typed.len("archive")?, 9);
+        assert_eq!(typed.get("archive", &5)?, Some("val5".to_owned()));
+        let keys = typed
+            .iter("archive")
+            .map(|res| res.unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec![0, 1, 2, 4, 5, 6, 7, 8, 9]);
+
+        // the renamed list is fully functional
+        typed.set("archive", &10, &"val10".to_owned())?;
+        assert_eq!(typed.pop_head("archive")?, Some((0, "val0".to_owned())));
+        assert_eq!(typed.len("archive")?, 9);
+
+        assert!(typed.rename("archive", "archive")?);
+        assert_eq!(typed.len("archive")?, 9);
+
+        db.set_in_list("raw", "a", "1")?;
+        assert!(db.rename_list("raw", "raw2")?);
+        assert_eq!(db.get_from_list("raw2", "a")?, Some("1".into()));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_queue_try_push_tail() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let queue = CandyTypedDeque::<String, u32>::new(db.clone());
+        assert!(!queue.try_push_tail("jobs", &0, 0)?);
+        assert_eq!(queue.len("jobs")?, 0);
+
+        for i in 0..3 {
+            assert!(queue.try_push_tail("jobs", &i, 3)?);
+        }
+        assert!(!queue.try_push_tail("jobs", &3, 3)?);
+        assert_eq!(queue.len("jobs")?, 3);
+
+        assert_eq!(queue.pop_head("jobs")?, Some(0));
+        assert!(queue.try_push_tail("jobs", &3, 3)?);
+        let items = queue
+            .iter("jobs")
+            .map(|res| res.unwrap().1)
+            .collect::<Vec<_>>();
+        assert_eq!(items, vec![1, 2, 3]);
+
+        assert_eq!(
+            db.try_push_to_queue_tail("raw", "a", 1)?,
+            Some(db.queue_range("raw")?.start)
+        );
+        assert_eq!(db.try_push_to_queue_tail("raw", "b", 1)?, None);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_queue_transfer_head() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let queue = CandyTypedDeque::<String, u32>::new(db.clone());
+        assert_eq!(queue.transfer_head("processing", "dead-letter")?, None);
+        assert_eq!(queue.len("dead-letter")?, 0);
+
+        for i in 0..3 {
+            queue.push_tail("processing", &i)?;
+        }
+        queue.push_tail("dead-letter", &100)?;
+
+        assert_eq!(queue.transfer_head("processing", "dead-letter")?, Some(0));
+        assert_eq!(queue.transfer_head("processing", "dead-letter")?, Some(1));
+        assert_eq!(queue.len("processing")?, 1);
+        let items = queue
+            .iter("dead-letter")
+            .map(|res| res.unwrap().1)
+            .collect::<Vec<_>>();
+        assert_eq!(items, vec![100, 0, 1]);
+
+        // transferring within the same queue rotates it
+        assert_eq!(
+            queue.transfer_head("dead-letter", "dead-letter")?,
+            Some(100)
+        );
+        let items = queue
+            .iter("dead-letter")
+            .map(|res| res.unwrap().1)
+            .collect::<Vec<_>>();
+        assert_eq!(items, vec![0, 1, 100]);
+
+        // concurrent transfers in opposite directions do not deadlock, and nothing is lost
+        for i in 0..100 {
+            queue.push_tail("a", &i)?;
+            queue.push_tail("b", &(1000 + i))?;
+        }
+        let handles = [("a", "b"), ("b", "a")].map(|(from, to)| {
+            let queue = queue.clone();
+            std::thread::spawn(move || {
+                for _ in 0..500 {
+                    queue.transfer_head(from, to).unwrap();
+                }
+            })
+        });
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(queue.len("a")? + queue.len("b")?, 200);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_list_page() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let typed = CandyTypedList::<String, u32, String>::new(db.clone());
+        assert_eq!(typed.page("feed", None, 10)?, (vec![], None));
+
+        for i in 0..10 {
+            typed.set("feed", &i, &format!("post{i}"))?;
+        }
+
+        let (page, cursor) = typed.page("feed", None, 4)?;
+        assert_eq!(
+            page.iter().map(|(k, _)| *k).collect::<Vec<_>>(),
+            vec![0, 1, 2, 3]
+        );
+        assert_eq!(page[0].1, "post0");
+        let cursor = cursor.unwrap();
+
+        // removing already-returned (and upcoming) items does not shift the cursor, and appended ones
+        // are picked up
+        typed.remove("feed", &1)?;
+        typed.remove("feed", &5)?;
+        typed.set("feed", &10, &"post10".to_owned())?;
+
+        // cursors survive a round trip through their cookie
+        let cursor = ListCursor::from_cookie(cursor.cookie());
+        let (page, cursor) = typed.page("feed", Some(cursor), 4)?;
+        assert_eq!(
+            page.iter().map(|(k, _)| *k).collect::<Vec<_>>(),
+            vec![4, 6, 7, 8]
+        );
+        let (page, cursor) = typed.page("feed", cursor, 4)?;
+        assert_eq!(
+            page.iter().map(|(k, _)| *k).collect::<Vec<_>>(),
+            vec![9, 10]
+        );
+        assert_eq!(cursor, None);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_list_remove_if() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let typed = CandyTypedList::<String, u32, String>::new(db.clone());
+        assert!(!typed.remove_if("offers", &1, "x")?);
+
+        typed.set("offers", &1, "open")?;
+        typed.set("offers", &2, "open")?;
+
+        assert!(!typed.remove_if("offers", &1, "taken")?);
+        assert_eq!(typed.get("offers", &1)?, Some("open".to_owned()));
+        assert!(typed.remove_if("offers", &1, "open")?);
+        assert_eq!(typed.get("offers", &1)?, None);
+        assert!(!typed.remove_if("offers", &1, "open")?);
+        assert_eq!(typed.len("offers")?, 1);
+
+        db.set_in_list("raw", "a", "1")?;
+        assert!(!db.remove_from_list_if("raw", "a", "2")?);
+        assert!(db.remove_from_list_if("raw", "a", "1")?);
+        assert_eq!(db.list_len("raw")?, 0);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_queue_iter_filter() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let queue = CandyTypedDeque::<String, (u8, String)>::new(db.clone());
+        for i in 0..10u8 {
+            queue.push_tail("jobs", &(i % 3, format!("job{i}")))?;
+        }
+
+        let urgent = queue
+            .iter_filter("jobs", |(prio, _)| *prio == 0)
+            .map(|res| res.unwrap().1 .1)
+            .collect::<Vec<_>>();
+        assert_eq!(urgent, vec!["job0", "job3", "job6", "job9"]);
+
+        // the priority is the first encoded byte
+        let urgent_raw = queue
+            .iter_filter_raw("jobs", |bytes| bytes[0] == 0)
+            .map(|res| res.unwrap().1 .1)
+            .collect::<Vec<_>>();
+        assert_eq!(urgent_raw, urgent);
+
+        assert_eq!(queue.iter_filter("jobs", |_| false).count(), 0);
+        assert_eq!(queue.iter_filter_raw("nonexistent", |_| true).count(), 0);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_priority_deque() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let queue = CandyTypedPriorityDeque::<String, String>::new(db.clone(), 3);
+        assert_eq!(queue.pop("jobs")?, None);
+        assert_eq!(queue.peek_with_priority("jobs")?, None);
+
+        queue.push("jobs", "low1", 0)?;
+        queue.push("jobs", "mid1", 1)?;
+        queue.push("jobs", "low2", 0)?;
+        queue.push("jobs", "high1", 2)?;
+        queue.push("jobs", "mid2", 1)?;
+        // priorities beyond the number of lanes go to the highest lane
+        queue.push("jobs", "high2", 200)?;
+        queue.push("other", "other1", 0)?;
+        assert_eq!(queue.len("jobs")?, 6);
+
+        // the lanes do not collide with a plain deque of the same key
+        let plain = CandyTypedDeque::<String, String>::new(db.clone());
+        assert_eq!(plain.len("jobs")?, 0);
+
+        assert_eq!(
+            queue.peek_with_priority("jobs")?,
+            Some((2, "high1".to_owned()))
+        );
+        assert_eq!(
+            queue.pop_with_priority("jobs")?,
+            Some((2, "high1".to_owned()))
+        );
+        assert_eq!(queue.pop("jobs")?.as_deref(), Some("high2"));
+        assert_eq!(queue.pop("jobs")?.as_deref(), Some("mid1"));
+
+        // a new high-priority value jumps the line
+        queue.push("jobs", "high3", 2)?;
+        assert_eq!(queue.pop("jobs")?.as_deref(), Some("high3"));
+        assert_eq!(queue.pop("jobs")?.as_deref(), Some("mid2"));
+        assert_eq!(queue.pop("jobs")?.as_deref(), Some("low1"));
+        assert_eq!(queue.len("jobs")?, 1);
+
+        assert_eq!(queue.clear("jobs")?, 1);
+        assert_eq!(queue.pop("jobs")?, None);
+        assert_eq!(queue.pop("other")?.as_deref(), Some("other1"));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_list_push_unique() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let recent = CandyTypedList::<String, String, u32>::new(db.clone());
+        assert!(recent.push_unique("user1", "page_a", &1)?);
+        assert!(recent.push_unique("user1", "page_b", &2)?);
+        assert!(recent.push_unique("user1", "page_c", &3)?);
+
+        // pushing an existing item promotes it instead of duplicating it
+        assert!(!recent.push_unique("user1", "page_a", &4)?);
+        assert_eq!(recent.len("user1")?, 3);
+
+        let newest_first = recent
+            .iter_backwards("user1")
+            .map(|res| res.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            newest_first,
+            vec![
+                ("page_a".to_owned(), 4),
+                ("page_c".to_owned(), 3),
+                ("page_b".to_owned(), 2)
+            ]
+        );
+        assert_eq!(recent.peek_tail("user1")?, Some(("page_a".to_owned(), 4)));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_queue_pop_head_batch() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let queue = CandyTypedDeque::<String, u32>::new(db.clone());
+        assert_eq!(queue.pop_head_batch("jobs", 10)?, vec![]);
+
+        for i in 0..10 {
+            queue.push_tail("jobs", &i)?;
+        }
+        // holes are skipped
+        queue.remove_by_idx("jobs", queue.range("jobs")?.start + 1)?;
+
+        let batch = queue.pop_head_batch("jobs", 3)?;
+        assert_eq!(
+            batch.iter().map(|(_, v)| *v).collect::<Vec<_>>(),
+            vec![0, 2, 3]
+        );
+        assert!(batch.windows(2).all(|w| w[0].0 < w[1].0));
+        assert_eq!(queue.len("jobs")?, 6);
+        assert_eq!(queue.pop_head_batch("jobs", 0)?, vec![]);
+
+        // the queue drains with fewer than n elements
+        let batch = queue.pop_head_batch("jobs", 100)?;
+        assert_eq!(
+            batch.into_iter().map(|(_, v)| v).collect::<Vec<_>>(),
+            vec![4, 5, 6, 7, 8, 9]
+        );
+        assert_eq!(queue.len("jobs")?, 0);
+
+        // concurrent consumers get disjoint, contiguous batches
+        for i in 0..1000 {
+            queue.push_tail("jobs", &i)?;
+        }
+        let batches = std::thread::scope(|s| {
+            let handles = (0..4)
+                .map(|_| {
+                    s.spawn(|| {
+                        let mut batches = vec![];
+                        loop {
+                            let batch = queue.pop_head_batch("jobs", 10).unwrap();
+                            if batch.is_empty() {
+                                break batches;
+                            }
+                            batches.push(batch.into_iter().map(|(_, v)| v).collect::<Vec<_>>());
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(batches.len(), 100);
+        for batch in &batches {
+            assert_eq!(batch.len(), 10);
+            assert_eq!(batch[0] % 10, 0);
+            assert!(batch.windows(2).all(|w| w[0] + 1 == w[1]));
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_list_compaction_stats() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+        let typed = CandyTypedList::<String, u32, u32>::new(db.clone());
+
+        assert_eq!(
+            typed.compact("xxx", ListCompactionParams::default())?,
+            ListCompactionStats::default()
+        );
+
+        for i in 0u32..1000 {
+            typed.set("xxx", &i, &i)?;
+        }
+        let stats = typed.compact("xxx", ListCompactionParams::default())?;
+        assert!(!stats.compacted);
+        assert_eq!(stats.num_items, 1000);
+        assert_eq!(stats.holes_removed(), 0);
+
+        for i in (1u32..1000).step_by(3) {
+            typed.remove("xxx", &i)?;
+        }
+
+        // below the threshold
+        let stats = typed.compact(
+            "xxx",
+            ListCompactionParams {
+                min_holes_ratio: 0.5,
+                ..Default::default()
+            },
+        )?;
+        assert!(!stats.compacted);
+        assert_eq!(stats.num_items, 667);
+        assert_eq!(stats.span_len_before, 1000);
+        assert_eq!(stats.span_len_after, 1000);
+        assert_eq!(stats.bytes_rewritten, 0);
+
+        let stats = typed.compact("xxx", ListCompactionParams::default())?;
+        assert!(stats.compacted);
+        assert_eq!(stats.num_items, 667);
+        assert_eq!(stats.span_len_before, 1000);
+        assert_eq!(stats.span_len_after, 667);
+        assert_eq!(stats.holes_removed(), 333);
+        assert!(stats.bytes_rewritten > 0);
+        assert_eq!(typed.len("xxx")?, 667);
+        assert_eq!(typed.get("xxx", &2)?, Some(2));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_list_peek_keys() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+        let typed = CandyTypedList::<String, u32, Vec<u8>>::new(db.clone());
+
+        assert_eq!(typed.peek_head_key("xxx")?, None);
+        assert_eq!(typed.peek_tail_key("xxx")?, None);
+
+        for i in 0u32..10 {
+            typed.set("xxx", &i, &vec![7; 1000])?;
+        }
+        typed.remove("xxx", &0)?;
+        typed.remove("xxx", &9)?;
+        assert_eq!(typed.peek_head_key("xxx")?, Some(1));
+        assert_eq!(typed.peek_tail_key("xxx")?, Some(8));
+
+        // the value is not decoded, so even values of another type do not matter
+        let keys_only = CandyTypedList::<String, u32, u64>::new(db);
+        assert_eq!(keys_only.peek_head_key("xxx")?, Some(1));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_queue_map_in_place() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let queue = CandyTypedDeque::<String, (String, u32)>::new(db.clone());
+        assert_eq!(queue.map_in_place("counters", |_| unreachable!())?, 0);
+
+        for i in 0..10u32 {
+            queue.push_tail("counters", &(format!("c{i}"), i * 10))?;
+        }
+        queue.pop_head("counters")?;
+        let range = queue.range("counters")?;
+        queue.remove_by_idx("counters", range.start + 2)?;
+        let before = queue
+            .iter("counters")
+            .map(|res| res.unwrap().0)
+            .collect::<Vec<_>>();
+
+        // decay all counters
+        assert_eq!(queue.map_in_place("counters", |(_, c)| *c /= 2)?, 8);
+
+        let after = queue
+            .iter("counters")
+            .map(|res| res.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            after.iter().map(|(idx, _)| *idx).collect::<Vec<_>>(),
+            before
+        );
+        assert_eq!(
+            after.into_iter().map(|(_, (_, c))| c).collect::<Vec<_>>(),
+            vec![5, 10, 20, 25, 30, 35, 40, 45]
+        );
+        assert_eq!(queue.range("counters")?, range);
+        assert_eq!(queue.len("counters")?, 8);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_queue_fold() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let queue = CandyTypedDeque::<String, u64>::new(db.clone());
+        assert_eq!(queue.fold("amounts", 17, |acc, v| acc + v)?, 17);
+
+        for i in 1..=100u64 {
+            queue.push_tail("amounts", &i)?;
+        }
+        queue.pop_head("amounts")?;
+        assert_eq!(queue.fold("amounts", 0, |acc, v| acc + v)?, 5049);
+        assert_eq!(
+            queue.fold("amounts", (0, u64::MAX), |(n, min), v| (n + 1, min.min(*v)))?,
+            (99, 2)
+        );
+        // folding does not modify the queue
+        assert_eq!(queue.len("amounts")?, 99);
+
+        // decode errors are returned
+        CandyTypedDeque::<String, String>::new(db).push_tail("names", "x")?;
+        assert!(queue.fold("names", 0, |acc, v| acc + v).is_err());
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_list_to_vec() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+        let typed = CandyTypedList::<String, String, u32>::new(db.clone());
+
+        assert_eq!(typed.to_vec("xxx")?, vec![]);
+        assert_eq!(typed.values("xxx")?, Vec::<u32>::new());
+        assert_eq!(typed.keys("xxx")?, Vec::<String>::new());
+
+        for i in 0u32..10 {
+            typed.set("xxx", &format!("k{i}"), &i)?;
+        }
+        typed.remove("xxx", "k3")?;
+        typed.set_promoting("xxx", "k0", &100)?;
+
+        let expected_keys = [1, 2, 4, 5, 6, 7, 8, 9, 0].map(|i| format!("k{i}"));
+        assert_eq!(typed.keys("xxx")?, expected_keys);
+        assert_eq!(typed.values("xxx")?, vec![1, 2, 4, 5, 6, 7, 8, 9, 100]);
+        assert_eq!(
+            typed.to_vec("xxx")?,
+            typed.iter("xxx").collect::<Result<Vec<_>>>()?
+        );
+
+        // keys are collected without decoding the values
+        let keys_only = CandyTypedList::<String, String, String>::new(db);
+        assert_eq!(keys_only.keys("xxx")?, expected_keys);
+        assert!(keys_only.values("xxx").is_err());
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_list_maintenance() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+        // report the span without compacting
+        let inspect = |list: &str| {
+            db.compact_list(
+                list,
+                ListCompactionParams {
+                    min_length: u64::MAX,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        };
+
+        for list in ["aaa", "bbb", "ccc"] {
+            for i in 0u32..300 {
+                db.set_in_list(list, &i.to_le_bytes(), "v")?;
+            }
+        }
+        for list in ["aaa", "bbb"] {
+            for i in (0u32..300).filter(|i| i % 2 == 1) {
+                db.remove_from_list(list, &i.to_le_bytes())?;
+            }
+        }
+        // other entries are not mistaken for lists
+        db.set("xxx", "yyy")?;
+        db.push_to_queue_tail("qqq", "zzz")?;
+
+        assert_eq!(
+            db.compact_lists_if_needed(ListCompactionParams::default())?,
+            2
+        );
+        assert_eq!(inspect("aaa").span_len_before, 150);
+        assert_eq!(inspect("bbb").span_len_before, 150);
+        assert_eq!(inspect("ccc").span_len_before, 300);
+        assert_eq!(
+            db.compact_lists_if_needed(ListCompactionParams::default())?,
+            0
+        );
+
+        for i in (0u32..300).filter(|i| i % 3 == 0) {
+            db.remove_from_list("ccc", &i.to_le_bytes())?;
+        }
+        let handle =
+            db.spawn_maintenance(Duration::from_millis(10), ListCompactionParams::default());
+        assert!(handle.is_running());
+        let t0 = Instant::now();
+        while inspect("ccc").span_len_before != 200 {
+            assert!(t0.elapsed() < Duration::from_secs(10));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(db.list_len("ccc")?, 200);
+        handle.stop()?;
+
+        // the thread does not keep the store alive, and is stopped and joined when it's dropped (even if
+        // it's in the middle of a long wait)
+        let handle =
+            db.spawn_maintenance(Duration::from_secs(3600), ListCompactionParams::default());
+        let t0 = Instant::now();
+        drop(db);
+        assert!(t0.elapsed() < Duration::from_secs(10));
+        assert!(!handle.is_running());
+        handle.stop()?;
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_queue_iter_from() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+        let queue = CandyTypedDeque::<String, u32>::new(db);
+
+        assert_eq!(queue.iter_from("events", 0).count(), 0);
+
+        for i in 0u32..20 {
+            queue.push_tail("events", &i)?;
+        }
+        queue.pop_head("events")?;
+        queue.pop_head("events")?;
+        let range = queue.range("events")?;
+
+        // resume after a checkpoint
+        let checkpoint = queue.iter("events").nth(4).unwrap()?.0;
+        assert_eq!(checkpoint, range.start + 4);
+        let rest = queue
+            .iter_from("events", checkpoint + 1)
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            rest.iter().map(|(_, v)| *v).collect::<Vec<_>>(),
+            (7..20).collect::<Vec<_>>()
+        );
+        assert_eq!(rest[0].0, checkpoint + 1);
+
+        // holes are skipped
+        queue.remove_by_idx("events", checkpoint + 1)?;
+        assert_eq!(
+            queue.iter_from("events", checkpoint + 1).next().unwrap()?,
+            (checkpoint + 2, 8)
+        );
+
+        // an index before the head starts at the head, and one past the tail yields nothing
+        assert_eq!(
+            queue.iter_from("events", 0).next().unwrap()?,
+            (range.start, 2)
+        );
+        assert_eq!(queue.iter_from("events", range.end).count(), 0);
+        assert_eq!(queue.iter_from("events", usize::MAX).count(), 0);
+
+        // backwards from the tail, down to start_idx
+        assert_eq!(
+            queue
+                .iter_from("events", range.end - 3)
+                .rev()
+                .map(|res| res.unwrap().1)
+                .collect::<Vec<_>>(),
+            vec![19, 18, 17]
+        );
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_list_extend() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+        let typed = CandyTypedList::<String, u32, String>::new(db.clone());
+
+        assert_eq!(typed.extend("events", vec![])?, 0);
+        assert_eq!(typed.len("events")?, 0);
+
+        typed.set("events", &5, "old five")?;
+        let batch = (0u32..10).map(|i| (i, format!("event {i}")));
+        assert_eq!(typed.extend("events", batch)?, 10);
+
+        // the existing item was updated in place, the rest were appended in order
+        assert_eq!(typed.len("events")?, 10);
+        assert_eq!(typed.keys("events")?, vec![5, 0, 1, 2, 3, 4, 6, 7, 8, 9]);
+        assert_eq!(typed.get("events", &5)?, Some("event 5".into()));
+
+        // a repeated key within the batch is updated in place, so the last value wins
+        assert_eq!(
+            typed.extend(
+                "events",
+                vec![(10, "a".into()), (11, "b".into()), (10, "c".into())]
+            )?,
+            3
+        );
+        assert_eq!(typed.len("events")?, 12);
+        assert_eq!(typed.peek_tail("events")?, Some((11, "b".into())));
+        assert_eq!(typed.get("events", &10)?, Some("c".into()));
+
+        // a failure mid-way leaves the prefix in place
+        let err = db
+            .extend_list(
+                "raw",
+                vec![
+                    (b"a".to_vec(), b"1".to_vec()),
+                    (b"b".to_vec(), vec![0; u16::MAX as usize + 1]),
+                    (b"c".to_vec(), b"3".to_vec()),
+                ],
+            )
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<CandyError>(),
+            Some(CandyError::BatchInterrupted(1))
+        ));
+        assert_eq!(db.list_len("raw")?, 1);
+        assert_eq!(db.get_from_list("raw", "a")?, Some(b"1".to_vec()));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_list_reverse() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+        let typed = CandyTypedList::<String, u32, String>::new(db.clone());
+
+        assert!(!typed.reverse("playlist")?);
+
+        for i in 0u32..10 {
+            typed.set("playlist", &i, &format!("song {i}"))?;
+        }
+        // leave some holes behind
+        typed.remove("playlist", &3)?;
+        typed.remove("playlist", &7)?;
+
+        assert!(typed.reverse("playlist")?);
+        assert_eq!(typed.len("playlist")?, 8);
+        assert_eq!(typed.keys("playlist")?, vec![9, 8, 6, 5, 4, 2, 1, 0]);
+        let backwards = typed
+            .iter_backwards("playlist")
+            .map(|res| res.map(|(k, _)| k))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(backwards, vec![0, 1, 2, 4, 5, 6, 8, 9]);
+        assert_eq!(typed.get("playlist", &6)?, Some("song 6".into()));
+
+        // the reversed list behaves like any other list
+        typed.set("playlist", &10, "song 10")?;
+        assert_eq!(typed.peek_head("playlist")?, Some((9, "song 9".into())));
+        assert_eq!(typed.peek_tail("playlist")?, Some((10, "song 10".into())));
+        assert_eq!(typed.remove("playlist", &8)?, Some("song 8".into()));
+
+        assert!(typed.reverse("playlist")?);
+        assert_eq!(typed.keys("playlist")?, vec![10, 0, 1, 2, 4, 5, 6, 9]);
+
+        // a single-element list is left as is
+        typed.set("single", &1, "one")?;
+        assert!(typed.reverse("single")?);
+        assert_eq!(typed.keys("single")?, vec![1]);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_queue_pop_head_if() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        // a delay queue of (due time, job) pairs
+        let queue = CandyTypedDeque::<String, (u64, String)>::new(db.clone());
+        assert_eq!(queue.pop_head_if("jobs", |_| true)?, None);
+
+        queue.push_tail("jobs", &(10, "a".to_string()))?;
+        queue.push_tail("jobs", &(20, "b".to_string()))?;
+        queue.push_tail("jobs", &(30, "c".to_string()))?;
+
+        let now = 15;
+        assert_eq!(
+            queue.pop_head_if("jobs", |(due, _)| *due <= now)?,
+            Some((10, "a".into()))
+        );
+        assert_eq!(queue.pop_head_if("jobs", |(due, _)| *due <= now)?, None);
+        assert_eq!(queue.len("jobs")?, 2);
+        assert_eq!(queue.peek_head("jobs")?, Some((20, "b".into())));
+
+        // holes at the head are skipped
+        let (idx, _) = queue.peek_head_with_idx("jobs")?.unwrap();
+        queue.remove_by_idx("jobs", idx)?;
+        assert_eq!(
+            queue.pop_head_if("jobs", |(due, _)| *due <= 30)?,
+            Some((30, "c".into()))
+        );
+        assert_eq!(queue.len("jobs")?, 0);
+
+        // indices keep increasing
+        queue.push_tail("jobs", &(40, "d".to_string()))?;
+        assert_eq!(
+            queue.peek_head_with_idx("jobs")?.map(|iv| iv.0),
+            Some(idx + 2)
+        );
+
+        // concurrent consumers never pop the same element
+        for i in 0..100u64 {
+            queue.push_tail("shared", &(i, i.to_string()))?;
+        }
+        let popped = std::thread::scope(|s| {
+            let handles = (0..4)
+                .map(|_| {
+                    s.spawn(|| {
+                        let mut mine = vec![];
+                        while let Some((due, _)) = queue.pop_head_if("shared", |_| true).unwrap() {
+                            mine.push(due);
+                        }
+                        mine
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        let mut popped = popped;
+        popped.sort();
+        assert_eq!(popped, (0..100).collect::<Vec<_>>());
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_list_get_or_create_status() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+        let members = CandyTypedList::<String, u32, String>::new(db.clone());
+
+        assert_eq!(
+            members.get_or_create_status("room", &1, "alice")?,
+            ("alice".into(), true)
+        );
+        assert_eq!(
+            members.get_or_create_status("room", &1, "bob")?,
+            ("alice".into(), false)
+        );
+        assert_eq!(
+            members.get_or_create_status("room", &2, "bob")?,
+            ("bob".into(), true)
+        );
+        assert_eq!(members.get_or_create("room", &2, "carol")?, "bob");
+        assert_eq!(members.keys("room")?, vec![1, 2]);
+
+        // removed items are inserted again
+        members.remove("room", &1)?;
+        assert_eq!(
+            members.get_or_create_status("room", &1, "dave")?,
+            ("dave".into(), true)
+        );
+        assert_eq!(members.keys("room")?, vec![2, 1]);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_queue_snapshot_restore() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(&format!("{dir}/1"), Config::default())?);
+        let queue = CandyTypedDeque::<String, String>::new(db.clone());
+
+        let empty = queue.snapshot("jobs")?;
+        assert!(empty.items.is_empty());
+        assert!(empty.range.is_empty());
+
+        for i in 0..10 {
+            queue.push_tail("jobs", &format!("job {i}"))?;
+        }
+        queue.pop_head("jobs")?;
+        queue.pop_head("jobs")?;
+        let (hole_idx, _) = queue.iter("jobs").nth(3).unwrap()?;
+        queue.remove_by_idx("jobs", hole_idx)?;
+
+        let snapshot = queue.snapshot("jobs")?;
+        assert_eq!(snapshot.range, queue.range("jobs")?);
+        assert_eq!(snapshot.items.len(), 7);
+        assert_eq!(
+            snapshot.items,
+            queue.iter("jobs").collect::<Result<Vec<_>>>()?
+        );
+
+        // restore into another store, and into the same store over a modified queue
+        let db2 = Arc::new(CandyStore::open(&format!("{dir}/2"), Config::default())?);
+        let queue2 = CandyTypedDeque::<String, String>::new(db2.clone());
+        queue2.restore("jobs", snapshot.clone())?;
+
+        queue.pop_head("jobs")?;
+        queue.push_tail("jobs", "extra 1")?;
+        queue.push_tail("jobs", "extra 2")?;
+        queue.restore("jobs", snapshot.clone())?;
+
+        for q in [&queue, &queue2] {
+            assert_eq!(q.snapshot("jobs")?, snapshot);
+            assert_eq!(q.len("jobs")?, 7);
+            assert_eq!(q.verify_queue("jobs")?.present_len, 7);
+            // index continuity
+            q.push_tail("jobs", "next")?;
+            assert_eq!(
+                q.peek_tail_with_idx("jobs")?,
+                Some((snapshot.range.end, "next".into()))
+            );
+            assert_eq!(
+                q.pop_head_with_idx("jobs")?,
+                Some(snapshot.items[0].clone())
+            );
+        }
+
+        // bad snapshots are rejected, leaving the queue untouched
+        let mut bad = snapshot.clone();
+        bad.items.swap(0, 1);
+        assert!(queue.restore("jobs", bad).is_err());
+        let mut bad = snapshot.clone();
+        bad.items.push((snapshot.range.end, "x".into()));
+        assert!(queue.restore("jobs", bad).is_err());
+        assert_eq!(queue.len("jobs")?, 7);
+
+        // restoring an empty snapshot empties the queue
+        queue.restore(
+            "jobs",
+            QueueSnapshot {
+                range: 5..5,
+                items: vec![],
+            },
+        )?;
+        assert_eq!(queue.len("jobs")?, 0);
+        let range = queue.range("jobs")?;
+        assert!(range.is_empty() && range.start >= 5);
+        // nothing is left behind
+        assert_eq!(db.iter_raw().count(), 0);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_list_needs_compaction() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+        let typed = CandyTypedList::<String, u32, String>::new(db.clone());
+        let params = ListCompactionParams {
+            min_length: 10,
+            min_holes_ratio: 0.5,
+        };
+
+        assert!(!typed.needs_compaction("list", params)?);
+
+        for i in 0u32..20 {
+            typed.set("list", &i, "val")?;
+        }
+        assert!(!typed.needs_compaction("list", params)?);
+
+        // remove from the middle, creating holes
+        for i in 1u32..10 {
+            typed.remove("list", &i)?;
+        }
+        assert!(!typed.needs_compaction("list", params)?);
+        typed.remove("list", &10)?;
+        assert!(typed.needs_compaction("list", params)?);
+        assert!(!typed.needs_compaction(
+            "list",
+            ListCompactionParams {
+                min_length: 21,
+                ..params
+            }
+        )?);
+
+        // checking does not compact, and agrees with compaction
+        assert_eq!(typed.len("list")?, 10);
+        assert!(typed.needs_compaction("list", params)?);
+        assert!(typed.compact_if_needed("list", params)?);
+        assert!(!typed.needs_compaction("list", params)?);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_queue_push_tail_bounded() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+        let queue = CandyTypedDeque::<String, u32>::new(db.clone());
+
+        assert!(queue.push_tail_bounded("recent", &0, 0).is_err());
+
+        for i in 0..3 {
+            assert_eq!(queue.push_tail_bounded("recent", &i, 3)?, None);
+        }
+        let (first_idx, _) = queue.peek_head_with_idx("recent")?.unwrap();
+
+        assert_eq!(
+            queue.push_tail_bounded("recent", &3, 3)?,
+            Some((first_idx, 0))
+        );
+        assert_eq!(queue.push_tail_bounded_value("recent", &4, 3)?, Some(1));
+        assert_eq!(
+            queue.iter("recent").collect::<Result<Vec<_>>>()?,
+            vec![(first_idx + 2, 2), (first_idx + 3, 3), (first_idx + 4, 4)]
+        );
+
+        // lowering the bound evicts all excess elements, returning the last of them
+        assert_eq!(
+            queue.push_tail_bounded("recent", &5, 1)?,
+            Some((first_idx + 4, 4))
+        );
+        assert_eq!(queue.len("recent")?, 1);
+        assert_eq!(queue.peek_head("recent")?, Some(5));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_list_contains_value() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+        let typed = CandyTypedList::<String, u32, String>::new(db.clone());
+
+        assert!(!typed.contains_value("list", &1, "one")?);
+        typed.set("list", &1, "one")?;
+        typed.set("list", &2, "two")?;
+
+        assert!(typed.contains_value("list", &1, "one")?);
+        assert!(typed.contains_value("list", &2, &"two".to_string())?);
+        assert!(!typed.contains_value("list", &1, "two")?);
+        assert!(!typed.contains_value("list", &1, "on")?);
+        assert!(!typed.contains_value("list", &3, "one")?);
+        assert!(!typed.contains_value("other", &1, "one")?);
+
+        typed.set("list", &1, "uno")?;
+        assert!(!typed.contains_value("list", &1, "one")?);
+        assert!(typed.contains_value("list", &1, "uno")?);
+        typed.remove("list", &1)?;
+        assert!(!typed.contains_value("list", &1, "uno")?);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_queue_state() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let queue = CandyTypedDeque::<String, u32>::new(db.clone());
+        let empty = queue.state("q")?;
+        assert_eq!(empty.len, 0);
+        assert_eq!(empty.head_idx, empty.tail_idx);
+
+        for i in 0..5 {
+            queue.push_tail("q", &i)?;
+        }
+        let state = queue.state("q")?;
+        assert_eq!(state.len, 5);
+        assert_eq!(state.head_idx..state.tail_idx, queue.range("q")?);
+
+        // removing from the middle leaves a hole in the range
+        queue.remove_by_idx("q", state.head_idx + 2)?;
+        queue.pop_head("q")?;
+        assert_eq!(
+            queue.state("q")?,
+            QueueState {
+                head_idx: state.head_idx + 1,
+                tail_idx: state.tail_idx,
+                len: 3
+            }
+        );
+
+        // the fields are always consistent with each other, even under concurrent pushes and pops
+        std::thread::scope(|s| {
+            let handles = (0..2)
+                .map(|_| {
+                    s.spawn(|| -> Result<()> {
+                        for i in 0..200 {
+                            queue.push_tail("q", &i)?;
+                            queue.pop_head("q")?;
+                        }
+                        Ok(())
+                    })
+                })
+                .collect::<Vec<_>>();
+            for _ in 0..200 {
+                let state = queue.state("q")?;
+                assert!(state.len <= state.tail_idx - state.head_idx);
+            }
+            for h in handles {
+                h.join().unwrap()?;
+            }
+            Result::<()>::Ok(())
+        })?;
+        assert_eq!(queue.state("q")?.len, 3);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_queue_peek_all_into() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let queue = CandyTypedDeque::<String, u32>::new(db);
+        let mut buf = vec![(0, 1234)];
+        queue.peek_all_into("q", &mut buf)?;
+        assert!(buf.is_empty());
+        assert!(queue.to_vec("q")?.is_empty());
+
+        for i in 0..10 {
+            queue.push_tail("q", &i)?;
+        }
+        queue.peek_all_into("q", &mut buf)?;
+        assert_eq!(buf, queue.to_vec("q")?);
+        assert_eq!(
+            buf.iter().map(|(_, v)| *v).collect::<Vec<_>>(),
+            (0..10).collect::<Vec<_>>()
+        );
+        assert_eq!(buf[0].0, queue.range("q")?.start);
+
+        // the buffer is reused, so its capacity is kept
+        let capacity = buf.capacity();
+        queue.pop_head("q")?;
+        queue.peek_all_into("q", &mut buf)?;
+        assert_eq!(buf.len(), 9);
+        assert_eq!(buf[0].1, 1);
+        assert_eq!(buf.capacity(), capacity);
+
+        Ok(())
+    })
+}
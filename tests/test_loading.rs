@@ -1,6 +1,8 @@
 mod common;
 
-use candystore::{CandyStore, Config, Result};
+use std::{os::unix::fs::FileExt, sync::Arc};
+
+use candystore::{CandyError, CandyStore, Config, EntrySeq, Result, ShardFileLayout, ValueCodec};
 
 use crate::common::{run_in_tempdir, LONG_VAL};
 
@@ -70,3 +72,225 @@ fn test_loading() -> Result<()> {
         Ok(())
     })
 }
+
+#[test]
+fn test_incompatible_format() -> Result<()> {
+    run_in_tempdir(|dir| {
+        {
+            let db = CandyStore::open(dir, Config::default())?;
+            db.set("hello", "world")?;
+        }
+
+        // migrating an up-to-date store is a no-op
+        {
+            let db = CandyStore::open_and_migrate(dir, Config::default())?;
+            assert_eq!(db.get("hello")?, Some("world".into()));
+        }
+
+        let shard = std::fs::read_dir(dir)?
+            .map(|res| res.unwrap().path())
+            .find(|path| {
+                path.file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .starts_with("shard_")
+            })
+            .unwrap();
+
+        // the version follows the 8-byte magic
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&shard)?;
+        let mut version = [0u8; 8];
+        file.read_at(&mut version, 8)?;
+        let supported = u64::from_ne_bytes(version);
+        file.write_at(&(supported + 1).to_ne_bytes(), 8)?;
+        drop(file);
+
+        let err = CandyStore::open(dir, Config::default()).err().unwrap();
+        assert!(matches!(
+            err.downcast_ref::<CandyError>(),
+            Some(CandyError::IncompatibleFormat { found, supported: s })
+                if *found == supported + 1 && *s == supported
+        ));
+
+        // there's no way to migrate a newer format
+        let err = CandyStore::open_and_migrate(dir, Config::default())
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err.downcast_ref::<CandyError>(),
+            Some(CandyError::IncompatibleFormat { .. })
+        ));
+
+        // the files were left untouched
+        let mut version = [0u8; 8];
+        std::fs::File::open(&shard)?.read_at(&mut version, 8)?;
+        assert_eq!(u64::from_ne_bytes(version), supported + 1);
+
+        let db = CandyStore::open(
+            dir,
+            Config {
+                clear_on_unsupported_version: true,
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(db.get("hello")?, None);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_value_layout_is_checked() -> Result<()> {
+    fn codec() -> Option<ValueCodec> {
+        Some(ValueCodec {
+            compress: |data| data.to_vec(),
+            decompress: |data| Ok(data.to_vec()),
+        })
+    }
+    fn is_incompatible(res: Result<CandyStore>) -> bool {
+        matches!(
+            res.err().unwrap().downcast_ref::<CandyError>(),
+            Some(CandyError::IncompatibleFormat { .. })
+        )
+    }
+
+    run_in_tempdir(|dir| {
+        let config = Config {
+            typed_entry_seq: EntrySeq::Counter,
+            ..Default::default()
+        };
+        {
+            let db = Arc::new(CandyStore::open(dir, config.clone())?);
+            db.typed::<String, u32>().set("hello", &5)?;
+        }
+
+        // the layout of typed values can't change
+        assert!(is_incompatible(CandyStore::open(dir, Config::default())));
+        assert!(is_incompatible(CandyStore::open(
+            dir,
+            Config {
+                typed_value_tags: true,
+                ..config.clone()
+            }
+        )));
+
+        // but the kind of sequence numbers can
+        let timestamps = Config {
+            typed_entry_seq: EntrySeq::Timestamp,
+            ..Default::default()
+        };
+        drop(CandyStore::open(dir, timestamps.clone())?);
+
+        // and compression can be enabled, but not disabled
+        let compressed = Config {
+            value_codec: codec(),
+            ..config.clone()
+        };
+        {
+            let db = Arc::new(CandyStore::open(dir, compressed.clone())?);
+            assert_eq!(db.typed::<String, u32>().get("hello")?, Some(5));
+        }
+        assert!(is_incompatible(CandyStore::open(dir, config.clone())));
+
+        // unless the store is cleared
+        let db = CandyStore::open(
+            dir,
+            Config {
+                clear_on_unsupported_version: true,
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(db.iter().count(), 0);
+        drop(db);
+        drop(CandyStore::open(dir, Config::default())?);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_nested_shard_file_layout() -> Result<()> {
+    fn shard_files(dir: &str) -> Result<(Vec<String>, Vec<String>)> {
+        let mut flat = vec![];
+        let mut nested = vec![];
+        for res in std::fs::read_dir(dir)? {
+            let entry = res?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if entry.file_type()?.is_dir() {
+                for res in std::fs::read_dir(entry.path())? {
+                    let filename = res?.file_name().to_string_lossy().to_string();
+                    // the subdirectory is named after the start of the span
+                    assert!(filename.starts_with(&format!("shard_{name}")), "{filename}");
+                    nested.push(filename);
+                }
+            } else if name.starts_with("shard_") {
+                flat.push(name);
+            }
+        }
+        Ok((flat, nested))
+    }
+
+    run_in_tempdir(|dir| {
+        let config = Config {
+            max_shard_size: 20 * 1024, // use small files to force lots of splits
+            min_compaction_threashold: 10 * 1024,
+            shard_file_layout: ShardFileLayout::Nested,
+            ..Default::default()
+        };
+
+        {
+            let db = CandyStore::open(dir, config.clone())?;
+            for i in 0..1000 {
+                db.set(&format!("unique key {i}"), LONG_VAL)?;
+            }
+            assert!(db.stats().num_splits > 1);
+        }
+
+        let (flat, nested) = shard_files(dir)?;
+        assert!(flat.is_empty());
+        assert!(nested.len() > 2);
+
+        {
+            let db = CandyStore::open(dir, config.clone())?;
+            assert_eq!(db.iter().count(), 1000);
+        }
+
+        // changing the layout moves the files
+        {
+            let db = CandyStore::open(
+                dir,
+                Config {
+                    shard_file_layout: ShardFileLayout::Flat,
+                    ..config.clone()
+                },
+            )?;
+            assert_eq!(db.iter().count(), 1000);
+        }
+        let (mut flat, _) = shard_files(dir)?;
+        flat.sort();
+        let mut nested = nested;
+        nested.sort();
+        assert_eq!(flat, nested);
+        // the emptied subdirectories were removed
+        assert!(std::fs::read_dir(dir)?.all(|res| !res.unwrap().file_type().unwrap().is_dir()));
+
+        {
+            let db = CandyStore::open(dir, config)?;
+            assert_eq!(db.iter().count(), 1000);
+            let (flat, nested) = shard_files(dir)?;
+            assert!(flat.is_empty());
+            assert_eq!(nested.len(), db.stats().num_shards);
+
+            db.clear()?;
+            assert_eq!(db.iter().count(), 0);
+            let (flat, nested) = shard_files(dir)?;
+            assert!(flat.is_empty());
+            assert_eq!(nested.len(), db.stats().num_shards);
+        }
+
+        Ok(())
+    })
+}
@@ -1,8 +1,8 @@
 mod common;
 
-use std::collections::HashSet;
+use std::{collections::HashSet, sync::Arc};
 
-use candystore::{CandyStore, Config, Result, MAX_VALUE_SIZE};
+use candystore::{CandyError, CandyStore, CandyTypedStore, Config, Result, MAX_VALUE_SIZE};
 
 use crate::common::{run_in_tempdir, LONG_VAL};
 
@@ -147,3 +147,100 @@ fn test_histogram() -> Result<()> {
         Ok(())
     })
 }
+
+#[test]
+fn test_max_inline_value() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(
+            dir,
+            Config {
+                max_inline_value: 1000,
+                ..Default::default()
+            },
+        )?);
+
+        let too_large = |res: Result<_>| {
+            matches!(
+                res.unwrap_err().downcast_ref::<CandyError>(),
+                Some(CandyError::ValueTooLarge {
+                    size: 1001,
+                    limit: 1000
+                })
+            )
+        };
+
+        db.set("k1", &vec![b'a'; 1000])?;
+        assert!(too_large(db.set("k2", &vec![b'a'; 1001]).map(|_| ())));
+        assert!(too_large(
+            db.replace("k1", &vec![b'a'; 1001], None).map(|_| ())
+        ));
+        assert!(too_large(
+            db.get_or_create("k2", &vec![b'a'; 1001]).map(|_| ())
+        ));
+        assert_eq!(db.get("k1")?, Some(vec![b'a'; 1000]));
+        assert_eq!(db.get("k2")?, None);
+
+        // values beyond MAX_VALUE_SIZE keep failing with ValueTooLong
+        let err = db.set("k2", &vec![b'a'; MAX_VALUE_SIZE + 1]).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<CandyError>(),
+            Some(&CandyError::ValueTooLong(MAX_VALUE_SIZE + 1))
+        );
+
+        // big values, lists and queues are not limited
+        db.set_big("k3", &vec![b'a'; 10_000])?;
+        db.set_in_list("list", "item", &vec![b'a'; 10_000])?;
+        db.push_to_queue_tail("queue", &vec![b'a'; 10_000])?;
+
+        // typed values are limited by their encoded size (a Vec<u8> is prefixed by its varint length)
+        let typed = CandyTypedStore::<String, Vec<u8>>::new(db.clone());
+        typed.set("k4", &vec![b'a'; 990])?;
+        assert!(typed.set("k4", &vec![b'a'; 1000]).is_err());
+        // set_auto routes such values through set_big
+        typed.set_auto("k5", &vec![b'a'; 1000])?;
+        assert_eq!(typed.get_auto("k5")?, Some(vec![b'a'; 1000]));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_clear_everything() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+        let typed = CandyTypedStore::<u32, String>::new_cached(db.clone(), 10);
+
+        let populate = || -> Result<usize> {
+            db.set("key", "val")?;
+            db.set_big(b"big", &vec![7u8; 100_000])?;
+            typed.set(&1, "one")?;
+            db.set_in_list("list", "item", "val")?;
+            db.push_to_queue_tail("queue", "a")?;
+            db.push_to_queue_tail("queue", "b")
+        };
+
+        let first_idx = populate()?;
+        db.pop_queue_head("queue")?;
+        assert_eq!(typed.get_cached(&1)?.as_deref(), Some(&"one".to_string()));
+        assert!(db.iter_raw().count() > 0);
+
+        db.clear_all()?;
+
+        assert_eq!(db.iter_raw().count(), 0);
+        assert_eq!(db.stats().num_entries(), 0);
+        assert_eq!(db.get("key")?, None);
+        assert_eq!(db.get_big(b"big")?, None);
+        assert_eq!(typed.get(&1)?, None);
+        assert_eq!(typed.get_cached(&1)?, None);
+        assert_eq!(db.list_len("list")?, 0);
+        assert_eq!(db.queue_len("queue")?, 0);
+
+        // the store is usable right away, and queue indices start over
+        assert_eq!(populate()?, first_idx);
+        assert_eq!(db.get("key")?, Some("val".into()));
+        assert_eq!(db.list_len("list")?, 1);
+        assert_eq!(db.queue_len("queue")?, 2);
+
+        Ok(())
+    })
+}
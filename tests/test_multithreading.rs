@@ -2,7 +2,7 @@ mod common;
 
 use std::sync::{atomic::AtomicUsize, Arc};
 
-use candystore::{CandyStore, Config, Result};
+use candystore::{CandyStore, CandyTypedStore, Config, Result};
 use rand::random;
 
 use crate::common::run_in_tempdir;
@@ -73,3 +73,83 @@ fn test_multithreaded() -> Result<()> {
         Ok(())
     })
 }
+
+#[test]
+fn test_typed_wrappers_concurrently() -> Result<()> {
+    fn assert_send_sync<T: Send + Sync + Clone>() {}
+    assert_send_sync::<CandyTypedStore<u32, String>>();
+
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(
+            dir,
+            Config {
+                max_shard_size: 20 * 1024, // force splits and compactions while the threads run
+                min_compaction_threashold: 10 * 1024,
+                ..Default::default()
+            },
+        )?);
+
+        const NUM_ITEMS: u32 = 2000;
+        const NUM_INCREMENTS: u32 = 200;
+
+        std::thread::scope(|s| {
+            // wrappers with different TYPE_IDs write the same (encoded) keys in parallel
+            let handles = (0..2)
+                .map(|thid| {
+                    let db = db.clone();
+                    s.spawn(move || -> Result<()> {
+                        if thid == 0 {
+                            let typed = db.typed::<u32, String>();
+                            for i in 0..NUM_ITEMS {
+                                typed.set(&i, &format!("u32-{i}"))?;
+                            }
+                        } else {
+                            let typed = db.typed::<i32, String>();
+                            for i in 0..NUM_ITEMS {
+                                typed.set(&(i as i32), &format!("i32-{i}"))?;
+                            }
+                        }
+                        Ok(())
+                    })
+                })
+                // and multiple wrappers of the same type update shared counters with compare-and-swap
+                .chain((0..4).map(|_| {
+                    let counters = db.typed::<String, u32>();
+                    s.spawn(move || -> Result<()> {
+                        for _ in 0..NUM_INCREMENTS {
+                            loop {
+                                let curr = counters.get_or_create("counter", &0)?;
+                                if counters
+                                    .replace("counter", &(curr + 1), Some(&curr))?
+                                    .is_some()
+                                {
+                                    break;
+                                }
+                            }
+                        }
+                        Ok(())
+                    })
+                }))
+                .collect::<Vec<_>>();
+            for handle in handles {
+                handle.join().unwrap()?;
+            }
+            Ok::<_, anyhow::Error>(())
+        })?;
+
+        let u32s = db.typed::<u32, String>();
+        let i32s = CandyTypedStore::<i32, String>::new(db.clone());
+        for i in 0..NUM_ITEMS {
+            assert_eq!(u32s.get(&i)?, Some(format!("u32-{i}")));
+            assert_eq!(i32s.get(&(i as i32))?, Some(format!("i32-{i}")));
+        }
+        assert_eq!(u32s.len()?, NUM_ITEMS as usize);
+        assert_eq!(i32s.len()?, NUM_ITEMS as usize);
+        assert_eq!(
+            db.typed::<String, u32>().get("counter")?,
+            Some(4 * NUM_INCREMENTS)
+        );
+
+        Ok(())
+    })
+}
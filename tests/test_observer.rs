@@ -0,0 +1,72 @@
+#![cfg(feature = "observer")]
+
+mod common;
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use candystore::{CandyStore, Config, Observer, Result};
+
+use crate::common::run_in_tempdir;
+
+#[derive(Default)]
+struct Counters {
+    gets: AtomicUsize,
+    misses: AtomicUsize,
+    sets: AtomicUsize,
+    removes: AtomicUsize,
+    big_values: AtomicUsize,
+}
+
+impl Observer for Counters {
+    fn on_get(&self, _elapsed: Duration) {
+        self.gets.fetch_add(1, Ordering::Relaxed);
+    }
+    fn on_miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+    }
+    fn on_set(&self, _elapsed: Duration) {
+        self.sets.fetch_add(1, Ordering::Relaxed);
+    }
+    fn on_remove(&self, _elapsed: Duration) {
+        self.removes.fetch_add(1, Ordering::Relaxed);
+    }
+    fn on_big_value(&self, len: usize) {
+        self.big_values.fetch_add(len, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn test_observer() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let counters = Arc::new(Counters::default());
+        let db = CandyStore::open(
+            dir,
+            Config {
+                observer: Some(counters.clone()),
+                ..Default::default()
+            },
+        )?;
+
+        db.set("hello", "world")?;
+        db.set("hello", "mundo")?;
+        assert_eq!(db.get("hello")?, Some("mundo".into()));
+        assert_eq!(db.get("nonexistent")?, None);
+        db.remove("hello")?;
+
+        assert_eq!(counters.sets.load(Ordering::Relaxed), 2);
+        assert_eq!(counters.gets.load(Ordering::Relaxed), 2);
+        assert_eq!(counters.misses.load(Ordering::Relaxed), 1);
+        assert_eq!(counters.removes.load(Ordering::Relaxed), 1);
+
+        db.set_big("big", &vec![7u8; 100_000])?;
+        assert_eq!(counters.big_values.load(Ordering::Relaxed), 100_000);
+
+        Ok(())
+    })
+}
@@ -1,6 +1,6 @@
 mod common;
 
-use candystore::{CandyError, CandyStore, Config, Result};
+use candystore::{CandyError, CandyStore, CompactionReport, Config, Result};
 
 use crate::common::run_in_tempdir;
 
@@ -65,6 +65,43 @@ fn test_pre_split() -> Result<()> {
     })
 }
 
+#[test]
+fn test_initial_num_shards() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(
+            dir,
+            Config {
+                initial_num_shards: 100, // rounded up to 128
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(db.stats().num_shards, 128);
+
+        for i in 0..1000 {
+            db.set(&format!("key{i}"), "val")?;
+        }
+        // merging won't go below the initial number of shards
+        assert!(!db.merge_small_shards(0.25)?);
+        assert_eq!(db.stats().num_shards, 128);
+        drop(db);
+
+        // the larger of the two wins
+        let db = CandyStore::open(
+            dir,
+            Config {
+                initial_num_shards: 4,
+                expected_number_of_keys: 1_000_000,
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(db.stats().num_shards, 128);
+        db.clear()?;
+        assert_eq!(db.stats().num_shards, 64);
+
+        Ok(())
+    })
+}
+
 #[test]
 fn test_compaction() -> Result<()> {
     run_in_tempdir(|dir| {
@@ -101,6 +138,51 @@ fn test_compaction() -> Result<()> {
     })
 }
 
+#[test]
+fn test_explicit_compaction() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(
+            dir,
+            Config {
+                initial_num_shards: 4,
+                ..Default::default()
+            },
+        )?;
+
+        assert_eq!(db.compact()?, CompactionReport::default());
+
+        for i in 0..1000 {
+            db.set(&format!("key{i}"), &format!("val{i:0100}"))?;
+        }
+        // no waste, nothing to compact
+        assert_eq!(db.compact()?.num_compacted_shards, 0);
+
+        for i in 0..900 {
+            db.remove(&format!("key{i}"))?;
+        }
+        let stats = db.stats();
+        assert!(stats.wasted_bytes > 0);
+        assert_eq!(stats.num_compactions, 0);
+
+        let report = db.compact()?;
+        assert_eq!(report.num_compacted_shards, 4);
+        assert_eq!(report.bytes_before, stats.occupied_bytes);
+        assert_eq!(report.bytes_freed(), stats.wasted_bytes);
+
+        let stats = db.stats();
+        assert_eq!(stats.wasted_bytes, 0);
+        assert_eq!(stats.num_compactions, 4);
+        assert_eq!(stats.occupied_bytes, report.bytes_after);
+
+        for i in 0..1000 {
+            assert_eq!(db.contains(&format!("key{i}"))?, i >= 900);
+        }
+        assert_eq!(db.compact()?.num_compacted_shards, 0);
+
+        Ok(())
+    })
+}
+
 #[test]
 fn test_too_large() -> Result<()> {
     run_in_tempdir(|dir| {
@@ -179,3 +261,102 @@ fn test_compaction_stats() -> Result<()> {
         Ok(())
     })
 }
+
+// a deliberately bad hasher, that sends all keys to the first quarter of the shard selectors
+fn skewed_key_hasher(seed: &candystore::HashSeed, key: &[u8]) -> (u64, u64) {
+    let (h1, h2) = candystore::siphash_key_hasher(seed, key);
+    (h1 & 0x3fff_ffff_ffff_ffff, h2)
+}
+
+#[test]
+fn test_key_hasher_and_shard_loads() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let config = Config {
+            initial_num_shards: 4,
+            ..Default::default()
+        };
+
+        let db = CandyStore::open(dir, config.clone())?;
+        for i in 0..1000u32 {
+            db.set(&i.to_le_bytes(), "val")?;
+        }
+        let loads = db.shard_loads()?;
+        assert_eq!(loads.len(), 4);
+        assert_eq!(loads.iter().map(|(_, n)| n).sum::<usize>(), 1000);
+        // sequential keys are spread evenly by the default hasher
+        assert!(loads.iter().all(|(_, n)| *n > 150), "{loads:?}");
+        drop(db);
+
+        let skewed_dir = format!("{dir}-skewed");
+        let skewed_config = Config {
+            key_hasher: skewed_key_hasher,
+            ..config
+        };
+        let db = CandyStore::open(&skewed_dir, skewed_config.clone())?;
+        for i in 0..1000u32 {
+            db.set(&i.to_le_bytes(), "val")?;
+        }
+        let loads = db.shard_loads()?;
+        assert_eq!(loads[0].1, 1000, "{loads:?}");
+        drop(db);
+
+        // the same hasher must be used when reopening
+        let db = CandyStore::open(&skewed_dir, skewed_config)?;
+        assert_eq!(db.get(&7u32.to_le_bytes())?, Some("val".into()));
+        drop(db);
+        std::fs::remove_dir_all(&skewed_dir)?;
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_shard_stats() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = CandyStore::open(
+            dir,
+            Config {
+                initial_num_shards: 4,
+                ..Default::default()
+            },
+        )?;
+
+        let stats = db.shard_stats()?;
+        assert_eq!(stats.len(), 4);
+        assert!(stats
+            .iter()
+            .all(|st| st.num_entries == 0 && st.occupied_bytes == 0));
+        // the spans are ordered and cover all shard selectors
+        assert_eq!(stats[0].span.start, 0);
+        assert!(stats.windows(2).all(|w| w[0].span.end == w[1].span.start));
+
+        for i in 0..1000u32 {
+            db.set(&i.to_le_bytes(), "val")?;
+        }
+        for i in 0..100u32 {
+            db.remove(&i.to_le_bytes())?;
+        }
+
+        let stats = db.shard_stats()?;
+        assert_eq!(stats.len(), 4);
+        assert_eq!(stats.iter().map(|st| st.num_entries).sum::<usize>(), 900);
+        assert!(stats.iter().all(|st| st.wasted_bytes > 0));
+        assert_eq!(
+            stats.iter().map(|st| st.occupied_bytes).sum::<usize>(),
+            db.stats().occupied_bytes
+        );
+        assert_eq!(
+            stats.iter().map(|st| st.data_bytes()).sum::<usize>(),
+            db.stats().data_bytes()
+        );
+        assert_eq!(
+            stats
+                .iter()
+                .map(|st| (st.span.clone(), st.num_entries))
+                .collect::<Vec<_>>(),
+            db.shard_loads()?
+        );
+
+        Ok(())
+    })
+}
@@ -107,3 +107,73 @@ fn test_queues() -> Result<()> {
         Ok(())
     })
 }
+
+#[test]
+fn test_queue_indices_not_reused() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let mut last_idx = 0;
+        {
+            let db = CandyStore::open(dir, Config::default())?;
+            for round in 0..3 {
+                for i in 0..5 {
+                    db.push_to_queue_tail("jobs", &format!("job{round}-{i}"))?;
+                }
+                while let Some((idx, _)) = db.pop_queue_head_with_idx("jobs")? {
+                    assert!(idx > last_idx, "{idx} <= {last_idx}");
+                    last_idx = idx;
+                }
+                assert_eq!(db.queue_len("jobs")?, 0);
+                assert!(db.queue_range("jobs")?.is_empty());
+            }
+
+            assert_eq!(db.queue_range("jobs")?.start, last_idx + 1);
+
+            // popping from the tail does not release the index
+            let idx = db.push_to_queue_tail("jobs", "x")?;
+            assert!(idx > last_idx);
+            assert_eq!(
+                db.pop_queue_tail_with_idx("jobs")?.map(|(i, _)| i),
+                Some(idx)
+            );
+            let idx2 = db.push_to_queue_tail("jobs", "x")?;
+            assert!(idx2 > idx);
+            last_idx = idx2;
+
+            // nor does removing the last element by index
+            assert!(db.remove_from_queue("jobs", idx2)?.is_some());
+            assert_eq!(db.queue_len("jobs")?, 0);
+            assert!(db.queue_range("jobs")?.is_empty());
+        }
+
+        // and they survive reopening the store
+        let db = CandyStore::open(dir, Config::default())?;
+        let idx = db.push_to_queue_tail("jobs", "y")?;
+        assert!(idx > last_idx);
+        let idx2 = db.push_to_queue_tail("jobs", "y")?;
+        assert_eq!(
+            db.pop_queue_tail_with_idx("jobs")?.map(|(i, _)| i),
+            Some(idx2)
+        );
+        assert!(db.push_to_queue_tail("jobs", "y")? > idx2);
+
+        // discarding resets the indices
+        let first_idx = db.queue_range("nothing")?.start;
+        assert!(db.discard_queue("jobs")?);
+        assert_eq!(db.push_to_queue_tail("jobs", "z")?, first_idx);
+
+        // a drained queue is removed
+        assert!(db.pop_queue_head("jobs")?.is_some());
+        assert!(!db.discard_queue("jobs")?);
+
+        // and short-lived queues leave nothing behind
+        let num_entries = db.stats().num_entries();
+        for i in 0..100 {
+            let queue_key = format!("tmp{i}");
+            db.push_to_queue_tail(&queue_key, "x")?;
+            db.pop_queue_tail(&queue_key)?;
+        }
+        assert_eq!(db.stats().num_entries(), num_entries);
+
+        Ok(())
+    })
+}
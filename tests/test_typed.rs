@@ -1,12 +1,20 @@
 mod common;
 
-use std::sync::Arc;
+use std::{
+    borrow::Borrow,
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
 
-use candystore::{CandyStore, CandyTypedKey, CandyTypedStore, Config, Result};
+use candystore::{
+    register_value_tag, CandyError, CandyFixedStore, CandyStore, CandyTypedDeque, CandyTypedKey,
+    CandyTypedList, CandyTypedStore, Config, EntrySeq, Result, StoreCursor, ValueCodec,
+};
 
 use crate::common::run_in_tempdir;
 
-use databuf::{Decode, Encode};
+use databuf::{config::num::LE, Decode, Encode};
 
 #[derive(Debug, Encode, Decode)]
 struct MyKey {
@@ -111,3 +119,1890 @@ fn test_typed() -> Result<()> {
         Ok(())
     })
 }
+
+#[derive(Debug, Encode, Decode)]
+struct OtherKey(u32);
+
+impl CandyTypedKey for OtherKey {
+    const TYPE_ID: u32 = 0x3476a551; // same as MyKey
+}
+
+#[test]
+fn test_type_id_collision() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        assert!(CandyTypedStore::<MyKey, MyVal>::new_checked(db.clone()).is_ok());
+        // same key type with a different value type is fine
+        assert!(CandyTypedStore::<MyKey, String>::new_checked(db.clone()).is_ok());
+
+        let err = CandyTypedStore::<OtherKey, MyVal>::new_checked(db.clone())
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err.downcast_ref::<CandyError>(),
+            Some(CandyError::TypeIdCollision(0x3476a551, _, _))
+        ));
+        // the other constructors check it as well
+        assert!(
+            CandyTypedStore::<OtherKey, MyVal>::new_in_namespace_checked(db.clone(), "ns").is_err()
+        );
+        assert!(CandyTypedStore::<OtherKey, MyVal>::new_cached_checked(db.clone(), 10).is_err());
+        assert!(
+            CandyTypedStore::<OtherKey, MyVal>::with_on_change_checked(db.clone(), |_| {}).is_err()
+        );
+        assert!(
+            CandyTypedStore::<OtherKey, MyVal>::new_with_normalizer_checked(db.clone(), |k| {
+                OtherKey(k.0)
+            })
+            .is_err()
+        );
+        assert!(CandyTypedStore::<MyKey, MyVal>::new_cached_checked(db.clone(), 10).is_ok());
+        assert!(CandyTypedList::<OtherKey, u32, u32>::new_checked(db.clone()).is_err());
+        assert!(CandyTypedDeque::<OtherKey, u32>::new_checked(db).is_err());
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_iter() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let typed = CandyTypedStore::<u32, String>::new(db.clone());
+        let typed2 = CandyTypedStore::<u64, String>::new(db.clone());
+        for i in 0..1000u32 {
+            typed.set(&i, &format!("val{i}"))?;
+        }
+        typed2.set(&7u64, "other")?;
+        db.set("untyped", "other")?;
+
+        let mut keys = typed
+            .iter()
+            .map(|res| res.map(|(k, v)| (k, v.len())))
+            .collect::<Result<Vec<_>>>()?;
+        keys.sort();
+        assert_eq!(keys.len(), 1000);
+        assert_eq!(keys[0], (0, 4));
+
+        let mut count = 0;
+        for res in typed.iter_chunked(64) {
+            let batch = res?;
+            assert!(!batch.is_empty() && batch.len() <= 64);
+            for (k, v) in batch {
+                assert_eq!(v, format!("val{k}"));
+                count += 1;
+            }
+        }
+        assert_eq!(count, 1000);
+
+        assert_eq!(typed2.iter().count(), 1);
+        assert_eq!(typed2.iter_chunked(0).next().unwrap()?.len(), 1);
+
+        assert_eq!(
+            typed2.to_hashmap()?,
+            HashMap::from([(7u64, "other".to_owned())])
+        );
+        let snapshot = typed.to_hashmap()?;
+        assert_eq!(snapshot.len(), 1000);
+        assert_eq!(snapshot[&999], "val999");
+
+        Ok(())
+    })
+}
+
+#[derive(Debug, PartialEq, Eq, Encode, Decode)]
+struct ValV1 {
+    a: u32,
+}
+
+#[derive(Debug, PartialEq, Eq, Encode, Decode)]
+struct ValV2 {
+    a: u32,
+    b: String,
+}
+
+#[test]
+fn test_typed_migrate() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let v1 = CandyTypedStore::<u16, ValV1>::new(db.clone());
+        for i in 0..100u16 {
+            v1.set(&i, &ValV1 { a: i as u32 })?;
+        }
+
+        let v2 = CandyTypedStore::<u16, ValV2>::new(db.clone());
+        // already in the new format, should be skipped
+        v2.set(
+            &1000,
+            &ValV2 {
+                a: 1000,
+                b: "new".into(),
+            },
+        )?;
+
+        let migrated = v1.migrate(|k, v| {
+            if k % 10 == 0 {
+                None
+            } else {
+                Some(ValV2 {
+                    a: v.a,
+                    b: format!("migrated {k}"),
+                })
+            }
+        })?;
+        assert_eq!(migrated, 100);
+
+        assert_eq!(v2.get(&10)?, None);
+        assert_eq!(
+            v2.get(&11)?,
+            Some(ValV2 {
+                a: 11,
+                b: "migrated 11".into()
+            })
+        );
+        assert_eq!(
+            v2.get(&1000)?,
+            Some(ValV2 {
+                a: 1000,
+                b: "new".into()
+            })
+        );
+        assert_eq!(v2.iter().count(), 91);
+
+        // running again is a no-op
+        assert_eq!(
+            v1.migrate(|_, v| Some(ValV2 {
+                a: v.a,
+                b: "".into()
+            }))?,
+            0
+        );
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_swap() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let typed = CandyTypedStore::<String, u32>::new(db);
+        typed.set("staging", &1)?;
+        typed.set("production", &2)?;
+
+        typed.swap("staging", "production")?;
+        assert_eq!(typed.get("staging")?, Some(2));
+        assert_eq!(typed.get("production")?, Some(1));
+
+        typed.swap("staging", "staging")?;
+        assert_eq!(typed.get("staging")?, Some(2));
+
+        // one side missing: the value moves
+        typed.swap("staging", "nothing")?;
+        assert_eq!(typed.get("staging")?, None);
+        assert_eq!(typed.get("nothing")?, Some(2));
+
+        // both missing: no-op
+        typed.swap("foo", "bar")?;
+        assert!(!typed.contains("foo")?);
+        assert!(!typed.contains("bar")?);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_swap_is_atomic() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(
+            dir,
+            Config {
+                max_shard_size: 20 * 1024, // swaps that don't fit force splits
+                min_compaction_threashold: 10 * 1024,
+                ..Default::default()
+            },
+        )?);
+
+        // concurrent sets are never lost by a swap
+        let typed = CandyTypedStore::<String, u32>::new(db.clone());
+        typed.set("a", &0)?;
+        typed.set("b", &1)?;
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                for _ in 0..2000 {
+                    typed.swap("a", "b").unwrap();
+                }
+            });
+            for i in 2..2000 {
+                typed.set("a", &i).unwrap();
+            }
+        });
+        let vals = [typed.get("a")?.unwrap(), typed.get("b")?.unwrap()];
+        assert!(vals.contains(&1999), "{vals:?}");
+
+        // moving values around in full shards
+        let big = CandyTypedStore::<u32, Vec<u8>>::new(db.clone());
+        for i in 0..100 {
+            big.set(&i, &vec![i as u8; 500])?;
+        }
+        for i in 0..100 {
+            big.swap(&i, &(i + 1000))?;
+        }
+        for i in 0..100 {
+            assert!(!big.contains(&i)?);
+            assert_eq!(big.get(&(i + 1000))?, Some(vec![i as u8; 500]));
+        }
+        for i in 0..50 {
+            big.swap(&(i + 1000), &(i + 1050))?;
+        }
+        for i in 0..100 {
+            assert_eq!(
+                big.get(&(i + 1000))?,
+                Some(vec![((i + 50) % 100) as u8; 500])
+            );
+        }
+        assert!(db.stats().num_splits > 0);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_get_or_create_status() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let typed = CandyTypedStore::<String, u32>::new(db);
+        assert_eq!(typed.get_or_create_status("visitor", &5)?, (5, true));
+        assert_eq!(typed.get_or_create_status("visitor", &7)?, (5, false));
+        assert_eq!(typed.get_or_create("visitor", &9)?, 5);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_tuple_keys() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        assert_ne!(<(u64, u32)>::TYPE_ID, <(u32, u64)>::TYPE_ID);
+        assert_ne!(<(u64, u32)>::TYPE_ID, <(u64, u32, u8)>::TYPE_ID);
+        assert_ne!(<(u64,)>::TYPE_ID, u64::TYPE_ID);
+
+        let typed = CandyTypedStore::<(u64, u32), String>::new(db.clone());
+        typed.set(&(1, 2), &"a".to_owned())?;
+        typed.set(&(2, 1), &"b".to_owned())?;
+        assert_eq!(typed.get(&(1, 2))?, Some("a".to_owned()));
+        assert_eq!(typed.get(&(2, 1))?, Some("b".to_owned()));
+        assert_eq!(typed.get(&(1, 1))?, None);
+
+        // the other order is a different type, so it doesn't see these keys
+        let swapped = CandyTypedStore::<(u32, u64), String>::new(db.clone());
+        assert_eq!(swapped.get(&(1, 2))?, None);
+
+        let wide = CandyTypedStore::<(u8, u16, u32, u64, String, bool), u32>::new(db);
+        let key = (1, 2, 3, 4, "five".to_owned(), true);
+        wide.set(&key, &6)?;
+        assert_eq!(wide.get(&key)?, Some(6));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_remove_many() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let typed = CandyTypedStore::<String, u32>::new(db.clone());
+        for i in 0..100 {
+            typed.set(&format!("user1/{i}"), &i)?;
+        }
+        typed.set("user2/0", &1000)?;
+
+        let keys = (0..100)
+            .map(|i| format!("user1/{i}"))
+            .chain(["user1/nonexistent".to_owned()])
+            .collect::<Vec<_>>();
+        let key_refs = keys.iter().map(|k| k.as_str()).collect::<Vec<_>>();
+        assert_eq!(typed.remove_many(&key_refs)?, 100);
+        assert_eq!(typed.remove_many(&key_refs)?, 0);
+        assert_eq!(typed.get("user1/7")?, None);
+        assert_eq!(typed.get("user2/0")?, Some(1000));
+
+        db.set("a", "1")?;
+        db.set("b", "2")?;
+        assert_eq!(db.remove_many(&["a", "b", "c"])?, 2);
+        assert_eq!(db.get("a")?, None);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_contains_many() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let typed = CandyTypedStore::<u32, String>::new(db.clone());
+        for i in (0..1000).step_by(2) {
+            typed.set(&i, "x")?;
+        }
+
+        let keys = (0..1000).collect::<Vec<u32>>();
+        let key_refs = keys.iter().collect::<Vec<_>>();
+        let exist = typed.contains_many(&key_refs)?;
+        assert_eq!(exist.len(), 1000);
+        for (i, e) in exist.into_iter().enumerate() {
+            assert_eq!(e, i % 2 == 0, "{i}");
+        }
+        assert_eq!(typed.contains_many(&[])?, vec![]);
+
+        // another key type does not see these entries
+        let other = CandyTypedStore::<u64, String>::new(db.clone());
+        assert_eq!(other.contains_many(&[&0, &2])?, vec![false, false]);
+
+        db.set("a", "1")?;
+        assert_eq!(db.contains_many(&["b", "a", "a"])?, vec![false, true, true]);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_entry_seq() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let config = Config {
+            typed_entry_seq: EntrySeq::Counter,
+            ..Default::default()
+        };
+
+        let last_seq = {
+            let db = Arc::new(CandyStore::open(dir, config.clone())?);
+            let typed = CandyTypedStore::<String, u32>::new(db);
+
+            typed.set("a", &1)?;
+            typed.set("b", &2)?;
+            let (v, seq_a) = typed.get_with_seq("a")?.unwrap();
+            assert_eq!(v, 1);
+            let (_, seq_b) = typed.get_with_seq("b")?.unwrap();
+            assert!(seq_a < seq_b);
+            assert_eq!(typed.get_with_seq("c")?, None);
+
+            // rewriting bumps the sequence number
+            assert_eq!(typed.set("a", &3)?, Some(1));
+            let (_, seq_a2) = typed.get_with_seq("a")?.unwrap();
+            assert!(seq_a2 > seq_b);
+
+            // replace compares only the value, not the sequence number
+            assert_eq!(typed.replace("a", &4, Some(&3))?, Some(3));
+            assert_eq!(typed.replace("a", &5, Some(&3))?, None);
+            assert_eq!(typed.get("a")?, Some(4));
+
+            let mut entries = typed
+                .iter_with_seq()
+                .map(|res| res.map(|(k, _, seq)| (seq.unwrap(), k)))
+                .collect::<Result<Vec<_>>>()?;
+            entries.sort();
+            assert_eq!(entries[0].1, "b");
+            entries[1].0
+        };
+
+        // the counter keeps increasing after reopening the store
+        let db = Arc::new(CandyStore::open(dir, config)?);
+        let typed = CandyTypedStore::<String, u32>::new(db.clone());
+        typed.set("c", &6)?;
+        assert!(typed.get_with_seq("c")?.unwrap().1 > last_seq);
+
+        let db2 = Arc::new(CandyStore::open(
+            format!("{dir}/nested"),
+            Config::default(),
+        )?);
+        let typed2 = CandyTypedStore::<String, u32>::new(db2);
+        typed2.set("a", &1)?;
+        assert_eq!(
+            typed2
+                .get_with_seq("a")
+                .unwrap_err()
+                .downcast_ref::<CandyError>(),
+            Some(&CandyError::NotEnabled("sequence numbers"))
+        );
+        assert_eq!(typed2.iter_with_seq().next().unwrap()?.2, None);
+
+        Ok(())
+    })
+}
+
+#[derive(Debug, PartialEq, Eq, Encode, Decode)]
+struct Celsius {
+    degrees: u32,
+}
+
+#[derive(Debug, PartialEq, Eq, Encode, Decode)]
+struct Fahrenheit {
+    degrees: u32,
+}
+
+#[test]
+fn test_typed_value_tags() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(
+            dir,
+            Config {
+                typed_value_tags: true,
+                typed_entry_seq: EntrySeq::Counter,
+                ..Default::default()
+            },
+        )?);
+
+        // both wrappers share the key type, and the values have the same encoding
+        let celsius = CandyTypedStore::<u32, Celsius>::new(db.clone());
+        let fahrenheit = CandyTypedStore::<u32, Fahrenheit>::new(db.clone());
+
+        // values can't be stored (or read) before their type is registered
+        let err = celsius.set(&1, &Celsius { degrees: 20 }).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<CandyError>(),
+            Some(CandyError::ValueTagNotRegistered(_))
+        ));
+        register_value_tag::<Celsius>(0xce15_1005)?;
+        register_value_tag::<Fahrenheit>(0xfa4e_2417)?;
+
+        // registering again with the same tag is fine, but tags can't be shared or changed
+        register_value_tag::<Celsius>(0xce15_1005)?;
+        assert!(matches!(
+            register_value_tag::<Celsius>(0xfa4e_2417)
+                .unwrap_err()
+                .downcast_ref::<CandyError>(),
+            Some(CandyError::ValueTagCollision(0xfa4e_2417, _, _))
+        ));
+        assert!(matches!(
+            register_value_tag::<Celsius>(1234)
+                .unwrap_err()
+                .downcast_ref::<CandyError>(),
+            Some(CandyError::AlreadyExists(_))
+        ));
+
+        celsius.set(&1, &Celsius { degrees: 20 })?;
+        assert_eq!(celsius.get(&1)?, Some(Celsius { degrees: 20 }));
+        assert_eq!(celsius.get_with_seq(&1)?.map(|(v, _)| v.degrees), Some(20));
+
+        let err = fahrenheit.get(&1).unwrap_err();
+        let Some(CandyError::WrongValueType { expected, found }) = err.downcast_ref::<CandyError>()
+        else {
+            panic!("unexpected error {err}");
+        };
+        assert_eq!((*expected, *found), (0xfa4e_2417, 0xce15_1005));
+
+        // the raw bytes are still accessible, without the tag
+        assert_eq!(
+            fahrenheit.get_raw_bytes(&1)?,
+            Some(20u32.to_le_bytes().to_vec())
+        );
+
+        // replace and copy keep the tag intact
+        assert_eq!(
+            celsius.replace(&1, &Celsius { degrees: 25 }, Some(&Celsius { degrees: 20 }))?,
+            Some(Celsius { degrees: 20 })
+        );
+        assert!(celsius.copy(&1, &2)?);
+        assert_eq!(celsius.get(&2)?, Some(Celsius { degrees: 25 }));
+        assert!(fahrenheit.get(&2).is_err());
+
+        // migrating rewrites the tag, so the entries now belong to the new value type
+        assert_eq!(
+            celsius.migrate(|_, c| Some(Fahrenheit {
+                degrees: c.degrees * 9 / 5 + 32
+            }))?,
+            2
+        );
+        assert_eq!(fahrenheit.get(&1)?, Some(Fahrenheit { degrees: 77 }));
+        assert!(celsius.get(&1).is_err());
+
+        // so are values stored by set_auto
+        celsius.set_auto(&3, &Celsius { degrees: 30 })?;
+        assert_eq!(celsius.get_auto(&3)?, Some(Celsius { degrees: 30 }));
+        assert!(fahrenheit.get_auto(&3).is_err());
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_get_raw_bytes() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let strings = CandyTypedStore::<u16, String>::new(db.clone());
+        strings.set(&5, "hello")?;
+
+        // reading it back with the wrong value type fails, but the raw bytes are still accessible
+        let numbers = CandyTypedStore::<u16, u64>::new(db);
+        assert!(matches!(
+            numbers.get(&5).unwrap_err().downcast_ref::<CandyError>(),
+            Some(CandyError::DecodeFailed(_))
+        ));
+        let raw = numbers.get_raw_bytes(&5)?.unwrap();
+        assert_eq!(raw, "hello".to_bytes::<LE>());
+        assert_eq!(numbers.get_raw_bytes(&6)?, None);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_bytes() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+        let typed = CandyTypedStore::<u32, Vec<u8>>::new(db);
+
+        // stands in for a shared buffer such as `bytes::Bytes`
+        let buf: Arc<[u8]> = Arc::from(&b"hello world"[..]);
+        assert_eq!(typed.set_bytes(&1, &buf)?, None);
+        assert_eq!(typed.get(&1)?, Some(b"hello world".to_vec()));
+        assert_eq!(typed.get_bytes::<_, Arc<[u8]>>(&1)?, Some(buf.clone()));
+
+        // values set as vectors can be read as buffers, and the other way around
+        typed.set(&2, &vec![1u8, 2, 3])?;
+        assert_eq!(
+            typed.get_bytes::<_, Box<[u8]>>(&2)?.as_deref(),
+            Some(&[1u8, 2, 3][..])
+        );
+        assert_eq!(typed.set_bytes(&2, &[4u8, 5][..])?, Some(vec![1, 2, 3]));
+        assert_eq!(typed.get(&2)?, Some(vec![4, 5]));
+
+        assert_eq!(typed.get_bytes::<_, Arc<[u8]>>(&3)?, None);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_namespaces() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let plain = CandyTypedStore::<String, u32>::new(db.clone());
+        let tenant_a = CandyTypedStore::<String, u32>::new_in_namespace(db.clone(), "tenant-a");
+        let tenant_b = CandyTypedStore::<String, u32>::new_in_namespace(db.clone(), "tenant-b");
+        // a namespace that's a suffix of another one
+        let tenant_aa = CandyTypedStore::<String, u32>::new_in_namespace(db.clone(), "-a");
+
+        plain.set("x", &1)?;
+        tenant_a.set("x", &2)?;
+        tenant_a.set("y", &3)?;
+        tenant_b.set("x", &4)?;
+        tenant_aa.set("x", &5)?;
+
+        assert_eq!(plain.get("x")?, Some(1));
+        assert_eq!(tenant_a.get("x")?, Some(2));
+        assert_eq!(tenant_b.get("x")?, Some(4));
+        assert_eq!(tenant_b.get("y")?, None);
+        assert_eq!(tenant_aa.get("x")?, Some(5));
+
+        assert_eq!(plain.len()?, 1);
+        assert_eq!(tenant_a.len()?, 2);
+        assert_eq!(tenant_aa.len()?, 1);
+        assert_eq!(
+            tenant_a.to_hashmap()?,
+            HashMap::from([("x".to_owned(), 2), ("y".to_owned(), 3)])
+        );
+
+        assert_eq!(tenant_a.clear()?, 2);
+        assert!(tenant_a.is_empty()?);
+        assert_eq!(plain.get("x")?, Some(1));
+        assert_eq!(tenant_b.get("x")?, Some(4));
+        assert_eq!(tenant_aa.get("x")?, Some(5));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_set_if_absent() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let locks = CandyTypedStore::<String, u64>::new(db.clone());
+        let mut handles = vec![];
+        for t in 0..8u64 {
+            let locks = locks.clone();
+            handles.push(std::thread::spawn(move || {
+                locks.set_if_absent("leader", &t).unwrap()
+            }));
+        }
+        let winners = handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .filter(|won| *won)
+            .count();
+        assert_eq!(winners, 1);
+        assert!(locks.get("leader")?.is_some());
+
+        assert!(db.set_if_absent("raw", "1")?);
+        assert!(!db.set_if_absent("raw", "2")?);
+        assert_eq!(db.get("raw")?, Some("1".into()));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_on_change() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let events = Arc::new(Mutex::new(vec![]));
+        let events2 = events.clone();
+        let typed = CandyTypedStore::<String, u32>::with_on_change(db.clone(), move |ev| {
+            events2
+                .lock()
+                .unwrap()
+                .push((ev.key.clone(), ev.old.copied(), ev.new.copied()));
+        });
+
+        typed.set("a", &1)?;
+        typed.set("a", &2)?;
+        typed.replace("a", &3, Some(&7))?; // wrong value, no event
+        typed.replace("a", &3, Some(&2))?;
+        typed.get_or_create("a", &9)?; // exists, no event
+        typed.get_or_create("b", &4)?;
+        typed.set_if_absent("c", &5)?;
+        typed.remove("a")?;
+        typed.remove("a")?; // does not exist, no event
+        typed.remove_many(&["b"])?;
+        typed.clear()?;
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                ("a".to_owned(), None, Some(1)),
+                ("a".to_owned(), Some(1), Some(2)),
+                ("a".to_owned(), Some(2), Some(3)),
+                ("b".to_owned(), None, Some(4)),
+                ("c".to_owned(), None, Some(5)),
+                ("a".to_owned(), Some(3), None),
+                ("b".to_owned(), Some(4), None),
+                ("c".to_owned(), Some(5), None),
+            ]
+        );
+
+        // other wrappers over the same store don't call the callback
+        CandyTypedStore::<String, u32>::new(db).set("d", &6)?;
+        assert_eq!(events.lock().unwrap().len(), 8);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_bulk_load() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let typed = CandyTypedStore::<u32, String>::new(db.clone());
+        let count = typed.bulk_load((0..5000u32).map(|i| (i, format!("val{i}"))))?;
+        assert_eq!(count, 5000);
+        assert_eq!(typed.len()?, 5000);
+        assert_eq!(typed.get(&1234)?, Some("val1234".to_owned()));
+
+        // overwriting counts as written as well
+        assert_eq!(typed.bulk_load([(1234, "new".to_owned())])?, 1);
+        assert_eq!(typed.get(&1234)?, Some("new".to_owned()));
+
+        Ok(())
+    })?;
+
+    // small shards, so that the batch has to split shards mid-way
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(
+            dir,
+            Config {
+                max_shard_size: 20 * 1024,
+                min_compaction_threashold: 10 * 1024,
+                ..Default::default()
+            },
+        )?);
+
+        let typed = CandyTypedStore::<u32, String>::new(db.clone());
+        assert_eq!(
+            typed.bulk_load((0..5000u32).map(|i| (i, format!("val{i}"))))?,
+            5000
+        );
+        assert!(db.stats().num_splits > 0);
+        assert_eq!(typed.len()?, 5000);
+        for i in 0..5000u32 {
+            assert_eq!(typed.get(&i)?, Some(format!("val{i}")));
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_set_many() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let events = Arc::new(Mutex::new(vec![]));
+        let events2 = events.clone();
+        let typed = CandyTypedStore::<u32, String>::with_on_change(db.clone(), move |ev| {
+            events2.lock().unwrap().push(*ev.key);
+        });
+
+        let prevs = typed.set_many((0..1000u32).map(|i| (i, format!("val{i}"))))?;
+        assert_eq!(prevs.len(), 1000);
+        assert!(prevs.iter().all(|p| p.is_none()));
+        assert_eq!(typed.len()?, 1000);
+
+        // previous values are returned in the given order, and repeated keys are written in order
+        let prevs = typed.set_many([
+            (500, "a".to_owned()),
+            (2000, "b".to_owned()),
+            (7, "c".to_owned()),
+            (500, "d".to_owned()),
+        ])?;
+        assert_eq!(
+            prevs,
+            vec![
+                Some("val500".to_owned()),
+                None,
+                Some("val7".to_owned()),
+                Some("a".to_owned())
+            ]
+        );
+        assert_eq!(typed.get(&500)?, Some("d".to_owned()));
+        assert_eq!(typed.get(&2000)?, Some("b".to_owned()));
+        assert_eq!(typed.set_many([])?, vec![]);
+
+        assert_eq!(events.lock().unwrap().len(), 1004);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_iter_sorted() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let typed = CandyTypedStore::<String, u32>::new(db.clone());
+        for i in (0..300u32).rev() {
+            typed.set(&format!("key{i:03}"), &i)?;
+        }
+        CandyTypedStore::<u32, u32>::new(db).set(&7, &7)?;
+
+        let entries = typed.iter_sorted()?.collect::<Result<Vec<_>>>()?;
+        assert_eq!(entries.len(), 300);
+        for (i, (k, v)) in entries.into_iter().enumerate() {
+            assert_eq!(k, format!("key{i:03}"));
+            assert_eq!(v, i as u32);
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_empty_keys() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let strings = CandyTypedStore::<String, u32>::new(db.clone());
+        let vecs = CandyTypedStore::<Vec<u8>, u32>::new(db.clone());
+        let unit_tuples = CandyTypedStore::<(String,), u32>::new(db.clone());
+
+        assert_eq!(strings.get("")?, None);
+        assert_eq!(strings.set("", &1)?, None);
+        assert_eq!(vecs.set(&vec![], &2)?, None);
+        assert_eq!(unit_tuples.set(&("".to_owned(),), &3)?, None);
+        db.set("", "4")?;
+
+        assert_eq!(strings.get("")?, Some(1));
+        assert_eq!(vecs.get(&vec![])?, Some(2));
+        assert_eq!(unit_tuples.get(&("".to_owned(),))?, Some(3));
+        assert_eq!(db.get("")?, Some("4".into()));
+
+        assert_eq!(
+            strings.iter().collect::<Result<Vec<_>>>()?,
+            vec![("".to_owned(), 1)]
+        );
+        assert_eq!(vecs.iter().collect::<Result<Vec<_>>>()?, vec![(vec![], 2)]);
+
+        assert_eq!(strings.remove("")?, Some(1));
+        assert_eq!(strings.get("")?, None);
+        assert_eq!(vecs.get(&vec![])?, Some(2));
+        assert_eq!(vecs.remove(&vec![])?, Some(2));
+        assert_eq!(unit_tuples.get(&("".to_owned(),))?, Some(3));
+        assert_eq!(db.remove("")?, Some("4".into()));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_in_memory() -> Result<()> {
+    let db = Arc::new(CandyStore::open_in_memory(Config::default())?);
+
+    let typed = CandyTypedStore::<String, Vec<u32>>::new(db.clone());
+    typed.set("hello", &vec![1, 2, 3])?;
+    assert_eq!(typed.get("hello")?, Some(vec![1, 2, 3]));
+    assert_eq!(typed.remove("hello")?, Some(vec![1, 2, 3]));
+
+    typed.set_big("big", &vec![7; 100_000])?;
+    assert_eq!(typed.get_big("big")?, Some(vec![7; 100_000]));
+
+    let list = CandyTypedList::<String, u32, u32>::new(db.clone());
+    list.set("xxx", &1, &10)?;
+    list.set("xxx", &2, &20)?;
+    assert_eq!(list.pop_head("xxx")?, Some((1, 10)));
+
+    let queue = CandyTypedDeque::<String, u32>::new(db.clone());
+    queue.push_tail("yyy", &5)?;
+    queue.push_head("yyy", &4)?;
+    assert_eq!(queue.pop_head("yyy")?, Some(4));
+
+    // the directory of a store whose process died (so its lock is not held) is left behind
+    let stale_dir = db
+        .get_shards_directory()
+        .with_file_name("candy-in-memory-stale-test");
+    std::fs::create_dir_all(&stale_dir)?;
+    std::fs::write(stale_dir.join(".lock"), "")?;
+    std::fs::File::open(&stale_dir)?.set_modified(SystemTime::now() - Duration::from_secs(3600))?;
+
+    // every in-memory store is a fresh one, and opening one removes stale directories, but not live ones
+    let db2 = Arc::new(CandyStore::open_in_memory(Config::default())?);
+    assert_eq!(
+        CandyTypedStore::<String, Vec<u32>>::new(db2).get_big("big")?,
+        None
+    );
+    assert!(!stale_dir.exists());
+    assert!(db.get_shards_directory().exists());
+
+    let dir = db.get_shards_directory().to_owned();
+    drop((typed, list, queue, db));
+    assert!(!dir.exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_typed_filter_keys() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let typed = CandyTypedStore::<u32, String>::new(db.clone());
+        for i in 0..100 {
+            typed.set(&i, &format!("val{i}"))?;
+        }
+        // entries of other types are not considered
+        CandyTypedStore::<u64, String>::new(db.clone()).set(&7, "other")?;
+        db.set("raw", "raw")?;
+
+        let mut odd = typed
+            .filter_keys(|k| k % 2 == 1)
+            .collect::<Result<Vec<_>>>()?;
+        odd.sort();
+        assert_eq!(odd, (0..100).filter(|k| k % 2 == 1).collect::<Vec<_>>());
+
+        // key-driven cleanup
+        for k in typed
+            .filter_keys(|k| *k >= 10)
+            .collect::<Result<Vec<_>>>()?
+        {
+            typed.remove(&k)?;
+        }
+        assert_eq!(typed.len()?, 10);
+        assert_eq!(typed.filter_keys(|_| false).count(), 0);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_copy() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(
+            dir,
+            Config {
+                typed_entry_seq: EntrySeq::Counter,
+                ..Default::default()
+            },
+        )?);
+
+        let typed = CandyTypedStore::<String, Vec<u32>>::new(db.clone());
+        assert!(!typed.copy("draft", "saved")?);
+        assert_eq!(typed.get("saved")?, None);
+
+        typed.set("draft", &vec![1, 2, 3])?;
+        typed.set("saved", &vec![9])?;
+        assert!(typed.copy("draft", "saved")?);
+        assert_eq!(typed.get("saved")?, Some(vec![1, 2, 3]));
+        assert_eq!(typed.get("draft")?, Some(vec![1, 2, 3]));
+
+        // the copy is a new write
+        let (_, src_seq) = typed.get_with_seq("draft")?.unwrap();
+        let (_, dst_seq) = typed.get_with_seq("saved")?.unwrap();
+        assert!(dst_seq > src_seq);
+
+        // copying does not link the entries
+        typed.set("draft", &vec![4])?;
+        assert_eq!(typed.get("saved")?, Some(vec![1, 2, 3]));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_fixed_store() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let fixed = CandyFixedStore::<8, String>::new(db.clone())?;
+        for i in 0..100u64 {
+            assert_eq!(fixed.set(&i.to_le_bytes(), &format!("val{i}"))?, None);
+        }
+        assert_eq!(
+            fixed.set(&7u64.to_le_bytes(), "seven")?,
+            Some("val7".to_owned())
+        );
+        assert_eq!(fixed.get(&7u64.to_le_bytes())?, Some("seven".to_owned()));
+        assert!(fixed.contains(&8u64.to_le_bytes())?);
+        assert_eq!(fixed.get(&1000u64.to_le_bytes())?, None);
+
+        assert_eq!(fixed.remove(&8u64.to_le_bytes())?, Some("val8".to_owned()));
+        assert_eq!(fixed.remove(&8u64.to_le_bytes())?, None);
+        assert!(!fixed.contains(&8u64.to_le_bytes())?);
+
+        // the entries are disjoint from those of other stores, even with the same key bytes
+        let typed = CandyTypedStore::<u64, String>::new(db.clone());
+        assert_eq!(typed.get(&7)?, None);
+        typed.set(&7, "typed")?;
+        db.set(&7u64.to_le_bytes(), "raw")?;
+        let fixed4 = CandyFixedStore::<4, String>::new(db.clone())?;
+        fixed4.set(&7u32.to_le_bytes(), "fixed4")?;
+        let numbers = CandyFixedStore::<8, u64>::new(db.clone())?;
+        assert_eq!(numbers.get(&7u64.to_le_bytes())?, None);
+        numbers.set(&7u64.to_le_bytes(), &7)?;
+        let other_ns = CandyFixedStore::<8, String>::new_in_namespace(db.clone(), 1)?;
+        assert_eq!(other_ns.get(&7u64.to_le_bytes())?, None);
+        other_ns.set(&7u64.to_le_bytes(), "other")?;
+        assert_eq!(fixed.get(&7u64.to_le_bytes())?, Some("seven".to_owned()));
+
+        let mut keys = fixed
+            .iter()
+            .map(|res| u64::from_le_bytes(res.unwrap().0))
+            .collect::<Vec<_>>();
+        keys.sort();
+        assert_eq!(keys, (0..100).filter(|&i| i != 8).collect::<Vec<_>>());
+        assert_eq!(fixed4.iter().count(), 1);
+        assert_eq!(numbers.iter().count(), 1);
+        assert_eq!(other_ns.iter().count(), 1);
+
+        // value types need a tag
+        #[derive(Encode, Decode)]
+        struct Untagged(u32);
+        assert!(matches!(
+            CandyFixedStore::<8, Untagged>::new(db.clone())
+                .err()
+                .unwrap()
+                .downcast_ref::<CandyError>(),
+            Some(CandyError::ValueTagNotRegistered(_))
+        ));
+
+        Ok(())
+    })?;
+
+    // values are stored according to the config
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(
+            dir,
+            Config {
+                typed_entry_seq: EntrySeq::Counter,
+                typed_value_tags: true,
+                value_codec: Some(ValueCodec {
+                    compress: rle_compress,
+                    decompress: rle_decompress,
+                }),
+                value_compression_threshold: 16,
+                ..Default::default()
+            },
+        )?);
+
+        let fixed = CandyFixedStore::<8, String>::new(db.clone())?;
+        let long = "x".repeat(1000);
+        fixed.set(&1u64.to_le_bytes(), &long)?;
+        assert_eq!(fixed.get(&1u64.to_le_bytes())?, Some(long.clone()));
+        assert_eq!(fixed.iter().next().unwrap()?, (1u64.to_le_bytes(), long));
+
+        // compressed, and followed by the tag and the sequence number
+        let (_, vbytes) = db.iter_raw().next().unwrap()?;
+        assert!(vbytes.len() < 100);
+        assert_eq!(
+            &vbytes[vbytes.len() - 12..vbytes.len() - 8],
+            15u32.to_le_bytes()
+        );
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_replace_with() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+        let counters = CandyTypedStore::<String, u64>::new(db);
+
+        assert_eq!(counters.replace_with("hits", |_| unreachable!())?, None);
+        assert_eq!(counters.get("hits")?, None);
+
+        counters.set("hits", &5)?;
+        assert_eq!(counters.replace_with("hits", |v| v * 2)?, Some((5, 10)));
+        assert_eq!(counters.get("hits")?, Some(10));
+
+        // concurrent calls on the same key do not lose updates
+        std::thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    for _ in 0..100 {
+                        counters.replace_with("hits", |v| v + 1).unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(counters.get("hits")?, Some(810));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_range() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+        let series = CandyTypedStore::<u64, f64>::new(db.clone());
+        for ts in 0..100u64 {
+            series.set(&(ts * 10), &(ts as f64))?;
+        }
+        // a different key type is not included
+        CandyTypedStore::<u32, f64>::new(db).set(&150, &-1.0)?;
+
+        let mut entries = series.range(100..200).collect::<Result<Vec<_>>>()?;
+        entries.sort_by_key(|(ts, _)| *ts);
+        assert_eq!(
+            entries,
+            (10..20).map(|i| (i * 10, i as f64)).collect::<Vec<_>>()
+        );
+
+        assert_eq!(series.range(..).count(), 100);
+        assert_eq!(series.range(985..).count(), 1);
+        assert_eq!(series.range(..=0).count(), 1);
+        assert_eq!(series.range(2000..3000).count(), 0);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_get_cached() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+        let cached = CandyTypedStore::<String, Vec<u32>>::new_cached(db.clone(), 2);
+
+        assert_eq!(cached.get_cached("missing")?, None);
+
+        cached.set("a", &vec![1, 2, 3])?;
+        let a1 = cached.get_cached("a")?.unwrap();
+        let a2 = cached.get_cached("a")?.unwrap();
+        assert_eq!(*a1, vec![1, 2, 3]);
+        assert!(Arc::ptr_eq(&a1, &a2));
+
+        // writes through this wrapper (and its clones) invalidate the cached value
+        cached.set("a", &vec![4])?;
+        assert_eq!(*cached.get_cached("a")?.unwrap(), vec![4]);
+        cached.clone().replace("a", &vec![5], Some(&vec![4]))?;
+        assert_eq!(*cached.get_cached("a")?.unwrap(), vec![5]);
+        cached.remove("a")?;
+        assert_eq!(cached.get_cached("a")?, None);
+
+        cached.set("a", &vec![1])?;
+        cached.set("b", &vec![2])?;
+        cached.swap("a", "b")?;
+        assert_eq!(*cached.get_cached("a")?.unwrap(), vec![2]);
+        assert_eq!(*cached.get_cached("b")?.unwrap(), vec![1]);
+
+        // a (which was used last) survives the eviction of b
+        let b = cached.get_cached("b")?.unwrap();
+        let a = cached.get_cached("a")?.unwrap();
+        cached.set("c", &vec![3])?;
+        cached.get_cached("c")?;
+        assert!(Arc::ptr_eq(&a, &cached.get_cached("a")?.unwrap()));
+        assert!(!Arc::ptr_eq(&b, &cached.get_cached("b")?.unwrap()));
+
+        // writes through other wrappers are not seen by the cache
+        let plain = CandyTypedStore::<String, Vec<u32>>::new(db.clone());
+        plain.set("a", &vec![100])?;
+        assert_eq!(*cached.get_cached("a")?.unwrap(), vec![2]);
+        assert_eq!(cached.get("a")?, Some(vec![100]));
+
+        // without a cache, get_cached is just get
+        assert_eq!(*plain.get_cached("a")?.unwrap(), vec![100]);
+        assert!(!Arc::ptr_eq(
+            &plain.get_cached("a")?.unwrap(),
+            &plain.get_cached("a")?.unwrap()
+        ));
+
+        cached.clear()?;
+        assert_eq!(cached.get_cached("a")?, None);
+        assert_eq!(cached.get_cached("c")?, None);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_nullable() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+        let typed = CandyTypedStore::<String, Option<u32>>::new(db);
+
+        // never set
+        assert_eq!(typed.get("a")?, None);
+        assert_eq!(typed.get_flat("a")?, None);
+        assert!(!typed.is_null("a")?);
+
+        // known to be absent
+        assert_eq!(typed.set_null("a")?, None);
+        assert_eq!(typed.get("a")?, Some(None));
+        assert_eq!(typed.get_flat("a")?, None);
+        assert!(typed.is_null("a")?);
+        assert!(typed.contains("a")?);
+
+        // present
+        assert_eq!(typed.set("a", &Some(7))?, Some(None));
+        assert_eq!(typed.get("a")?, Some(Some(7)));
+        assert_eq!(typed.get_flat("a")?, Some(7));
+        assert!(!typed.is_null("a")?);
+        assert_eq!(typed.set_null("a")?, Some(Some(7)));
+
+        // a null is an entry like any other
+        assert_eq!(typed.replace("a", &Some(8), Some(&None))?, Some(None));
+        assert_eq!(typed.get_or_create("b", &None)?, None);
+        assert_eq!(typed.len()?, 2);
+        assert_eq!(typed.remove("b")?, Some(None));
+        assert_eq!(typed.get("b")?, None);
+
+        Ok(())
+    })
+}
+
+// encodes as its bytes, as-is (without a length), and decodes as all the remaining bytes
+#[derive(Debug)]
+struct RawBytes(Vec<u8>);
+
+impl Encode for RawBytes {
+    fn encode<const CONFIG: u16>(
+        &self,
+        c: &mut (impl std::io::Write + ?Sized),
+    ) -> std::io::Result<()> {
+        c.write_all(&self.0)
+    }
+}
+
+impl Decode<'_> for RawBytes {
+    fn decode<const CONFIG: u16>(c: &mut &[u8]) -> databuf::Result<Self> {
+        let bytes = c.to_vec();
+        *c = &[];
+        Ok(Self(bytes))
+    }
+}
+
+#[derive(Debug)]
+struct Panicky;
+
+impl Encode for Panicky {
+    fn encode<const CONFIG: u16>(
+        &self,
+        _: &mut (impl std::io::Write + ?Sized),
+    ) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Decode<'_> for Panicky {
+    fn decode<const CONFIG: u16>(_: &mut &[u8]) -> databuf::Result<Self> {
+        panic!("boom")
+    }
+}
+
+// reads back all the (random) entries as a `V`, returning the number of entries that failed to decode.
+// decoding may fail, but must never panic
+fn decode_all_as<V: Encode + for<'de> Decode<'de>>(db: &Arc<CandyStore>) -> Result<usize> {
+    let typed = CandyTypedStore::<u32, V>::new(db.clone());
+    let mut failures = 0;
+    for k in 0u32..1000 {
+        match typed.get(&k) {
+            Ok(v) => assert!(v.is_some()),
+            Err(e) => {
+                assert!(matches!(
+                    e.downcast_ref::<CandyError>(),
+                    Some(CandyError::DecodeFailed(_))
+                ));
+                failures += 1;
+            }
+        }
+    }
+    assert_eq!(typed.iter().filter(|res| res.is_err()).count(), failures);
+    Ok(failures)
+}
+
+#[test]
+fn test_typed_decode_fuzz() -> Result<()> {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+        let mut rng = StdRng::seed_from_u64(0x5eed);
+
+        let raw = CandyTypedStore::<u32, RawBytes>::new(db.clone());
+        for k in 0u32..1000 {
+            let len = rng.random_range(0..64);
+            let bytes = (0..len).map(|_| rng.random()).collect::<Vec<u8>>();
+            raw.set(&k, &RawBytes(bytes))?;
+        }
+
+        assert!(decode_all_as::<u64>(&db)? > 0);
+        assert!(decode_all_as::<bool>(&db)? > 0);
+        assert!(decode_all_as::<char>(&db)? > 0);
+        assert!(decode_all_as::<String>(&db)? > 0);
+        assert!(decode_all_as::<Option<String>>(&db)? > 0);
+        assert!(decode_all_as::<(u32, String, bool)>(&db)? > 0);
+        assert!(decode_all_as::<[u16; 3]>(&db)? > 0);
+        assert!(decode_all_as::<Vec<u8>>(&db)? > 0);
+        assert!(decode_all_as::<Vec<bool>>(&db)? > 0);
+        assert!(decode_all_as::<MyVal>(&db)? > 0);
+        assert_eq!(decode_all_as::<RawBytes>(&db)?, 0);
+        // panics in Decode impls are turned into errors as well
+        let err = CandyTypedStore::<u32, Panicky>::new(db.clone())
+            .get(&0)
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<CandyError>(),
+            Some(CandyError::DecodeFailed(reason)) if reason.contains("boom")
+        ));
+
+        // same for queues
+        let raw_queue = CandyTypedDeque::<String, RawBytes>::new(db.clone());
+        for _ in 0..100 {
+            let bytes = (0..rng.random_range(0..16))
+                .map(|_| rng.random())
+                .collect::<Vec<u8>>();
+            raw_queue.push_tail("q", &RawBytes(bytes))?;
+        }
+        let queue = CandyTypedDeque::<String, Vec<u32>>::new(db.clone());
+        assert!(queue.iter("q").filter(|res| res.is_err()).count() > 0);
+        assert!(queue.iter_backwards("q").filter(|res| res.is_err()).count() > 0);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_get_or_default() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+        let typed = CandyTypedStore::<String, Vec<u32>>::new(db);
+
+        // does not persist the default
+        assert_eq!(typed.get_or_default("a")?, Vec::<u32>::new());
+        assert!(!typed.contains("a")?);
+
+        typed.set("a", &vec![1, 2])?;
+        assert_eq!(typed.get_or_default("a")?, vec![1, 2]);
+
+        // persists the default
+        assert_eq!(typed.get_or_insert_default("b")?, Vec::<u32>::new());
+        assert_eq!(typed.get("b")?, Some(vec![]));
+        assert_eq!(typed.get_or_insert_default("a")?, vec![1, 2]);
+        assert_eq!(typed.len()?, 2);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_keys_page() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+        let typed = CandyTypedStore::<u32, String>::new(db.clone());
+        let other = CandyTypedStore::<String, String>::new(db.clone());
+
+        let (keys, cursor) = typed.keys_page(None, 10)?;
+        assert!(keys.is_empty());
+        assert_eq!(cursor, None);
+
+        for i in 0u32..95 {
+            typed.set(&i, &format!("val {i}"))?;
+            other.set(&format!("other {i}"), "x")?;
+            db.set(&format!("raw {i}"), "y")?;
+        }
+
+        let mut all = vec![];
+        let mut cursor = None;
+        let mut pages = 0;
+        loop {
+            let (keys, next) = typed.keys_page(cursor, 10)?;
+            assert!(keys.len() <= 10);
+            all.extend(keys);
+            pages += 1;
+            // cursors are plain cookies that can be handed around
+            cursor = next.map(|c| StoreCursor::from_cookie(c.cookie()));
+            if cursor.is_none() {
+                break;
+            }
+        }
+        assert_eq!(pages, 10);
+        all.sort();
+        assert_eq!(all, (0u32..95).collect::<Vec<_>>());
+
+        // modifications between pages don't invalidate the cursor
+        let (first, cursor) = typed.keys_page(None, 50)?;
+        for k in first.iter() {
+            typed.remove(k)?;
+        }
+        typed.set(&1000, "new")?;
+        let (rest, _) = typed.keys_page(cursor, 100)?;
+        assert_eq!(first.len() + rest.len(), 95 + rest.contains(&1000) as usize);
+        assert!(rest.iter().all(|k| !first.contains(k)));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_value_size_histogram() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+        let typed = CandyTypedStore::<u32, Vec<u8>>::new(db.clone());
+        let other = CandyTypedStore::<String, Vec<u8>>::new(db.clone());
+
+        assert_eq!(
+            typed.value_size_histogram(&[10, 100])?,
+            vec![(10, 0), (100, 0), (usize::MAX, 0)]
+        );
+
+        // a Vec<u8> is serialized with a one-byte length prefix (for short vectors)
+        for i in 0u32..10 {
+            typed.set(&i, &vec![0u8; 5])?;
+        }
+        for i in 10u32..13 {
+            typed.set(&i, &vec![0u8; 9])?;
+        }
+        for i in 13u32..15 {
+            typed.set(&i, &vec![0u8; 50])?;
+        }
+        typed.set(&15, &vec![0u8; 500])?;
+        typed.set_big(&16, &vec![0u8; 500])?;
+        other.set("x", &vec![0u8; 5])?;
+
+        // bounds are sorted and deduplicated, and bounds are inclusive
+        assert_eq!(
+            typed.value_size_histogram(&[100, 10, 10])?,
+            vec![(10, 13), (100, 2), (usize::MAX, 1)]
+        );
+        assert_eq!(typed.value_size_histogram(&[])?, vec![(usize::MAX, 16)]);
+        assert_eq!(
+            typed.value_size_histogram(&[6, usize::MAX])?,
+            vec![(6, 10), (usize::MAX, 6)]
+        );
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_slice_keys() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+        let typed = CandyTypedStore::<Vec<u8>, Vec<u8>>::new(db.clone());
+
+        let buf = b"some packet with a key inside".to_vec();
+        let key: &[u8] = &buf[5..11];
+        let val: &[u8] = &buf[19..];
+
+        // slices encode exactly like vectors, so entries are shared between the two forms
+        assert_eq!(typed.get(key)?, None);
+        assert_eq!(typed.set(key, val)?, None);
+        assert_eq!(typed.get(key)?, Some(val.to_vec()));
+        assert_eq!(typed.get(&b"packet".to_vec())?, Some(val.to_vec()));
+        assert!(typed.contains(key)?);
+        assert!(!typed.contains(&[][..])?);
+
+        typed.set(&b"other".to_vec(), &b"val".to_vec())?;
+        assert_eq!(typed.get(&b"other"[..])?, Some(b"val".to_vec()));
+
+        assert_eq!(typed.remove(key)?, Some(val.to_vec()));
+        assert_eq!(typed.get(key)?, None);
+        assert_eq!(typed.remove(&b"other"[..])?, Some(b"val".to_vec()));
+        assert_eq!(typed.iter().count(), 0);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_sample() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+        let typed = CandyTypedStore::<u32, u32>::new(db.clone());
+        let other = CandyTypedStore::<String, u32>::new(db.clone());
+
+        assert!(typed.sample(10)?.is_empty());
+
+        for i in 0u32..10_000 {
+            typed.set(&i, &(i * 2))?;
+            if i % 10 == 0 {
+                other.set(&i.to_string(), &i)?;
+                db.set(&i.to_string(), "raw")?;
+            }
+        }
+
+        assert!(typed.sample(0)?.is_empty());
+
+        let sample = typed.sample(500)?;
+        assert_eq!(sample.len(), 500);
+        let keys = sample.iter().map(|(k, _)| *k).collect::<HashSet<_>>();
+        assert_eq!(keys.len(), 500);
+        assert!(sample.iter().all(|(k, v)| *k < 10_000 && *v == k * 2));
+        // keys are hashed, so even clustered samples spread over the whole key range
+        let mean = sample.iter().map(|(k, _)| *k as u64).sum::<u64>() / 500;
+        assert!((3000..7000).contains(&mean), "{mean}");
+
+        // a sample larger than the typed store returns all of it
+        assert_eq!(other.sample(5000)?.len(), 1000);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_key_normalizer() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+        let users =
+            CandyTypedStore::<String, u32>::new_with_normalizer(db.clone(), |k| k.to_lowercase());
+
+        assert_eq!(users.set("Foo", &1)?, None);
+        assert_eq!(users.get("foo")?, Some(1));
+        assert_eq!(users.get("FOO")?, Some(1));
+        assert!(users.contains(&"fOo".to_string())?);
+        assert_eq!(users.set("FOO", &2)?, Some(1));
+        assert_eq!(users.get_or_create("foO", &3)?, 2);
+
+        // entries are stored under their normalized keys
+        assert_eq!(
+            users.iter().collect::<Result<Vec<_>>>()?,
+            vec![("foo".to_string(), 2)]
+        );
+        let plain = CandyTypedStore::<String, u32>::new(db.clone());
+        assert_eq!(plain.get("foo")?, Some(2));
+        assert_eq!(plain.get("Foo")?, None);
+
+        // clones keep the normalizer
+        assert_eq!(users.clone().remove("Foo")?, Some(2));
+        assert_eq!(users.len()?, 0);
+
+        // full keys can be built into reused buffers, which are not reallocated if large enough
+        let mut buf = vec![];
+        users.make_key_into("Foo", &mut buf)?;
+        let foo = buf.clone();
+        let ptr = buf.as_ptr();
+        users.make_key_into("bar", &mut buf)?;
+        assert_eq!(buf.as_ptr(), ptr);
+        assert_ne!(buf, foo);
+        users.make_key_into("FOO", &mut buf)?;
+        assert_eq!(buf, foo);
+
+        // a borrowed key that does not decode as the key type can't be normalized
+        let tagged = CandyTypedStore::<TaggedKey, u32>::new_with_normalizer(db, |k| TaggedKey {
+            name: k.name.to_ascii_lowercase(),
+            tag: k.tag,
+        });
+        let key = TaggedKey {
+            name: b"Foo".to_vec(),
+            tag: 7,
+        };
+        tagged.set(&key, &1)?;
+        assert_eq!(tagged.get(&key)?, Some(1));
+        assert!(matches!(
+            tagged
+                .get(&b"Foo"[..])
+                .unwrap_err()
+                .downcast_ref::<CandyError>(),
+            Some(CandyError::DecodeFailed(_))
+        ));
+
+        Ok(())
+    })
+}
+
+// a key type whose borrowed form (the name alone) does not encode like the key itself
+#[derive(Debug, Encode, Decode)]
+struct TaggedKey {
+    name: Vec<u8>,
+    tag: u8,
+}
+
+impl CandyTypedKey for TaggedKey {
+    const TYPE_ID: u32 = 0x7d1a0c92;
+}
+
+impl Borrow<[u8]> for TaggedKey {
+    fn borrow(&self) -> &[u8] {
+        &self.name
+    }
+}
+
+#[test]
+fn test_typed_par_for_each() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(
+            dir,
+            Config {
+                max_shard_size: 64 * 1024,
+                ..Default::default()
+            },
+        )?);
+        let typed = CandyTypedStore::<u32, u64>::new(db.clone());
+        let other = CandyTypedStore::<String, u64>::new(db.clone());
+
+        typed.par_for_each(4, |_, _| panic!("empty store"))?;
+
+        for i in 0u32..5000 {
+            typed.set(&i, &(i as u64 * 3))?;
+            if i % 10 == 0 {
+                other.set(&i.to_string(), &0)?;
+            }
+        }
+        assert!(db.stats().num_shards > 1);
+
+        for num_threads in [0, 1, 3] {
+            let seen = Mutex::new(HashMap::new());
+            typed.par_for_each(num_threads, |k, v| {
+                assert!(seen.lock().unwrap().insert(k, v).is_none());
+                Ok(())
+            })?;
+            let seen = seen.into_inner().unwrap();
+            assert_eq!(seen.len(), 5000);
+            assert!(seen.iter().all(|(k, v)| *v == *k as u64 * 3));
+        }
+
+        // the first error stops the scan and is returned
+        let count = std::sync::atomic::AtomicUsize::new(0);
+        let err = typed
+            .par_for_each(2, |k, _| {
+                count.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                if k == 1234 {
+                    Err(CandyError::AlreadyExists("1234").into())
+                } else {
+                    Ok(())
+                }
+            })
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<CandyError>(),
+            Some(&CandyError::AlreadyExists("1234"))
+        );
+        assert!(count.into_inner() <= 5000);
+
+        Ok(())
+    })
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn test_typed_par_iter() -> Result<()> {
+    use rayon::iter::ParallelIterator;
+
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(
+            dir,
+            Config {
+                max_shard_size: 20 * 1024, // use small files to get many shards
+                min_compaction_threashold: 10 * 1024,
+                ..Default::default()
+            },
+        )?);
+        let typed = CandyTypedStore::<u32, String>::new(db.clone());
+        assert_eq!(typed.par_iter().count(), 0);
+
+        for i in 0..5000u32 {
+            typed.set(&i, &format!("val{i}"))?;
+        }
+        // entries of other stores are skipped
+        CandyTypedStore::<u64, String>::new(db.clone()).set(&7, "other")?;
+        db.set("raw", "raw")?;
+        assert!(db.stats().num_splits > 0);
+
+        let mut entries = typed.par_iter().collect::<Result<Vec<_>>>()?;
+        entries.sort();
+        assert_eq!(
+            entries,
+            (0..5000u32)
+                .map(|i| (i, format!("val{i}")))
+                .collect::<Vec<_>>()
+        );
+
+        // decoding errors are yielded as items
+        CandyTypedStore::<u32, u8>::new(db).set(&5000, &1)?;
+        assert_eq!(typed.par_iter().filter(|res| res.is_err()).count(), 1);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_delete() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+        let typed = CandyTypedStore::<u32, String>::new_cached(db.clone(), 10);
+
+        assert!(!typed.delete(&1)?);
+        typed.set(&1, "one")?;
+        assert_eq!(typed.get_cached(&1)?.as_deref(), Some(&"one".to_string()));
+        assert!(typed.delete(&1)?);
+        assert!(!typed.delete(&1)?);
+        assert_eq!(typed.get(&1)?, None);
+        assert_eq!(typed.get_cached(&1)?, None);
+
+        // the old value is not decoded, so undecodable entries can be deleted too
+        CandyTypedStore::<u32, u8>::new(db.clone()).set(&2, &7)?;
+        assert!(typed.get(&2).is_err());
+        assert!(typed.delete(&2)?);
+
+        // the on-change callback still gets the old value
+        let events = Arc::new(Mutex::new(vec![]));
+        let events2 = events.clone();
+        let watched = CandyTypedStore::<u32, String>::with_on_change(db.clone(), move |ev| {
+            events2
+                .lock()
+                .unwrap()
+                .push((*ev.key, ev.old.cloned(), ev.new.cloned()))
+        });
+        watched.set(&3, "three")?;
+        assert!(watched.delete(&3)?);
+        assert!(!watched.delete(&3)?);
+        assert_eq!(
+            events.lock().unwrap().clone(),
+            vec![
+                (3, None, Some("three".to_string())),
+                (3, Some("three".to_string()), None)
+            ]
+        );
+
+        Ok(())
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, CandyTypedKey)]
+#[candy(type_id = 0x5c1e_07a3)]
+enum Color {
+    Red,
+    Green,
+    Blue = 7,
+    Black = -1,
+}
+
+#[derive(Debug, PartialEq, Eq, Encode, Decode, CandyTypedKey)]
+#[candy(type_id = 0x5c1e_07a4)]
+struct Pixel {
+    x: u16,
+    y: u16,
+}
+
+#[test]
+fn test_typed_derived_keys() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(dir, Config::default())?);
+
+        let colors = [Color::Red, Color::Green, Color::Blue, Color::Black];
+        let typed = CandyTypedStore::<Color, u32>::new(db.clone());
+        for (i, color) in colors.iter().enumerate() {
+            typed.set(color, &(i as u32))?;
+        }
+        for (i, color) in colors.iter().enumerate() {
+            assert_eq!(typed.get(color)?, Some(i as u32));
+        }
+        let mut keys = typed.iter().map(|res| res.unwrap().0).collect::<Vec<_>>();
+        keys.sort_by_key(|c| *c as i64);
+        assert_eq!(
+            keys,
+            vec![Color::Black, Color::Red, Color::Green, Color::Blue]
+        );
+
+        // enums can also be values
+        let by_pixel = CandyTypedStore::<Pixel, Color>::new(db.clone());
+        by_pixel.set(&Pixel { x: 1, y: 2 }, &Color::Blue)?;
+        assert_eq!(by_pixel.get(&Pixel { x: 1, y: 2 })?, Some(Color::Blue));
+        assert_eq!(by_pixel.get(&Pixel { x: 2, y: 1 })?, None);
+
+        // unknown discriminants fail to decode
+        assert_eq!(
+            Color::from_bytes::<LE>(&Color::Blue.to_bytes::<LE>()).unwrap(),
+            Color::Blue
+        );
+        assert!(Color::from_bytes::<LE>(&3i64.to_bytes::<LE>()).is_err());
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_typed_update() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let db = Arc::new(CandyStore::open(
+            dir,
+            Config {
+                max_update_retries: 2,
+                ..Default::default()
+            },
+        )?);
+        let typed = CandyTypedStore::<String, u64>::new(db.clone());
+
+        assert_eq!(typed.update("counter", |v| v.unwrap_or(10) + 1)?, 11);
+        assert_eq!(typed.update("counter", |v| v.unwrap_or(10) + 1)?, 12);
+        assert_eq!(typed.get("counter")?, Some(12));
+
+        // concurrent updates are never lost
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..100 {
+                        // retries are bounded, so they may run out under this contention
+                        while typed.update("hits", |v| v.unwrap_or(0) + 1).is_err() {}
+                    }
+                });
+            }
+        });
+        assert_eq!(typed.get("hits")?, Some(400));
+
+        // a writer that always gets in the way exhausts the retries
+        let mut calls = 0;
+        let e = typed
+            .update("counter", |v| {
+                calls += 1;
+                typed.set("counter", &(v.unwrap() + 100)).unwrap();
+                0
+            })
+            .unwrap_err();
+        assert_eq!(
+            e.downcast_ref::<CandyError>(),
+            Some(&CandyError::TooManyRetries(2))
+        );
+        assert_eq!(calls, 3);
+        assert_eq!(typed.get("counter")?, Some(312));
+
+        Ok(())
+    })
+}
+
+// a run-length encoding, which is enough to exercise the compression of values
+fn rle_compress(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![];
+    for chunk in data.chunk_by(|a, b| a == b) {
+        for run in chunk.chunks(255) {
+            out.push(run.len() as u8);
+            out.push(run[0]);
+        }
+    }
+    out
+}
+
+fn rle_decompress(data: &[u8]) -> Result<Vec<u8>> {
+    if data.len() % 2 != 0 {
+        return Err(anyhow::anyhow!("truncated run"));
+    }
+    Ok(data
+        .chunks(2)
+        .flat_map(|run| std::iter::repeat_n(run[1], run[0] as usize))
+        .collect())
+}
+
+#[test]
+fn test_typed_value_compression() -> Result<()> {
+    run_in_tempdir(|dir| {
+        let long = "x".repeat(1000);
+
+        // entries written before compression was enabled, including one that starts with a byte that may
+        // look like a compressed entry
+        {
+            let db = Arc::new(CandyStore::open(dir, Config::default())?);
+            let typed = CandyTypedStore::<String, String>::new(db.clone());
+            typed.set("legacy", &long)?;
+            let tricky = CandyTypedStore::<u32, Vec<u8>>::new(db.clone());
+            tricky.set(&1, &vec![0xc7; 0xc7])?;
+        }
+
+        let config = Config {
+            value_codec: Some(ValueCodec {
+                compress: rle_compress,
+                decompress: rle_decompress,
+            }),
+            value_compression_threshold: 100,
+            ..Default::default()
+        };
+        let db = Arc::new(CandyStore::open(dir, config)?);
+        let typed = CandyTypedStore::<String, String>::new(db.clone());
+        let tricky = CandyTypedStore::<u32, Vec<u8>>::new(db.clone());
+        assert_eq!(typed.get("legacy")?, Some(long.clone()));
+        assert_eq!(tricky.get(&1)?, Some(vec![0xc7; 0xc7]));
+
+        typed.set("long", &long)?;
+        typed.set("short", &"y".repeat(50))?;
+        assert_eq!(typed.get("long")?, Some(long.clone()));
+        assert_eq!(typed.get("short")?, Some("y".repeat(50)));
+        assert_eq!(
+            typed.get_raw_bytes("long")?,
+            Some(databuf::Encode::to_bytes::<LE>(&long))
+        );
+
+        // only the new long value is stored compressed
+        assert_eq!(
+            typed.value_size_histogram(&[40, 100])?,
+            vec![(40, 1), (100, 1), (usize::MAX, 1)]
+        );
+
+        // comparisons are made against the uncompressed values
+        let longer = "x".repeat(2000);
+        assert_eq!(typed.replace("long", &longer, Some(&"z".into()))?, None);
+        assert_eq!(
+            typed.replace("long", &longer, Some(&long))?,
+            Some(long.clone())
+        );
+        assert_eq!(
+            typed.iter().collect::<Result<HashMap<_, _>>>()?,
+            HashMap::from([
+                ("legacy".into(), long.clone()),
+                ("long".into(), longer),
+                ("short".into(), "y".repeat(50)),
+            ])
+        );
+
+        // migrated values are compressed just like set ones
+        let migrated = CandyTypedStore::<String, String>::new_in_namespace(db.clone(), "migrated");
+        migrated.set("k", &"x".to_owned())?;
+        assert_eq!(migrated.migrate(|_, v| Some(v.repeat(1000)))?, 1);
+        assert_eq!(migrated.get("k")?, Some(long.clone()));
+        assert_eq!(
+            migrated.value_size_histogram(&[40])?,
+            vec![(40, 1), (usize::MAX, 0)]
+        );
+
+        Ok(())
+    })
+}